@@ -0,0 +1,131 @@
+// Times what every obfuscated connection does before the handshake: deriving the
+// keys, setting up the ciphers and decrypting and unpacking the req_pq_multi, and
+// counts its allocations. Run with `cargo bench`, plain timing keeps the benchmark
+// free of dependencies
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    hint::black_box,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Instant,
+};
+
+use aes::cipher::{KeyIvInit, StreamCipher};
+use rand::RngCore;
+use srv::{frame_abridged, parse_req_pq_multi, validate_obfuscation_header, ObfuscationKeys};
+
+type Aes256Ctr64Be = ctr::Ctr64BE<aes::Aes256>;
+
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+const ABRIDGED_TAG: [u8; 4] = [0xef; 4];
+const ITERATIONS: u32 = 100_000;
+
+// The key derivation before the keys were sliced out of arrays, kept to compare
+fn derive_with_iterators(init: &[u8; 64]) -> ObfuscationKeys {
+    let reversed: Vec<u8> = init.iter().rev().copied().collect();
+    ObfuscationKeys {
+        encrypt_key: init.iter().skip(8).take(32).copied().collect::<Vec<_>>()[..]
+            .try_into()
+            .unwrap(),
+        encrypt_iv: init.iter().skip(40).take(16).copied().collect::<Vec<_>>()[..]
+            .try_into()
+            .unwrap(),
+        decrypt_key: reversed
+            .iter()
+            .skip(8)
+            .take(32)
+            .copied()
+            .collect::<Vec<_>>()[..]
+            .try_into()
+            .unwrap(),
+        decrypt_iv: reversed
+            .iter()
+            .skip(40)
+            .take(16)
+            .copied()
+            .collect::<Vec<_>>()[..]
+            .try_into()
+            .unwrap(),
+    }
+}
+
+fn init() -> [u8; 64] {
+    let mut init = [0; 64];
+    loop {
+        rand::thread_rng().fill_bytes(&mut init);
+        if validate_obfuscation_header(&init).is_ok() {
+            break;
+        }
+    }
+    init[56..60].copy_from_slice(&ABRIDGED_TAG);
+    init
+}
+
+// What a client sends first: the header and an abridged req_pq_multi
+fn first_packet(init: &[u8; 64]) -> Vec<u8> {
+    let mut packet = vec![0; 8];
+    packet.extend(0x51e57ac42770964ai64.to_le_bytes());
+    packet.extend(20u32.to_le_bytes());
+    packet.extend(0xbe7e8ef1u32.to_le_bytes());
+    packet.extend([0x42; 16]);
+
+    let keys = ObfuscationKeys::derive(init);
+    let mut encryptor = Aes256Ctr64Be::new(&keys.encrypt_key.into(), &keys.encrypt_iv.into());
+    let mut encrypted = *init;
+    encryptor.apply_keystream(&mut encrypted);
+    let mut bytes = init[..56].to_vec();
+    bytes.extend(&encrypted[56..]);
+    let mut frame = frame_abridged(&packet);
+    encryptor.apply_keystream(&mut frame);
+    bytes.extend(frame);
+    bytes
+}
+
+fn bench(name: &str, mut f: impl FnMut()) {
+    // Warm up the caches and the CPU frequency
+    for _ in 0..ITERATIONS / 10 {
+        f();
+    }
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    let per_iteration = start.elapsed() / ITERATIONS;
+    let allocations = (ALLOCATIONS.load(Ordering::Relaxed) - allocations) / ITERATIONS as u64;
+    println!(
+        "{:<40} {:>10?} {:>3} allocations",
+        name, per_iteration, allocations
+    );
+}
+
+fn main() {
+    let init = init();
+    let packet = first_packet(&init);
+
+    bench("derive keys, slices", || {
+        black_box(ObfuscationKeys::derive(black_box(&init)));
+    });
+    bench("derive keys, iterators", || {
+        black_box(derive_with_iterators(black_box(&init)));
+    });
+    bench("ciphers + decrypt + unpack req_pq_multi", || {
+        black_box(parse_req_pq_multi(black_box(&packet), None, 1 << 20).unwrap());
+    });
+}
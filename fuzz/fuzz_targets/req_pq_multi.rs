@@ -0,0 +1,10 @@
+#![no_main]
+
+use grammers_tl_types::Cursor;
+use libfuzzer_sys::fuzz_target;
+use srv::messages::ReqPqMulti;
+
+// Any input has to be parsed or rejected with an error, never panic
+fuzz_target!(|data: &[u8]| {
+    let _ = ReqPqMulti::parse(&mut Cursor::from_slice(data));
+});
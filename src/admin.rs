@@ -0,0 +1,136 @@
+// Admin control socket, changes some of the settings of a running server. One
+// command per line, each answered with `OK` or `ERR REASON`:
+//
+//   fault FAULT[@PROBABILITY] on    inject the fault, as with --fault
+//   fault FAULT off                 stop injecting the fault, at any probability
+//   faults clear                    stop injecting any fault
+//   delay MS                        set --response-delay
+//   loglevel LEVEL                  off, error, warn, info, debug or trace
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    sync::{Arc, RwLock},
+    thread,
+    time::Duration,
+};
+
+use log::{debug, info, LevelFilter};
+
+use crate::{
+    config::Config,
+    error::{Result, ServerError},
+    fault::FaultRule,
+    logging,
+};
+
+// The settings changed over the admin socket, which take precedence over the ones
+// the server started with
+#[derive(Default)]
+pub struct Overrides {
+    faults: RwLock<Option<Vec<FaultRule>>>,
+    response_delay: RwLock<Option<Duration>>,
+}
+
+impl Overrides {
+    pub fn faults(&self) -> Option<Vec<FaultRule>> {
+        self.faults.read().unwrap().clone()
+    }
+
+    pub fn response_delay(&self) -> Option<Duration> {
+        *self.response_delay.read().unwrap()
+    }
+}
+
+// Runs a command of the grammar above
+pub fn execute(config: &Config, command: &str) -> Result<()> {
+    let invalid = |reason: &str| ServerError::InvalidAdminCommand {
+        command: command.to_owned(),
+        reason: reason.to_owned(),
+    };
+    let words: Vec<&str> = command.split_whitespace().collect();
+    match words[..] {
+        ["fault", rule, on_off] => {
+            let rule: FaultRule = rule.parse()?;
+            let on = match on_off {
+                "on" => true,
+                "off" => false,
+                _ => return Err(invalid("expected on or off")),
+            };
+            // Read and written under one lock, so concurrent commands don't lose
+            // each other's faults
+            let mut faults = config.overrides.faults.write().unwrap();
+            let faults = faults.get_or_insert_with(|| config.faults.clone());
+            faults.retain(|other| other.fault != rule.fault);
+            if on {
+                faults.push(rule);
+            }
+        }
+        ["faults", "clear"] => *config.overrides.faults.write().unwrap() = Some(Vec::new()),
+        ["delay", ms] => {
+            let ms = ms
+                .parse()
+                .map_err(|_| invalid("expected the milliseconds"))?;
+            *config.overrides.response_delay.write().unwrap() = Some(Duration::from_millis(ms));
+        }
+        ["loglevel", level] => {
+            let level: LevelFilter = level
+                .parse()
+                .map_err(|_| invalid("expected off, error, warn, info, debug or trace"))?;
+            logging::set_level(level);
+        }
+        [] => return Err(invalid("empty")),
+        _ => return Err(invalid("unknown command")),
+    }
+    Ok(())
+}
+
+// Answers the commands of a client until it closes the connection
+pub fn handle(stream: impl Read + Write, config: &Config) -> Result<()> {
+    let mut stream = BufReader::new(stream);
+    let mut line = String::new();
+    while stream.read_line(&mut line)? != 0 {
+        let command = line.trim();
+        match execute(config, command) {
+            Ok(()) => {
+                info!("admin: {}", command);
+                stream.get_mut().write_all(b"OK\n")?;
+            }
+            Err(e) => {
+                debug!("admin: {}", e);
+                writeln!(stream.get_mut(), "ERR {}", e)?;
+            }
+        }
+        line.clear();
+    }
+    Ok(())
+}
+
+// Serves every client on a thread of its own, blocks the calling thread
+pub fn serve(listener: TcpListener, config: Arc<Config>) -> Result<()> {
+    info!("serving admin commands on {}", listener.local_addr()?);
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let config = config.clone();
+        thread::spawn(move || {
+            if let Err(e) = handle(stream, &config) {
+                debug!("admin client failed: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+pub fn serve_unix(listener: std::os::unix::net::UnixListener, config: Arc<Config>) -> Result<()> {
+    info!("serving admin commands on {:?}", listener.local_addr()?);
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let config = config.clone();
+        thread::spawn(move || {
+            if let Err(e) = handle(stream, &config) {
+                debug!("admin client failed: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
@@ -0,0 +1,104 @@
+use std::{future::Future, io, time::Duration};
+
+use log::info;
+#[cfg(unix)]
+use tokio::net::UnixStream;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+    time,
+};
+
+use crate::{
+    config::Config,
+    connection::ConnId,
+    driver::{Driver, Step},
+    dump::DumpStream,
+    error::Result,
+    handshake::HandshakeOutcome,
+};
+
+pub struct AsyncConnection<'a, S> {
+    stream: DumpStream<S>,
+    driver: Driver<'a>,
+    timeout: Duration,
+}
+
+impl<'a, S: AsyncRead + AsyncWrite + Unpin> AsyncConnection<'a, S> {
+    pub fn new(id: ConnId, stream: S, config: &'a Config) -> Self {
+        Self {
+            stream: DumpStream::new(id, stream, config.dump_dir.as_deref()),
+            driver: Driver::new(id, config),
+            timeout: config.timeout,
+        }
+    }
+
+    // Reads, writes and sleeps as the driver asks until it closes the connection
+    pub async fn run(mut self) -> Result<HandshakeOutcome> {
+        loop {
+            match self.driver.poll() {
+                Step::Read => {
+                    let mut chunk = [0; 1024];
+                    match with_timeout(self.timeout, self.stream.read(&mut chunk)).await {
+                        Ok(len) => self.driver.feed(&mut chunk[..len]),
+                        Err(e) => self.driver.fail(e.into()),
+                    }
+                }
+                Step::Write(data) => {
+                    if let Err(e) = with_timeout(self.timeout, self.stream.write_all(&data)).await {
+                        self.driver.fail(e.into());
+                    }
+                }
+                Step::Sleep(delay) => time::sleep(delay).await,
+                Step::Close => {
+                    // Sends FIN right away, so the client doesn't wait on a socket we
+                    // stopped reading. The client may have closed it first, which is fine
+                    let _ = with_timeout(self.timeout, self.stream.shutdown()).await;
+                    return self.driver.finish();
+                }
+            }
+        }
+    }
+}
+
+// Mirrors the socket timeouts of the blocking connection
+async fn with_timeout<T>(
+    timeout: Duration,
+    future: impl Future<Output = io::Result<T>>,
+) -> io::Result<T> {
+    time::timeout(timeout, future)
+        .await
+        .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into()))
+}
+
+pub async fn handle_connection_async(stream: TcpStream, config: &Config) -> Result<()> {
+    let id = ConnId::new(stream.peer_addr()?);
+    info!("{} connection accepted on {}", id, stream.local_addr()?);
+    AsyncConnection::new(id, stream, config)
+        .run()
+        .await
+        .map(drop)
+}
+
+#[cfg(unix)]
+pub async fn handle_unix_connection_async(stream: UnixStream, config: &Config) -> Result<()> {
+    let id = ConnId::unaddressed("unix");
+    match stream.local_addr()?.as_pathname() {
+        Some(path) => info!("{} connection accepted on {}", id, path.display()),
+        None => info!("{} connection accepted", id),
+    }
+    AsyncConnection::new(id, stream, config)
+        .run()
+        .await
+        .map(drop)
+}
+
+// Runs the handshake over any stream, e.g. a Unix socket or an in-memory duplex
+pub async fn serve_handshake_async<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: S,
+    config: &Config,
+) -> Result<HandshakeOutcome> {
+    let id = ConnId::unaddressed("stream");
+    info!("{} connection accepted", id);
+    AsyncConnection::new(id, stream, config).run().await
+}
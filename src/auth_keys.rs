@@ -0,0 +1,74 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    error::{Result, ServerError},
+    keys,
+};
+
+pub struct AuthKey {
+    pub key: [u8; 256],
+    // Set for the temporary keys of p_q_inner_data_temp
+    pub expires_in: Option<Duration>,
+}
+
+impl AuthKey {
+    pub fn id(&self) -> i64 {
+        keys::auth_key_id(&self.key)
+    }
+}
+
+struct StoredKey {
+    key: [u8; 256],
+    expires_at: Option<Instant>,
+}
+
+impl StoredKey {
+    fn expired(&self, now: Instant) -> bool {
+        self.expires_at.is_some_and(|expires_at| now >= expires_at)
+    }
+}
+
+// The keys negotiated by the handshakes, shared by all connections
+#[derive(Default)]
+pub struct AuthKeyStore {
+    keys: Mutex<HashMap<i64, StoredKey>>,
+}
+
+impl AuthKeyStore {
+    pub fn insert(&self, auth_key: &AuthKey) {
+        self.insert_at(auth_key, Instant::now())
+    }
+
+    pub fn insert_at(&self, auth_key: &AuthKey, now: Instant) {
+        let mut keys = self.keys.lock().unwrap();
+        keys.retain(|_, key| !key.expired(now));
+        keys.insert(
+            auth_key.id(),
+            StoredKey {
+                key: auth_key.key,
+                expires_at: auth_key.expires_in.map(|expires_in| now + expires_in),
+            },
+        );
+    }
+
+    pub fn get(&self, auth_key_id: i64) -> Result<[u8; 256]> {
+        self.get_at(auth_key_id, Instant::now())
+    }
+
+    // An expired key is forgotten, so it's only reported as expired once
+    pub fn get_at(&self, auth_key_id: i64, now: Instant) -> Result<[u8; 256]> {
+        let mut keys = self.keys.lock().unwrap();
+        match keys.get(&auth_key_id) {
+            Some(key) if key.expired(now) => {
+                keys.remove(&auth_key_id);
+                Err(ServerError::ExpiredAuthKey(auth_key_id))
+            }
+            Some(key) => Ok(key.key),
+            None => Err(ServerError::UnknownAuthKey(auth_key_id)),
+        }
+    }
+}
@@ -0,0 +1,90 @@
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::{
+    io::{Read, Write},
+    net::{Shutdown, TcpStream},
+    thread,
+};
+
+use log::info;
+
+use crate::{
+    config::Config,
+    connection::ConnId,
+    driver::{Driver, Step},
+    dump::DumpStream,
+    error::Result,
+    handshake::HandshakeOutcome,
+};
+
+pub struct Connection<'a, S> {
+    stream: DumpStream<S>,
+    driver: Driver<'a>,
+}
+
+impl<'a, S: Read + Write> Connection<'a, S> {
+    pub fn new(id: ConnId, stream: S, config: &'a Config) -> Self {
+        Self {
+            stream: DumpStream::new(id, stream, config.dump_dir.as_deref()),
+            driver: Driver::new(id, config),
+        }
+    }
+
+    // Reads, writes and sleeps as the driver asks until it closes the connection
+    pub fn run(mut self) -> Result<HandshakeOutcome> {
+        loop {
+            match self.driver.poll() {
+                Step::Read => {
+                    let mut chunk = [0; 1024];
+                    match self.stream.read(&mut chunk) {
+                        Ok(len) => self.driver.feed(&mut chunk[..len]),
+                        Err(e) => self.driver.fail(e.into()),
+                    }
+                }
+                Step::Write(data) => {
+                    if let Err(e) = self.stream.write_all(&data) {
+                        self.driver.fail(e.into());
+                    }
+                }
+                Step::Sleep(delay) => thread::sleep(delay),
+                Step::Close => return self.driver.finish(),
+            }
+        }
+    }
+}
+
+pub fn handle_connection(stream: TcpStream, config: &Config) -> Result<()> {
+    let id = ConnId::new(stream.peer_addr()?);
+    info!("{} connection accepted on {}", id, stream.local_addr()?);
+    stream.set_read_timeout(Some(config.timeout))?;
+    stream.set_write_timeout(Some(config.timeout))?;
+    let socket = stream.try_clone()?;
+    let res = Connection::new(id, stream, config).run();
+    // Closes both directions right away, so the client doesn't wait on a socket we
+    // stopped reading. The client may have closed it first, which is fine
+    let _ = socket.shutdown(Shutdown::Both);
+    res.map(drop)
+}
+
+#[cfg(unix)]
+pub fn handle_unix_connection(stream: UnixStream, config: &Config) -> Result<()> {
+    let id = ConnId::unaddressed("unix");
+    match stream.local_addr()?.as_pathname() {
+        Some(path) => info!("{} connection accepted on {}", id, path.display()),
+        None => info!("{} connection accepted", id),
+    }
+    stream.set_read_timeout(Some(config.timeout))?;
+    stream.set_write_timeout(Some(config.timeout))?;
+    let socket = stream.try_clone()?;
+    let res = Connection::new(id, stream, config).run();
+    let _ = socket.shutdown(Shutdown::Both);
+    res.map(drop)
+}
+
+// Runs the handshake over any stream, e.g. a Unix socket or an in-memory pipe. The
+// timeouts of the config are left to the stream, and it's closed by dropping it
+pub fn serve_handshake<S: Read + Write>(stream: S, config: &Config) -> Result<HandshakeOutcome> {
+    let id = ConnId::unaddressed("stream");
+    info!("{} connection accepted", id);
+    Connection::new(id, stream, config).run()
+}
@@ -0,0 +1,199 @@
+use std::{path::PathBuf, time::Duration};
+
+use crate::{
+    admin::Overrides,
+    auth_keys::AuthKeyStore,
+    connection::TransportHook,
+    dh::DhParams,
+    error::{Result, ServerError},
+    fake_tls::FakeTlsSecret,
+    fault::FaultRule,
+    handshake::HandshakeCallback,
+    message_id::{MessageIds, MessageIdsFactory},
+    messages::SERVER_NONCE,
+    policy::{DefaultPolicy, HandshakePolicy},
+    pq::DEFAULT_PQ_BITS,
+    records::JsonRecords,
+    rng::ServerRng,
+    rsa_key::{RsaKey, TELEGRAM_FINGERPRINT},
+    salts::SaltStore,
+    script::Script,
+    stats::{HandshakeLatency, StageStats},
+};
+
+// Telegram accepts up to 1020 messages in a container
+pub const DEFAULT_MAX_VECTOR_LEN: usize = 1024;
+
+// What happens to the connection once the auth key is created
+#[derive(Clone, Copy, Debug, Default, PartialEq, clap::ValueEnum)]
+pub enum AfterHandshake {
+    // Shut the connection down right away
+    #[default]
+    Close,
+    // Read and drop whatever the client sends until it's idle for the timeout
+    Wait,
+    // Answer the encrypted messages of the client
+    Echo,
+}
+
+pub struct Config {
+    // Read and write timeout of a connection
+    pub timeout: Duration,
+    // Longest transport frame a client may announce, checked before it's buffered
+    pub max_packet: usize,
+    // Elements accepted in a vector of the client, e.g. the messages of a container
+    pub max_vector_len: usize,
+    // Bit length of the generated pq
+    pub pq_bits: u32,
+    // Use the fixed `SERVER_NONCE` instead of a random one for every connection
+    pub deterministic_nonce: bool,
+    // Reject the handshake messages whose msg_id is too far from the server time
+    pub check_msgid_window: bool,
+    // The random values of the answers, seeded for reproducible transcripts
+    pub rng: ServerRng,
+    // The msg_ids of the answers of a connection, from the clock unless a test
+    // wants a fixed sequence
+    pub message_ids: MessageIdsFactory,
+    // Decrypt the client's encrypted_data, new_nonce stays zeroed without any
+    pub rsa_keys: Vec<RsaKey>,
+    // Advertised instead of the fingerprints of the keys, for clients which pinned
+    // one. It selects the first key, if any
+    pub fingerprint_override: Option<i64>,
+    // Also accept the clients of MTProxy's fake TLS mode with this secret
+    pub fake_tls: Option<FakeTlsSecret>,
+    // Serves the obfuscated clients with a transport of one's own, instead of the
+    // built-in ones, for the tags it picks
+    pub custom_transport: Option<TransportHook>,
+    // Directory to dump the raw bytes of every connection into
+    pub dump_dir: Option<PathBuf>,
+    // Close the connection after answering ReqPqMulti, for testing how clients
+    // handle it
+    pub stop_after_res_pq: bool,
+    // Answer ReqPqMulti with this transport error instead of ResPq, for testing how
+    // clients handle it
+    pub respond_error: Option<i32>,
+    pub after_handshake: AfterHandshake,
+    // Wait for another handshake on the connection once one is done, and restart
+    // whenever the client sends a new req_pq_multi. Telegram doesn't do it, but some
+    // test clients create several keys over one connection
+    pub repeat_handshakes: bool,
+    // Added before every answer, like the latency of a slow DC. Read it with
+    // `response_delay()`, which takes the admin socket into account
+    pub response_delay: Duration,
+    // Faults injected into the answers, read them with `faults()` too
+    pub faults: Vec<FaultRule>,
+    // Serve the canned responses of the script instead of running the handshake
+    pub script: Option<Script>,
+    // Layers of invokeWithLayer answered, the others get CONNECTION_LAYER_INVALID
+    pub min_layer: Option<i32>,
+    pub max_layer: Option<i32>,
+    // dh_prime and g sent in server_DH_inner_data
+    pub dh: DhParams,
+    // Builds the answers of the handshake
+    pub policy: Box<dyn HandshakePolicy>,
+    // Where the JSON record of every connection goes
+    pub json_records: Option<JsonRecords>,
+    // Called with the new auth key once a handshake is done
+    pub on_handshake_complete: Option<HandshakeCallback>,
+    // Not configuration, but shared by all the connections the same way
+    pub auth_keys: AuthKeyStore,
+    pub stage_stats: StageStats,
+    pub handshake_latency: HandshakeLatency,
+    pub salts: SaltStore,
+    // Changed at runtime over the admin socket
+    pub overrides: Overrides,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(30),
+            max_packet: 1 << 20,
+            max_vector_len: DEFAULT_MAX_VECTOR_LEN,
+            pq_bits: DEFAULT_PQ_BITS,
+            deterministic_nonce: false,
+            check_msgid_window: false,
+            rng: ServerRng::default(),
+            message_ids: Box::new(MessageIds::default),
+            rsa_keys: Vec::new(),
+            fingerprint_override: None,
+            fake_tls: None,
+            custom_transport: None,
+            dump_dir: None,
+            stop_after_res_pq: false,
+            respond_error: None,
+            after_handshake: AfterHandshake::default(),
+            repeat_handshakes: false,
+            response_delay: Duration::ZERO,
+            faults: Vec::new(),
+            script: None,
+            min_layer: None,
+            max_layer: None,
+            dh: DhParams::default(),
+            policy: Box::new(DefaultPolicy),
+            json_records: None,
+            on_handshake_complete: None,
+            auth_keys: AuthKeyStore::default(),
+            stage_stats: StageStats::default(),
+            handshake_latency: HandshakeLatency::default(),
+            salts: SaltStore::default(),
+            overrides: Overrides::default(),
+        }
+    }
+}
+
+impl Config {
+    pub fn server_nonce(&self) -> [u8; 16] {
+        if self.deterministic_nonce {
+            return SERVER_NONCE;
+        }
+        let mut server_nonce = [0; 16];
+        self.rng.fill_bytes(&mut server_nonce);
+        server_nonce
+    }
+
+    pub fn faults(&self) -> Vec<FaultRule> {
+        self.overrides
+            .faults()
+            .unwrap_or_else(|| self.faults.clone())
+    }
+
+    pub fn response_delay(&self) -> Duration {
+        self.overrides
+            .response_delay()
+            .unwrap_or(self.response_delay)
+    }
+
+    pub fn fingerprints(&self) -> Vec<i64> {
+        if let Some(fingerprint) = self.fingerprint_override {
+            return vec![fingerprint];
+        }
+        if self.rsa_keys.is_empty() {
+            return vec![TELEGRAM_FINGERPRINT];
+        }
+        self.rsa_keys.iter().map(RsaKey::fingerprint).collect()
+    }
+
+    pub fn supports_layer(&self, layer: i32) -> bool {
+        self.min_layer.is_none_or(|min| layer >= min)
+            && self.max_layer.is_none_or(|max| layer <= max)
+    }
+
+    // Returns `None` for the placeholder fingerprint advertised without any keys
+    pub fn rsa_key(&self, fingerprint: i64) -> Result<Option<&RsaKey>> {
+        if self.fingerprint_override == Some(fingerprint) {
+            return Ok(self.rsa_keys.first());
+        }
+        if self.rsa_keys.is_empty() && fingerprint == TELEGRAM_FINGERPRINT {
+            return Ok(None);
+        }
+        match self
+            .rsa_keys
+            .iter()
+            .find(|key| key.fingerprint() == fingerprint)
+        {
+            Some(key) => Ok(Some(key)),
+            None => Err(ServerError::UnknownFingerprint(fingerprint)),
+        }
+    }
+}
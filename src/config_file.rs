@@ -0,0 +1,196 @@
+// The options of a TOML file, which are handed to clap like the same flags on the
+// command line. Only the part of TOML the options need is parsed: `key = value`
+// lines with strings, numbers, booleans or one line arrays of them, and comments:
+//
+//     bind = ["127.0.0.1:11337", "[::1]:11337"]
+//     max-connections = 128   # the keys are the flags, `_` works like `-`
+//     stop-after-res-pq = true
+use std::{fs, path::Path};
+
+use crate::error::{Result, ServerError};
+
+pub struct ConfigFile {
+    // The keys with `_` instead of `-`, like the ids of clap
+    pub options: Vec<(String, Value)>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    // Strings and numbers, which clap parses like the flags
+    Scalar(String),
+    Array(Vec<String>),
+}
+
+impl ConfigFile {
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(toml: &str) -> Result<Self> {
+        let mut options: Vec<(String, Value)> = Vec::new();
+        for (i, line) in toml.lines().enumerate() {
+            let invalid = |reason: &str| ServerError::InvalidConfigFile {
+                line: i + 1,
+                reason: reason.to_owned(),
+            };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line.starts_with('[') {
+                return Err(invalid("tables aren't supported"));
+            }
+            let (key, value) = line.split_once('=').ok_or_else(|| invalid("expected ="))?;
+            let key = key.trim();
+            if key.is_empty()
+                || !key
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                return Err(invalid("invalid key"));
+            }
+            let key = key.replace('-', "_");
+            if options.iter().any(|(other, _)| *other == key) {
+                return Err(invalid("the key is set twice"));
+            }
+            let value = Parser {
+                s: value.trim_start(),
+            }
+            .value()
+            .map_err(invalid)?;
+            options.push((key, value));
+        }
+        Ok(Self { options })
+    }
+
+    // The options as flags, without the ones `skip` is true for. A false boolean
+    // is left out like a flag which isn't given
+    pub fn args(&self, skip: impl Fn(&str) -> bool) -> Vec<String> {
+        let mut args = Vec::new();
+        for (key, value) in self.options.iter().filter(|(key, _)| !skip(key)) {
+            let flag = format!("--{}", key.replace('_', "-"));
+            match value {
+                Value::Bool(true) => args.push(flag),
+                Value::Bool(false) => {}
+                // With `=`, so values starting with `-` aren't taken for flags
+                Value::Scalar(value) => args.push(format!("{}={}", flag, value)),
+                Value::Array(values) => {
+                    args.extend(values.iter().map(|value| format!("{}={}", flag, value)))
+                }
+            }
+        }
+        args
+    }
+}
+
+struct Parser<'a> {
+    s: &'a str,
+}
+
+impl Parser<'_> {
+    fn value(&mut self) -> Result<Value, &'static str> {
+        let value = if self.eat('[') {
+            let mut values = Vec::new();
+            self.skip_whitespace();
+            if !self.eat(']') {
+                loop {
+                    self.skip_whitespace();
+                    values.push(self.scalar()?);
+                    self.skip_whitespace();
+                    if self.eat(']') {
+                        break;
+                    }
+                    if !self.eat(',') {
+                        return Err("expected , or ] in the array");
+                    }
+                    self.skip_whitespace();
+                    // A trailing comma
+                    if self.eat(']') {
+                        break;
+                    }
+                }
+            }
+            Value::Array(values)
+        } else if self.eat_word("true") {
+            Value::Bool(true)
+        } else if self.eat_word("false") {
+            Value::Bool(false)
+        } else {
+            Value::Scalar(self.scalar()?)
+        };
+        self.skip_whitespace();
+        if !self.s.is_empty() && !self.s.starts_with('#') {
+            return Err("trailing characters after the value");
+        }
+        Ok(value)
+    }
+
+    fn scalar(&mut self) -> Result<String, &'static str> {
+        if self.eat('"') {
+            return self.basic_string();
+        }
+        if self.eat('\'') {
+            let (string, rest) = self.s.split_once('\'').ok_or("unterminated string")?;
+            self.s = rest;
+            return Ok(string.to_owned());
+        }
+        let len = self
+            .s
+            .find(|c: char| !(c.is_ascii_alphanumeric() || "+-._:".contains(c)))
+            .unwrap_or(self.s.len());
+        if len == 0 {
+            return Err("expected a value");
+        }
+        let (number, rest) = self.s.split_at(len);
+        self.s = rest;
+        // Underscores separate the digits in TOML
+        Ok(number.replace('_', ""))
+    }
+
+    fn basic_string(&mut self) -> Result<String, &'static str> {
+        let mut string = String::new();
+        let mut chars = self.s.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.s = &self.s[i + 1..];
+                    return Ok(string);
+                }
+                '\\' => string.push(match chars.next() {
+                    Some((_, '"')) => '"',
+                    Some((_, '\\')) => '\\',
+                    Some((_, 'n')) => '\n',
+                    Some((_, 't')) => '\t',
+                    _ => return Err("unsupported escape in a string"),
+                }),
+                c => string.push(c),
+            }
+        }
+        Err("unterminated string")
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        match self.s.strip_prefix(c) {
+            Some(rest) => {
+                self.s = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn eat_word(&mut self, word: &str) -> bool {
+        match self.s.strip_prefix(word) {
+            Some(rest) if !rest.starts_with(|c: char| c.is_ascii_alphanumeric()) => {
+                self.s = rest;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        self.s = self.s.trim_start();
+    }
+}
@@ -0,0 +1,667 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        LazyLock, Mutex,
+    },
+};
+
+use aes::cipher::{KeyIvInit, StreamCipher};
+use bytes::{Buf, BufMut, BytesMut};
+use grammers_mtproto::transport::{self, Abridged, Full, Intermediate, Transport};
+use log::{debug, trace, warn};
+use rand::{Rng, RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use sha1::{Digest, Sha1};
+use sha2::{Digest as _, Sha256};
+
+use crate::{
+    error::{Result, ServerError},
+    fake_tls::{self, FakeTlsSecret, RecordReader},
+    rng::ServerRng,
+};
+
+pub type Aes256Ctr64Be = ctr::Ctr64BE<aes::Aes256>;
+
+pub const ABRIDGED_TAG: [u8; 4] = [0xef; 4];
+pub const INTERMEDIATE_TAG: [u8; 4] = [0xee; 4];
+pub const PADDED_INTERMEDIATE_TAG: [u8; 4] = [0xdd; 4];
+
+// Clients never start the obfuscation header with these, so they don't look like
+// the unobfuscated transports, HTTP or TLS
+const FORBIDDEN_PREFIXES: [(&[u8; 4], &str); 7] = [
+    (b"HEAD", "HTTP request"),
+    (b"POST", "HTTP request"),
+    (b"GET ", "HTTP request"),
+    (b"OPTI", "HTTP request"),
+    (&INTERMEDIATE_TAG, "unobfuscated intermediate transport"),
+    (
+        &PADDED_INTERMEDIATE_TAG,
+        "unobfuscated padded intermediate transport",
+    ),
+    (&[0x16, 0x03, 0x01, 0x02], "TLS handshake"),
+];
+
+static NEXT_CONN_ID: AtomicU64 = AtomicU64::new(1);
+// The handshake stage of every open connection, so the failure logs and the JSON
+// logs can tell it
+static STAGES: LazyLock<Mutex<HashMap<u64, &'static str>>> = LazyLock::new(Default::default);
+
+// Prefixes the log lines of a connection, so interleaved connections can be told apart
+#[derive(Clone, Copy)]
+pub struct ConnId {
+    id: u64,
+    peer: Peer,
+}
+
+#[derive(Clone, Copy)]
+enum Peer {
+    Addr(SocketAddr),
+    // Streams without an address, named after their transport
+    Unaddressed(&'static str),
+}
+
+impl ConnId {
+    pub fn new(peer: SocketAddr) -> Self {
+        // IPv4 clients of a dual-stack listener show up as ::ffff:a.b.c.d
+        let peer = match peer {
+            SocketAddr::V6(v6) => match v6.ip().to_ipv4_mapped() {
+                Some(ip) => SocketAddr::new(ip.into(), v6.port()),
+                None => peer,
+            },
+            SocketAddr::V4(_) => peer,
+        };
+        Self {
+            id: NEXT_CONN_ID.fetch_add(1, Ordering::Relaxed),
+            peer: Peer::Addr(peer),
+        }
+    }
+
+    pub fn unaddressed(transport: &'static str) -> Self {
+        Self {
+            id: NEXT_CONN_ID.fetch_add(1, Ordering::Relaxed),
+            peer: Peer::Unaddressed(transport),
+        }
+    }
+
+    pub fn number(&self) -> u64 {
+        self.id
+    }
+
+    // The address of the client, or the transport without one
+    pub fn peer(&self) -> String {
+        match self.peer {
+            Peer::Addr(addr) => addr.to_string(),
+            Peer::Unaddressed(transport) => transport.to_string(),
+        }
+    }
+
+    pub fn set_stage(&self, stage: &'static str) {
+        STAGES.lock().unwrap().insert(self.id, stage);
+    }
+
+    pub fn stage(&self) -> &'static str {
+        stage(self.id).unwrap_or("obfuscation header")
+    }
+
+    // Forgets the stage, the id shouldn't be logged after it
+    pub fn close(&self) {
+        STAGES.lock().unwrap().remove(&self.id);
+    }
+}
+
+pub fn stage(id: u64) -> Option<&'static str> {
+    STAGES.lock().unwrap().get(&id).copied()
+}
+
+impl fmt::Display for ConnId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.peer {
+            Peer::Addr(addr) => write!(f, "[#{} {}]", self.id, addr),
+            Peer::Unaddressed(transport) => write!(f, "[#{} {}]", self.id, transport),
+        }
+    }
+}
+
+// Rejects the headers a client wouldn't generate, which are mostly probes of other
+// protocols
+pub fn validate_obfuscation_header(init: &[u8; 64]) -> Result<()> {
+    if init[0] == ABRIDGED_TAG[0] {
+        return Err(ServerError::InvalidObfuscationHeader(
+            "unobfuscated abridged transport",
+        ));
+    }
+    if let Some((_, reason)) = FORBIDDEN_PREFIXES
+        .iter()
+        .find(|(prefix, _)| init[..4] == prefix[..])
+    {
+        return Err(ServerError::InvalidObfuscationHeader(reason));
+    }
+    if init[4..8] == [0; 4] {
+        return Err(ServerError::InvalidObfuscationHeader("full transport"));
+    }
+    Ok(())
+}
+
+// The client encrypts with the keys from the obfuscation header and the server with
+// the ones from the reversed header
+pub struct ObfuscationKeys {
+    pub encrypt_key: [u8; 32],
+    pub encrypt_iv: [u8; 16],
+    pub decrypt_key: [u8; 32],
+    pub decrypt_iv: [u8; 16],
+}
+
+impl ObfuscationKeys {
+    pub fn derive(init: &[u8; 64]) -> Self {
+        let mut reversed = *init;
+        reversed.reverse();
+        Self {
+            encrypt_key: init[8..40].try_into().unwrap(),
+            encrypt_iv: init[40..56].try_into().unwrap(),
+            decrypt_key: reversed[8..40].try_into().unwrap(),
+            decrypt_iv: reversed[40..56].try_into().unwrap(),
+        }
+    }
+
+    // Clients of a proxy hash the keys with its secret: key = SHA256(key + secret)
+    pub fn derive_with_secret(init: &[u8; 64], secret: &FakeTlsSecret) -> Self {
+        let mut keys = Self::derive(init);
+        for key in [&mut keys.encrypt_key, &mut keys.decrypt_key] {
+            *key = Sha256::new()
+                .chain(&key[..])
+                .chain(secret.0)
+                .finalize()
+                .into();
+        }
+        keys
+    }
+}
+
+// The DC the client connects to, the 2 bytes after the transport tag of the decrypted
+// obfuscation header. It's negative for the media DCs, and the test DCs count from
+// 10000
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DcId(pub i16);
+
+impl DcId {
+    pub fn from_header(decrypted_init: &[u8; 64]) -> Self {
+        Self(i16::from_le_bytes(
+            decrypted_init[60..62].try_into().unwrap(),
+        ))
+    }
+
+    pub fn number(self) -> u16 {
+        self.0.unsigned_abs() % 10000
+    }
+
+    pub fn is_media(self) -> bool {
+        self.0 < 0
+    }
+
+    pub fn is_test(self) -> bool {
+        self.0.unsigned_abs() > 10000
+    }
+
+    // Telegram has 5 DCs, clients which don't care about the DC tend to send garbage
+    pub fn is_plausible(self) -> bool {
+        (1..=5).contains(&self.number()) && self.0.unsigned_abs() < 20000
+    }
+}
+
+impl fmt::Display for DcId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.number())?;
+        if self.is_test() {
+            write!(f, " (test)")?;
+        }
+        if self.is_media() {
+            write!(f, " (media)")?;
+        }
+        Ok(())
+    }
+}
+
+// Picks the transport of a tag of the obfuscation header before the built-in ones,
+// for experimenting with transports of one's own. `None` leaves the tag to the
+// built-in ones
+pub type TransportHook = Box<dyn Fn([u8; 4]) -> Option<Box<dyn Transport + Send>> + Send + Sync>;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Framing {
+    Full,
+    Abridged,
+    Intermediate,
+    // The intermediate one with 0 to 15 random bytes after every packet
+    PaddedIntermediate,
+    // Of a `TransportHook`, which frames and unframes the packets all by itself
+    Custom,
+}
+
+// Obfuscation and transport framing without any I/O, shared by the sync and
+// async connections
+pub struct Codec {
+    id: ConnId,
+    // Both are missing when the client doesn't use the obfuscated transport
+    decryptor: Option<Aes256Ctr64Be>,
+    encryptor: Option<Aes256Ctr64Be>,
+    // Unpacks every framing, but only packs the Full and the custom ones, see `pack`
+    transport: Box<dyn Transport + Send>,
+    framing: Framing,
+    // Draws the paddings of the padded intermediate transport, missing with the others
+    padding: Option<ChaCha20Rng>,
+    // Missing without the obfuscated transport, which doesn't tell the DC
+    dc_id: Option<DcId>,
+    // Set once the quick ack bit of the packet in the buffer is cleared
+    quick_ack_requested: bool,
+    // The token of the last unpacked packet, if the client asked for it
+    quick_ack: Option<u32>,
+    // Set for the clients of the fake TLS mode, which wrap everything in TLS records
+    records: Option<RecordReader>,
+    // Longest frame the client may announce
+    max_packet: usize,
+    // Decrypted bytes which aren't unpacked yet
+    buffer: BytesMut,
+    // Reused for every unpacked packet and every frame, instead of allocating them.
+    // An unpacked packet is split off of it, the allocation is reclaimed once the
+    // packet is dropped
+    scratch: BytesMut,
+}
+
+impl Codec {
+    // Returns `None` if the rest of the obfuscation header has to be read
+    pub fn full(id: ConnId, init: &[u8; 8], max_packet: usize) -> Option<Self> {
+        // The obfuscation header can't have zeroes here, while the first packet of
+        // the Full transport always has: it's the sequence number
+        if init[4..8] != [0; 4] {
+            return None;
+        }
+        debug!("{} full transport", id);
+        Some(Self {
+            id,
+            decryptor: None,
+            encryptor: None,
+            transport: Box::new(Full::new()),
+            framing: Framing::Full,
+            padding: None,
+            dc_id: None,
+            quick_ack_requested: false,
+            quick_ack: None,
+            records: None,
+            max_packet,
+            buffer: BytesMut::from(&init[..]),
+            scratch: BytesMut::new(),
+        })
+    }
+
+    // `secret` is only set for the fake TLS mode, the obfuscated transport of a direct
+    // connection has none
+    pub fn obfuscated(
+        id: ConnId,
+        init: [u8; 64],
+        max_packet: usize,
+        secret: Option<&FakeTlsSecret>,
+        custom_transport: Option<&TransportHook>,
+    ) -> Result<Self> {
+        let keys = match secret {
+            Some(secret) => ObfuscationKeys::derive_with_secret(&init, secret),
+            None => ObfuscationKeys::derive(&init),
+        };
+        Self::with_keys(id, init, &keys, max_packet, custom_transport)
+    }
+
+    // Like `obfuscated`, with keys which may not come from the header
+    pub fn with_keys(
+        id: ConnId,
+        mut init: [u8; 64],
+        keys: &ObfuscationKeys,
+        max_packet: usize,
+        custom_transport: Option<&TransportHook>,
+    ) -> Result<Self> {
+        trace!("{} init: {:02x?}", id, init);
+        validate_obfuscation_header(&init)?;
+
+        trace!("{} encrypt_key: {:02x?}", id, keys.encrypt_key);
+        trace!("{} encrypt_iv: {:02x?}", id, keys.encrypt_iv);
+        trace!("{} decrypt_key: {:02x?}", id, keys.decrypt_key);
+        trace!("{} decrypt_iv: {:02x?}", id, keys.decrypt_iv);
+
+        let mut decryptor = Aes256Ctr64Be::new(&keys.encrypt_key.into(), &keys.encrypt_iv.into());
+        decryptor.apply_keystream(&mut init);
+        trace!("{} init: {:02x?}", id, init);
+        let encryptor = Aes256Ctr64Be::new(&keys.decrypt_key.into(), &keys.decrypt_iv.into());
+
+        let tag: [u8; 4] = init[56..60].try_into().unwrap();
+        debug!("{} transport tag: {:02x?}", id, tag);
+        let custom_transport = custom_transport.and_then(|hook| hook(tag));
+        let (transport, framing): (Box<dyn Transport + Send>, _) = match (tag, custom_transport) {
+            (_, Some(transport)) => {
+                debug!("{} custom transport", id);
+                (transport, Framing::Custom)
+            }
+            (ABRIDGED_TAG, None) => (Box::new(Abridged::new()), Framing::Abridged),
+            (INTERMEDIATE_TAG, None) => (Box::new(Intermediate::new()), Framing::Intermediate),
+            // The length of the intermediate transport covers the padding
+            (PADDED_INTERMEDIATE_TAG, None) => {
+                (Box::new(Intermediate::new()), Framing::PaddedIntermediate)
+            }
+            _ => return Err(ServerError::UnknownTransport(tag)),
+        };
+        let dc_id = DcId::from_header(&init);
+        if !dc_id.is_plausible() {
+            warn!("{} implausible DC id {}", id, dc_id.0);
+        }
+
+        Ok(Self {
+            id,
+            decryptor: Some(decryptor),
+            encryptor: Some(encryptor),
+            transport,
+            framing,
+            padding: (framing == Framing::PaddedIntermediate)
+                .then(|| ChaCha20Rng::from_rng(rand::thread_rng()).unwrap()),
+            dc_id: Some(dc_id),
+            quick_ack_requested: false,
+            quick_ack: None,
+            records: None,
+            max_packet,
+            buffer: BytesMut::new(),
+            scratch: BytesMut::new(),
+        })
+    }
+
+    pub fn dc_id(&self) -> Option<DcId> {
+        self.dc_id
+    }
+
+    // Draws the paddings from `rng` instead, so a seeded one covers them too
+    pub fn seed_padding(&mut self, rng: &ServerRng) {
+        if let Some(padding) = &mut self.padding {
+            *padding = ChaCha20Rng::from_seed(rng.gen());
+        }
+    }
+
+    // Returns `None` if more bytes have to be fed
+    pub fn unpack(&mut self) -> Result<Option<BytesMut>> {
+        self.clear_quick_ack_bit();
+        if let Some(len) = self.announced_len() {
+            if len > self.max_packet {
+                warn!(
+                    "{} rejecting a packet of {} bytes, at most {} are allowed",
+                    self.id, len, self.max_packet
+                );
+                return Err(transport::Error::BadLen { got: len as u32 }.into());
+            }
+        }
+        let frame_len = self.frame_len();
+        self.scratch.clear();
+        match self.transport.unpack(&self.buffer, &mut self.scratch) {
+            Ok(len) => {
+                self.buffer.advance(len);
+                if self.framing == Framing::PaddedIntermediate {
+                    let unpadded = strip_padding(&self.scratch).len();
+                    self.scratch.truncate(unpadded);
+                }
+                let packet = self.scratch.split();
+                trace!("{} packet: {:02x?}", self.id, &packet[..]);
+                // The header of a custom transport is unknown, so its packets are only
+                // checked once they're unpacked
+                if self.framing == Framing::Custom && packet.len() > self.max_packet {
+                    warn!(
+                        "{} rejecting a packet of {} bytes, at most {} are allowed",
+                        self.id,
+                        packet.len(),
+                        self.max_packet
+                    );
+                    return Err(transport::Error::BadLen {
+                        got: packet.len() as u32,
+                    }
+                    .into());
+                }
+                // Anything but the frame the header announced, or a payload of a part
+                // of a word, would leave the stream out of sync
+                let custom = self.framing == Framing::Custom;
+                if (!custom && frame_len != Some(len)) || !packet.len().is_multiple_of(4) {
+                    warn!(
+                        "{} inconsistent frame: {} bytes consumed of {:?}, {} unpacked",
+                        self.id,
+                        len,
+                        frame_len,
+                        packet.len()
+                    );
+                    return Err(transport::Error::BadLen {
+                        got: packet.len() as u32,
+                    }
+                    .into());
+                }
+                if std::mem::take(&mut self.quick_ack_requested) {
+                    let hash = Sha1::digest(&packet);
+                    let token = u32::from_be_bytes(hash[..4].try_into().unwrap()) | 1 << 31;
+                    debug!("{} quick ack requested: {:08x}", self.id, token);
+                    self.quick_ack = Some(token);
+                }
+                // Nothing but a transport error fits in 4 bytes
+                if let Ok(code) = <[u8; 4]>::try_from(&packet[..]) {
+                    return Err(ServerError::TransportError(i32::from_le_bytes(code)));
+                }
+                Ok(Some(packet))
+            }
+            Err(transport::Error::MissingBytes) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // The error for a buffer which ends before the frame in it does. Until the
+    // length is known, its header is what's missing
+    pub(crate) fn incomplete_frame(&self) -> ServerError {
+        let header_len = match self.framing {
+            Framing::Abridged if self.buffer.is_empty() => 1,
+            _ => 4,
+        };
+        ServerError::ShortField {
+            field: "frame",
+            expected: self.frame_len().unwrap_or(header_len),
+            available: self.buffer.len(),
+        }
+    }
+
+    // The length in the header of the packet in the buffer, so a long one can be
+    // rejected before the rest of it is buffered
+    fn announced_len(&self) -> Option<usize> {
+        let len = match self.framing {
+            Framing::Custom => return None,
+            Framing::Full | Framing::Intermediate | Framing::PaddedIntermediate => {
+                u32::from_le_bytes(self.buffer.get(..4)?.try_into().unwrap())
+            }
+            Framing::Abridged => match *self.buffer.first()? {
+                // The extended length, in the next 3 bytes
+                0x7f => {
+                    let mut len = [0; 4];
+                    len[..3].copy_from_slice(self.buffer.get(1..4)?);
+                    u32::from_le_bytes(len) * 4
+                }
+                len => len as u32 * 4,
+            },
+        };
+        Some(len as usize)
+    }
+
+    // The bytes the frame in the buffer takes, header included
+    fn frame_len(&self) -> Option<usize> {
+        let header_len = match self.framing {
+            // The length of the full transport covers the whole frame
+            Framing::Full => 0,
+            Framing::Intermediate | Framing::PaddedIntermediate => 4,
+            Framing::Abridged if *self.buffer.first()? == 0x7f => 4,
+            Framing::Abridged => 1,
+            Framing::Custom => return None,
+        };
+        Some(header_len + self.announced_len()?)
+    }
+
+    // The highest bit of the length asks for a quick ack, the transports of grammers
+    // would take it for a part of the length
+    fn clear_quick_ack_bit(&mut self) {
+        let index = match self.framing {
+            Framing::Full | Framing::Custom => return,
+            Framing::Abridged => 0,
+            Framing::Intermediate | Framing::PaddedIntermediate => 3,
+        };
+        if let Some(byte) = self.buffer.get_mut(index) {
+            if *byte & 0x80 != 0 {
+                *byte &= 0x7f;
+                self.quick_ack_requested = true;
+            }
+        }
+    }
+
+    // The ack of the last unpacked packet, to be sent before the answer. It's big
+    // endian in the abridged transport and little endian in the intermediate one
+    pub fn take_quick_ack(&mut self) -> Option<&[u8]> {
+        let token = self.quick_ack.take()?;
+        let ack = match self.framing {
+            Framing::Abridged => token.to_be_bytes(),
+            _ => token.to_le_bytes(),
+        };
+        self.scratch.clear();
+        self.scratch.extend_from_slice(&ack);
+        Some(self.seal())
+    }
+
+    // Unwraps the TLS records from now on, `payload` is what the records read so far
+    // held after the obfuscation header
+    pub fn unwrap_tls(&mut self, records: RecordReader, payload: &mut [u8]) {
+        self.records = Some(records);
+        self.feed_payload(payload);
+    }
+
+    pub fn feed(&mut self, chunk: &mut [u8]) -> Result<()> {
+        let Some(records) = &mut self.records else {
+            self.feed_payload(chunk);
+            return Ok(());
+        };
+        records.feed(chunk);
+        let mut payloads = Vec::new();
+        while let Some(payload) = records.next_payload()? {
+            payloads.push(payload);
+        }
+        for mut payload in payloads {
+            self.feed_payload(&mut payload);
+        }
+        Ok(())
+    }
+
+    fn feed_payload(&mut self, payload: &mut [u8]) {
+        if let Some(decryptor) = &mut self.decryptor {
+            decryptor.apply_keystream(payload);
+        }
+        self.buffer.extend_from_slice(payload);
+    }
+
+    // Transport errors are a negative code instead of a whole packet, e.g. -404
+    pub fn pack_transport_error(&mut self, code: i32) -> &[u8] {
+        self.frame(&code.to_le_bytes())
+    }
+
+    // The returned frame is only valid until the codec is used again. None of the
+    // transports can frame a partial word
+    pub fn pack(&mut self, packet: &[u8]) -> Result<&[u8]> {
+        if !packet.len().is_multiple_of(4) {
+            return Err(ServerError::UnalignedPacket(packet.len()));
+        }
+        Ok(self.frame(packet))
+    }
+
+    fn frame(&mut self, packet: &[u8]) -> &[u8] {
+        self.scratch.clear();
+        // The transports of grammers start their first frame with the transport tag,
+        // which the client sent in the obfuscation header already, so the server
+        // frames the obfuscated transports itself
+        match self.framing {
+            Framing::Full | Framing::Custom => self.transport.pack(packet, &mut self.scratch),
+            Framing::Abridged => put_abridged(packet, &mut self.scratch),
+            Framing::Intermediate => put_intermediate(packet, &mut self.scratch),
+            Framing::PaddedIntermediate => {
+                let padding = self.padding.as_mut().unwrap();
+                put_padded_intermediate(packet, padding, &mut self.scratch)
+            }
+        }
+        trace!("{} packet_mtproto: {:02x?}", self.id, &self.scratch[..]);
+        self.seal()
+    }
+
+    // Obfuscates the scratch buffer for the client, and wraps it in TLS records if it
+    // asked
+    fn seal(&mut self) -> &[u8] {
+        if let Some(encryptor) = &mut self.encryptor {
+            encryptor.apply_keystream(&mut self.scratch);
+        }
+        if self.records.is_some() {
+            let records = fake_tls::wrap(&self.scratch);
+            self.scratch.clear();
+            self.scratch.extend_from_slice(&records);
+        }
+        &self.scratch
+    }
+}
+
+// The length in 4 byte words, in 1 byte below 0x7f and otherwise in the 3 bytes after
+// a 0x7f byte. `packet` is a whole number of words, like every MTProto message
+pub fn frame_abridged(packet: &[u8]) -> Vec<u8> {
+    assert!(packet.len().is_multiple_of(4));
+    let mut frame = Vec::with_capacity(4 + packet.len());
+    put_abridged(packet, &mut frame);
+    frame
+}
+
+// The length in bytes, in 4 bytes
+pub fn frame_intermediate(packet: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(4 + packet.len());
+    put_intermediate(packet, &mut frame);
+    frame
+}
+
+// The MTProto header tells the length of the packet in front of the padding: the
+// length of the body follows it in an unencrypted message, the encrypted data is a
+// whole number of 16 byte blocks. Anything shorter, like a transport error, is
+// a whole number of words
+pub fn strip_padding(packet: &[u8]) -> &[u8] {
+    let len = packet.len();
+    let unpadded = match packet.get(..20) {
+        Some(header) if header[..8] == [0; 8] => {
+            let body_len = u32::from_le_bytes(header[16..20].try_into().unwrap()) as usize;
+            // Too long a body is left for the message to be rejected as truncated
+            (20 + body_len).min(len)
+        }
+        Some(_) if len >= 24 => 24 + (len - 24) / 16 * 16,
+        _ => len / 4 * 4,
+    };
+    &packet[..unpadded]
+}
+
+fn put_abridged(packet: &[u8], frame: &mut impl BufMut) {
+    let words = packet.len() / 4;
+    if words < 0x7f {
+        frame.put_u8(words as u8);
+    } else {
+        frame.put_u8(0x7f);
+        frame.put_slice(&(words as u32).to_le_bytes()[..3]);
+    }
+    frame.put_slice(packet);
+}
+
+fn put_intermediate(packet: &[u8], frame: &mut impl BufMut) {
+    frame.put_u32_le(packet.len() as u32);
+    frame.put_slice(packet);
+}
+
+fn put_padded_intermediate(packet: &[u8], rng: &mut impl RngCore, frame: &mut impl BufMut) {
+    let mut padding = [0; 15];
+    let padding = &mut padding[..rng.gen_range(0..=15)];
+    rng.fill_bytes(padding);
+    frame.put_u32_le((packet.len() + padding.len()) as u32);
+    frame.put_slice(packet);
+    frame.put_slice(padding);
+}
@@ -0,0 +1,73 @@
+use crate::error::{Result, ServerError};
+use aes::{
+    cipher::{consts::U16, BlockDecrypt, BlockEncrypt, BlockSizeUser, KeyInit},
+    Aes256, Block,
+};
+
+// The first half of `iv` is the previous ciphertext block, the second one is the
+// previous plaintext block
+pub fn ige_encrypt(data: &[u8], key: &[u8; 32], iv: &[u8; 32]) -> Result<Vec<u8>> {
+    ige_encrypt_with(&Aes256::new(key.into()), data, iv)
+}
+
+pub fn ige_decrypt(data: &[u8], key: &[u8; 32], iv: &[u8; 32]) -> Result<Vec<u8>> {
+    ige_decrypt_with(&Aes256::new(key.into()), data, iv)
+}
+
+// With any cipher of 16 byte blocks, e.g. the AES-128 of OpenSSL's test vectors.
+// MTProto only uses AES-256
+pub fn ige_encrypt_with<C>(cipher: &C, data: &[u8], iv: &[u8; 32]) -> Result<Vec<u8>>
+where
+    C: BlockEncrypt + BlockSizeUser<BlockSize = U16>,
+{
+    check_len(data)?;
+    let mut prev_cipher = Block::clone_from_slice(&iv[..16]);
+    let mut prev_plain = Block::clone_from_slice(&iv[16..]);
+
+    let mut res = Vec::with_capacity(data.len());
+    for chunk in data.chunks_exact(16) {
+        let plain = Block::clone_from_slice(chunk);
+        let mut block = xor(&plain, &prev_cipher);
+        cipher.encrypt_block(&mut block);
+        block = xor(&block, &prev_plain);
+        res.extend_from_slice(&block);
+        prev_cipher = block;
+        prev_plain = plain;
+    }
+    Ok(res)
+}
+
+pub fn ige_decrypt_with<C>(cipher: &C, data: &[u8], iv: &[u8; 32]) -> Result<Vec<u8>>
+where
+    C: BlockDecrypt + BlockSizeUser<BlockSize = U16>,
+{
+    check_len(data)?;
+    let mut prev_cipher = Block::clone_from_slice(&iv[..16]);
+    let mut prev_plain = Block::clone_from_slice(&iv[16..]);
+
+    let mut res = Vec::with_capacity(data.len());
+    for chunk in data.chunks_exact(16) {
+        let encrypted = Block::clone_from_slice(chunk);
+        let mut block = xor(&encrypted, &prev_plain);
+        cipher.decrypt_block(&mut block);
+        block = xor(&block, &prev_cipher);
+        res.extend_from_slice(&block);
+        prev_cipher = encrypted;
+        prev_plain = block;
+    }
+    Ok(res)
+}
+
+fn check_len(data: &[u8]) -> Result<()> {
+    if !data.len().is_multiple_of(16) {
+        return Err(ServerError::CryptoError(format!(
+            "IGE data length {} isn't a multiple of 16",
+            data.len()
+        )));
+    }
+    Ok(())
+}
+
+fn xor(a: &Block, b: &Block) -> Block {
+    a.iter().zip(b).map(|(a, b)| a ^ b).collect()
+}
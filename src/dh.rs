@@ -0,0 +1,185 @@
+use std::fmt;
+
+use num_bigint::BigUint;
+use rand::RngCore;
+
+pub const G: i32 = 3;
+// The auth key is g^ab mod dh_prime, in 256 bytes
+pub const PRIME_BITS: u64 = 2048;
+const PRIME: &str = "\
+    C71CAEB9C6B1C9048E6C522F70F13F73980D40238E3E21C14934D037563D930F\
+    48198A0AA7C14058229493D22530F4DBFA336F6E0AC925139543AED44CCE7C37\
+    20FD51F69458705AC68CD4FE6B6B13ABDC9746512969328454F18FAF8C595F64\
+    2477FE96BB2A941D5BCD1D4AC8CC49880708FA9B378E3C4F3A9060BEE67CF9A4\
+    A4A695811051907E162753B56B0F6B410DBA74D8A84B2A14B3144E0EF1284754\
+    FD17ED950D5965B4B9DD46582DB1178D169C6BC465B0D6FF9CA3928FEF5B9AE4\
+    E418FC15E83EBEA0F87FA9FF5EED70050DED2849F47BF959D956850CE929851F\
+    0D8115F635B105EE2E4E15D04B2454BF6F4FADF034B10403119CD8E3B92FCC5B";
+
+#[derive(Debug)]
+pub enum DhGenError {
+    Retry,
+    Fail,
+}
+
+impl fmt::Display for DhGenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DhGenError::Retry => write!(f, "dh_gen_retry"),
+            DhGenError::Fail => write!(f, "dh_gen_fail"),
+        }
+    }
+}
+
+impl std::error::Error for DhGenError {}
+
+pub fn prime() -> BigUint {
+    BigUint::parse_bytes(PRIME.as_bytes(), 16).unwrap()
+}
+
+// dh_prime and g of the handshake, the well-known ones of MTProto unless a fresh
+// safe prime was generated
+#[derive(Clone, Debug)]
+pub struct DhParams {
+    pub prime: BigUint,
+    pub g: u32,
+}
+
+impl Default for DhParams {
+    fn default() -> Self {
+        Self {
+            prime: prime(),
+            g: G as u32,
+        }
+    }
+}
+
+impl DhParams {
+    // A random safe prime of the 2048 bits MTProto asks for, which `auth_key` relies
+    // on, and a generator the clients accept for it. Takes minutes
+    pub fn generate() -> Self {
+        let prime = generate_safe_prime(PRIME_BITS);
+        let g = generator(&prime).unwrap();
+        Self { prime, g }
+    }
+
+    pub fn g_a(&self, a: &BigUint) -> BigUint {
+        BigUint::from(self.g).modpow(a, &self.prime)
+    }
+
+    // 1 < g_b < dh_prime - 1, and 2^(2048-64) <= g_b <= dh_prime - 2^(2048-64) like
+    // MTProto asks, so a client can't force the key into a small subgroup
+    pub fn is_valid_g_b(&self, g_b: &BigUint) -> bool {
+        let one = BigUint::from(1u32);
+        let margin = BigUint::from(1u32) << (self.prime.bits().saturating_sub(64));
+        &one < g_b && *g_b < &self.prime - &one && &margin <= g_b && *g_b <= &self.prime - margin
+    }
+
+    pub fn auth_key(&self, g_b: &BigUint, a: &BigUint) -> [u8; 256] {
+        let g_ab = g_b.modpow(a, &self.prime).to_bytes_be();
+        let mut auth_key = [0; 256];
+        auth_key[256 - g_ab.len()..].copy_from_slice(&g_ab);
+        auth_key
+    }
+}
+
+// Rounds of Miller-Rabin, a composite passes with a probability of at most 4^-ROUNDS
+const ROUNDS: usize = 40;
+
+// `p` and (p - 1) / 2 are both prime
+pub fn is_safe_prime(p: &BigUint) -> bool {
+    is_probable_prime(p, ROUNDS) && is_probable_prime(&(p >> 1), ROUNDS)
+}
+
+// The generator the clients check for: g generates the subgroup of order (p - 1) / 2
+// when p is 3 mod 8 for 3, 7 mod 8 for 2, and so on. 4 is always a quadratic
+// residue, so it's only the fallback
+pub fn generator(p: &BigUint) -> Option<u32> {
+    let rem = |m: u32| (p % m).to_u32_digits().first().copied().unwrap_or(0);
+    [3, 2, 5, 6, 7, 4].into_iter().find(|&g| match g {
+        2 => rem(8) == 7,
+        3 => rem(3) == 2,
+        4 => true,
+        5 => [1, 4].contains(&rem(5)),
+        6 => [19, 23].contains(&rem(24)),
+        7 => [3, 5, 6].contains(&rem(7)),
+        _ => unreachable!(),
+    })
+}
+
+pub fn generate_safe_prime(bits: u64) -> BigUint {
+    // Smaller q would be caught by the sieve of its own small primes
+    assert!(bits >= 64);
+    let small_primes = small_primes(2000);
+    let mut rng = rand::thread_rng();
+    let mut bytes = vec![0; (bits as usize).div_ceil(8)];
+    loop {
+        rng.fill_bytes(&mut bytes);
+        // q of `bits - 1` bits, so p = 2q + 1 has `bits`
+        let mut q = BigUint::from_bytes_be(&bytes) >> (bytes.len() as u64 * 8 - bits + 1);
+        q.set_bit(bits - 2, true);
+        q.set_bit(0, true);
+        // Neither q nor 2q + 1 may have a small factor, which rules out most
+        // candidates before the expensive tests
+        let sieved = small_primes.iter().any(|&r| {
+            let rem = (&q % r).to_u32_digits().first().copied().unwrap_or(0);
+            rem == 0 || rem == (r - 1) / 2
+        });
+        if sieved {
+            continue;
+        }
+        let p = (&q << 1) + 1u32;
+        if is_probable_prime(&q, 1) && is_probable_prime(&p, 1) && is_safe_prime(&p) {
+            return p;
+        }
+    }
+}
+
+// The odd primes below `limit`
+fn small_primes(limit: u32) -> Vec<u32> {
+    (3..limit)
+        .step_by(2)
+        .filter(|&n| {
+            (3..)
+                .step_by(2)
+                .take_while(|d| d * d <= n)
+                .all(|d| n % d != 0)
+        })
+        .collect()
+}
+
+fn is_probable_prime(n: &BigUint, rounds: usize) -> bool {
+    let one = BigUint::from(1u32);
+    let two = BigUint::from(2u32);
+    if *n < two {
+        return false;
+    }
+    if *n <= BigUint::from(3u32) {
+        return true;
+    }
+    if !n.bit(0) {
+        return false;
+    }
+    let n_minus_one = n - &one;
+    let s = n_minus_one.trailing_zeros().unwrap();
+    let d = &n_minus_one >> s;
+    let mut rng = rand::thread_rng();
+    let mut bytes = vec![0; n.to_bytes_be().len()];
+    'rounds: for _ in 0..rounds {
+        // A random base in [2, n - 2]
+        rng.fill_bytes(&mut bytes);
+        let base = BigUint::from_bytes_be(&bytes) % (n - 3u32) + &two;
+        let mut x = base.modpow(&d, n);
+        if x == one || x == n_minus_one {
+            continue;
+        }
+        for _ in 1..s {
+            x = x.modpow(&two, n);
+            if x == n_minus_one {
+                continue 'rounds;
+            }
+        }
+        return false;
+    }
+    true
+}
@@ -0,0 +1,473 @@
+// What a connection does, whatever its I/O: the driver is fed what was read from the
+// client and tells the connection what to write back, how long to wait before it and
+// when to close. The blocking and the async connections only run these steps
+use std::{
+    collections::VecDeque,
+    mem,
+    time::{Duration, Instant},
+};
+
+use bytes::BytesMut;
+use log::{debug, error, info, warn};
+
+use crate::{
+    auth_keys::AuthKey,
+    config::{AfterHandshake, Config},
+    connection::{Codec, ConnId},
+    error::{Result, ServerError},
+    fake_tls::{self, ClientHello, RecordReader},
+    fault,
+    handshake::{self, HandshakeOutcome, HandshakeState},
+    metrics,
+    session::{self, Session},
+};
+
+// What the connection does next
+#[derive(Debug, PartialEq)]
+pub enum Step {
+    // Read from the client and feed what was read, nothing once it closed the
+    // connection
+    Read,
+    Write(Vec<u8>),
+    // Wait before the next write, for the response delay and the injected faults
+    Sleep(Duration),
+    // Close the connection, `finish` tells how it went
+    Close,
+}
+
+enum State {
+    // The first bytes, until they tell the transport apart
+    Header(Vec<u8>),
+    // A client of the fake TLS mode sends the obfuscation header in application
+    // data records
+    FakeTlsHeader {
+        records: RecordReader,
+        payload: Vec<u8>,
+    },
+    // The first packet tells a new handshake from a client which already has a key
+    FirstPacket,
+    Handshake {
+        state: HandshakeState,
+        // From the request, without the wait for the client to send it
+        start: Instant,
+    },
+    // Drops the packets of the client until it closes the connection or stays idle
+    // for the timeout
+    Idle,
+    Session(Session),
+    Script,
+    Closed,
+}
+
+pub struct Driver<'a> {
+    id: ConnId,
+    config: &'a Config,
+    start: Instant,
+    state: State,
+    codec: Option<Codec>,
+    // The key of the handshake, while the connection stays open after it
+    auth_key: Option<AuthKey>,
+    // Answered once the quick ack it asked for is sent
+    unanswered: Option<BytesMut>,
+    steps: VecDeque<Step>,
+    res: Option<Result<Option<AuthKey>>>,
+}
+
+impl<'a> Driver<'a> {
+    pub fn new(id: ConnId, config: &'a Config) -> Self {
+        id.set_stage("obfuscation header");
+        Self {
+            id,
+            config,
+            start: Instant::now(),
+            state: State::Header(Vec::with_capacity(64)),
+            codec: None,
+            auth_key: None,
+            unanswered: None,
+            steps: VecDeque::new(),
+            res: None,
+        }
+    }
+
+    pub fn poll(&mut self) -> Step {
+        loop {
+            if let Some(step) = self.steps.pop_front() {
+                return step;
+            }
+            if self.res.is_some() {
+                return Step::Close;
+            }
+            if let Some(packet) = self.unanswered.take() {
+                if let Err(e) = self.answer(&packet) {
+                    self.close(Err(e));
+                }
+                continue;
+            }
+            match self.unpack() {
+                Ok(true) => {}
+                Ok(false) => return Step::Read,
+                Err(e) => self.close(Err(e)),
+            }
+        }
+    }
+
+    // An empty read means the client closed the connection
+    pub fn feed(&mut self, data: &mut [u8]) {
+        if self.res.is_some() {
+            return;
+        }
+        if data.is_empty() {
+            return self.closed();
+        }
+        if let Err(e) = self.try_feed(data) {
+            self.close(Err(e));
+        }
+    }
+
+    // A read or a write failed, nothing else is written but the answer to the error
+    pub fn fail(&mut self, e: ServerError) {
+        self.steps.clear();
+        if matches!(self.state, State::Idle) && e.is_timeout() {
+            info!("{} idle, closing the connection", self.id);
+            let auth_key = self.auth_key.take();
+            return self.close(Ok(auth_key));
+        }
+        self.close(Err(e));
+    }
+
+    // Logs how the connection went, once it's closed
+    pub fn finish(self) -> Result<HandshakeOutcome> {
+        let (id, config, start) = (self.id, self.config, self.start);
+        let dc_id = self.codec.as_ref().and_then(Codec::dc_id);
+        let res = self
+            .res
+            .expect("the connection is closed")
+            .map(|auth_key| HandshakeOutcome { dc_id, auth_key });
+        let stage = id.stage();
+        match &res {
+            Ok(_) => metrics::record_handshake(start.elapsed()),
+            Err(e) => {
+                if e.is_timeout() {
+                    warn!("{} timed out waiting for {}", id, stage);
+                    metrics::record_failure(stage);
+                } else if e.is_disconnect() {
+                    debug!("{} client disconnected during {}: {}", id, stage, e);
+                    metrics::record_disconnect(stage);
+                } else {
+                    error!("{} connection failed at {}: {}", id, stage, e);
+                    metrics::record_failure(stage);
+                }
+            }
+        }
+        info!("{} connection closed after {:?}", id, start.elapsed());
+        if let Some(records) = &config.json_records {
+            if let Err(e) = records.finish(id, start.elapsed(), &res) {
+                warn!("{} failed to write the JSON record: {}", id, e);
+            }
+        }
+        id.close();
+        res
+    }
+
+    fn try_feed(&mut self, data: &mut [u8]) -> Result<()> {
+        match &mut self.state {
+            State::Header(header) => {
+                header.extend_from_slice(data);
+                let header = mem::take(header);
+                self.accept(header)
+            }
+            State::FakeTlsHeader { records, payload } => {
+                records.feed(data);
+                while let Some(record) = records.next_payload()? {
+                    payload.extend(record);
+                }
+                if payload.len() < 64 {
+                    return Ok(());
+                }
+                let State::FakeTlsHeader {
+                    records,
+                    mut payload,
+                } = mem::replace(&mut self.state, State::Closed)
+                else {
+                    unreachable!("matched above");
+                };
+                let mut rest = payload.split_off(64);
+                let mut codec = Codec::obfuscated(
+                    self.id,
+                    payload.try_into().unwrap(),
+                    self.config.max_packet,
+                    self.config.fake_tls.as_ref(),
+                    self.config.custom_transport.as_ref(),
+                )?;
+                codec.unwrap_tls(records, &mut rest);
+                self.accepted(codec, &mut [])
+            }
+            _ => self.codec.as_mut().expect("accepted").feed(data),
+        }
+    }
+
+    // Clients of the fake TLS mode are told apart by their ClientHello when there is
+    // a secret, the others are served as without one
+    fn accept(&mut self, mut header: Vec<u8>) -> Result<()> {
+        let (id, config) = (self.id, self.config);
+        let Some(init) = header.get(..8) else {
+            self.state = State::Header(header);
+            return Ok(());
+        };
+        if let Some(secret) = config
+            .fake_tls
+            .as_ref()
+            .filter(|_| fake_tls::is_client_hello(init))
+        {
+            let len = fake_tls::record_len(init)?.max(init.len());
+            if header.len() < len {
+                self.state = State::Header(header);
+                return Ok(());
+            }
+            let client_hello = ClientHello::parse(&header[..len], secret)?;
+            debug!("{} fake TLS, client time {}", id, client_hello.timestamp);
+            self.steps.push_back(Step::Write(fake_tls::server_hello(
+                &client_hello,
+                secret,
+                &config.rng,
+            )));
+            self.state = State::FakeTlsHeader {
+                records: RecordReader::default(),
+                payload: Vec::new(),
+            };
+            return self.try_feed(&mut header[len..]);
+        }
+        if let Some(codec) = Codec::full(id, init.try_into().unwrap(), config.max_packet) {
+            return self.accepted(codec, &mut header[8..]);
+        }
+
+        if header.len() < 64 {
+            self.state = State::Header(header);
+            return Ok(());
+        }
+        let codec = Codec::obfuscated(
+            id,
+            header[..64].try_into().unwrap(),
+            config.max_packet,
+            None,
+            config.custom_transport.as_ref(),
+        )?;
+        self.accepted(codec, &mut header[64..])
+    }
+
+    // `rest` is what the client sent after the header
+    fn accepted(&mut self, mut codec: Codec, rest: &mut [u8]) -> Result<()> {
+        codec.seed_padding(&self.config.rng);
+        codec.feed(rest)?;
+        self.codec = Some(codec);
+        self.state = match self.config.script {
+            Some(_) => {
+                self.id.set_stage("script");
+                State::Script
+            }
+            None => {
+                self.id.set_stage("ReqPqMulti");
+                State::FirstPacket
+            }
+        };
+        Ok(())
+    }
+
+    // Whether there is a packet to answer, after the quick ack it asked for
+    fn unpack(&mut self) -> Result<bool> {
+        let Some(codec) = &mut self.codec else {
+            return Ok(false);
+        };
+        let Some(packet) = codec.unpack()? else {
+            return Ok(false);
+        };
+        if let Some(ack) = codec.take_quick_ack() {
+            self.steps.push_back(Step::Write(ack.to_vec()));
+        }
+        self.unanswered = Some(packet);
+        Ok(true)
+    }
+
+    fn answer(&mut self, packet: &[u8]) -> Result<()> {
+        let (id, config) = (self.id, self.config);
+        match &mut self.state {
+            // A client which already has a key skips the handshake
+            State::FirstPacket if session::is_encrypted(packet) => {
+                id.set_stage("encrypted message");
+                self.state = State::Session(Session::new(id, config));
+                self.answer(packet)
+            }
+            State::FirstPacket => match config.respond_error {
+                Some(code) => {
+                    handshake::check_req_pq_multi(id, packet)?;
+                    info!("{} answering ReqPqMulti with transport error {}", id, code);
+                    let error = self
+                        .codec
+                        .as_mut()
+                        .expect("accepted")
+                        .pack_transport_error(code);
+                    self.steps.push_back(Step::Write(error.to_vec()));
+                    self.close(Ok(None));
+                    Ok(())
+                }
+                None => {
+                    let dc_id = self.codec.as_ref().and_then(Codec::dc_id);
+                    self.state = State::Handshake {
+                        state: HandshakeState::new(id, dc_id),
+                        start: Instant::now(),
+                    };
+                    self.answer(packet)
+                }
+            },
+            State::Handshake { .. } => self.handshake(packet),
+            State::Idle => {
+                debug!("{} dropping a packet of {} bytes", id, packet.len());
+                Ok(())
+            }
+            State::Session(session) => {
+                if let Some(answer) = session.handle_message(config, packet)? {
+                    self.write_answer("encrypted answer", &answer)?;
+                }
+                Ok(())
+            }
+            State::Script => {
+                let response = config.script.as_ref().expect("scripted").respond(packet)?;
+                debug!("{} scripted response: {:02x?}", id, response);
+                self.write(response)
+            }
+            State::Header(_) | State::FakeTlsHeader { .. } | State::Closed => {
+                unreachable!("packets are only unpacked once accepted")
+            }
+        }
+    }
+
+    fn handshake(&mut self, packet: &[u8]) -> Result<()> {
+        let (id, config) = (self.id, self.config);
+        let State::Handshake {
+            mut state,
+            mut start,
+        } = mem::replace(&mut self.state, State::Closed)
+        else {
+            unreachable!("called in the handshake");
+        };
+        if config.repeat_handshakes
+            && !matches!(state, HandshakeState::AwaitReqPq { .. })
+            && handshake::is_req_pq(packet)
+        {
+            info!("{} starting another handshake", id);
+            start = Instant::now();
+            state = HandshakeState::new(id, self.codec.as_ref().and_then(Codec::dc_id));
+        }
+        if let Some(answer) = state.step(config, packet)? {
+            let name = state.answered().unwrap_or_default();
+            self.write_answer(name, &answer)?;
+            if let HandshakeState::Established(_) = state {
+                config.handshake_latency.record(start.elapsed());
+                metrics::record_handshake_latency(&config.handshake_latency);
+            }
+        }
+        match state {
+            HandshakeState::Established(auth_key) if !config.repeat_handshakes => {
+                self.after_handshake(auth_key);
+            }
+            // Until the client starts another handshake or closes the connection
+            HandshakeState::Established(_) => {
+                id.set_stage("ReqPqMulti");
+                self.state = State::Handshake { state, start };
+            }
+            HandshakeState::AwaitReqDhParams(_)
+                if config.stop_after_res_pq
+                    || fault::drop_after_res_pq(id, &config.faults(), &config.rng) =>
+            {
+                info!("{} stopping after ResPq, closing the connection", id);
+                self.close(Ok(None));
+            }
+            _ => self.state = State::Handshake { state, start },
+        }
+        Ok(())
+    }
+
+    fn after_handshake(&mut self, auth_key: AuthKey) {
+        let id = self.id;
+        match self.config.after_handshake {
+            AfterHandshake::Close => {
+                info!("{} closing the connection", id);
+                self.close(Ok(Some(auth_key)));
+            }
+            AfterHandshake::Wait => {
+                id.set_stage("idle");
+                self.auth_key = Some(auth_key);
+                self.state = State::Idle;
+            }
+            AfterHandshake::Echo => {
+                id.set_stage("encrypted message");
+                self.auth_key = Some(auth_key);
+                self.state = State::Session(Session::new(id, self.config));
+            }
+        }
+    }
+
+    // The client is done once it closes the connection after the handshake, or
+    // after anything it sent in the session or to the script
+    fn closed(&mut self) {
+        let res = match mem::replace(&mut self.state, State::Closed) {
+            // Between the repeated handshakes
+            State::Handshake {
+                state: HandshakeState::Established(auth_key),
+                ..
+            } => Ok(Some(auth_key)),
+            State::Idle | State::Session(_) => Ok(self.auth_key.take()),
+            State::Script => Ok(None),
+            _ => Err(ServerError::ConnectionClosed),
+        };
+        self.close(res);
+    }
+
+    // Sends an answer with the faults of the config injected
+    fn write_answer(&mut self, name: &str, answer: &[u8]) -> Result<()> {
+        let faulty = fault::apply(
+            self.id,
+            &self.config.faults(),
+            &self.config.rng,
+            name,
+            answer,
+        );
+        let response_delay = self.config.response_delay();
+        if !response_delay.is_zero() {
+            debug!("{} delaying {} by {:?}", self.id, name, response_delay);
+        }
+        let delay = response_delay + faulty.delay;
+        if !delay.is_zero() {
+            self.steps.push_back(Step::Sleep(delay));
+        }
+        self.write(&faulty.answer)
+    }
+
+    fn write(&mut self, packet: &[u8]) -> Result<()> {
+        let frame = self.codec.as_mut().expect("accepted").pack(packet)?;
+        self.steps.push_back(Step::Write(frame.to_vec()));
+        Ok(())
+    }
+
+    // A failing connection answers the error if it can. The first error is the one
+    // the connection is closed with, the writes of its answer may fail too
+    fn close(&mut self, res: Result<Option<AuthKey>>) {
+        if let Some(Err(_)) = self.res {
+            return;
+        }
+        self.state = State::Closed;
+        if let (Err(e), Some(codec)) = (&res, &mut self.codec) {
+            if let Some(answer) = e.answer() {
+                if let Ok(frame) = codec.pack(answer) {
+                    self.steps.push_back(Step::Write(frame.to_vec()));
+                }
+            }
+            if let Some(code) = e.transport_error_code() {
+                debug!("{} sending transport error {}", self.id, code);
+                let error = codec.pack_transport_error(code);
+                self.steps.push_back(Step::Write(error.to_vec()));
+            }
+        }
+        self.res = Some(res);
+    }
+}
@@ -0,0 +1,194 @@
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use grammers_tl_types::Cursor;
+use log::{debug, warn};
+
+use crate::{
+    connection::{Codec, ConnId, ObfuscationKeys},
+    error::{Result, ServerError},
+    messages::ReqPqMulti,
+};
+
+// Copies everything read from and written to the stream into `<timestamp>-<id>.in`
+// and `<timestamp>-<id>.out`, before any deobfuscation. Does nothing without a
+// dump directory
+pub struct DumpStream<S> {
+    stream: S,
+    id: ConnId,
+    inbound: Option<File>,
+    outbound: Option<File>,
+}
+
+impl<S> DumpStream<S> {
+    pub fn new(id: ConnId, stream: S, dir: Option<&Path>) -> Self {
+        let (inbound, outbound) = match dir {
+            Some(dir) => {
+                let timestamp = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis();
+                let name = format!("{}-{}", timestamp, id.number());
+                (
+                    create(id, &dir.join(format!("{}.in", name))),
+                    create(id, &dir.join(format!("{}.out", name))),
+                )
+            }
+            None => (None, None),
+        };
+        Self {
+            stream,
+            id,
+            inbound,
+            outbound,
+        }
+    }
+}
+
+fn create(id: ConnId, path: &Path) -> Option<File> {
+    match File::create(path) {
+        Ok(file) => {
+            debug!("{} dumping to {}", id, path.display());
+            Some(file)
+        }
+        Err(e) => {
+            warn!("{} failed to create {}: {}", id, path.display(), e);
+            None
+        }
+    }
+}
+
+// A failed dump shouldn't break the connection, so it is only logged and the
+// dumping is stopped
+fn record(id: ConnId, file: &mut Option<File>, bytes: &[u8]) {
+    if let Some(f) = file {
+        if let Err(e) = f.write_all(bytes) {
+            warn!("{} failed to dump, stop dumping: {}", id, e);
+            *file = None;
+        }
+    }
+}
+
+impl<S: Read> Read for DumpStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.stream.read(buf)?;
+        record(self.id, &mut self.inbound, &buf[..len]);
+        Ok(len)
+    }
+}
+
+impl<S: Write> Write for DumpStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.stream.write(buf)?;
+        record(self.id, &mut self.outbound, &buf[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+// Parses the req_pq_multi of an inbound dump like the handshake does, without a
+// connection. `key_iv` decrypts the client's bytes instead of the keys of the
+// obfuscation header, e.g. for the clients of a proxy
+pub fn parse_req_pq_multi(
+    dump: &[u8],
+    key_iv: Option<([u8; 32], [u8; 16])>,
+    max_packet: usize,
+) -> Result<ReqPqMulti> {
+    let id = ConnId::unaddressed("dump");
+    let full = match key_iv {
+        Some(_) => None,
+        None => dump
+            .get(..8)
+            .and_then(|init| Codec::full(id, init.try_into().unwrap(), max_packet)),
+    };
+    let (mut codec, rest) = match full {
+        Some(codec) => (codec, &dump[8..]),
+        None => {
+            let init: [u8; 64] = dump
+                .get(..64)
+                .ok_or(ServerError::ShortField {
+                    field: "obfuscation header",
+                    expected: 64,
+                    available: dump.len(),
+                })?
+                .try_into()
+                .unwrap();
+            let mut keys = ObfuscationKeys::derive(&init);
+            if let Some((key, iv)) = key_iv {
+                keys.encrypt_key = key;
+                keys.encrypt_iv = iv;
+            }
+            (
+                Codec::with_keys(id, init, &keys, max_packet, None)?,
+                &dump[64..],
+            )
+        }
+    };
+    codec.feed(&mut rest.to_vec())?;
+    let packet = match codec.unpack()? {
+        Some(packet) => packet,
+        None => return Err(codec.incomplete_frame()),
+    };
+    ReqPqMulti::parse(&mut Cursor::from_slice(&packet))
+}
+
+#[cfg(feature = "tokio")]
+mod tokio_impl {
+    use std::{
+        io,
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+    use super::{record, DumpStream};
+
+    // The dump files are written synchronously, they only get a few kilobytes
+    // per connection
+    impl<S: AsyncRead + Unpin> AsyncRead for DumpStream<S> {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context,
+            buf: &mut ReadBuf,
+        ) -> Poll<io::Result<()>> {
+            let this = self.get_mut();
+            let filled = buf.filled().len();
+            let res = Pin::new(&mut this.stream).poll_read(cx, buf);
+            if let Poll::Ready(Ok(())) = res {
+                record(this.id, &mut this.inbound, &buf.filled()[filled..]);
+            }
+            res
+        }
+    }
+
+    impl<S: AsyncWrite + Unpin> AsyncWrite for DumpStream<S> {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut Context,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            let this = self.get_mut();
+            let res = Pin::new(&mut this.stream).poll_write(cx, buf);
+            if let Poll::Ready(Ok(len)) = res {
+                record(this.id, &mut this.outbound, &buf[..len]);
+            }
+            res
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use std::io;
+
+use grammers_mtproto::transport;
+use grammers_tl_types::deserialize;
+use thiserror::Error;
+
+use crate::{dh::DhGenError, messages::PqInnerDataError};
+
+pub type Result<T, E = ServerError> = std::result::Result<T, E>;
+
+#[derive(Debug, Error)]
+pub enum ServerError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("connection closed")]
+    ConnectionClosed,
+    #[error(transparent)]
+    TransportFrame(#[from] transport::Error),
+    #[error("invalid obfuscation header: looks like {0}")]
+    InvalidObfuscationHeader(&'static str),
+    #[error("transport error {0}")]
+    TransportError(i32),
+    #[error("can't frame a packet of {0} bytes, only whole 4 byte words")]
+    UnalignedPacket(usize),
+    #[error("unknown transport tag: {0:02x?}")]
+    UnknownTransport([u8; 4]),
+    #[error("unexpected constructor {got:08x}, expected {expected}")]
+    MagicMismatch { expected: &'static str, got: u32 },
+    #[error("unexpected auth_key_id {0:016x} in an unencrypted message")]
+    UnexpectedAuthKeyId(i64),
+    #[error("message_id {message_id:016x} is {skew:+} seconds off the server time")]
+    MessageIdOutOfWindow { message_id: i64, skew: i64 },
+    #[error("unknown auth_key_id {0:016x}")]
+    UnknownAuthKey(i64),
+    #[error("the temporary auth key {0:016x} has expired")]
+    ExpiredAuthKey(i64),
+    #[error("invalid encrypted message: {0}")]
+    InvalidEncryptedMessage(&'static str),
+    #[error("{name} mismatch: got {got:02x?}, expected {expected:02x?}")]
+    NonceMismatch {
+        name: &'static str,
+        got: [u8; 16],
+        expected: [u8; 16],
+    },
+    #[error("p, q mismatch: got {p:02x?}, {q:02x?}, expected {expected_p}, {expected_q}")]
+    PqMismatch {
+        p: Vec<u8>,
+        q: Vec<u8>,
+        expected_p: u32,
+        expected_q: u32,
+    },
+    #[error("invalid pq {pq:02x?}: {reason}")]
+    InvalidPq { pq: Vec<u8>, reason: String },
+    #[error("pq can't be {0} bits long, only 16 to 63")]
+    InvalidPqBits(u32),
+    #[error("invalid fault {0:?}, expected drop-after-respq, truncate, corrupt-msg-key or delay=MS, optionally followed by @PROBABILITY")]
+    InvalidFault(String),
+    #[error("invalid fake TLS secret {0:?}, expected 32 hex digits, optionally in the ee form")]
+    InvalidFakeTlsSecret(String),
+    #[error("fake TLS: {0}")]
+    FakeTls(&'static str),
+    #[error("unknown public_key_fingerprint {0:016x}")]
+    UnknownFingerprint(i64),
+    #[error("invalid fingerprint {0:?}, expected up to 16 hex digits")]
+    InvalidFingerprint(String),
+    #[error("invalid RSA key: {0}")]
+    InvalidRsaKey(String),
+    #[error("invalid script: {0}")]
+    InvalidScript(String),
+    #[error("invalid admin command {command:?}: {reason}")]
+    InvalidAdminCommand { command: String, reason: String },
+    #[error("invalid config file, line {line}: {reason}")]
+    InvalidConfigFile { line: usize, reason: String },
+    #[error("no scripted response to constructor {0:08x}")]
+    Unscripted(u32),
+    #[error("crypto error: {0}")]
+    CryptoError(String),
+    #[error("short read: {0}")]
+    ShortRead(#[from] deserialize::Error),
+    #[error("short read: {field} needs {expected} bytes, only {available} available")]
+    ShortField {
+        field: &'static str,
+        expected: usize,
+        available: usize,
+    },
+    #[error(transparent)]
+    DhGen(#[from] DhGenError),
+    #[error(transparent)]
+    PqInnerData(#[from] PqInnerDataError),
+    #[error("{reason}, answered with server_DH_params_fail")]
+    ServerDhParamsFail {
+        reason: PqInnerDataError,
+        answer: Vec<u8>,
+    },
+}
+
+impl ServerError {
+    // The answer the client gets before the connection is closed
+    pub fn answer(&self) -> Option<&[u8]> {
+        match self {
+            ServerError::ServerDhParamsFail { answer, .. } => Some(answer),
+            _ => None,
+        }
+    }
+
+    // The client is told about these with a transport error before the connection
+    // is closed
+    pub fn transport_error_code(&self) -> Option<i32> {
+        match self {
+            ServerError::MagicMismatch { .. }
+            | ServerError::NonceMismatch { .. }
+            | ServerError::UnknownAuthKey(_)
+            | ServerError::ExpiredAuthKey(_) => Some(-404),
+            _ => None,
+        }
+    }
+
+    // The client went away, which is routine rather than a protocol error
+    pub fn is_disconnect(&self) -> bool {
+        match self {
+            ServerError::ConnectionClosed => true,
+            ServerError::Io(e) => matches!(
+                e.kind(),
+                io::ErrorKind::UnexpectedEof
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::BrokenPipe
+            ),
+            _ => false,
+        }
+    }
+
+    // A blocking socket reports an expired timeout as `WouldBlock` on Unix and as
+    // `TimedOut` on Windows
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, ServerError::Io(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut))
+    }
+}
@@ -0,0 +1,205 @@
+// The fake TLS mode of MTProxy: the obfuscated transport is sent in TLS application
+// data records, after a ClientHello whose random is an HMAC of the proxy secret
+use std::str::FromStr;
+
+use bytes::{Buf, BytesMut};
+use hmac::{Hmac, Mac, NewMac};
+use rand::Rng;
+use sha2::Sha256;
+
+use crate::{
+    error::{Result, ServerError},
+    rng::ServerRng,
+};
+
+const HANDSHAKE: u8 = 0x16;
+const CHANGE_CIPHER_SPEC: u8 = 0x14;
+const APPLICATION_DATA: u8 = 0x17;
+
+// A TLS 1.2 record header claims TLS 1.0 in the ClientHello and 1.2 afterwards
+const CLIENT_HELLO_PREFIX: [u8; 3] = [HANDSHAKE, 0x03, 0x01];
+const CHANGE_CIPHER_SPEC_RECORD: [u8; 6] = [CHANGE_CIPHER_SPEC, 0x03, 0x03, 0x00, 0x01, 0x01];
+// The random of the ClientHello and the ServerHello is after the record header, the
+// handshake header and the version
+const RANDOM_OFFSET: usize = 5 + 4 + 2;
+// Records carry at most 2^14 bytes of plaintext, encrypted ones up to 2048 more
+const MAX_PAYLOAD: usize = 1 << 14;
+const MAX_RECORD_PAYLOAD: usize = MAX_PAYLOAD + 2048;
+
+// The 16 byte secret of the proxy, parsed from its hex. The `ee` form of the clients'
+// links, followed by the hex of the disguise domain, is accepted too
+#[derive(Clone, Debug, PartialEq)]
+pub struct FakeTlsSecret(pub [u8; 16]);
+
+impl FromStr for FakeTlsSecret {
+    type Err = ServerError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || ServerError::InvalidFakeTlsSecret(s.to_owned());
+        let hex = match s.len() {
+            32 => s,
+            len if len > 32 => s.strip_prefix("ee").ok_or_else(invalid)?,
+            _ => return Err(invalid()),
+        };
+        let mut secret = [0; 16];
+        for (i, byte) in secret.iter_mut().enumerate() {
+            *byte = hex
+                .get(2 * i..2 * i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .ok_or_else(invalid)?;
+        }
+        Ok(Self(secret))
+    }
+}
+
+pub fn is_client_hello(prefix: &[u8]) -> bool {
+    prefix.starts_with(&CLIENT_HELLO_PREFIX)
+}
+
+// The length of the whole record from its header, to read the rest of the ClientHello
+pub fn record_len(header: &[u8]) -> Result<usize> {
+    let len = u16::from_be_bytes([header[3], header[4]]) as usize;
+    if len > MAX_RECORD_PAYLOAD {
+        return Err(ServerError::FakeTls("record too long"));
+    }
+    Ok(5 + len)
+}
+
+#[derive(Debug)]
+pub struct ClientHello {
+    pub random: [u8; 32],
+    pub session_id: Vec<u8>,
+    // The unix time of the client, XORed into the last 4 bytes of the HMAC
+    pub timestamp: u32,
+}
+
+impl ClientHello {
+    // `record` is the whole TLS record, its random must be HMAC-SHA256(secret, record
+    // with a zeroed random), except for the timestamp
+    pub fn parse(record: &[u8], secret: &FakeTlsSecret) -> Result<Self> {
+        if !is_client_hello(record) || record.len() < RANDOM_OFFSET + 32 + 1 {
+            return Err(ServerError::FakeTls("not a ClientHello"));
+        }
+        let random: [u8; 32] = record[RANDOM_OFFSET..RANDOM_OFFSET + 32]
+            .try_into()
+            .unwrap();
+        let mut zeroed = record.to_vec();
+        zeroed[RANDOM_OFFSET..RANDOM_OFFSET + 32].fill(0);
+        let digest = hmac(secret, &[&zeroed]);
+        let mut xored = [0; 32];
+        for (xored, (random, digest)) in xored.iter_mut().zip(random.iter().zip(digest)) {
+            *xored = random ^ digest;
+        }
+        if xored[..28] != [0; 28] {
+            return Err(ServerError::FakeTls("ClientHello HMAC mismatch"));
+        }
+
+        let session_id_len = record[RANDOM_OFFSET + 32] as usize;
+        let session_id = record
+            .get(RANDOM_OFFSET + 33..RANDOM_OFFSET + 33 + session_id_len)
+            .ok_or(ServerError::FakeTls(
+                "session id longer than the ClientHello",
+            ))?;
+        Ok(Self {
+            random,
+            session_id: session_id.to_vec(),
+            timestamp: u32::from_le_bytes(xored[28..].try_into().unwrap()),
+        })
+    }
+}
+
+// ServerHello, ChangeCipherSpec and an application data record of random bytes, like
+// a TLS 1.3 server answers. The random of the ServerHello is
+// HMAC-SHA256(secret, client random + the answer with a zeroed random)
+pub fn server_hello(
+    client_hello: &ClientHello,
+    secret: &FakeTlsSecret,
+    rng: &ServerRng,
+) -> Vec<u8> {
+    let mut key_share = [0; 32];
+    rng.fill_bytes(&mut key_share);
+
+    let mut hello = vec![0x03, 0x03];
+    hello.extend([0; 32]);
+    hello.push(client_hello.session_id.len() as u8);
+    hello.extend(&client_hello.session_id);
+    // TLS_AES_128_GCM_SHA256 without compression
+    hello.extend([0x13, 0x01, 0x00]);
+    // The x25519 key_share and supported_versions of TLS 1.3
+    hello.extend([0x00, 0x2e, 0x00, 0x33, 0x00, 0x24, 0x00, 0x1d, 0x00, 0x20]);
+    hello.extend(key_share);
+    hello.extend([0x00, 0x2b, 0x00, 0x02, 0x03, 0x04]);
+
+    let mut answer = vec![HANDSHAKE, 0x03, 0x03];
+    answer.extend(((hello.len() + 4) as u16).to_be_bytes());
+    answer.push(0x02);
+    answer.extend(&(hello.len() as u32).to_be_bytes()[1..]);
+    answer.extend(hello);
+    answer.extend(CHANGE_CIPHER_SPEC_RECORD);
+    let mut encrypted_extensions = vec![0; rng.with(|rng| rng.gen_range(1024..4096))];
+    rng.fill_bytes(&mut encrypted_extensions);
+    answer.extend(wrap(&encrypted_extensions));
+
+    let digest = hmac(secret, &[&client_hello.random, &answer]);
+    answer[RANDOM_OFFSET..RANDOM_OFFSET + 32].copy_from_slice(&digest);
+    answer
+}
+
+// Unwraps the application data records of the client, it may send ChangeCipherSpec
+// first
+#[derive(Default)]
+pub struct RecordReader {
+    buffer: BytesMut,
+}
+
+impl RecordReader {
+    pub fn feed(&mut self, chunk: &[u8]) {
+        self.buffer.extend_from_slice(chunk);
+    }
+
+    // Returns `None` if more bytes have to be fed
+    pub fn next_payload(&mut self) -> Result<Option<Vec<u8>>> {
+        loop {
+            let Some(header) = self.buffer.get(..5) else {
+                return Ok(None);
+            };
+            if header[1..3] != [0x03, 0x03] {
+                return Err(ServerError::FakeTls("unexpected record version"));
+            }
+            let len = u16::from_be_bytes([header[3], header[4]]) as usize;
+            if len > MAX_RECORD_PAYLOAD {
+                return Err(ServerError::FakeTls("record too long"));
+            }
+            if self.buffer.len() < 5 + len {
+                return Ok(None);
+            }
+            let kind = header[0];
+            self.buffer.advance(5);
+            let payload = self.buffer.split_to(len);
+            match kind {
+                CHANGE_CIPHER_SPEC if payload[..] == [0x01] => {}
+                APPLICATION_DATA => return Ok(Some(payload.to_vec())),
+                _ => return Err(ServerError::FakeTls("unexpected record type")),
+            }
+        }
+    }
+}
+
+// Application data records of `data`
+pub fn wrap(data: &[u8]) -> Vec<u8> {
+    let mut records = Vec::with_capacity(data.len() + 5 * (data.len() / MAX_PAYLOAD + 1));
+    for chunk in data.chunks(MAX_PAYLOAD) {
+        records.extend([APPLICATION_DATA, 0x03, 0x03]);
+        records.extend((chunk.len() as u16).to_be_bytes());
+        records.extend(chunk);
+    }
+    records
+}
+
+fn hmac(secret: &FakeTlsSecret, parts: &[&[u8]]) -> [u8; 32] {
+    let mut mac = Hmac::<Sha256>::new_from_slice(&secret.0).unwrap();
+    for part in parts {
+        mac.update(part);
+    }
+    mac.finalize().into_bytes().into()
+}
@@ -0,0 +1,129 @@
+use std::{str::FromStr, time::Duration};
+
+use log::warn;
+use rand::Rng;
+
+use crate::{
+    connection::ConnId,
+    error::{Result, ServerError},
+    rng::ServerRng,
+    session,
+};
+
+// A fault injected into the answers of the server, for testing how clients cope
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fault {
+    // Close the connection once ResPq is sent
+    DropAfterResPq,
+    // Send the first half of an answer only, in a frame of its own
+    Truncate,
+    // Flip a bit of the msg_key of an encrypted answer
+    CorruptMsgKey,
+    // Wait before sending an answer
+    Delay(Duration),
+}
+
+// A fault and how likely it's injected whenever it applies, parsed from
+// `FAULT[@PROBABILITY]`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FaultRule {
+    pub fault: Fault,
+    pub probability: f64,
+}
+
+impl FromStr for FaultRule {
+    type Err = ServerError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || ServerError::InvalidFault(s.to_owned());
+        let (fault, probability) = match s.split_once('@') {
+            Some((fault, probability)) => (
+                fault,
+                probability
+                    .parse()
+                    .ok()
+                    .filter(|probability| (0.0..=1.0).contains(probability))
+                    .ok_or_else(invalid)?,
+            ),
+            None => (s, 1.0),
+        };
+        let fault = match fault {
+            "drop-after-respq" => Fault::DropAfterResPq,
+            "truncate" => Fault::Truncate,
+            "corrupt-msg-key" => Fault::CorruptMsgKey,
+            _ => match fault.strip_prefix("delay=").map(str::parse) {
+                Some(Ok(ms)) => Fault::Delay(Duration::from_millis(ms)),
+                _ => return Err(invalid()),
+            },
+        };
+        Ok(Self { fault, probability })
+    }
+}
+
+impl FaultRule {
+    fn roll(&self, rng: &ServerRng) -> bool {
+        rng.with(|rng| rng.gen_bool(self.probability))
+    }
+}
+
+// An answer after the faults, the connection sends it once `delay` is over
+pub struct FaultyAnswer {
+    pub delay: Duration,
+    pub answer: Vec<u8>,
+}
+
+// Every fault which applies is rolled for separately, `name` is the answer's
+pub fn apply(
+    id: ConnId,
+    rules: &[FaultRule],
+    rng: &ServerRng,
+    name: &str,
+    answer: &[u8],
+) -> FaultyAnswer {
+    let mut faulty = FaultyAnswer {
+        delay: Duration::ZERO,
+        answer: answer.to_vec(),
+    };
+    for rule in rules {
+        match rule.fault {
+            Fault::Delay(delay) if rule.roll(rng) => {
+                warn!("{} fault: delaying {} by {:?}", id, name, delay);
+                faulty.delay += delay;
+            }
+            Fault::Truncate if rule.roll(rng) => {
+                // The framing of the abridged transport needs a multiple of 4
+                let len = (faulty.answer.len() / 2) & !3;
+                warn!(
+                    "{} fault: truncating {} from {} to {} bytes",
+                    id,
+                    name,
+                    faulty.answer.len(),
+                    len
+                );
+                faulty.answer.truncate(len);
+            }
+            // The unencrypted answers of the handshake have no msg_key
+            Fault::CorruptMsgKey
+                if faulty.answer.len() >= 24
+                    && session::is_encrypted(&faulty.answer)
+                    && rule.roll(rng) =>
+            {
+                warn!("{} fault: corrupting the msg_key of {}", id, name);
+                faulty.answer[8] ^= 1;
+            }
+            _ => {}
+        }
+    }
+    faulty
+}
+
+pub fn drop_after_res_pq(id: ConnId, rules: &[FaultRule], rng: &ServerRng) -> bool {
+    if rules
+        .iter()
+        .any(|rule| rule.fault == Fault::DropAfterResPq && rule.roll(rng))
+    {
+        warn!("{} fault: dropping the connection after ResPq", id);
+        return true;
+    }
+    false
+}
@@ -0,0 +1,401 @@
+use std::time::Duration;
+
+use grammers_tl_types::Cursor;
+use log::{debug, info, trace, warn};
+use num_bigint::BigUint;
+
+use crate::{
+    auth_keys::AuthKey,
+    config::Config,
+    connection::{ConnId, DcId},
+    dh,
+    error::{Result, ServerError},
+    keys,
+    message_id::{self, Clock, MessageIdProvider, MessageIds},
+    messages::{
+        decrypt_client_dh_inner_data, encrypt_answer, unvalidated_new_nonce, validate_pq_inner,
+        DhGenOk, ReqDHParams, ReqPqMulti, ServerDHParams, ServerDHParamsFail, SetClientDHParams,
+        REQ_PQ, REQ_PQ_MULTI,
+    },
+    nonce::NonceContext,
+    pq, session,
+    stats::Stage,
+};
+
+// How far a connection got, once it's closed
+pub struct HandshakeOutcome {
+    // Missing without the obfuscated transport
+    pub dc_id: Option<DcId>,
+    // The key created by the handshake, missing when it was stopped early, scripted
+    // or skipped by a client which already had one
+    pub auth_key: Option<AuthKey>,
+}
+
+// Given to the callback of the config once a handshake is done, e.g. for tests to
+// check the server derived the same key as the client
+pub struct HandshakeComplete<'a> {
+    pub id: ConnId,
+    pub auth_key: &'a AuthKey,
+    pub auth_key_id: i64,
+    pub nonces: NonceContext,
+    pub new_nonce: [u8; 32],
+}
+
+pub type HandshakeCallback = Box<dyn Fn(&HandshakeComplete) + Send + Sync>;
+
+// The handshake of a connection, one state per packet expected from the client. The
+// connection reads a packet, steps the state and writes the answer, if any
+pub enum HandshakeState {
+    AwaitReqPq { id: ConnId, dc_id: Option<DcId> },
+    AwaitReqDhParams(ResPqSent),
+    AwaitSetClientDhParams(ServerDHParamsSent),
+    // What follows the handshake is up to the session
+    Established(AuthKey),
+}
+
+impl HandshakeState {
+    // `dc_id` is the DC from the obfuscation header, if any
+    pub fn new(id: ConnId, dc_id: Option<DcId>) -> Self {
+        Self::AwaitReqPq { id, dc_id }
+    }
+
+    // Answers `packet` and moves on to the next state. A failed step leaves the
+    // state waiting for a new req_pq_multi, though the connection is closed anyway
+    pub fn step(&mut self, config: &Config, packet: &[u8]) -> Result<Option<Vec<u8>>> {
+        let id = match self {
+            Self::AwaitReqPq { id, .. } => *id,
+            Self::AwaitReqDhParams(state) => state.id,
+            Self::AwaitSetClientDhParams(state) => state.id,
+            Self::Established(_) => return Ok(None),
+        };
+        if config.check_msgid_window {
+            if let Some(message_id) = packet.get(8..16) {
+                let message_id = i64::from_le_bytes(message_id.try_into().unwrap());
+                message_id::check_window(message_id, Clock.now() >> 32)?;
+            }
+        }
+        let answer = match std::mem::replace(self, Self::new(id, None)) {
+            Self::AwaitReqPq { id, dc_id } => {
+                let (res_pq, state) = res_pq(id, config, dc_id, packet)?;
+                config.stage_stats.record(Stage::ReqPqMulti);
+                id.set_stage("ReqDHParams");
+                *self = Self::AwaitReqDhParams(state);
+                res_pq
+            }
+            Self::AwaitReqDhParams(state) => {
+                let (server_dh_params, state) = state.server_dh_params(config, packet)?;
+                config.stage_stats.record(Stage::ReqDHParams);
+                id.set_stage("SetClientDHParams");
+                *self = Self::AwaitSetClientDhParams(state);
+                server_dh_params
+            }
+            Self::AwaitSetClientDhParams(state) => {
+                let (nonces, new_nonce) = (state.nonces, state.new_nonce);
+                let (dh_gen_ok, auth_key) = state.dh_gen_ok(config, packet)?;
+                config.stage_stats.record(Stage::DhGenOk);
+                config.auth_keys.insert(&auth_key);
+                match auth_key.expires_in {
+                    Some(expires_in) => info!(
+                        "{} handshake done, auth_key_id {:016x} expires in {:?}",
+                        id,
+                        auth_key.id(),
+                        expires_in
+                    ),
+                    None => info!("{} handshake done, auth_key_id {:016x}", id, auth_key.id()),
+                }
+                if let Some(on_handshake_complete) = &config.on_handshake_complete {
+                    on_handshake_complete(&HandshakeComplete {
+                        id,
+                        auth_key: &auth_key,
+                        auth_key_id: auth_key.id(),
+                        nonces,
+                        new_nonce,
+                    });
+                }
+                *self = Self::Established(auth_key);
+                dh_gen_ok
+            }
+            Self::Established(_) => unreachable!("returned above"),
+        };
+        Ok(Some(answer))
+    }
+
+    pub fn into_auth_key(self) -> Option<AuthKey> {
+        match self {
+            Self::Established(auth_key) => Some(auth_key),
+            _ => None,
+        }
+    }
+
+    // The name of the last answer, for the faults
+    pub fn answered(&self) -> Option<&'static str> {
+        match self {
+            Self::AwaitReqPq { .. } => None,
+            Self::AwaitReqDhParams(_) => Some("ResPq"),
+            Self::AwaitSetClientDhParams(_) => Some("ServerDHParams"),
+            Self::Established(_) => Some("DhGenOk"),
+        }
+    }
+}
+
+pub struct ResPqSent {
+    id: ConnId,
+    nonces: NonceContext,
+    p: u32,
+    q: u32,
+    message_ids: MessageIds,
+}
+
+pub struct ServerDHParamsSent {
+    id: ConnId,
+    nonces: NonceContext,
+    new_nonce: [u8; 32],
+    tmp_aes_key: [u8; 32],
+    tmp_aes_iv: [u8; 32],
+    a: BigUint,
+    // Set when the client asks for a temporary key
+    expires_in: Option<Duration>,
+    message_ids: MessageIds,
+}
+
+// `dc_id` is the DC from the obfuscation header, for the answers which would depend
+// on it
+pub fn res_pq(
+    id: ConnId,
+    config: &Config,
+    dc_id: Option<DcId>,
+    packet: &[u8],
+) -> Result<(Vec<u8>, ResPqSent)> {
+    // ReqPqMulti
+    info!("{} handshake stage: ReqPqMulti", id);
+    if let Some(dc_id) = dc_id {
+        debug!("{} connecting to DC {}", id, dc_id);
+    }
+    let mut cur = Cursor::from_slice(packet);
+    let req_pq_multi = ReqPqMulti::parse(&mut cur)?;
+    debug!("{} client sent {}", id, req_pq_multi.name());
+    trace!("{} req_pq_multi: {:02x?}", id, req_pq_multi);
+
+    // ResPq
+    let mut message_ids = (config.message_ids)();
+    let res_pq = config.policy.on_req_pq_multi(
+        config,
+        &req_pq_multi,
+        config.server_nonce(),
+        message_ids.next_id(),
+    )?;
+    trace!("{} res_pq: {:02x?}", id, res_pq);
+    if let Some(records) = &config.json_records {
+        records.res_pq(id, &req_pq_multi, &res_pq);
+    }
+    // A policy may answer with a pq which can't be factorized, no p and q of the
+    // client match then
+    let (p, q) = match pq::from_be_bytes(&res_pq.pq).map(pq::factorize) {
+        Some(Ok((p, q))) => {
+            debug!("{} pq: {} = {} * {}", id, p as u64 * q as u64, p, q);
+            (p, q)
+        }
+        _ => {
+            warn!("{} pq {:02x?} can't be factorized", id, res_pq.pq);
+            (0, 0)
+        }
+    };
+
+    Ok((
+        res_pq.ser(),
+        ResPqSent {
+            id,
+            nonces: NonceContext::new(res_pq.nonce, res_pq.server_nonce),
+            p,
+            q,
+            message_ids,
+        },
+    ))
+}
+
+// Whether an unencrypted packet is a req_pq_multi or a req_pq, without parsing it
+pub fn is_req_pq(packet: &[u8]) -> bool {
+    packet.get(20..24).is_some_and(|magic| {
+        [REQ_PQ_MULTI, REQ_PQ].contains(&u32::from_le_bytes(magic.try_into().unwrap()))
+    }) && !session::is_encrypted(packet)
+}
+
+// Parses req_pq_multi without answering it, for the errors sent instead of ResPq
+pub fn check_req_pq_multi(id: ConnId, packet: &[u8]) -> Result<()> {
+    info!("{} handshake stage: ReqPqMulti", id);
+    let req_pq_multi = ReqPqMulti::parse(&mut Cursor::from_slice(packet))?;
+    trace!("{} req_pq_multi: {:02x?}", id, req_pq_multi);
+    Ok(())
+}
+
+impl ResPqSent {
+    pub fn server_dh_params(
+        mut self,
+        config: &Config,
+        packet: &[u8],
+    ) -> Result<(Vec<u8>, ServerDHParamsSent)> {
+        // ReqDHParams
+        info!("{} handshake stage: ReqDHParams", self.id);
+        let mut cur = Cursor::from_slice(packet);
+        let req_dh_params = ReqDHParams::parse(&mut cur)?;
+        trace!("{} req_dh_params: {:02x?}", self.id, req_dh_params);
+        self.nonces
+            .verify(&req_dh_params.nonce, &req_dh_params.server_nonce)?;
+        if pq::from_be_bytes(&req_dh_params.p) != Some(self.p.into())
+            || pq::from_be_bytes(&req_dh_params.q) != Some(self.q.into())
+        {
+            return Err(ServerError::PqMismatch {
+                p: req_dh_params.p,
+                q: req_dh_params.q,
+                expected_p: self.p,
+                expected_q: self.q,
+            });
+        }
+
+        // new_nonce lives in encrypted_data, which can't be decrypted without the
+        // private key behind the advertised fingerprint
+        let (new_nonce, expires_in) = match config.rsa_key(req_dh_params.public_key_fingerprint)? {
+            Some(key) => {
+                let data_with_hash = key.decrypt(&req_dh_params.encrypted_data)?;
+                let pq_inner_data = match validate_pq_inner(
+                    &data_with_hash,
+                    &self.nonces.nonce,
+                    &self.nonces.server_nonce,
+                    self.p,
+                    self.q,
+                ) {
+                    Err(ServerError::PqInnerData(reason)) => {
+                        let server_dh_params_fail = ServerDHParamsFail::generate(
+                            self.nonces.nonce,
+                            self.nonces.server_nonce,
+                            self.message_ids.next_id(),
+                            &unvalidated_new_nonce(&data_with_hash),
+                        );
+                        trace!(
+                            "{} server_dh_params_fail: {:02x?}",
+                            self.id,
+                            server_dh_params_fail
+                        );
+                        return Err(ServerError::ServerDhParamsFail {
+                            reason,
+                            answer: server_dh_params_fail.ser(),
+                        });
+                    }
+                    pq_inner_data => pq_inner_data?,
+                };
+                trace!("{} pq_inner_data: {:02x?}", self.id, pq_inner_data);
+                let expires_in = pq_inner_data
+                    .expires_in
+                    .map(|expires_in| Duration::from_secs(expires_in.max(0) as u64));
+                (pq_inner_data.new_nonce, expires_in)
+            }
+            None => ([0; 32], None),
+        };
+
+        // ServerDHParams
+        let (tmp_aes_key, tmp_aes_iv) = keys::derive_tmp_aes(new_nonce, self.nonces.server_nonce);
+        let mut a = [0; 256];
+        config.rng.fill_bytes(&mut a);
+        let a = BigUint::from_bytes_be(&a);
+        let message_id = self.message_ids.next_id();
+        let server_dh_inner_data =
+            config
+                .policy
+                .on_req_dh_params(config, &req_dh_params, &a, message_id)?;
+        trace!(
+            "{} server_dh_inner_data: {:02x?}",
+            self.id,
+            server_dh_inner_data
+        );
+        let encrypted_answer = encrypt_answer(
+            &server_dh_inner_data.ser(),
+            &tmp_aes_key,
+            &tmp_aes_iv,
+            &config.rng,
+        )?;
+
+        let server_dh_params = ServerDHParams::generate(
+            self.nonces.nonce,
+            self.nonces.server_nonce,
+            message_id,
+            encrypted_answer,
+        );
+        trace!("{} server_dh_params: {:02x?}", self.id, server_dh_params);
+
+        Ok((
+            server_dh_params.ser(),
+            ServerDHParamsSent {
+                id: self.id,
+                nonces: self.nonces,
+                new_nonce,
+                tmp_aes_key,
+                tmp_aes_iv,
+                a,
+                expires_in,
+                message_ids: self.message_ids,
+            },
+        ))
+    }
+}
+
+impl ServerDHParamsSent {
+    // Returns the dh_gen_ok answer and the new auth key
+    pub fn dh_gen_ok(mut self, config: &Config, packet: &[u8]) -> Result<(Vec<u8>, AuthKey)> {
+        // SetClientDHParams
+        info!("{} handshake stage: SetClientDHParams", self.id);
+        let mut cur = Cursor::from_slice(packet);
+        let set_client_dh_params = SetClientDHParams::parse(&mut cur)?;
+        trace!(
+            "{} set_client_dh_params: {:02x?}",
+            self.id,
+            set_client_dh_params
+        );
+        self.nonces.verify(
+            &set_client_dh_params.nonce,
+            &set_client_dh_params.server_nonce,
+        )?;
+
+        let client_dh_inner_data = decrypt_client_dh_inner_data(
+            &set_client_dh_params.encrypted_data,
+            &self.tmp_aes_key,
+            &self.tmp_aes_iv,
+        )?;
+        trace!(
+            "{} client_dh_inner_data: {:02x?}",
+            self.id,
+            client_dh_inner_data
+        );
+        self.nonces.verify(
+            &client_dh_inner_data.nonce,
+            &client_dh_inner_data.server_nonce,
+        )?;
+        // We never answer with dh_gen_retry, so there is no previous attempt to refer to
+        if client_dh_inner_data.retry_id != 0 {
+            return Err(dh::DhGenError::Retry.into());
+        }
+
+        let g_b = BigUint::from_bytes_be(&client_dh_inner_data.g_b);
+        if !config.dh.is_valid_g_b(&g_b) {
+            warn!("{} g_b out of the safe range", self.id);
+            return Err(dh::DhGenError::Fail.into());
+        }
+        let auth_key = config.dh.auth_key(&g_b, &self.a);
+
+        // DhGenOk
+        let dh_gen_ok = DhGenOk::generate(
+            self.nonces.nonce,
+            self.nonces.server_nonce,
+            self.message_ids.next_id(),
+            keys::new_nonce_hash(&self.new_nonce, 1, &auth_key),
+        );
+        trace!("{} dh_gen_ok: {:02x?}", self.id, dh_gen_ok);
+
+        Ok((
+            dh_gen_ok.ser(),
+            AuthKey {
+                key: auth_key,
+                expires_in: self.expires_in,
+            },
+        ))
+    }
+}
@@ -0,0 +1,67 @@
+// Health check endpoint for liveness probes, separate from the MTProto port
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use log::{debug, info};
+
+use crate::error::Result;
+
+// Only set while the accept loop runs, so the server is drained on shutdown
+static HEALTHY: AtomicBool = AtomicBool::new(false);
+
+// A client of the endpoint can't hold it for longer than this
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+pub fn set_healthy(healthy: bool) {
+    HEALTHY.store(healthy, Ordering::SeqCst);
+}
+
+pub fn is_healthy() -> bool {
+    HEALTHY.load(Ordering::SeqCst)
+}
+
+// Answers `GET /healthz` with 200 while healthy and 503 otherwise, blocks the
+// calling thread
+pub fn serve(listener: TcpListener) -> Result<()> {
+    info!("serving health checks on http://{}", listener.local_addr()?);
+    for stream in listener.incoming() {
+        // A failing probe only concerns that probe
+        if let Err(e) = answer(stream?) {
+            debug!("health check failed: {}", e);
+        }
+    }
+    Ok(())
+}
+
+fn answer(mut stream: TcpStream) -> Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    // The request line is all that matters
+    let mut request = [0; 1024];
+    let mut len = 0;
+    while len < request.len() && !request[..len].contains(&b'\n') {
+        match stream.read(&mut request[len..])? {
+            0 => break,
+            read => len += read,
+        }
+    }
+    let request_line = request[..len].split(|&byte| byte == b'\r').next().unwrap();
+    let response = match request_line.strip_prefix(b"GET /healthz ") {
+        Some(_) if is_healthy() => "200 OK",
+        Some(_) => "503 Service Unavailable",
+        None => "404 Not Found",
+    };
+    let body = &response[4..];
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}\n",
+        response,
+        body.len() + 1,
+        body
+    )?;
+    Ok(())
+}
@@ -0,0 +1,91 @@
+use sha1::{Digest, Sha1};
+use sha2::{Digest as _, Sha256};
+
+// tmp_aes_key = SHA1(new_nonce + server_nonce) + substr(SHA1(server_nonce + new_nonce), 0, 12)
+// tmp_aes_iv = substr(SHA1(server_nonce + new_nonce), 12, 8) + SHA1(new_nonce + new_nonce)
+//     + substr(new_nonce, 0, 4)
+pub fn derive_tmp_aes(new_nonce: [u8; 32], server_nonce: [u8; 16]) -> ([u8; 32], [u8; 32]) {
+    let new_server = Sha1::new()
+        .chain_update(new_nonce)
+        .chain_update(server_nonce)
+        .finalize();
+    let server_new = Sha1::new()
+        .chain_update(server_nonce)
+        .chain_update(new_nonce)
+        .finalize();
+    let new_new = Sha1::new()
+        .chain_update(new_nonce)
+        .chain_update(new_nonce)
+        .finalize();
+
+    let mut key = [0; 32];
+    key[..20].copy_from_slice(&new_server);
+    key[20..].copy_from_slice(&server_new[..12]);
+
+    let mut iv = [0; 32];
+    iv[..8].copy_from_slice(&server_new[12..]);
+    iv[8..28].copy_from_slice(&new_new);
+    iv[28..].copy_from_slice(&new_nonce[..4]);
+
+    (key, iv)
+}
+
+pub fn auth_key_id(auth_key: &[u8; 256]) -> i64 {
+    let hash = Sha1::digest(auth_key);
+    i64::from_le_bytes(hash[12..].try_into().unwrap())
+}
+
+// new_nonce_hash = substr(SHA1(new_nonce + number + auth_key_aux_hash), 4, 16), where
+// auth_key_aux_hash = substr(SHA1(auth_key), 0, 8)
+pub fn new_nonce_hash(new_nonce: &[u8; 32], number: u8, auth_key: &[u8; 256]) -> [u8; 16] {
+    let auth_key_aux_hash = &Sha1::digest(auth_key)[..8];
+    let hash = Sha1::new()
+        .chain_update(new_nonce)
+        .chain_update([number])
+        .chain_update(auth_key_aux_hash)
+        .finalize();
+    hash[4..].try_into().unwrap()
+}
+
+// MTProto 2.0 takes x = 0 for the messages of the client and x = 8 for the ones of
+// the server:
+// sha256_a = SHA256(msg_key + substr(auth_key, x, 36))
+// sha256_b = SHA256(substr(auth_key, 40 + x, 36) + msg_key)
+// aes_key = substr(sha256_a, 0, 8) + substr(sha256_b, 8, 16) + substr(sha256_a, 24, 8)
+// aes_iv = substr(sha256_b, 0, 8) + substr(sha256_a, 8, 16) + substr(sha256_b, 24, 8)
+pub fn derive_message_aes(
+    auth_key: &[u8; 256],
+    msg_key: &[u8; 16],
+    x: usize,
+) -> ([u8; 32], [u8; 32]) {
+    let sha256_a = Sha256::new()
+        .chain(msg_key)
+        .chain(&auth_key[x..x + 36])
+        .finalize();
+    let sha256_b = Sha256::new()
+        .chain(&auth_key[40 + x..40 + x + 36])
+        .chain(msg_key)
+        .finalize();
+
+    let mut key = [0; 32];
+    key[..8].copy_from_slice(&sha256_a[..8]);
+    key[8..24].copy_from_slice(&sha256_b[8..24]);
+    key[24..].copy_from_slice(&sha256_a[24..]);
+
+    let mut iv = [0; 32];
+    iv[..8].copy_from_slice(&sha256_b[..8]);
+    iv[8..24].copy_from_slice(&sha256_a[8..24]);
+    iv[24..].copy_from_slice(&sha256_b[24..]);
+
+    (key, iv)
+}
+
+// msg_key = substr(SHA256(substr(auth_key, 88 + x, 32) + plaintext), 8, 16), where the
+// plaintext includes the padding
+pub fn msg_key(auth_key: &[u8; 256], plaintext: &[u8], x: usize) -> [u8; 16] {
+    let msg_key_large = Sha256::new()
+        .chain(&auth_key[88 + x..88 + x + 32])
+        .chain(plaintext)
+        .finalize();
+    msg_key_large[8..24].try_into().unwrap()
+}
@@ -0,0 +1,53 @@
+pub mod admin;
+#[cfg(feature = "tokio")]
+mod async_connection;
+pub mod auth_keys;
+mod blocking_connection;
+mod config;
+pub mod config_file;
+mod connection;
+pub mod crypto;
+pub mod dh;
+mod driver;
+mod dump;
+pub mod error;
+pub mod fake_tls;
+pub mod fault;
+mod handshake;
+pub mod health;
+pub mod keys;
+pub mod logging;
+pub mod message_id;
+pub mod messages;
+pub mod metrics;
+pub mod nonce;
+pub mod peer_failures;
+pub mod policy;
+pub mod pq;
+pub mod rate_limit;
+pub mod records;
+pub mod rng;
+pub mod rsa_key;
+pub mod salts;
+pub mod script;
+mod session;
+pub mod stats;
+#[cfg(feature = "test-client")]
+pub mod test_client;
+
+#[cfg(all(unix, feature = "tokio"))]
+pub use async_connection::handle_unix_connection_async;
+#[cfg(feature = "tokio")]
+pub use async_connection::{handle_connection_async, serve_handshake_async, AsyncConnection};
+#[cfg(unix)]
+pub use blocking_connection::handle_unix_connection;
+pub use blocking_connection::{handle_connection, serve_handshake, Connection};
+pub use config::{AfterHandshake, Config, DEFAULT_MAX_VECTOR_LEN};
+pub use connection::{
+    frame_abridged, frame_intermediate, strip_padding, validate_obfuscation_header, Codec, ConnId,
+    DcId, ObfuscationKeys, TransportHook,
+};
+pub use driver::{Driver, Step};
+pub use dump::parse_req_pq_multi;
+pub use error::ServerError;
+pub use handshake::{HandshakeCallback, HandshakeComplete, HandshakeOutcome, HandshakeState};
@@ -0,0 +1,148 @@
+use std::{
+    env,
+    fmt::Write as _,
+    io::{self, Write},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use log::{LevelFilter, Log, Metadata, Record, SetLoggerError};
+use pretty_env_logger::env_logger::{self, fmt::Formatter, Builder};
+
+use crate::connection;
+
+// `level` takes precedence over RUST_LOG, the default is the pretty output of
+// pretty_env_logger. Fails if a logger is already installed, which is then kept. The
+// library never calls it, embedders install their own
+pub fn init(level: Option<LevelFilter>, json: bool) -> Result<(), SetLoggerError> {
+    let new_builder = || {
+        if json {
+            let mut builder = Builder::new();
+            builder.format(format_json);
+            builder
+        } else {
+            pretty_env_logger::formatted_builder()
+        }
+    };
+    let mut builder = new_builder();
+    match level {
+        Some(level) => {
+            builder.filter_level(level);
+        }
+        None => {
+            if let Ok(filters) = env::var("RUST_LOG") {
+                builder.parse_filters(&filters);
+            }
+        }
+    }
+    let filtered = builder.build();
+    let max_level = filtered.filter();
+    let logger = Logger {
+        filtered,
+        unfiltered: new_builder().filter_level(LevelFilter::Trace).build(),
+    };
+    log::set_boxed_logger(Box::new(logger))?;
+    log::set_max_level(max_level);
+    Ok(())
+}
+
+// Replaces the filters of `init` with `level` for all the targets, e.g. from the
+// admin socket. Only the max level of the `log` crate is set with another logger
+pub fn set_level(level: LevelFilter) {
+    LEVEL_OVERRIDE.store(level as usize + 1, Ordering::SeqCst);
+    log::set_max_level(level);
+}
+
+// 0 until `set_level` is called, the level plus one then
+static LEVEL_OVERRIDE: AtomicUsize = AtomicUsize::new(0);
+
+fn level_override() -> Option<LevelFilter> {
+    match LEVEL_OVERRIDE.load(Ordering::SeqCst) {
+        0 => None,
+        level => LevelFilter::iter().nth(level - 1),
+    }
+}
+
+// The logger built by `init`, and the same one without filters for the level set
+// at runtime, as the filters of env_logger are fixed once it's built
+struct Logger {
+    filtered: env_logger::Logger,
+    unfiltered: env_logger::Logger,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        match level_override() {
+            Some(level) => metadata.level() <= level,
+            None => self.filtered.enabled(metadata),
+        }
+    }
+
+    fn log(&self, record: &Record) {
+        match level_override() {
+            Some(level) if record.level() <= level => self.unfiltered.log(record),
+            Some(_) => {}
+            None => self.filtered.log(record),
+        }
+    }
+
+    fn flush(&self) {
+        self.filtered.flush();
+    }
+}
+
+// One JSON object per line. The connection id and its stage are taken out of the
+// `[#N peer]` prefix of the connection's log lines
+fn format_json(buf: &mut Formatter, record: &Record) -> io::Result<()> {
+    let message = record.args().to_string();
+    let (conn, message) = split_conn_id(&message);
+
+    let mut line = String::new();
+    write!(
+        line,
+        "{{\"timestamp\":\"{}\",\"level\":\"{}\",\"target\":",
+        buf.timestamp_millis(),
+        record.level()
+    )
+    .unwrap();
+    push_json_string(&mut line, record.target());
+    if let Some((id, peer)) = conn {
+        write!(line, ",\"conn\":{},\"peer\":", id).unwrap();
+        push_json_string(&mut line, peer);
+        if let Some(stage) = connection::stage(id) {
+            line.push_str(",\"stage\":");
+            push_json_string(&mut line, stage);
+        }
+    }
+    line.push_str(",\"message\":");
+    push_json_string(&mut line, message);
+    writeln!(buf, "{}}}", line)
+}
+
+fn split_conn_id(message: &str) -> (Option<(u64, &str)>, &str) {
+    let split = || {
+        let (conn, rest) = message.strip_prefix("[#")?.split_once("] ")?;
+        let (id, peer) = conn.split_once(' ')?;
+        Some(((id.parse().ok()?, peer), rest))
+    };
+    match split() {
+        Some((conn, rest)) => (Some(conn), rest),
+        None => (None, message),
+    }
+}
+
+// Also escapes the strings of the JSON records
+pub(crate) fn push_json_string(line: &mut String, s: &str) {
+    line.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => line.push_str("\\\""),
+            '\\' => line.push_str("\\\\"),
+            '\n' => line.push_str("\\n"),
+            '\r' => line.push_str("\\r"),
+            '\t' => line.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(line, "\\u{:04x}", c as u32).unwrap(),
+            c => line.push(c),
+        }
+    }
+    line.push('"');
+}
@@ -1,234 +1,989 @@
 use std::{
-    io::{Read, Write},
-    net::{TcpListener, TcpStream},
-    time::SystemTime,
+    fmt, fs,
+    io::{self, Write},
+    net::{SocketAddr, TcpListener},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
 };
 
-use aes::cipher::{KeyIvInit, StreamCipher};
-use anyhow::Result;
-use bytes::BytesMut;
-use grammers_mtproto::transport::{Abridged, Transport};
-use grammers_tl_types::{Cursor, Deserializable, Serializable};
-use log::{debug, error};
-
-type Aes256Ctr64Be = ctr::Ctr64BE<aes::Aes256>;
-const SERVER_NONCE: [u8; 16] = 0x1337u128.to_le_bytes();
-
-fn main() {
-    pretty_env_logger::init();
-
-    let listener = TcpListener::bind("127.0.0.1:11337").unwrap();
-    for stream in listener.incoming() {
-        let stream = stream.unwrap();
-        if let Err(e) = handle_connection(stream) {
-            for e in e.chain() {
-                error!("{}", e);
-            }
+use anyhow::{Context, Result};
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+
+#[cfg(unix)]
+use clap::builder::ArgPredicate;
+use clap::{parser::ValueSource, CommandFactory, FromArgMatches, Parser, Subcommand};
+use log::{debug, info, warn, LevelFilter};
+use socket2::{Domain, Protocol, Socket, Type};
+use srv::{
+    admin,
+    config_file::ConfigFile,
+    dh::DhParams,
+    fake_tls::FakeTlsSecret,
+    fault::FaultRule,
+    health,
+    message_id::{FixedClock, MessageIds, MessageIdsFactory},
+    metrics, parse_req_pq_multi,
+    peer_failures::PeerFailures,
+    policy::DefaultPolicy,
+    pq,
+    rate_limit::RateLimiter,
+    records::JsonRecords,
+    rng::ServerRng,
+    rsa_key::{self, RsaKey},
+    script::Script,
+    AfterHandshake, Config, DEFAULT_MAX_VECTOR_LEN,
+};
+
+#[derive(Parser)]
+#[command(version, about = "MTProto server for testing clients")]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// TOML file setting any of the options below, with the flags as keys, e.g.
+    /// `max-connections = 128`. The flags given on the command line take precedence
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>,
+    /// Address to listen on, can be repeated to listen on several. `[::]:PORT` also
+    /// accepts IPv4 connections unless --ipv6-only is set
+    #[arg(long, default_value = "127.0.0.1:11337")]
+    #[cfg_attr(unix, arg(default_value_if("unix", ArgPredicate::IsPresent, None)))]
+    bind: Vec<SocketAddr>,
+    /// Unix domain socket to listen on, only TCP addresses given with --bind are
+    /// listened on besides it
+    #[cfg(unix)]
+    #[arg(long, value_name = "PATH")]
+    unix: Option<PathBuf>,
+    /// Don't accept IPv4 connections on an IPv6 address
+    #[arg(long)]
+    ipv6_only: bool,
+    /// Set SO_REUSEPORT on the listeners, so several servers can listen on the same
+    /// port and share its connections
+    #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+    #[arg(long)]
+    reuse_port: bool,
+    /// Length of the queue of the connections not accepted yet, capped by the system
+    #[arg(long, default_value_t = 128, value_parser = clap::value_parser!(i32).range(1..))]
+    backlog: i32,
+    /// Times to retry binding an address in use, e.g. by the server a rolling restart
+    /// replaces
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    bind_retries: u32,
+    /// Milliseconds before the first retry of --bind-retries, doubled after every one
+    #[arg(long, value_name = "MS", default_value_t = 500)]
+    bind_retry_delay: u64,
+    /// Log level: off, error, warn, info, debug or trace, overrides RUST_LOG
+    #[arg(long, value_name = "LEVEL")]
+    log_level: Option<LevelFilter>,
+    /// Log JSON lines instead of the human readable output
+    #[arg(long)]
+    log_json: bool,
+    /// Maximum number of connections handled at the same time
+    #[arg(long, default_value_t = 64)]
+    max_connections: usize,
+    /// Handle a single connection and exit, with a failure status if it failed
+    #[arg(long)]
+    once: bool,
+    /// Connections per second allowed from one IP, unlimited by default
+    #[arg(long, value_name = "PER_SECOND")]
+    rate_limit: Option<f64>,
+    /// Connections allowed from one IP at once when --rate-limit is set
+    #[arg(long, default_value_t = 10, requires = "rate_limit")]
+    rate_burst: u32,
+    /// Reject the connections of an IP once this many of its connections failed,
+    /// until the restart
+    #[arg(long, value_name = "FAILURES", value_parser = clap::value_parser!(u32).range(1..))]
+    ban_threshold: Option<u32>,
+    /// Seconds to wait on a read or a write before dropping the connection
+    #[arg(long, default_value_t = 30, value_parser = clap::value_parser!(u64).range(1..))]
+    timeout: u64,
+    /// Longest packet in bytes a client may announce, longer ones are rejected
+    /// before being buffered
+    #[arg(long, value_name = "BYTES", default_value_t = 1 << 20)]
+    max_packet: usize,
+    /// Most elements a client may declare in a vector, e.g. the messages of a
+    /// container, more are rejected before anything is allocated for them
+    #[arg(long, value_name = "LEN", default_value_t = DEFAULT_MAX_VECTOR_LEN)]
+    max_vector_len: usize,
+    /// Bit length of the pq to factorize, up to 63
+    #[arg(
+        long,
+        default_value_t = pq::DEFAULT_PQ_BITS,
+        value_parser = clap::value_parser!(u32).range(pq::MIN_PQ_BITS as i64..=pq::MAX_PQ_BITS as i64),
+    )]
+    pq_bits: u32,
+    /// Use a fixed server_nonce instead of a random one, for reproducible runs
+    #[arg(long)]
+    deterministic_nonce: bool,
+    /// Derive every random value of the handshake from this 32 byte seed in hex, for
+    /// reproducible transcripts. The clock of the answers is stopped then too.
+    /// INSECURE: the keys are as predictable as the seed
+    #[arg(long, value_name = "HEX", value_parser = parse_hex::<32>)]
+    seed: Option<[u8; 32]>,
+    /// Reject the handshake messages whose msg_id is more than 300 seconds old or 30
+    /// seconds ahead of the server time, for testing clients with skewed clocks
+    #[arg(long)]
+    check_msgid_window: bool,
+    /// PEM file with an RSA private key used to decrypt the client's encrypted_data,
+    /// can be repeated to advertise several keys
+    #[arg(long, value_name = "PATH")]
+    rsa_key: Vec<PathBuf>,
+    /// Advertise this fingerprint in hex instead of the ones of the keys, for testing
+    /// clients which pinned one. The first --rsa-key still decrypts
+    #[arg(long, value_name = "HEX", value_parser = rsa_key::parse_fingerprint)]
+    fingerprint: Option<i64>,
+    /// Also accept clients of MTProxy's fake TLS mode, which hide the obfuscated
+    /// transport in TLS records, with this secret in hex, e.g. the `ee` one of a link
+    #[arg(long, value_name = "SECRET")]
+    fake_tls: Option<FakeTlsSecret>,
+    /// Directory to write the raw inbound and outbound bytes of every connection to
+    #[arg(long, value_name = "DIR")]
+    dump_dir: Option<PathBuf>,
+    /// Append a JSON record of every connection's handshake to this file, one per
+    /// line, `-` for stdout
+    #[arg(long, value_name = "PATH")]
+    emit_json: Option<PathBuf>,
+    /// Close the connection after answering req_pq_multi
+    #[arg(long)]
+    stop_after_res_pq: bool,
+    /// Answer req_pq_multi with this transport error code instead of ResPq, e.g.
+    /// -404 or -429
+    #[arg(
+        long,
+        value_name = "CODE",
+        allow_negative_numbers = true,
+        value_parser = clap::value_parser!(i32).range(..0)
+    )]
+    respond_error: Option<i32>,
+    /// What to do once the auth key is created: close the connection, wait until the
+    /// client is idle for --timeout, or echo, i.e. answer its encrypted messages
+    #[arg(long, value_enum, default_value_t = AfterHandshake::Close)]
+    after_handshake: AfterHandshake,
+    /// Accept several handshakes on one connection, a new req_pq_multi starts over.
+    /// The connection stays open until the client closes it
+    #[arg(long, conflicts_with_all = ["after_handshake", "stop_after_res_pq"])]
+    repeat_handshakes: bool,
+    /// Wait this many milliseconds before every answer, to emulate a slow DC
+    #[arg(long, value_name = "MS", default_value_t = 0)]
+    response_delay: u64,
+    /// Fault to inject for chaos testing clients: drop-after-respq, truncate,
+    /// corrupt-msg-key or delay=MS, with an optional @PROBABILITY each time it
+    /// applies, e.g. truncate@0.1. Can be repeated
+    #[arg(long, value_name = "FAULT")]
+    fault: Vec<FaultRule>,
+    /// JSON file with canned responses to serve instead of running the handshake
+    #[arg(long, value_name = "PATH")]
+    script: Option<PathBuf>,
+    /// Lowest layer of invokeWithLayer answered, lower ones get the
+    /// CONNECTION_LAYER_INVALID RPC error
+    #[arg(long, value_name = "LAYER")]
+    min_layer: Option<i32>,
+    /// Highest layer of invokeWithLayer answered, higher ones get the
+    /// CONNECTION_LAYER_INVALID RPC error
+    #[arg(long, value_name = "LAYER")]
+    max_layer: Option<i32>,
+    /// Generate a fresh 2048-bit safe prime at startup instead of using the
+    /// well-known dh_prime of MTProto, which takes a while
+    #[arg(long)]
+    generate_prime: bool,
+    /// Seconds between the logged tallies of the handshake stages the connections
+    /// reached, which is otherwise only logged on shutdown
+    #[arg(long, value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..))]
+    stats_interval: Option<u64>,
+    /// Address to serve health checks on, `GET /healthz` answers 200 while
+    /// connections are accepted and 503 during shutdown
+    #[arg(long, value_name = "ADDR")]
+    health_addr: Option<SocketAddr>,
+    /// Address to accept admin commands on, one per line, e.g. `delay 500`, `fault
+    /// drop-after-respq on` or `loglevel trace`. A path is a Unix domain socket
+    #[arg(long, value_name = "ADDR", value_parser = parse_admin_addr)]
+    admin_addr: Option<AdminAddr>,
+    /// Address to serve Prometheus metrics on
+    #[cfg(feature = "metrics")]
+    #[arg(long, value_name = "ADDR")]
+    metrics_bind: Option<SocketAddr>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Parse the req_pq_multi of a captured packet, e.g. a `.in` file of --dump-dir,
+    /// print it and exit
+    Parse {
+        /// File with the client's bytes, from the obfuscation header on
+        #[arg(long, value_name = "PATH")]
+        file: PathBuf,
+        /// Key in hex the client encrypts with, instead of the one of the header
+        #[arg(long, value_name = "HEX", value_parser = parse_hex::<32>, requires = "iv")]
+        key: Option<[u8; 32]>,
+        /// IV in hex the client encrypts with, instead of the one of the header
+        #[arg(long, value_name = "HEX", value_parser = parse_hex::<16>, requires = "key")]
+        iv: Option<[u8; 16]>,
+    },
+    /// Generate an RSA key for --rsa-key, write it as PEM and print its fingerprint
+    Keygen {
+        /// Size of the key, MTProto only accepts 2048
+        #[arg(long, default_value_t = rsa_key::RSA_KEY_BITS)]
+        bits: usize,
+        /// File to write the private key to, which mustn't exist yet
+        #[arg(long, value_name = "PATH")]
+        out: PathBuf,
+    },
+}
+
+// The options of --config come before the command line, and are dropped when the
+// command line sets them too, so it takes precedence even for the repeated ones
+fn parse_args() -> Result<Args> {
+    let matches = Args::command().get_matches();
+    let Some(path) = matches.get_one::<PathBuf>("config") else {
+        return Ok(Args::from_arg_matches(&matches)?);
+    };
+    let file = ConfigFile::load(path)
+        .with_context(|| format!("failed to load the config file {}", path.display()))?;
+    let command = Args::command();
+    for (key, _) in &file.options {
+        let known = command.get_arguments().any(|arg| arg.get_id() == key);
+        if !known || ["config", "help", "version"].contains(&key.as_str()) {
+            anyhow::bail!("unknown option {} in {}", key, path.display());
         }
     }
+    let file_args = file.args(|key| matches.value_source(key) == Some(ValueSource::CommandLine));
+    let mut argv = std::env::args_os();
+    Ok(Args::parse_from(
+        argv.next()
+            .into_iter()
+            .chain(file_args.into_iter().map(Into::into))
+            .chain(argv),
+    ))
+}
+
+fn parse_hex<const N: usize>(s: &str) -> Result<[u8; N], String> {
+    let invalid = || format!("expected {} hex digits", 2 * N);
+    if s.len() != 2 * N {
+        return Err(invalid());
+    }
+    let mut bytes = [0; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = s
+            .get(2 * i..2 * i + 2)
+            .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+            .ok_or_else(invalid)?;
+    }
+    Ok(bytes)
 }
 
-#[allow(clippy::unused_io_amount)]
-fn handle_connection(mut stream: TcpStream) -> Result<()> {
-    // Init connection
-    let mut init = [0; 64];
-    let mut encrypted_init = [0; 8];
-    let mut packet_len = [0; 1];
-    std::io::Read::by_ref(&mut stream)
-        .take(56)
-        .read(&mut init)?;
-    stream.read_exact(&mut encrypted_init)?;
-    stream.read_exact(&mut packet_len)?;
-    debug!("init: {:02x?}", init);
-    debug!("encrypted_init: {:02x?}", encrypted_init);
-    debug!("packet_len: {:02x?}", packet_len);
-
-    let encrypt_key: Vec<u8> = init.into_iter().skip(8).take(32).collect();
-    let encrypt_iv: Vec<u8> = init.into_iter().skip(40).take(16).collect();
-    debug!("encrypt_key: {:02x?}", encrypt_key);
-    debug!("encrypt_iv: {:02x?}", encrypt_iv);
-
-    let decrypt_key: Vec<u8> = init.into_iter().rev().skip(8).take(32).collect();
-    let decrypt_iv: Vec<u8> = init.into_iter().rev().skip(40).take(16).collect();
-    debug!("decrypt_key: {:02x?}", decrypt_key);
-    debug!("decrypt_iv: {:02x?}", decrypt_iv);
-
-    let mut decryptor =
-        Aes256Ctr64Be::new(encrypt_key.as_slice().into(), encrypt_iv.as_slice().into());
-    decryptor.apply_keystream(&mut init);
-    debug!("init: {:02x?}", init);
-
-    // ReqPqMulti
-    decryptor.apply_keystream(&mut packet_len);
-    debug!("packet_len: {:02x?}", packet_len);
-    let packet_len = packet_len[0] as usize * 4;
-
-    let mut packet = vec![0; packet_len];
-    stream.read(&mut packet)?;
-    decryptor.apply_keystream(&mut packet);
-    debug!("packet: {:02x?}", packet);
-
-    let mut cur = Cursor::from_slice(&packet);
-    let req_pq_multi = ReqPqMulti::parse(&mut cur)?;
-    debug!("req_pq_multi: {:02x?}", req_pq_multi);
-
-    // ResPq
-    let res_pq = ResPq::generate(
-        req_pq_multi.nonce,
-        0x17ED48941A08F981u64.to_le_bytes().into_iter().collect(),
-        // 0x0u64.to_le_bytes().into_iter().collect(), // SIGFPE
-    );
-    let mut res_pq_mtproto = BytesMut::new();
-    Abridged::new().pack(&res_pq.ser(), &mut res_pq_mtproto);
-    let _ = res_pq_mtproto.split_to(1);
-    debug!("res_pq: {:02x?}", res_pq);
-    debug!("res_pq_mtproto: {:02x?}", res_pq_mtproto.to_vec());
-
-    let mut encryptor =
-        Aes256Ctr64Be::new(decrypt_key.as_slice().into(), decrypt_iv.as_slice().into());
-    encryptor.apply_keystream(&mut res_pq_mtproto);
-    stream.write_all(&res_pq_mtproto)?;
-
-    // ReqDHParams
-
-    let mut packet_len = [0; 1];
-    stream.read_exact(&mut packet_len)?;
-
-    decryptor.apply_keystream(&mut packet_len);
-    debug!("packet_len: {:02x?}", packet_len);
-    let packet_len = packet_len[0] as usize * 4;
-
-    let mut packet = vec![0; packet_len];
-    stream.read(&mut packet)?;
-    decryptor.apply_keystream(&mut packet);
-    debug!("packet: {:02x?}", packet);
-
-    // ResDHParams
-    let res_dh_params = ResDHParams::generate(req_pq_multi.nonce, Vec::new());
-    let mut res_dh_params_mtproto = BytesMut::new();
-    Abridged::new().pack(&res_dh_params.ser(), &mut res_dh_params_mtproto);
-    let _ = res_dh_params_mtproto.split_to(1);
-    debug!("res_dh_params: {:02x?}", res_dh_params);
-    debug!(
-        "res_dh_params_mtproto: {:02x?}",
-        res_dh_params_mtproto.to_vec()
-    );
-
-    encryptor.apply_keystream(&mut res_dh_params_mtproto);
-    stream.write_all(&res_dh_params_mtproto)?;
-
-    // debug!("answer: {:02x?}", {
-    //     let mut buf = Vec::new();
-    //     stream.read_to_end(&mut buf)?;
-    //     buf
-    // });
+#[derive(Clone)]
+enum AdminAddr {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
 
-    Ok(())
+fn parse_admin_addr(s: &str) -> Result<AdminAddr, String> {
+    match s.parse() {
+        Ok(addr) => Ok(AdminAddr::Tcp(addr)),
+        #[cfg(unix)]
+        Err(_) if s.contains('/') => Ok(AdminAddr::Unix(s.into())),
+        #[cfg(not(unix))]
+        Err(e) => Err(format!(
+            "{}, Unix domain sockets are only supported on Unix",
+            e
+        )),
+        #[cfg(unix)]
+        Err(e) => Err(e.to_string()),
+    }
 }
 
-#[allow(dead_code)]
-#[derive(Debug)]
-struct ReqPqMulti {
-    auth_key_id: i64,
-    message_id: i64,
-    message_length: u32,
-    magic: u32,
-    nonce: [u8; 16],
-}
-
-impl ReqPqMulti {
-    fn parse(cur: &mut Cursor) -> Result<Self> {
-        Ok(ReqPqMulti {
-            auth_key_id: i64::deserialize(cur)?,
-            message_id: i64::deserialize(cur)?,
-            message_length: u32::deserialize(cur)?,
-            magic: u32::deserialize(cur)?,
-            nonce: <[u8; 16]>::deserialize(cur)?,
+// How often the accept loop checks for the shutdown flag
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+// How long active connections are waited for on shutdown
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+// The time of the msg_ids and server_time of a seeded server, 2023-11-14
+const SEEDED_TIME: i64 = 1_700_000_000;
+
+fn main() -> Result<()> {
+    let args = parse_args()?;
+    if let Err(e) = srv::logging::init(args.log_level, args.log_json) {
+        eprintln!("keeping the installed logger: {}", e);
+    }
+    match &args.command {
+        Some(Command::Parse { file, key, iv }) => {
+            return parse(file, key.zip(*iv), args.max_packet)
+        }
+        Some(Command::Keygen { bits, out }) => return keygen(*bits, out),
+        None => {}
+    }
+
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let flag = shutdown.clone();
+    // Ctrl+C and SIGTERM on Unix, Ctrl+C, Ctrl+Break and closing the console on Windows
+    ctrlc::set_handler(move || flag.store(true, Ordering::SeqCst))
+        .context("failed to install the signal handler")?;
+
+    let rsa_keys = args
+        .rsa_key
+        .iter()
+        .map(|path| {
+            RsaKey::load(path)
+                .with_context(|| format!("failed to load the RSA key {}", path.display()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    for key in &rsa_keys {
+        info!("loaded RSA key with fingerprint {:016x}", key.fingerprint());
+    }
+    if let Some(fingerprint) = args.fingerprint {
+        warn!(
+            "advertising the fingerprint {:016x} instead of the keys', for testing only",
+            fingerprint
+        );
+    }
+    if args.seed.is_some() {
+        warn!(
+            "seeded randomness, the auth keys are predictable, never use --seed outside of tests"
+        );
+    }
+    let script = args
+        .script
+        .as_deref()
+        .map(|path| {
+            Script::load(path)
+                .with_context(|| format!("failed to load the script {}", path.display()))
+        })
+        .transpose()?;
+    if let (Some(min), Some(max)) = (args.min_layer, args.max_layer) {
+        anyhow::ensure!(
+            min <= max,
+            "--min-layer {} is above --max-layer {}",
+            min,
+            max
+        );
+    }
+    let dh = if args.generate_prime {
+        info!("generating a 2048-bit safe prime, this may take minutes");
+        let dh = DhParams::generate();
+        info!("generated dh_prime {:x} with g {}", dh.prime, dh.g);
+        dh
+    } else {
+        DhParams::default()
+    };
+    if let Some(dir) = &args.dump_dir {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create the dump directory {}", dir.display()))?;
+    }
+    let json_records = args
+        .emit_json
+        .as_deref()
+        .map(|path| {
+            JsonRecords::open(path)
+                .with_context(|| format!("failed to open {} for the JSON records", path.display()))
         })
+        .transpose()?;
+    // The msg_ids and server_time are part of the transcript too
+    let message_ids: MessageIdsFactory = match args.seed {
+        Some(_) => Box::new(|| MessageIds::new(FixedClock(SEEDED_TIME))),
+        None => Box::new(MessageIds::default),
+    };
+    let config = Arc::new(Config {
+        timeout: Duration::from_secs(args.timeout),
+        max_packet: args.max_packet,
+        max_vector_len: args.max_vector_len,
+        pq_bits: args.pq_bits,
+        deterministic_nonce: args.deterministic_nonce,
+        check_msgid_window: args.check_msgid_window,
+        rng: args.seed.map(ServerRng::seeded).unwrap_or_default(),
+        message_ids,
+        rsa_keys,
+        fingerprint_override: args.fingerprint,
+        fake_tls: args.fake_tls.clone(),
+        custom_transport: None,
+        dump_dir: args.dump_dir.clone(),
+        stop_after_res_pq: args.stop_after_res_pq,
+        respond_error: args.respond_error,
+        after_handshake: args.after_handshake,
+        repeat_handshakes: args.repeat_handshakes,
+        response_delay: Duration::from_millis(args.response_delay),
+        faults: args.fault.clone(),
+        script,
+        min_layer: args.min_layer,
+        max_layer: args.max_layer,
+        dh,
+        policy: Box::new(DefaultPolicy),
+        json_records,
+        on_handshake_complete: Some(Box::new(|complete| {
+            debug!(
+                "{} auth_key_id {:016x} from nonce {:02x?} and server_nonce {:02x?}",
+                complete.id,
+                complete.auth_key_id,
+                complete.nonces.nonce,
+                complete.nonces.server_nonce
+            )
+        })),
+        auth_keys: Default::default(),
+        stage_stats: Default::default(),
+        handshake_latency: Default::default(),
+        salts: Default::default(),
+        overrides: Default::default(),
+    });
+
+    if let Some(interval) = args.stats_interval {
+        let config = config.clone();
+        thread::spawn(move || loop {
+            thread::sleep(Duration::from_secs(interval));
+            info!("handshake stages reached: {}", config.stage_stats);
+            info!("handshake latency: {}", config.handshake_latency);
+        });
+    }
+    if let Some(addr) = args.health_addr {
+        let listener =
+            TcpListener::bind(addr).with_context(|| format!("failed to bind {}", addr))?;
+        thread::spawn(move || {
+            if let Err(e) = health::serve(listener) {
+                log::error!("health check server failed: {}", e);
+            }
+        });
+    }
+    // Kept until the server stops, removing the socket file of the admin socket
+    #[cfg(unix)]
+    let mut _admin_socket_file = None;
+    match args.admin_addr.clone() {
+        Some(AdminAddr::Tcp(addr)) => {
+            let listener =
+                TcpListener::bind(addr).with_context(|| format!("failed to bind {}", addr))?;
+            let config = config.clone();
+            thread::spawn(move || {
+                if let Err(e) = admin::serve(listener, config) {
+                    log::error!("admin server failed: {}", e);
+                }
+            });
+        }
+        #[cfg(unix)]
+        Some(AdminAddr::Unix(path)) => {
+            let (listener, socket_file) = bind_unix(&path)?;
+            listener.set_nonblocking(false)?;
+            _admin_socket_file = Some(socket_file);
+            let config = config.clone();
+            thread::spawn(move || {
+                if let Err(e) = admin::serve_unix(listener, config) {
+                    log::error!("admin server failed: {}", e);
+                }
+            });
+        }
+        None => {}
+    }
+    #[cfg(feature = "metrics")]
+    if let Some(addr) = args.metrics_bind {
+        let listener =
+            TcpListener::bind(addr).with_context(|| format!("failed to bind {}", addr))?;
+        thread::spawn(move || {
+            if let Err(e) = srv::metrics::serve(listener) {
+                log::error!("metrics server failed: {}", e);
+            }
+        });
     }
+
+    serve(args, config, shutdown)
+}
+
+fn parse(file: &Path, key_iv: Option<([u8; 32], [u8; 16])>, max_packet: usize) -> Result<()> {
+    let dump = fs::read(file).with_context(|| format!("failed to read {}", file.display()))?;
+    let req_pq_multi = parse_req_pq_multi(&dump, key_iv, max_packet)
+        .with_context(|| format!("failed to parse {}", file.display()))?;
+    println!("{:#x?}", req_pq_multi);
+    Ok(())
+}
+
+fn keygen(bits: usize, out: &Path) -> Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    // Only the user may read the private key
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    // Opened first, so an existing file fails before the key is generated
+    let mut file = options
+        .open(out)
+        .with_context(|| format!("failed to create {}", out.display()))?;
+    let key = RsaKey::generate(bits).and_then(|(key, pem)| {
+        file.write_all(pem.as_bytes())?;
+        Ok(key)
+    });
+    let key = match key {
+        Ok(key) => key,
+        Err(e) => {
+            let _ = fs::remove_file(out);
+            return Err(e).with_context(|| format!("failed to write {}", out.display()));
+        }
+    };
+    info!("wrote the RSA key to {}", out.display());
+    println!("{:016x}", key.fingerprint());
+    Ok(())
 }
 
-#[derive(Debug)]
-struct ResPq {
-    auth_key_id: i64,
-    message_id: i64,
-    message_length: u32,
-    magic: u32,
-    nonce: [u8; 16],
-    server_nonce: [u8; 16],
-    pq: Vec<u8>,
-    server_public_key_fingerprints: Vec<i64>,
+// What the accept loops of all the listeners share
+struct Shared {
+    config: Arc<Config>,
+    shutdown: Arc<AtomicBool>,
+    active_connections: Arc<AtomicUsize>,
+    max_connections: usize,
+    once: bool,
+    rate_limiter: Option<RateLimiter>,
+    peer_failures: Arc<PeerFailures>,
 }
 
-impl ResPq {
-    #[allow(overflowing_literals)]
-    fn generate(nonce: [u8; 16], pq: Vec<u8>) -> Self {
+impl Shared {
+    fn new(args: &Args, config: Arc<Config>, shutdown: Arc<AtomicBool>) -> Self {
         Self {
-            auth_key_id: 0,
-            message_id: time_now(),
-            message_length: 0,
-            magic: 0x05162463,
-            nonce,
-            server_nonce: SERVER_NONCE,
-            pq,
-            server_public_key_fingerprints: vec![0xd09d1d85de64fd85],
-        }
-    }
-
-    fn ser(&self) -> Vec<u8> {
-        let mut res = Vec::new();
-        self.auth_key_id.serialize(&mut res);
-        self.message_id.serialize(&mut res);
-        self.message_length.serialize(&mut res);
-        self.magic.serialize(&mut res);
-        self.nonce.serialize(&mut res);
-        self.server_nonce.serialize(&mut res);
-        self.pq.serialize(&mut res);
-        self.server_public_key_fingerprints.serialize(&mut res);
+            config,
+            shutdown,
+            active_connections: Arc::new(AtomicUsize::new(0)),
+            max_connections: args.max_connections,
+            once: args.once,
+            rate_limiter: args
+                .rate_limit
+                .map(|rate| RateLimiter::new(rate, args.rate_burst)),
+            peer_failures: Arc::new(PeerFailures::new(args.ban_threshold)),
+        }
+    }
+
+    // Whether to hand a connection accepted on `listener` to a handler, the caller
+    // releases the slot taken for it
+    fn admit(&self, listener: SocketAddr, peer: SocketAddr) -> bool {
+        metrics::record_connection(listener);
+        if !allow(self.rate_limiter.as_ref(), peer) {
+            return false;
+        }
+        if self.peer_failures.is_banned(peer.ip()) {
+            warn!(
+                "{} is banned after {} failed connections, rejecting",
+                peer,
+                self.peer_failures.count(peer.ip())
+            );
+            return false;
+        }
+        self.take_slot(listener, peer)
+    }
+
+    // Unix socket clients have no address to rate limit
+    #[cfg(unix)]
+    fn admit_unix(&self, listener: &Path) -> bool {
+        metrics::record_connection(listener.display());
+        self.take_slot(listener.display(), "a unix socket client")
+    }
+
+    fn take_slot(&self, listener: impl fmt::Display, peer: impl fmt::Display) -> bool {
+        if self.active_connections.fetch_add(1, Ordering::SeqCst) >= self.max_connections {
+            self.active_connections.fetch_sub(1, Ordering::SeqCst);
+            warn!(
+                "too many connections ({}), rejecting {} on {}",
+                self.max_connections, peer, listener
+            );
+            return false;
+        }
+        true
+    }
+
+    // With --once, the only connection is handled by the accept loop itself, which
+    // stops them all afterwards
+    fn finish_once(&self, res: srv::error::Result<()>, peer: impl fmt::Display) -> Result<()> {
+        self.active_connections.fetch_sub(1, Ordering::SeqCst);
+        self.shutdown.store(true, Ordering::SeqCst);
+        res.with_context(|| format!("the connection of {} failed", peer))
+    }
+
+    // A failed accept loop stops the others too
+    fn stop_on_error(&self, res: Result<()>) -> Result<()> {
+        if res.is_err() {
+            self.shutdown.store(true, Ordering::SeqCst);
+        }
         res
     }
 }
 
-#[derive(Debug)]
-struct ResDHParams {
-    auth_key_id: i64,
-    message_id: i64,
-    message_length: u32,
-    magic: u32,
-    nonce: [u8; 16],
-    server_nonce: [u8; 16],
-    encrypted_answer: Vec<u8>,
+#[cfg(not(feature = "tokio"))]
+fn serve(args: Args, config: Arc<Config>, shutdown: Arc<AtomicBool>) -> Result<()> {
+    let listeners = bind_all(&args)?;
+    #[cfg(unix)]
+    let unix_listener = args.unix.as_deref().map(bind_unix).transpose()?;
+    let shared = Shared::new(&args, config, shutdown);
+    health::set_healthy(true);
+    let res = thread::scope(|scope| {
+        #[allow(unused_mut)]
+        let mut accept_loops = listeners
+            .into_iter()
+            .map(|listener| scope.spawn(|| shared.stop_on_error(accept_loop(listener, &shared))))
+            .collect::<Vec<_>>();
+        #[cfg(unix)]
+        if let Some((listener, socket_file)) = &unix_listener {
+            accept_loops.push(scope.spawn(|| {
+                shared.stop_on_error(unix_accept_loop(listener, &socket_file.0, &shared))
+            }));
+        }
+        // Keep the first error, but wait for every loop to stop
+        let mut res = Ok(());
+        for accept_loop in accept_loops {
+            let accept_loop_res = accept_loop.join().unwrap();
+            res = res.and(accept_loop_res);
+        }
+        res
+    });
+
+    drain(&shared);
+    res
 }
 
-impl ResDHParams {
-    fn generate(nonce: [u8; 16], encrypted_answer: Vec<u8>) -> Self {
-        Self {
-            auth_key_id: 0,
-            message_id: time_now(),
-            message_length: 0,
-            magic: 0xd0e8075c,
-            nonce,
-            server_nonce: SERVER_NONCE,
-            encrypted_answer,
-        }
-    }
-
-    fn ser(&self) -> Vec<u8> {
-        let mut res = Vec::new();
-        self.auth_key_id.serialize(&mut res);
-        self.message_id.serialize(&mut res);
-        self.message_length.serialize(&mut res);
-        self.magic.serialize(&mut res);
-        self.nonce.serialize(&mut res);
-        self.server_nonce.serialize(&mut res);
-        self.encrypted_answer.serialize(&mut res);
+#[cfg(not(feature = "tokio"))]
+fn accept_loop(listener: TcpListener, shared: &Shared) -> Result<()> {
+    use std::io::ErrorKind;
+
+    use srv::handle_connection;
+
+    let local = listener.local_addr()?;
+    while !shared.shutdown.load(Ordering::SeqCst) {
+        let (stream, peer) = match listener.accept() {
+            Ok(accepted) => accepted,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                thread::sleep(POLL_INTERVAL);
+                continue;
+            }
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("failed to accept a connection on {}", local))
+            }
+        };
+        // Accepted sockets inherit the non-blocking mode on some platforms
+        stream
+            .set_nonblocking(false)
+            .with_context(|| format!("failed to set up the connection of {}", peer))?;
+        if !shared.admit(local, peer) {
+            continue;
+        }
+        if shared.once {
+            return shared.finish_once(handle_connection(stream, &shared.config), peer);
+        }
+
+        let active_connections = shared.active_connections.clone();
+        let config = shared.config.clone();
+        let peer_failures = shared.peer_failures.clone();
+        thread::spawn(move || {
+            // The handler logs its failures along with the connection id, clients
+            // which just went away aren't held against the peer
+            let res = handle_connection(stream, &config);
+            if res.is_err_and(|e| !e.is_disconnect()) {
+                record_failure(&peer_failures, peer);
+            }
+            active_connections.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+    Ok(())
+}
+
+#[cfg(all(unix, not(feature = "tokio")))]
+fn unix_accept_loop(listener: &UnixListener, path: &Path, shared: &Shared) -> Result<()> {
+    use std::io::ErrorKind;
+
+    use srv::handle_unix_connection;
+
+    while !shared.shutdown.load(Ordering::SeqCst) {
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                thread::sleep(POLL_INTERVAL);
+                continue;
+            }
+            Err(e) => {
+                return Err(e).with_context(|| {
+                    format!("failed to accept a connection on {}", path.display())
+                })
+            }
+        };
+        stream.set_nonblocking(false)?;
+        if !shared.admit_unix(path) {
+            continue;
+        }
+        if shared.once {
+            let res = handle_unix_connection(stream, &shared.config);
+            return shared.finish_once(res, "a unix socket client");
+        }
+
+        let active_connections = shared.active_connections.clone();
+        let config = shared.config.clone();
+        thread::spawn(move || {
+            // The handler logs its failures along with the connection id
+            let _ = handle_unix_connection(stream, &config);
+            active_connections.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+    Ok(())
+}
+
+#[cfg(feature = "tokio")]
+fn serve(args: Args, config: Arc<Config>, shutdown: Arc<AtomicBool>) -> Result<()> {
+    use tokio::runtime::Runtime;
+
+    let runtime = Runtime::new().context("failed to start the tokio runtime")?;
+    let shared = Arc::new(Shared::new(&args, config, shutdown));
+    #[cfg(unix)]
+    let unix_listener = args.unix.as_deref().map(bind_unix).transpose()?;
+    let res = runtime.block_on(async {
+        let listeners = bind_all(&args)?
+            .into_iter()
+            .map(tokio::net::TcpListener::from_std)
+            .collect::<std::io::Result<Vec<_>>>()?;
+        health::set_healthy(true);
+        #[allow(unused_mut)]
+        let mut accept_loops = listeners
+            .into_iter()
+            .map(|listener| {
+                let shared = shared.clone();
+                tokio::spawn(
+                    async move { shared.stop_on_error(accept_loop(listener, &shared).await) },
+                )
+            })
+            .collect::<Vec<_>>();
+        #[cfg(unix)]
+        if let Some((listener, socket_file)) = &unix_listener {
+            let listener = tokio::net::UnixListener::from_std(listener.try_clone()?)?;
+            let path = socket_file.0.clone();
+            let shared = shared.clone();
+            accept_loops.push(tokio::spawn(async move {
+                shared.stop_on_error(unix_accept_loop(listener, &path, &shared).await)
+            }));
+        }
+        // Keep the first error, but wait for every loop to stop
+        let mut res = Ok(());
+        for accept_loop in accept_loops {
+            let accept_loop_res = accept_loop.await?;
+            res = res.and(accept_loop_res);
+        }
         res
+    });
+
+    // The handlers keep running on the runtime's worker threads until it's dropped,
+    // and are drained even when an accept loop failed
+    drain(&shared);
+    res
+}
+
+#[cfg(feature = "tokio")]
+async fn accept_loop(listener: tokio::net::TcpListener, shared: &Shared) -> Result<()> {
+    use srv::handle_connection_async;
+    use tokio::time;
+
+    let local = listener.local_addr()?;
+    while !shared.shutdown.load(Ordering::SeqCst) {
+        let (stream, peer) = match time::timeout(POLL_INTERVAL, listener.accept()).await {
+            Ok(accepted) => {
+                accepted.with_context(|| format!("failed to accept a connection on {}", local))?
+            }
+            Err(_) => continue,
+        };
+        if !shared.admit(local, peer) {
+            continue;
+        }
+        if shared.once {
+            let res = handle_connection_async(stream, &shared.config).await;
+            return shared.finish_once(res, peer);
+        }
+
+        let active_connections = shared.active_connections.clone();
+        let config = shared.config.clone();
+        let peer_failures = shared.peer_failures.clone();
+        tokio::spawn(async move {
+            // The handler logs its failures along with the connection id, clients
+            // which just went away aren't held against the peer
+            let res = handle_connection_async(stream, &config).await;
+            if res.is_err_and(|e| !e.is_disconnect()) {
+                record_failure(&peer_failures, peer);
+            }
+            active_connections.fetch_sub(1, Ordering::SeqCst);
+        });
     }
+    Ok(())
 }
 
-fn time_now() -> i64 {
-    (SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .unwrap()
-        .as_nanos()) as i64
+#[cfg(all(unix, feature = "tokio"))]
+async fn unix_accept_loop(
+    listener: tokio::net::UnixListener,
+    path: &Path,
+    shared: &Shared,
+) -> Result<()> {
+    use srv::handle_unix_connection_async;
+    use tokio::time;
+
+    while !shared.shutdown.load(Ordering::SeqCst) {
+        let stream = match time::timeout(POLL_INTERVAL, listener.accept()).await {
+            Ok(accepted) => {
+                accepted
+                    .with_context(|| {
+                        format!("failed to accept a connection on {}", path.display())
+                    })?
+                    .0
+            }
+            Err(_) => continue,
+        };
+        if !shared.admit_unix(path) {
+            continue;
+        }
+        if shared.once {
+            let res = handle_unix_connection_async(stream, &shared.config).await;
+            return shared.finish_once(res, "a unix socket client");
+        }
+
+        let active_connections = shared.active_connections.clone();
+        let config = shared.config.clone();
+        tokio::spawn(async move {
+            // The handler logs its failures along with the connection id
+            let _ = handle_unix_connection_async(stream, &config).await;
+            active_connections.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+    Ok(())
+}
+
+// Counts a failed connection against the peer, which is banned once it reaches
+// --ban-threshold
+fn record_failure(peer_failures: &PeerFailures, peer: SocketAddr) {
+    let failures = peer_failures.record(peer.ip());
+    if peer_failures.is_banned(peer.ip()) {
+        warn!(
+            "banning {} after {} failed connections",
+            peer.ip(),
+            failures
+        );
+    } else {
+        debug!("{} failed {} connections so far", peer.ip(), failures);
+    }
+}
+
+fn allow(rate_limiter: Option<&RateLimiter>, peer: SocketAddr) -> bool {
+    if rate_limiter.is_some_and(|rate_limiter| !rate_limiter.allow(peer.ip())) {
+        warn!("rate limit exceeded by {}, rejecting", peer);
+        metrics::record_rate_limited();
+        return false;
+    }
+    true
+}
+
+// Binds every --bind address before any of them accepts a connection
+fn bind_all(args: &Args) -> Result<Vec<TcpListener>> {
+    args.bind
+        .iter()
+        .map(|&addr| {
+            let listener = bind_with_retries(addr, args)?;
+            info!("listening on {}", listener.local_addr()?);
+            Ok(listener)
+        })
+        .collect()
+}
+
+// Retries while the address is in use, up to --bind-retries times with the delay
+// doubled every time
+fn bind_with_retries(addr: SocketAddr, args: &Args) -> Result<TcpListener> {
+    let mut delay = Duration::from_millis(args.bind_retry_delay);
+    let mut retries = 0;
+    loop {
+        match bind(addr, args) {
+            Err(e) if retries < args.bind_retries && is_addr_in_use(&e) => {
+                retries += 1;
+                warn!(
+                    "{} is in use, retry {} of {} in {:?}",
+                    addr, retries, args.bind_retries, delay
+                );
+                thread::sleep(delay);
+                delay *= 2;
+            }
+            res => return res,
+        }
+    }
+}
+
+fn is_addr_in_use(e: &anyhow::Error) -> bool {
+    e.root_cause()
+        .downcast_ref::<io::Error>()
+        .is_some_and(|e| e.kind() == io::ErrorKind::AddrInUse)
+}
+
+// Binds a non-blocking listener, std and tokio leave IPV6_V6ONLY to the system
+// default
+fn bind(addr: SocketAddr, args: &Args) -> Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(args.ipv6_only)?;
+    }
+    // Like std does, so a restarted server doesn't wait for TIME_WAIT
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+    if args.reuse_port {
+        socket.set_reuse_port(true)?;
+    }
+    socket
+        .bind(&addr.into())
+        .with_context(|| format!("failed to bind {}", addr))?;
+    socket.listen(args.backlog)?;
+    socket.set_nonblocking(true)?;
+    Ok(socket.into())
+}
+
+// Removes the socket file of the Unix listener once the server stops
+#[cfg(unix)]
+struct SocketFile(PathBuf);
+
+#[cfg(unix)]
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.0) {
+            warn!("failed to remove the socket {}: {}", self.0.display(), e);
+        }
+    }
+}
+
+// Binds a non-blocking listener on `path`, replacing the socket a killed server left
+// behind
+#[cfg(unix)]
+fn bind_unix(path: &Path) -> Result<(UnixListener, SocketFile)> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            warn!("removing the stale socket {}", path.display());
+            fs::remove_file(path)
+                .with_context(|| format!("failed to remove the socket {}", path.display()))?;
+        }
+        Ok(_) => anyhow::bail!("{} exists and isn't a socket", path.display()),
+        Err(_) => {}
+    }
+    let listener =
+        UnixListener::bind(path).with_context(|| format!("failed to bind {}", path.display()))?;
+    let socket_file = SocketFile(path.to_owned());
+    // Only the user and its group may connect
+    fs::set_permissions(path, fs::Permissions::from_mode(0o660))?;
+    listener.set_nonblocking(true)?;
+    info!("listening on {}", path.display());
+    Ok((listener, socket_file))
+}
+
+fn drain(shared: &Shared) {
+    let active_connections = &shared.active_connections;
+    health::set_healthy(false);
+    info!("shutting down, waiting for active connections");
+    let start = Instant::now();
+    while active_connections.load(Ordering::SeqCst) > 0 && start.elapsed() < SHUTDOWN_GRACE {
+        thread::sleep(POLL_INTERVAL);
+    }
+    match active_connections.load(Ordering::SeqCst) {
+        0 => info!("shut down cleanly"),
+        n => warn!("shut down with {} connections still active", n),
+    }
+    info!("handshake stages reached: {}", shared.config.stage_stats);
+    info!("handshake latency: {}", shared.config.handshake_latency);
+    if !shared.peer_failures.is_empty() {
+        info!("failed connections per peer: {}", shared.peer_failures);
+    }
 }
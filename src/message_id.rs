@@ -0,0 +1,84 @@
+use std::time::SystemTime;
+
+use crate::error::{Result, ServerError};
+
+// A msg_id may be at most 300 seconds in the past and 30 seconds in the future
+pub const MAX_MSG_ID_AGE: i64 = 300;
+pub const MAX_MSG_ID_LEAD: i64 = 30;
+
+// Source of the message ids, usually the clock. Tests can supply a fixed sequence
+// with a closure
+pub trait MessageIdProvider: Send {
+    fn now(&mut self) -> i64;
+}
+
+impl<F: FnMut() -> i64 + Send> MessageIdProvider for F {
+    fn now(&mut self) -> i64 {
+        self()
+    }
+}
+
+// Unix time in 2^-32 seconds, as MTProto expects
+pub struct Clock;
+
+impl MessageIdProvider for Clock {
+    fn now(&mut self) -> i64 {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap();
+        let fraction = ((now.subsec_nanos() as u64) << 32) / 1_000_000_000;
+        ((now.as_secs() << 32) | fraction) as i64
+    }
+}
+
+// A clock stopped at `time`, in unix seconds, for transcripts which don't depend on
+// when they were taken. MessageIds still makes the ids increase
+pub struct FixedClock(pub i64);
+
+impl MessageIdProvider for FixedClock {
+    fn now(&mut self) -> i64 {
+        self.0 << 32
+    }
+}
+
+// The ids of the messages sent on one connection. They have to increase, and the
+// ids of the server's answers are 1 mod 4, whatever the provider returns
+pub struct MessageIds {
+    provider: Box<dyn MessageIdProvider>,
+    last: i64,
+}
+
+impl MessageIds {
+    pub fn new(provider: impl MessageIdProvider + 'static) -> Self {
+        Self {
+            provider: Box::new(provider),
+            last: 0,
+        }
+    }
+
+    pub fn next_id(&mut self) -> i64 {
+        let id = self.provider.now().max(self.last + 1);
+        let id = id + (1 - id).rem_euclid(4);
+        self.last = id;
+        id
+    }
+}
+
+// Builds the MessageIds of every connection, see `Config::message_ids`
+pub type MessageIdsFactory = Box<dyn Fn() -> MessageIds + Send + Sync>;
+
+impl Default for MessageIds {
+    fn default() -> Self {
+        Self::new(Clock)
+    }
+}
+
+// Checks the unixtime in the upper 32 bits of a client's msg_id against `now`, in
+// seconds
+pub fn check_window(message_id: i64, now: i64) -> Result<()> {
+    let skew = (message_id >> 32) - now;
+    if !(-MAX_MSG_ID_AGE..=MAX_MSG_ID_LEAD).contains(&skew) {
+        return Err(ServerError::MessageIdOutOfWindow { message_id, skew });
+    }
+    Ok(())
+}
@@ -0,0 +1,844 @@
+use std::fmt;
+
+use grammers_mtproto::transport;
+use grammers_tl_types::{deserialize, enums, Cursor, Deserializable, Serializable};
+use log::error;
+use num_bigint::BigUint;
+use sha1::{Digest, Sha1};
+
+use crate::{
+    crypto,
+    dh::{self, DhParams},
+    error::{Result, ServerError},
+    keys, pq,
+    rng::ServerRng,
+};
+
+// Used instead of a random server_nonce when reproducible runs are needed
+pub const SERVER_NONCE: [u8; 16] = 0x1337u128.to_le_bytes();
+
+#[derive(Debug)]
+// Also the legacy req_pq of older clients, which takes the same arguments and gets
+// the same ResPq. `magic` tells them apart
+pub struct ReqPqMulti {
+    pub auth_key_id: i64,
+    pub message_id: i64,
+    pub message_length: u32,
+    pub magic: u32,
+    pub nonce: [u8; 16],
+}
+
+pub const REQ_PQ_MULTI: u32 = 0xbe7e8ef1;
+pub const REQ_PQ: u32 = 0x60469778;
+
+impl ReqPqMulti {
+    pub fn parse(cur: &mut Cursor) -> Result<Self> {
+        let req_pq_multi = ReqPqMulti {
+            auth_key_id: i64::deserialize(cur)?,
+            message_id: i64::deserialize(cur)?,
+            message_length: u32::deserialize(cur)?,
+            magic: u32::deserialize(cur)?,
+            nonce: read_fixed(cur, "nonce")?,
+        };
+        if req_pq_multi.auth_key_id != 0 {
+            return Err(ServerError::UnexpectedAuthKeyId(req_pq_multi.auth_key_id));
+        }
+        if req_pq_multi.magic != REQ_PQ_MULTI && req_pq_multi.magic != REQ_PQ {
+            return Err(ServerError::MagicMismatch {
+                expected: "req_pq_multi or req_pq",
+                got: req_pq_multi.magic,
+            });
+        }
+        Ok(req_pq_multi)
+    }
+
+    pub fn is_legacy(&self) -> bool {
+        self.magic == REQ_PQ
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self.is_legacy() {
+            true => "req_pq",
+            false => "req_pq_multi",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ResPq {
+    pub auth_key_id: i64,
+    pub message_id: i64,
+    pub magic: u32,
+    pub nonce: [u8; 16],
+    pub server_nonce: [u8; 16],
+    pub pq: Vec<u8>,
+    pub server_public_key_fingerprints: Vec<i64>,
+}
+
+impl ResPq {
+    pub fn generate(
+        nonce: [u8; 16],
+        server_nonce: [u8; 16],
+        message_id: i64,
+        pq: Vec<u8>,
+        server_public_key_fingerprints: Vec<i64>,
+    ) -> Result<Self> {
+        if let Err(e) = check_pq(&pq) {
+            error!("rejected pq: {:02x?}", pq);
+            return Err(e);
+        }
+        Ok(Self {
+            auth_key_id: 0,
+            message_id,
+            magic: 0x05162463,
+            nonce,
+            server_nonce,
+            pq,
+            server_public_key_fingerprints,
+        })
+    }
+
+    pub fn ser(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        self.magic.serialize(&mut body);
+        self.nonce.serialize(&mut body);
+        self.server_nonce.serialize(&mut body);
+        self.pq.serialize(&mut body);
+        self.server_public_key_fingerprints.serialize(&mut body);
+        ser_message(self.auth_key_id, self.message_id, &body)
+    }
+}
+
+#[derive(Debug)]
+pub struct ReqDHParams {
+    pub auth_key_id: i64,
+    pub message_id: i64,
+    pub message_length: u32,
+    pub magic: u32,
+    pub nonce: [u8; 16],
+    pub server_nonce: [u8; 16],
+    pub p: Vec<u8>,
+    pub q: Vec<u8>,
+    pub public_key_fingerprint: i64,
+    pub encrypted_data: Vec<u8>,
+}
+
+pub const REQ_DH_PARAMS: u32 = 0xd712e4be;
+
+impl ReqDHParams {
+    pub fn parse(cur: &mut Cursor) -> Result<Self> {
+        let req_dh_params = ReqDHParams {
+            auth_key_id: i64::deserialize(cur)?,
+            message_id: i64::deserialize(cur)?,
+            message_length: u32::deserialize(cur)?,
+            magic: u32::deserialize(cur)?,
+            nonce: read_fixed(cur, "nonce")?,
+            server_nonce: read_fixed(cur, "server_nonce")?,
+            p: read_bytes(cur)?,
+            q: read_bytes(cur)?,
+            public_key_fingerprint: i64::deserialize(cur)?,
+            encrypted_data: read_bytes(cur)?,
+        };
+        if req_dh_params.auth_key_id != 0 {
+            return Err(ServerError::UnexpectedAuthKeyId(req_dh_params.auth_key_id));
+        }
+        if req_dh_params.magic != REQ_DH_PARAMS {
+            return Err(ServerError::MagicMismatch {
+                expected: "req_DH_params",
+                got: req_dh_params.magic,
+            });
+        }
+        Ok(req_dh_params)
+    }
+}
+
+const PQ_INNER_DATA: u32 = 0x83c95aec;
+const PQ_INNER_DATA_DC: u32 = 0xa9f55f95;
+const PQ_INNER_DATA_TEMP: u32 = 0x3c6a84d4;
+const PQ_INNER_DATA_TEMP_DC: u32 = 0x56fddf88;
+
+// All four variants, `dc` is set by the _dc ones and `expires_in` by the _temp ones
+#[derive(Debug)]
+pub struct PQInnerData {
+    pub magic: u32,
+    pub pq: Vec<u8>,
+    pub p: Vec<u8>,
+    pub q: Vec<u8>,
+    pub nonce: [u8; 16],
+    pub server_nonce: [u8; 16],
+    pub new_nonce: [u8; 32],
+    pub dc: Option<i32>,
+    pub expires_in: Option<i32>,
+}
+
+impl PQInnerData {
+    pub fn parse(cur: &mut Cursor) -> Result<Self> {
+        let magic = u32::deserialize(cur)?;
+        let (has_dc, temp) = match magic {
+            PQ_INNER_DATA => (false, false),
+            PQ_INNER_DATA_DC => (true, false),
+            PQ_INNER_DATA_TEMP => (false, true),
+            PQ_INNER_DATA_TEMP_DC => (true, true),
+            got => {
+                return Err(ServerError::MagicMismatch {
+                    expected: "p_q_inner_data",
+                    got,
+                })
+            }
+        };
+        Ok(PQInnerData {
+            magic,
+            pq: read_bytes(cur)?,
+            p: read_bytes(cur)?,
+            q: read_bytes(cur)?,
+            nonce: read_fixed(cur, "nonce")?,
+            server_nonce: read_fixed(cur, "server_nonce")?,
+            new_nonce: read_fixed(cur, "new_nonce")?,
+            dc: has_dc.then(|| i32::deserialize(cur)).transpose()?,
+            expires_in: temp.then(|| i32::deserialize(cur)).transpose()?,
+        })
+    }
+
+    pub fn is_temp(&self) -> bool {
+        self.expires_in.is_some()
+    }
+}
+
+#[derive(Debug)]
+pub struct ServerDHParams {
+    pub auth_key_id: i64,
+    pub message_id: i64,
+    pub magic: u32,
+    pub nonce: [u8; 16],
+    pub server_nonce: [u8; 16],
+    pub encrypted_answer: Vec<u8>,
+}
+
+impl ServerDHParams {
+    pub fn generate(
+        nonce: [u8; 16],
+        server_nonce: [u8; 16],
+        message_id: i64,
+        encrypted_answer: Vec<u8>,
+    ) -> Self {
+        Self {
+            auth_key_id: 0,
+            message_id,
+            magic: 0xd0e8075c,
+            nonce,
+            server_nonce,
+            encrypted_answer,
+        }
+    }
+
+    pub fn ser(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        self.magic.serialize(&mut body);
+        self.nonce.serialize(&mut body);
+        self.server_nonce.serialize(&mut body);
+        self.encrypted_answer.serialize(&mut body);
+        ser_message(self.auth_key_id, self.message_id, &body)
+    }
+}
+
+// Answers a req_DH_params with an invalid p_q_inner_data
+#[derive(Debug)]
+pub struct ServerDHParamsFail {
+    pub auth_key_id: i64,
+    pub message_id: i64,
+    pub magic: u32,
+    pub nonce: [u8; 16],
+    pub server_nonce: [u8; 16],
+    pub new_nonce_hash: [u8; 16],
+}
+
+impl ServerDHParamsFail {
+    // new_nonce_hash = substr(SHA1(new_nonce), 4, 16)
+    pub fn generate(
+        nonce: [u8; 16],
+        server_nonce: [u8; 16],
+        message_id: i64,
+        new_nonce: &[u8; 32],
+    ) -> Self {
+        Self {
+            auth_key_id: 0,
+            message_id,
+            magic: 0x79cb045d,
+            nonce,
+            server_nonce,
+            new_nonce_hash: Sha1::digest(new_nonce)[4..].try_into().unwrap(),
+        }
+    }
+
+    pub fn ser(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        self.magic.serialize(&mut body);
+        self.nonce.serialize(&mut body);
+        self.server_nonce.serialize(&mut body);
+        self.new_nonce_hash.serialize(&mut body);
+        ser_message(self.auth_key_id, self.message_id, &body)
+    }
+}
+
+#[derive(Debug)]
+pub struct ServerDHInnerData {
+    pub magic: u32,
+    pub nonce: [u8; 16],
+    pub server_nonce: [u8; 16],
+    pub g: i32,
+    pub dh_prime: Vec<u8>,
+    pub g_a: Vec<u8>,
+    pub server_time: i32,
+}
+
+impl ServerDHInnerData {
+    pub fn generate(
+        nonce: [u8; 16],
+        server_nonce: [u8; 16],
+        dh_params: &DhParams,
+        a: &BigUint,
+        server_time: i32,
+    ) -> Self {
+        Self {
+            magic: 0xb5890dba,
+            nonce,
+            server_nonce,
+            g: dh_params.g as i32,
+            dh_prime: dh_params.prime.to_bytes_be(),
+            g_a: dh_params.g_a(a).to_bytes_be(),
+            server_time,
+        }
+    }
+
+    pub fn ser(&self) -> Vec<u8> {
+        let mut res = Vec::new();
+        self.magic.serialize(&mut res);
+        self.nonce.serialize(&mut res);
+        self.server_nonce.serialize(&mut res);
+        self.g.serialize(&mut res);
+        self.dh_prime.serialize(&mut res);
+        self.g_a.serialize(&mut res);
+        self.server_time.serialize(&mut res);
+        res
+    }
+}
+
+#[derive(Debug)]
+pub struct SetClientDHParams {
+    pub auth_key_id: i64,
+    pub message_id: i64,
+    pub message_length: u32,
+    pub magic: u32,
+    pub nonce: [u8; 16],
+    pub server_nonce: [u8; 16],
+    pub encrypted_data: Vec<u8>,
+}
+
+pub const SET_CLIENT_DH_PARAMS: u32 = 0xf5045f1f;
+
+impl SetClientDHParams {
+    pub fn parse(cur: &mut Cursor) -> Result<Self> {
+        let set_client_dh_params = SetClientDHParams {
+            auth_key_id: i64::deserialize(cur)?,
+            message_id: i64::deserialize(cur)?,
+            message_length: u32::deserialize(cur)?,
+            magic: u32::deserialize(cur)?,
+            nonce: read_fixed(cur, "nonce")?,
+            server_nonce: read_fixed(cur, "server_nonce")?,
+            encrypted_data: read_bytes(cur)?,
+        };
+        if set_client_dh_params.auth_key_id != 0 {
+            return Err(ServerError::UnexpectedAuthKeyId(
+                set_client_dh_params.auth_key_id,
+            ));
+        }
+        if set_client_dh_params.magic != SET_CLIENT_DH_PARAMS {
+            return Err(ServerError::MagicMismatch {
+                expected: "set_client_DH_params",
+                got: set_client_dh_params.magic,
+            });
+        }
+        Ok(set_client_dh_params)
+    }
+}
+
+#[derive(Debug)]
+pub struct ClientDHInnerData {
+    pub magic: u32,
+    pub nonce: [u8; 16],
+    pub server_nonce: [u8; 16],
+    pub retry_id: i64,
+    pub g_b: Vec<u8>,
+}
+
+pub const CLIENT_DH_INNER_DATA: u32 = 0x6643b654;
+
+impl ClientDHInnerData {
+    pub fn parse(cur: &mut Cursor) -> Result<Self> {
+        let client_dh_inner_data = ClientDHInnerData {
+            magic: u32::deserialize(cur)?,
+            nonce: read_fixed(cur, "nonce")?,
+            server_nonce: read_fixed(cur, "server_nonce")?,
+            retry_id: i64::deserialize(cur)?,
+            g_b: read_bytes(cur)?,
+        };
+        if client_dh_inner_data.magic != CLIENT_DH_INNER_DATA {
+            return Err(ServerError::MagicMismatch {
+                expected: "client_DH_inner_data",
+                got: client_dh_inner_data.magic,
+            });
+        }
+        Ok(client_dh_inner_data)
+    }
+}
+
+#[derive(Debug)]
+pub struct DhGenOk {
+    pub auth_key_id: i64,
+    pub message_id: i64,
+    pub magic: u32,
+    pub nonce: [u8; 16],
+    pub server_nonce: [u8; 16],
+    pub new_nonce_hash1: [u8; 16],
+}
+
+impl DhGenOk {
+    pub fn generate(
+        nonce: [u8; 16],
+        server_nonce: [u8; 16],
+        message_id: i64,
+        new_nonce_hash1: [u8; 16],
+    ) -> Self {
+        Self {
+            auth_key_id: 0,
+            message_id,
+            magic: 0x3bcbf734,
+            nonce,
+            server_nonce,
+            new_nonce_hash1,
+        }
+    }
+
+    pub fn ser(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        self.magic.serialize(&mut body);
+        self.nonce.serialize(&mut body);
+        self.server_nonce.serialize(&mut body);
+        self.new_nonce_hash1.serialize(&mut body);
+        ser_message(self.auth_key_id, self.message_id, &body)
+    }
+}
+
+pub fn encrypt_answer(
+    answer: &[u8],
+    key: &[u8; 32],
+    iv: &[u8; 32],
+    rng: &ServerRng,
+) -> Result<Vec<u8>> {
+    let mut answer_with_hash = Sha1::digest(answer).to_vec();
+    answer_with_hash.extend(answer);
+    let mut padding = vec![0; (16 - answer_with_hash.len() % 16) % 16];
+    rng.fill_bytes(&mut padding);
+    answer_with_hash.extend(padding);
+    crypto::ige_encrypt(&answer_with_hash, key, iv)
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PqInnerDataError {
+    BadHash,
+    NonceMismatch,
+    PqMismatch,
+}
+
+impl fmt::Display for PqInnerDataError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PqInnerDataError::BadHash => write!(f, "p_q_inner_data hash mismatch"),
+            PqInnerDataError::NonceMismatch => write!(f, "p_q_inner_data nonce mismatch"),
+            PqInnerDataError::PqMismatch => write!(f, "p_q_inner_data pq mismatch"),
+        }
+    }
+}
+
+impl std::error::Error for PqInnerDataError {}
+
+// `data_with_hash` is the RSA decrypted encrypted_data: SHA1(data) + data + padding
+pub fn validate_pq_inner(
+    data_with_hash: &[u8],
+    nonce: &[u8; 16],
+    server_nonce: &[u8; 16],
+    p: u32,
+    q: u32,
+) -> Result<PQInnerData> {
+    if data_with_hash.len() < 20 {
+        return Err(PqInnerDataError::BadHash.into());
+    }
+    let mut cur = Cursor::from_slice(&data_with_hash[20..]);
+    let pq_inner_data = PQInnerData::parse(&mut cur).map_err(|_| PqInnerDataError::BadHash)?;
+    if Sha1::digest(&data_with_hash[20..20 + cur.pos()])[..] != data_with_hash[..20] {
+        return Err(PqInnerDataError::BadHash.into());
+    }
+
+    if pq_inner_data.nonce != *nonce || pq_inner_data.server_nonce != *server_nonce {
+        return Err(PqInnerDataError::NonceMismatch.into());
+    }
+    if pq::from_be_bytes(&pq_inner_data.pq) != Some(p as u64 * q as u64)
+        || pq::from_be_bytes(&pq_inner_data.p) != Some(p.into())
+        || pq::from_be_bytes(&pq_inner_data.q) != Some(q.into())
+    {
+        return Err(PqInnerDataError::PqMismatch.into());
+    }
+    Ok(pq_inner_data)
+}
+
+// The new_nonce of a p_q_inner_data which failed the validation, zeroed if it
+// can't even be parsed
+pub fn unvalidated_new_nonce(data_with_hash: &[u8]) -> [u8; 32] {
+    data_with_hash
+        .get(20..)
+        .and_then(|data| PQInnerData::parse(&mut Cursor::from_slice(data)).ok())
+        .map_or([0; 32], |pq_inner_data| pq_inner_data.new_nonce)
+}
+
+pub fn decrypt_client_dh_inner_data(
+    encrypted_data: &[u8],
+    key: &[u8; 32],
+    iv: &[u8; 32],
+) -> Result<ClientDHInnerData> {
+    let data_with_hash = crypto::ige_decrypt(encrypted_data, key, iv)?;
+    if data_with_hash.len() < 20 {
+        return Err(dh::DhGenError::Fail.into());
+    }
+    let mut cur = Cursor::from_slice(&data_with_hash[20..]);
+    let client_dh_inner_data =
+        ClientDHInnerData::parse(&mut cur).map_err(|_| dh::DhGenError::Fail)?;
+    if Sha1::digest(&data_with_hash[20..20 + cur.pos()])[..] != data_with_hash[..20] {
+        return Err(dh::DhGenError::Fail.into());
+    }
+    Ok(client_dh_inner_data)
+}
+
+// The plaintext of an encrypted message, without the padding
+#[derive(Debug)]
+pub struct Message {
+    pub salt: i64,
+    pub session_id: i64,
+    pub message_id: i64,
+    pub seq_no: i32,
+    pub body: Vec<u8>,
+}
+
+// msg_container#73f1f8dc messages:vector<message> = MessageContainer, where
+// message msg_id:long seqno:int bytes:int body:Object = Message and the vector is bare
+pub const MSG_CONTAINER: u32 = 0x73f1f8dc;
+
+impl Message {
+    // The constructor of the method or the container in the body
+    pub fn constructor(&self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.body.get(..4)?.try_into().unwrap()))
+    }
+
+    // The messages of a msg_container, which share its salt and session_id. There may
+    // be at most `max_vector_len` of them
+    pub fn unpack_container(&self, max_vector_len: usize) -> Result<Vec<Message>> {
+        let mut cur = Cursor::from_slice(&self.body);
+        let magic = u32::deserialize(&mut cur)?;
+        if magic != MSG_CONTAINER {
+            return Err(ServerError::MagicMismatch {
+                expected: "msg_container",
+                got: magic,
+            });
+        }
+        let count = read_vector_len(&mut cur, max_vector_len)?;
+        let mut messages = Vec::new();
+        for _ in 0..count {
+            let message_id = i64::deserialize(&mut cur)?;
+            let seq_no = i32::deserialize(&mut cur)?;
+            let len = u32::deserialize(&mut cur)? as usize;
+            // Checked before allocating, like `read_bytes`
+            if len > self.body.len() - cur.pos() {
+                return Err(ServerError::InvalidEncryptedMessage(
+                    "container longer than the message",
+                ));
+            }
+            let mut body = vec![0; len];
+            cur.read_exact(&mut body)?;
+            messages.push(Message {
+                salt: self.salt,
+                session_id: self.session_id,
+                message_id,
+                seq_no,
+                body,
+            });
+        }
+        Ok(messages)
+    }
+
+    // A msg_container of `messages`, its own msg_id must be greater than theirs
+    pub fn pack_container(message_id: i64, seq_no: i32, messages: &[Message]) -> Message {
+        let mut body = MSG_CONTAINER.to_le_bytes().to_vec();
+        (messages.len() as u32).serialize(&mut body);
+        for message in messages {
+            message.message_id.serialize(&mut body);
+            message.seq_no.serialize(&mut body);
+            (message.body.len() as u32).serialize(&mut body);
+            body.extend(&message.body);
+        }
+        let first = &messages[0];
+        Message {
+            salt: first.salt,
+            session_id: first.session_id,
+            message_id,
+            seq_no,
+            body,
+        }
+    }
+}
+
+// invokeWithLayer#da9b0d0d {X:Type} layer:int query:!X = X
+pub const INVOKE_WITH_LAYER: u32 = 0xda9b0d0d;
+// initConnection#c1cd5ea9 {X:Type} flags:# api_id:int device_model:string
+// system_version:string app_version:string system_lang_code:string lang_pack:string
+// lang_code:string proxy:flags.0?InputClientProxy params:flags.1?JSONValue query:!X = X
+pub const INIT_CONNECTION: u32 = 0xc1cd5ea9;
+
+// The wrapper of the first request of a client, usually around initConnection
+#[derive(Debug)]
+pub struct InvokeWithLayer {
+    pub layer: i32,
+    pub init_connection: Option<InitConnection>,
+    // The wrapped request, with its constructor
+    pub query: Vec<u8>,
+}
+
+impl InvokeWithLayer {
+    pub fn parse(cur: &mut Cursor) -> Result<Self> {
+        let magic = u32::deserialize(cur)?;
+        if magic != INVOKE_WITH_LAYER {
+            return Err(ServerError::MagicMismatch {
+                expected: "invokeWithLayer",
+                got: magic,
+            });
+        }
+        let layer = i32::deserialize(cur)?;
+        let mut query = Vec::new();
+        cur.read_to_end(&mut query)?;
+        let init_connection = match query.get(..4) {
+            Some(magic) if magic == INIT_CONNECTION.to_le_bytes() => {
+                let mut cur = Cursor::from_slice(&query);
+                let init_connection = InitConnection::parse(&mut cur)?;
+                query.drain(..cur.pos());
+                Some(init_connection)
+            }
+            _ => None,
+        };
+        Ok(Self {
+            layer,
+            init_connection,
+            query,
+        })
+    }
+}
+
+// The app of the client, the proxy and params are skipped
+#[derive(Debug)]
+pub struct InitConnection {
+    pub api_id: i32,
+    pub device_model: String,
+    pub system_version: String,
+    pub app_version: String,
+    pub system_lang_code: String,
+    pub lang_pack: String,
+    pub lang_code: String,
+}
+
+impl InitConnection {
+    // Leaves `cur` at the wrapped request
+    pub fn parse(cur: &mut Cursor) -> Result<Self> {
+        let magic = u32::deserialize(cur)?;
+        if magic != INIT_CONNECTION {
+            return Err(ServerError::MagicMismatch {
+                expected: "initConnection",
+                got: magic,
+            });
+        }
+        let flags = u32::deserialize(cur)?;
+        let init_connection = InitConnection {
+            api_id: i32::deserialize(cur)?,
+            device_model: read_string(cur)?,
+            system_version: read_string(cur)?,
+            app_version: read_string(cur)?,
+            system_lang_code: read_string(cur)?,
+            lang_pack: read_string(cur)?,
+            lang_code: read_string(cur)?,
+        };
+        if flags & 1 != 0 {
+            enums::InputClientProxy::deserialize(cur)?;
+        }
+        if flags & 2 != 0 {
+            enums::Jsonvalue::deserialize(cur)?;
+        }
+        Ok(init_connection)
+    }
+}
+
+// rpc_error#2144ca19 error_code:int error_message:string = RpcError
+#[derive(Debug)]
+pub struct RpcError {
+    pub error_code: i32,
+    pub error_message: String,
+}
+
+impl RpcError {
+    // rpc_result#f35c6d01 req_msg_id:long result:Object = RpcResult
+    pub fn ser(&self, req_msg_id: i64) -> Vec<u8> {
+        let mut body = 0xf35c6d01u32.to_le_bytes().to_vec();
+        req_msg_id.serialize(&mut body);
+        0x2144ca19u32.serialize(&mut body);
+        self.error_code.serialize(&mut body);
+        self.error_message.serialize(&mut body);
+        body
+    }
+}
+
+// `packet` is auth_key_id + msg_key + the AES-IGE encrypted message of a client
+pub fn decrypt_message(packet: &[u8], auth_key: &[u8; 256]) -> Result<Message> {
+    if packet.len() < 24 {
+        return Err(ServerError::InvalidEncryptedMessage(
+            "shorter than its header",
+        ));
+    }
+    let auth_key_id = i64::from_le_bytes(packet[..8].try_into().unwrap());
+    if auth_key_id != keys::auth_key_id(auth_key) {
+        return Err(ServerError::UnknownAuthKey(auth_key_id));
+    }
+    let msg_key: [u8; 16] = packet[8..24].try_into().unwrap();
+    let (key, iv) = keys::derive_message_aes(auth_key, &msg_key, 0);
+    let plaintext = crypto::ige_decrypt(&packet[24..], &key, &iv)?;
+    if keys::msg_key(auth_key, &plaintext, 0) != msg_key {
+        return Err(ServerError::InvalidEncryptedMessage("msg_key mismatch"));
+    }
+
+    let mut cur = Cursor::from_slice(&plaintext);
+    let salt = i64::deserialize(&mut cur)?;
+    let session_id = i64::deserialize(&mut cur)?;
+    let message_id = i64::deserialize(&mut cur)?;
+    let seq_no = i32::deserialize(&mut cur)?;
+    let len = u32::deserialize(&mut cur)? as usize;
+    // MTProto 2.0 pads with 12 to 1024 bytes
+    let padding = (plaintext.len() - cur.pos()).checked_sub(len).ok_or(
+        ServerError::InvalidEncryptedMessage("longer than the packet"),
+    )?;
+    if !(12..=1024).contains(&padding) || !len.is_multiple_of(4) {
+        return Err(ServerError::InvalidEncryptedMessage("invalid length"));
+    }
+    Ok(Message {
+        salt,
+        session_id,
+        message_id,
+        seq_no,
+        body: plaintext[cur.pos()..cur.pos() + len].to_vec(),
+    })
+}
+
+// Encrypts an answer of the server for the client of `auth_key`
+pub fn encrypt_message(
+    message: &Message,
+    auth_key: &[u8; 256],
+    rng: &ServerRng,
+) -> Result<Vec<u8>> {
+    let mut plaintext = Vec::new();
+    message.salt.serialize(&mut plaintext);
+    message.session_id.serialize(&mut plaintext);
+    message.message_id.serialize(&mut plaintext);
+    message.seq_no.serialize(&mut plaintext);
+    (message.body.len() as u32).serialize(&mut plaintext);
+    plaintext.extend(&message.body);
+    // The shortest padding of at least 12 bytes
+    let mut padding = vec![0; 12 + (16 - (plaintext.len() + 12) % 16) % 16];
+    rng.fill_bytes(&mut padding);
+    plaintext.extend(padding);
+
+    let msg_key = keys::msg_key(auth_key, &plaintext, 8);
+    let (key, iv) = keys::derive_message_aes(auth_key, &msg_key, 8);
+    let mut packet = keys::auth_key_id(auth_key).to_le_bytes().to_vec();
+    packet.extend(msg_key);
+    packet.extend(crypto::ige_encrypt(&plaintext, &key, &iv)?);
+    Ok(packet)
+}
+
+// `Vec::<u8>::deserialize` allocates the length from the prefix before checking it
+// against the input, so a short packet claiming a 16 MB string is read in chunks
+// and fails at its end instead
+fn read_bytes(cur: &mut Cursor) -> Result<Vec<u8>, deserialize::Error> {
+    let first_byte = cur.read_byte()?;
+    let (len, prefix_len) = if first_byte == 254 {
+        let mut len = [0; 4];
+        cur.read_exact(&mut len[..3])?;
+        (u32::from_le_bytes(len) as usize, 4)
+    } else {
+        (first_byte as usize, 1)
+    };
+
+    let mut res = Vec::new();
+    let mut chunk = [0; 256];
+    while res.len() < len {
+        let chunk_len = chunk.len().min(len - res.len());
+        cur.read_exact(&mut chunk[..chunk_len])?;
+        res.extend_from_slice(&chunk[..chunk_len]);
+    }
+    // The prefix and the string are padded to 4 bytes
+    for _ in 0..(4 - (prefix_len + len) % 4) % 4 {
+        cur.read_byte()?;
+    }
+    Ok(res)
+}
+
+// The count of a vector of the client, checked before anything is allocated for it.
+// A count past `max` is taken for a broken frame
+pub fn read_vector_len(cur: &mut Cursor, max: usize) -> Result<usize> {
+    let len = u32::deserialize(cur)?;
+    if len as usize > max {
+        return Err(transport::Error::BadLen { got: len }.into());
+    }
+    Ok(len as usize)
+}
+
+// Strings of the client are only logged, so invalid UTF-8 is replaced
+fn read_string(cur: &mut Cursor) -> Result<String> {
+    Ok(String::from_utf8_lossy(&read_bytes(cur)?).into_owned())
+}
+
+// Nonces are read with this instead of `<[u8; N]>::deserialize` to report which
+// field was cut short and how much of it the client actually sent
+fn read_fixed<const N: usize>(cur: &mut Cursor, field: &'static str) -> Result<[u8; N]> {
+    let mut res = [0; N];
+    if cur.read_exact(&mut res).is_err() {
+        let mut rest = Vec::new();
+        cur.read_to_end(&mut rest)?;
+        return Err(ServerError::ShortField {
+            field,
+            expected: N,
+            available: rest.len(),
+        });
+    }
+    Ok(res)
+}
+
+fn ser_message(auth_key_id: i64, message_id: i64, body: &[u8]) -> Vec<u8> {
+    let mut res = Vec::new();
+    auth_key_id.serialize(&mut res);
+    message_id.serialize(&mut res);
+    (body.len() as u32).serialize(&mut res);
+    res.extend(body);
+    res
+}
+
+fn check_pq(pq: &[u8]) -> Result<()> {
+    let pq = u64::from_be_bytes(pq.try_into().map_err(|_| ServerError::InvalidPq {
+        pq: pq.to_vec(),
+        reason: "must be 8 bytes long".to_string(),
+    })?);
+    pq::factorize(pq)?;
+    Ok(())
+}
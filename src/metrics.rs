@@ -0,0 +1,168 @@
+// Prometheus metrics, every function is a no-op without the `metrics` feature
+use std::{fmt, time::Duration};
+
+use crate::stats::HandshakeLatency;
+#[cfg(feature = "metrics")]
+use crate::stats::PERCENTILES;
+
+#[cfg(feature = "metrics")]
+pub use server::serve;
+
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+pub fn record_connection(listener: impl fmt::Display) {
+    #[cfg(feature = "metrics")]
+    server::CONNECTIONS
+        .with_label_values(&[&listener.to_string()])
+        .inc();
+}
+
+pub fn record_rate_limited() {
+    #[cfg(feature = "metrics")]
+    server::RATE_LIMITED.inc();
+}
+
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+pub fn record_failure(stage: &str) {
+    #[cfg(feature = "metrics")]
+    server::HANDSHAKE_FAILURES.with_label_values(&[stage]).inc();
+}
+
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+pub fn record_disconnect(stage: &str) {
+    #[cfg(feature = "metrics")]
+    server::DISCONNECTS.with_label_values(&[stage]).inc();
+}
+
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+pub fn record_handshake(duration: Duration) {
+    #[cfg(feature = "metrics")]
+    server::HANDSHAKE_DURATION.observe(duration.as_secs_f64());
+}
+
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+pub fn record_handshake_latency(latency: &HandshakeLatency) {
+    #[cfg(feature = "metrics")]
+    if let Some(durations) = latency.percentiles(PERCENTILES) {
+        for (p, duration) in PERCENTILES.into_iter().zip(durations) {
+            server::HANDSHAKE_LATENCY
+                .with_label_values(&[&(p / 100.0).to_string()])
+                .set(duration.as_secs_f64());
+        }
+    }
+}
+
+#[cfg(feature = "metrics")]
+mod server {
+    use std::{convert::Infallible, io, net::TcpListener, sync::LazyLock};
+
+    use hyper::{
+        header::CONTENT_TYPE,
+        service::{make_service_fn, service_fn},
+        Body, Request, Response, Server, StatusCode,
+    };
+    use log::info;
+    use prometheus::{
+        register_gauge_vec, register_histogram, register_int_counter, register_int_counter_vec,
+        Encoder, GaugeVec, Histogram, IntCounter, IntCounterVec, TextEncoder,
+    };
+    use tokio::runtime::Builder;
+
+    use crate::{error::Result, health};
+
+    pub static CONNECTIONS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+        register_int_counter_vec!(
+            "tg_srv_connections_total",
+            "Accepted connections by the listener they were accepted on",
+            &["listener"]
+        )
+        .unwrap()
+    });
+    pub static RATE_LIMITED: LazyLock<IntCounter> = LazyLock::new(|| {
+        register_int_counter!(
+            "tg_srv_rate_limited_total",
+            "Connections dropped by the per IP rate limit"
+        )
+        .unwrap()
+    });
+    pub static HANDSHAKE_FAILURES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+        register_int_counter_vec!(
+            "tg_srv_handshake_failures_total",
+            "Failed handshakes by the stage they failed at",
+            &["stage"]
+        )
+        .unwrap()
+    });
+    pub static DISCONNECTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+        register_int_counter_vec!(
+            "tg_srv_disconnects_total",
+            "Handshakes the client walked away from by the stage it was at",
+            &["stage"]
+        )
+        .unwrap()
+    });
+    pub static HANDSHAKE_DURATION: LazyLock<Histogram> = LazyLock::new(|| {
+        register_histogram!(
+            "tg_srv_handshake_duration_seconds",
+            "Duration of successful handshakes"
+        )
+        .unwrap()
+    });
+    pub static HANDSHAKE_LATENCY: LazyLock<GaugeVec> = LazyLock::new(|| {
+        register_gauge_vec!(
+            "tg_srv_handshake_latency_seconds",
+            "Percentiles of the full handshake duration over the recent handshakes",
+            &["quantile"]
+        )
+        .unwrap()
+    });
+
+    // Serves the metrics over HTTP on its own runtime, blocks the calling thread
+    pub fn serve(listener: TcpListener) -> Result<()> {
+        // Register the metrics, so they are exported before anything happens
+        LazyLock::force(&CONNECTIONS);
+        LazyLock::force(&RATE_LIMITED);
+        LazyLock::force(&HANDSHAKE_FAILURES);
+        LazyLock::force(&DISCONNECTS);
+        LazyLock::force(&HANDSHAKE_DURATION);
+        LazyLock::force(&HANDSHAKE_LATENCY);
+
+        let addr = listener.local_addr()?;
+        listener.set_nonblocking(true)?;
+        let runtime = Builder::new_current_thread().enable_all().build()?;
+        runtime.block_on(async {
+            let make_service =
+                make_service_fn(|_| async { Ok::<_, Infallible>(service_fn(metrics)) });
+            let server = Server::from_tcp(listener)
+                .map_err(io::Error::other)?
+                .serve(make_service);
+            info!("serving metrics on http://{}", addr);
+            server.await.map_err(io::Error::other)?;
+            Ok(())
+        })
+    }
+
+    async fn metrics(request: Request<Body>) -> Result<Response<Body>, Infallible> {
+        // The health check is also served here, for deployments which only open one
+        // HTTP port
+        if request.uri().path() == "/healthz" {
+            let status = match health::is_healthy() {
+                true => StatusCode::OK,
+                false => StatusCode::SERVICE_UNAVAILABLE,
+            };
+            return Ok(Response::builder()
+                .status(status)
+                .body(Body::from(format!(
+                    "{}\n",
+                    status.canonical_reason().unwrap()
+                )))
+                .unwrap());
+        }
+        let encoder = TextEncoder::new();
+        let mut body = Vec::new();
+        encoder.encode(&prometheus::gather(), &mut body).unwrap();
+        Ok(Response::builder()
+            .header(CONTENT_TYPE, encoder.format_type())
+            .body(Body::from(body))
+            .unwrap())
+    }
+}
@@ -0,0 +1,35 @@
+use crate::error::{Result, ServerError};
+
+// The nonces of ResPq, which every later message of the handshake has to echo
+#[derive(Clone, Copy, Debug)]
+pub struct NonceContext {
+    pub nonce: [u8; 16],
+    pub server_nonce: [u8; 16],
+}
+
+impl NonceContext {
+    pub fn new(nonce: [u8; 16], server_nonce: [u8; 16]) -> Self {
+        Self {
+            nonce,
+            server_nonce,
+        }
+    }
+
+    pub fn verify(&self, nonce: &[u8; 16], server_nonce: &[u8; 16]) -> Result<()> {
+        if *nonce != self.nonce {
+            return Err(ServerError::NonceMismatch {
+                name: "nonce",
+                got: *nonce,
+                expected: self.nonce,
+            });
+        }
+        if *server_nonce != self.server_nonce {
+            return Err(ServerError::NonceMismatch {
+                name: "server_nonce",
+                got: *server_nonce,
+                expected: self.server_nonce,
+            });
+        }
+        Ok(())
+    }
+}
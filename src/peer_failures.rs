@@ -0,0 +1,74 @@
+use std::{collections::HashMap, fmt, net::IpAddr, sync::Mutex};
+
+// The peers with the fewest failures are forgotten once there are this many
+const MAX_PEERS: usize = 1024;
+// Peers listed by the summary, the ones with the most failures first
+const MAX_LISTED: usize = 20;
+
+// Failed connections per peer IP, to tell a single misbehaving client apart from
+// many. With a threshold, the peers which reach it are banned until the restart
+pub struct PeerFailures {
+    ban_threshold: Option<u32>,
+    counts: Mutex<HashMap<IpAddr, u32>>,
+}
+
+impl PeerFailures {
+    pub fn new(ban_threshold: Option<u32>) -> Self {
+        Self {
+            ban_threshold,
+            counts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Returns the failures of the peer so far, this one included
+    pub fn record(&self, ip: IpAddr) -> u32 {
+        let ip = ip.to_canonical();
+        let mut counts = self.counts.lock().unwrap();
+        if counts.len() >= MAX_PEERS && !counts.contains_key(&ip) {
+            let fewest = counts.iter().min_by_key(|(_, &count)| count);
+            if let Some((&fewest, _)) = fewest {
+                counts.remove(&fewest);
+            }
+        }
+        let count = counts.entry(ip).or_insert(0);
+        *count += 1;
+        *count
+    }
+
+    pub fn count(&self, ip: IpAddr) -> u32 {
+        let counts = self.counts.lock().unwrap();
+        counts.get(&ip.to_canonical()).copied().unwrap_or(0)
+    }
+
+    pub fn is_banned(&self, ip: IpAddr) -> bool {
+        self.ban_threshold
+            .is_some_and(|threshold| self.count(ip) >= threshold)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counts.lock().unwrap().is_empty()
+    }
+}
+
+impl fmt::Display for PeerFailures {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut counts: Vec<_> = self
+            .counts
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(&ip, &count)| (ip, count))
+            .collect();
+        counts.sort_by(|(a_ip, a), (b_ip, b)| b.cmp(a).then(a_ip.cmp(b_ip)));
+        for (i, (ip, count)) in counts.iter().take(MAX_LISTED).enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{} {}", ip, count)?;
+        }
+        if counts.len() > MAX_LISTED {
+            write!(f, " and {} more", counts.len() - MAX_LISTED)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,74 @@
+// What the server answers at the stages of the handshake, for embedders which want
+// other pq values, fingerprints or deliberately malformed answers. The server
+// keeps checking the client against what the policy answered
+use num_bigint::BigUint;
+
+use crate::{
+    config::Config,
+    error::Result,
+    messages::{ReqDHParams, ReqPqMulti, ResPq, ServerDHInnerData},
+    pq,
+};
+
+pub trait HandshakePolicy: Send + Sync {
+    // The p and q the client sends back are checked against the factors of the pq,
+    // and the nonces it echoes against the nonces of the answer
+    fn on_req_pq_multi(
+        &self,
+        config: &Config,
+        req: &ReqPqMulti,
+        server_nonce: [u8; 16],
+        message_id: i64,
+    ) -> Result<ResPq>;
+
+    // The data encrypted into server_DH_params_ok. `a` is the secret of the server,
+    // the auth key is computed from it and the dh_prime of the config. `message_id`
+    // is the one of server_DH_params_ok, the server_time of a server matches it
+    fn on_req_dh_params(
+        &self,
+        config: &Config,
+        req: &ReqDHParams,
+        a: &BigUint,
+        message_id: i64,
+    ) -> Result<ServerDHInnerData>;
+}
+
+// The answers of a Telegram server
+pub struct DefaultPolicy;
+
+impl HandshakePolicy for DefaultPolicy {
+    fn on_req_pq_multi(
+        &self,
+        config: &Config,
+        req: &ReqPqMulti,
+        server_nonce: [u8; 16],
+        message_id: i64,
+    ) -> Result<ResPq> {
+        let pq = config
+            .rng
+            .with(|rng| pq::generate_pq_with(config.pq_bits, rng))?;
+        ResPq::generate(
+            req.nonce,
+            server_nonce,
+            message_id,
+            pq.to_be_bytes().to_vec(),
+            config.fingerprints(),
+        )
+    }
+
+    fn on_req_dh_params(
+        &self,
+        config: &Config,
+        req: &ReqDHParams,
+        a: &BigUint,
+        message_id: i64,
+    ) -> Result<ServerDHInnerData> {
+        Ok(ServerDHInnerData::generate(
+            req.nonce,
+            req.server_nonce,
+            &config.dh,
+            a,
+            (message_id >> 32) as i32,
+        ))
+    }
+}
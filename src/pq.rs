@@ -0,0 +1,174 @@
+use crate::error::{Result, ServerError};
+use rand::Rng;
+
+const WITNESSES: [u64; 12] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37];
+
+// MTProto needs pq to fit in 63 bits, while the clients factorize anything
+// shorter than 16 bits before they even get to the DH parameters
+pub const MIN_PQ_BITS: u32 = 16;
+pub const MAX_PQ_BITS: u32 = 63;
+pub const DEFAULT_PQ_BITS: u32 = 62;
+
+// A product of two distinct primes of about half of `bits` each, `bits` long
+pub fn generate_pq(bits: u32) -> Result<u64> {
+    generate_pq_with(bits, &mut rand::thread_rng())
+}
+
+pub fn generate_pq_with<R: Rng + ?Sized>(bits: u32, rng: &mut R) -> Result<u64> {
+    if !(MIN_PQ_BITS..=MAX_PQ_BITS).contains(&bits) {
+        return Err(ServerError::InvalidPqBits(bits));
+    }
+    let mut prime = |bits: u32| loop {
+        let candidate = rng.gen_range(1u64 << (bits - 1)..1u64 << bits) | 1;
+        if is_prime(candidate) {
+            return candidate;
+        }
+    };
+    let p_bits = bits / 2;
+    loop {
+        let p = prime(p_bits);
+        let q = prime(bits - p_bits);
+        // The product of a and b bits long numbers is a + b or a + b - 1 bits long
+        let pq = p * q;
+        if p != q && pq >> (bits - 1) == 1 {
+            return Ok(pq);
+        }
+    }
+}
+
+// The big endian integers of MTProto may come with leading zeroes or without them
+pub fn from_be_bytes(bytes: &[u8]) -> Option<u64> {
+    let start = bytes
+        .iter()
+        .position(|&byte| byte != 0)
+        .unwrap_or(bytes.len());
+    let bytes = &bytes[start..];
+    if bytes.len() > 8 {
+        return None;
+    }
+    let mut padded = [0; 8];
+    padded[8 - bytes.len()..].copy_from_slice(bytes);
+    Some(u64::from_be_bytes(padded))
+}
+
+pub fn factorize(pq: u64) -> Result<(u32, u32)> {
+    if pq < 4 {
+        return Err(invalid_pq(pq, "no nontrivial factors"));
+    }
+    if is_prime(pq) {
+        return Err(invalid_pq(pq, "prime"));
+    }
+
+    let p = if pq.is_multiple_of(2) { 2 } else { brent(pq) };
+    let (p, q) = (p.min(pq / p), p.max(pq / p));
+    if !is_prime(p) || !is_prime(q) {
+        return Err(invalid_pq(pq, "not a product of two primes"));
+    }
+    Ok((
+        p.try_into()
+            .map_err(|_| invalid_pq(pq, "p doesn't fit in 32 bits"))?,
+        q.try_into()
+            .map_err(|_| invalid_pq(pq, "q doesn't fit in 32 bits"))?,
+    ))
+}
+
+fn invalid_pq(pq: u64, reason: &str) -> ServerError {
+    ServerError::InvalidPq {
+        pq: pq.to_be_bytes().to_vec(),
+        reason: reason.to_string(),
+    }
+}
+
+// Deterministic Miller-Rabin, the witnesses are enough for any u64
+fn is_prime(n: u64) -> bool {
+    if n < 2 {
+        return false;
+    }
+    for p in WITNESSES {
+        if n.is_multiple_of(p) {
+            return n == p;
+        }
+    }
+
+    let s = (n - 1).trailing_zeros();
+    let d = (n - 1) >> s;
+    'witness: for a in WITNESSES {
+        let mut x = pow_mod(a, d, n);
+        if x == 1 || x == n - 1 {
+            continue;
+        }
+        for _ in 1..s {
+            x = mul_mod(x, x, n);
+            if x == n - 1 {
+                continue 'witness;
+            }
+        }
+        return false;
+    }
+    true
+}
+
+// Pollard's rho with Brent's cycle detection, `n` must be odd and composite
+fn brent(n: u64) -> u64 {
+    const M: u64 = 128;
+
+    for c in 1.. {
+        let f = |v: u64| ((mul_mod(v, v, n) as u128 + c as u128) % n as u128) as u64;
+        let (mut x, mut y, mut ys) = (0, 2, 0);
+        let (mut g, mut r, mut q) = (1, 1, 1);
+        while g == 1 {
+            x = y;
+            for _ in 0..r {
+                y = f(y);
+            }
+            let mut k = 0;
+            while k < r && g == 1 {
+                ys = y;
+                for _ in 0..M.min(r - k) {
+                    y = f(y);
+                    q = mul_mod(q, x.abs_diff(y), n);
+                }
+                g = gcd(q, n);
+                k += M;
+            }
+            r *= 2;
+        }
+        if g == n {
+            loop {
+                ys = f(ys);
+                g = gcd(x.abs_diff(ys), n);
+                if g > 1 {
+                    break;
+                }
+            }
+        }
+        if g != n {
+            return g;
+        }
+    }
+    unreachable!()
+}
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+fn mul_mod(a: u64, b: u64, m: u64) -> u64 {
+    (a as u128 * b as u128 % m as u128) as u64
+}
+
+fn pow_mod(mut base: u64, mut exp: u64, m: u64) -> u64 {
+    let mut res = 1;
+    base %= m;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            res = mul_mod(res, base, m);
+        }
+        base = mul_mod(base, base, m);
+        exp >>= 1;
+    }
+    res
+}
@@ -0,0 +1,132 @@
+// Machine readable records of the handshakes, one JSON object per line and per
+// connection, for external tools. Written by hand like the script is parsed:
+//
+//     {"connection":1,"peer":"127.0.0.1:50000","req_pq":{"name":"req_pq_multi",
+//     "message_id":...,"nonce":"..."},"res_pq":{...},"stage":"DhGenOk",
+//     "duration_ms":12.5,"outcome":"done","auth_key_id":"...","error":null}
+//
+// The fields of a stage the connection didn't get to are null
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    fs::OpenOptions,
+    io::{self, Write},
+    path::Path,
+    sync::Mutex,
+    time::Duration,
+};
+
+use crate::{
+    connection::ConnId,
+    error::Result,
+    handshake::HandshakeOutcome,
+    logging::push_json_string,
+    messages::{ReqPqMulti, ResPq},
+};
+
+pub struct JsonRecords {
+    out: Mutex<Box<dyn Write + Send>>,
+    // The parts of the records of the open connections
+    pending: Mutex<HashMap<u64, Pending>>,
+}
+
+#[derive(Default)]
+struct Pending {
+    req_pq: Option<String>,
+    res_pq: Option<String>,
+}
+
+impl JsonRecords {
+    pub fn new(out: impl Write + Send + 'static) -> Self {
+        Self {
+            out: Mutex::new(Box::new(out)),
+            pending: Mutex::default(),
+        }
+    }
+
+    // Appends to the file, `-` is stdout
+    pub fn open(path: &Path) -> io::Result<Self> {
+        if path == Path::new("-") {
+            return Ok(Self::new(io::stdout()));
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self::new(file))
+    }
+
+    pub fn res_pq(&self, id: ConnId, req_pq_multi: &ReqPqMulti, res_pq: &ResPq) {
+        let req_pq = format!(
+            r#"{{"name":{},"message_id":{},"nonce":{}}}"#,
+            string(req_pq_multi.name()),
+            req_pq_multi.message_id,
+            string(&hex(&req_pq_multi.nonce)),
+        );
+        let fingerprints: Vec<_> = res_pq
+            .server_public_key_fingerprints
+            .iter()
+            .map(|fingerprint| string(&format!("{:016x}", fingerprint)))
+            .collect();
+        let res_pq = format!(
+            r#"{{"message_id":{},"nonce":{},"server_nonce":{},"pq":{},"fingerprints":[{}]}}"#,
+            res_pq.message_id,
+            string(&hex(&res_pq.nonce)),
+            string(&hex(&res_pq.server_nonce)),
+            string(&hex(&res_pq.pq)),
+            fingerprints.join(","),
+        );
+        let mut pending = self.pending.lock().unwrap();
+        let pending = pending.entry(id.number()).or_default();
+        pending.req_pq = Some(req_pq);
+        pending.res_pq = Some(res_pq);
+    }
+
+    // Writes the record of a closed connection
+    pub fn finish(
+        &self,
+        id: ConnId,
+        duration: Duration,
+        res: &Result<HandshakeOutcome>,
+    ) -> io::Result<()> {
+        let pending = self
+            .pending
+            .lock()
+            .unwrap()
+            .remove(&id.number())
+            .unwrap_or_default();
+        let (outcome, auth_key_id, error) = match res {
+            Ok(HandshakeOutcome {
+                auth_key: Some(auth_key),
+                ..
+            }) => ("done", string(&format!("{:016x}", auth_key.id())), None),
+            Ok(_) => ("closed", "null".to_string(), None),
+            Err(e) => ("failed", "null".to_string(), Some(e.to_string())),
+        };
+        let mut record = String::new();
+        write!(
+            record,
+            r#"{{"connection":{},"peer":{},"req_pq":{},"res_pq":{},"stage":{},"duration_ms":{},"outcome":{},"auth_key_id":{},"error":{}}}"#,
+            id.number(),
+            string(&id.peer()),
+            pending.req_pq.as_deref().unwrap_or("null"),
+            pending.res_pq.as_deref().unwrap_or("null"),
+            string(id.stage()),
+            duration.as_secs_f64() * 1000.0,
+            string(outcome),
+            auth_key_id,
+            error.as_deref().map_or("null".to_string(), string),
+        )
+        .unwrap();
+        let mut out = self.out.lock().unwrap();
+        writeln!(out, "{}", record)?;
+        out.flush()
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn string(s: &str) -> String {
+    let mut json = String::new();
+    push_json_string(&mut json, s);
+    json
+}
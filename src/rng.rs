@@ -0,0 +1,47 @@
+// The randomness of the answers. Seeded, the server_nonce, pq, DH exponent and
+// paddings are the same on every run, for handshake transcripts which can be diffed
+// against another implementation. Anyone who knows the seed knows the keys then
+use std::sync::Mutex;
+
+use rand::{
+    distributions::{Distribution, Standard},
+    Rng, RngCore, SeedableRng,
+};
+use rand_chacha::ChaCha20Rng;
+
+#[derive(Default)]
+pub enum ServerRng {
+    #[default]
+    Thread,
+    Seeded(Box<Mutex<ChaCha20Rng>>),
+}
+
+impl ServerRng {
+    pub fn seeded(seed: [u8; 32]) -> Self {
+        Self::Seeded(Box::new(Mutex::new(ChaCha20Rng::from_seed(seed))))
+    }
+
+    pub fn is_seeded(&self) -> bool {
+        matches!(self, Self::Seeded(_))
+    }
+
+    // A seeded generator is shared by all the connections, so only sequential
+    // connections are reproducible
+    pub fn with<T>(&self, f: impl FnOnce(&mut dyn RngCore) -> T) -> T {
+        match self {
+            Self::Thread => f(&mut rand::thread_rng()),
+            Self::Seeded(rng) => f(&mut *rng.lock().unwrap()),
+        }
+    }
+
+    pub fn fill_bytes(&self, dest: &mut [u8]) {
+        self.with(|rng| rng.fill_bytes(dest))
+    }
+
+    pub fn gen<T>(&self) -> T
+    where
+        Standard: Distribution<T>,
+    {
+        self.with(|rng| rng.gen())
+    }
+}
@@ -0,0 +1,118 @@
+use std::{fs, path::Path};
+
+use grammers_tl_types::Serializable;
+use num_bigint::BigUint;
+use rsa::{
+    pkcs1::{DecodeRsaPrivateKey, EncodeRsaPrivateKey, LineEnding},
+    pkcs8::DecodePrivateKey,
+    traits::{PrivateKeyParts, PublicKeyParts},
+    RsaPrivateKey,
+};
+use sha1::{Digest, Sha1};
+
+use crate::error::{Result, ServerError};
+
+// One of Telegram's keys, advertised when no key is loaded. Clients accept it, but
+// their encrypted_data can't be decrypted
+pub const TELEGRAM_FINGERPRINT: i64 = 0xd09d1d85de64fd85u64 as i64;
+
+// The only size MTProto accepts
+pub const RSA_KEY_BITS: usize = 2048;
+
+// Parses the hex of a fingerprint as it's logged, e.g. d09d1d85de64fd85
+pub fn parse_fingerprint(s: &str) -> Result<i64> {
+    let hex = s.strip_prefix("0x").unwrap_or(s);
+    if hex.is_empty() || hex.len() > 16 {
+        return Err(ServerError::InvalidFingerprint(s.to_owned()));
+    }
+    u64::from_str_radix(hex, 16)
+        .map(|fingerprint| fingerprint as i64)
+        .map_err(|_| ServerError::InvalidFingerprint(s.to_owned()))
+}
+
+pub struct RsaKey {
+    n: BigUint,
+    d: BigUint,
+    fingerprint: i64,
+}
+
+impl RsaKey {
+    pub fn load(path: &Path) -> Result<Self> {
+        let pem = fs::read_to_string(path)?;
+        Self::from_pem(&pem)
+    }
+
+    // Accepts both PKCS#1 ("BEGIN RSA PRIVATE KEY") and PKCS#8 ("BEGIN PRIVATE KEY")
+    pub fn from_pem(pem: &str) -> Result<Self> {
+        let key = RsaPrivateKey::from_pkcs1_pem(pem)
+            .ok()
+            .or_else(|| RsaPrivateKey::from_pkcs8_pem(pem).ok())
+            .ok_or_else(|| {
+                ServerError::InvalidRsaKey("not a PEM encoded RSA private key".to_string())
+            })?;
+        let n = BigUint::from_bytes_be(&key.n().to_bytes_be());
+        let e = BigUint::from_bytes_be(&key.e().to_bytes_be());
+        if n.bits() != RSA_KEY_BITS as u64 {
+            return Err(ServerError::InvalidRsaKey(format!(
+                "the key has {} bits, expected {}",
+                n.bits(),
+                RSA_KEY_BITS
+            )));
+        }
+        Ok(Self {
+            fingerprint: fingerprint(&n, &e),
+            d: BigUint::from_bytes_be(&key.d().to_bytes_be()),
+            n,
+        })
+    }
+
+    // A new key, in the PKCS#1 PEM `from_pem` reads back
+    pub fn generate(bits: usize) -> Result<(Self, String)> {
+        if bits != RSA_KEY_BITS {
+            return Err(ServerError::InvalidRsaKey(format!(
+                "can't generate a key of {} bits, MTProto only accepts {}",
+                bits, RSA_KEY_BITS
+            )));
+        }
+        let pem = RsaPrivateKey::new(&mut rand::thread_rng(), bits)
+            .and_then(|key| Ok(key.to_pkcs1_pem(LineEnding::LF)?))
+            .map_err(|e| ServerError::InvalidRsaKey(e.to_string()))?;
+        Ok((Self::from_pem(&pem)?, pem.to_string()))
+    }
+
+    pub fn fingerprint(&self) -> i64 {
+        self.fingerprint
+    }
+
+    // encrypted_data = (SHA1(data) + data + padding)^e mod n, without any other
+    // padding scheme. Returns the 255 bytes of SHA1(data) + data + padding
+    pub fn decrypt(&self, encrypted_data: &[u8]) -> Result<Vec<u8>> {
+        let encrypted = BigUint::from_bytes_be(encrypted_data);
+        if encrypted >= self.n {
+            return Err(ServerError::CryptoError(
+                "encrypted_data is not less than the RSA modulus".to_string(),
+            ));
+        }
+        let decrypted = encrypted.modpow(&self.d, &self.n).to_bytes_be();
+        if decrypted.len() > 255 {
+            return Err(ServerError::CryptoError(format!(
+                "decrypted data is {} bytes long, expected at most 255",
+                decrypted.len()
+            )));
+        }
+
+        // The leading zeroes are lost in the conversion
+        let mut res = vec![0; 255 - decrypted.len()];
+        res.extend(decrypted);
+        Ok(res)
+    }
+}
+
+// The lower 64 bits of SHA1(rsa_public_key n:bytes e:bytes)
+fn fingerprint(n: &BigUint, e: &BigUint) -> i64 {
+    let mut public_key = Vec::new();
+    n.to_bytes_be().serialize(&mut public_key);
+    e.to_bytes_be().serialize(&mut public_key);
+    let hash = Sha1::digest(&public_key);
+    i64::from_le_bytes(hash[12..].try_into().unwrap())
+}
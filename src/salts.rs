@@ -0,0 +1,84 @@
+// Server salts, one random salt per window of `SALT_PERIOD` seconds since the epoch.
+// A salt stays valid until the end of the window after its own, so a client which
+// switches late isn't rejected
+use std::{
+    collections::BTreeMap,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use rand::Rng;
+
+pub const SALT_PERIOD: i32 = 30 * 60;
+// get_future_salts answers with at most this many
+pub const MAX_FUTURE_SALTS: usize = 64;
+
+// future_salt#0949d9dc valid_since:int valid_until:int salt:long
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FutureSalt {
+    pub valid_since: i32,
+    pub valid_until: i32,
+    pub salt: i64,
+}
+
+// Shared by all the connections, the salts are generated when first needed
+#[derive(Default)]
+pub struct SaltStore {
+    // By the start of their window
+    salts: Mutex<BTreeMap<i32, i64>>,
+}
+
+impl SaltStore {
+    pub fn current(&self) -> FutureSalt {
+        self.current_at(now())
+    }
+
+    pub fn current_at(&self, now: i32) -> FutureSalt {
+        self.future_at(now, 1)[0]
+    }
+
+    // The current salt and the next ones, `num` is clamped to 1..=MAX_FUTURE_SALTS
+    pub fn future(&self, num: usize) -> Vec<FutureSalt> {
+        self.future_at(now(), num)
+    }
+
+    pub fn future_at(&self, now: i32, num: usize) -> Vec<FutureSalt> {
+        let window = now - now.rem_euclid(SALT_PERIOD);
+        let mut salts = self.salts.lock().unwrap();
+        // Rotate out the salts which are no longer valid
+        salts.retain(|&valid_since, _| valid_since >= window - SALT_PERIOD);
+        (0..num.clamp(1, MAX_FUTURE_SALTS) as i32)
+            .map(|i| {
+                let valid_since = window + i * SALT_PERIOD;
+                FutureSalt {
+                    valid_since,
+                    valid_until: valid_since + 2 * SALT_PERIOD,
+                    salt: *salts
+                        .entry(valid_since)
+                        .or_insert_with(|| rand::thread_rng().gen()),
+                }
+            })
+            .collect()
+    }
+
+    pub fn is_valid(&self, salt: i64) -> bool {
+        self.is_valid_at(salt, now())
+    }
+
+    pub fn is_valid_at(&self, salt: i64, now: i32) -> bool {
+        // Makes sure the current salt exists and the expired ones are gone
+        self.current_at(now);
+        self.salts
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|(&valid_since, &valid)| valid == salt && valid_since <= now)
+    }
+}
+
+fn now() -> i32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i32
+}
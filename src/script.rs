@@ -0,0 +1,128 @@
+// Canned responses, served instead of running the handshake. The script is a JSON
+// object mapping the constructor id of a request, as 8 hex digits, to the hex of
+// the unencrypted message sent back, which is framed and obfuscated as usual:
+//
+//     {
+//         "be7e8ef1": "0000000000000000015c3a62ab3f1e6b50000000632416050000...",
+//         "d712e4be": "..."
+//     }
+use std::{collections::HashMap, fs, path::Path};
+
+use grammers_tl_types::{Cursor, Deserializable};
+
+use crate::error::{Result, ServerError};
+
+pub struct Script {
+    responses: HashMap<u32, Vec<u8>>,
+}
+
+impl Script {
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(json: &str) -> Result<Self> {
+        let mut parser = Parser { json, pos: 0 };
+        let mut responses = HashMap::new();
+        parser.expect('{')?;
+        if !parser.eat('}') {
+            loop {
+                let key = parser.string()?;
+                let constructor = u32::from_str_radix(key, 16)
+                    .ok()
+                    .filter(|_| key.len() == 8)
+                    .ok_or_else(|| invalid(format!("{:?} is not a constructor id", key)))?;
+                parser.expect(':')?;
+                let value = parser.string()?;
+                let response = parse_hex(value)
+                    .ok_or_else(|| invalid(format!("the response to {} is not hex", key)))?;
+                if !response.len().is_multiple_of(4) {
+                    return Err(invalid(format!(
+                        "the response to {} is {} bytes, not a whole number of 4 byte words",
+                        key,
+                        response.len()
+                    )));
+                }
+                if responses.insert(constructor, response).is_some() {
+                    return Err(invalid(format!("{} is scripted twice", key)));
+                }
+                if !parser.eat(',') {
+                    break;
+                }
+            }
+            parser.expect('}')?;
+        }
+        if parser.pos != parser.json.trim_end().len() {
+            return Err(invalid("trailing characters after the object".to_string()));
+        }
+        Ok(Self { responses })
+    }
+
+    // The response to an unencrypted message
+    pub fn respond(&self, packet: &[u8]) -> Result<&[u8]> {
+        let mut cur = Cursor::from_slice(packet);
+        let _auth_key_id = i64::deserialize(&mut cur)?;
+        let _message_id = i64::deserialize(&mut cur)?;
+        let _message_length = u32::deserialize(&mut cur)?;
+        let constructor = u32::deserialize(&mut cur)?;
+        self.responses
+            .get(&constructor)
+            .map(Vec::as_slice)
+            .ok_or(ServerError::Unscripted(constructor))
+    }
+}
+
+fn invalid(reason: String) -> ServerError {
+    ServerError::InvalidScript(reason)
+}
+
+fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+// Just enough JSON for an object of strings without escapes
+struct Parser<'a> {
+    json: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn skip_whitespace(&mut self) {
+        let rest = &self.json[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        if self.json[self.pos..].starts_with(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<()> {
+        if !self.eat(c) {
+            return Err(invalid(format!("expected '{}' at offset {}", c, self.pos)));
+        }
+        Ok(())
+    }
+
+    fn string(&mut self) -> Result<&'a str> {
+        self.expect('"')?;
+        let rest = &self.json[self.pos..];
+        let len = rest
+            .find(['"', '\\'])
+            .filter(|&len| rest[len..].starts_with('"'))
+            .ok_or_else(|| invalid(format!("unterminated string at offset {}", self.pos)))?;
+        self.pos += len + 1;
+        Ok(&rest[..len])
+    }
+}
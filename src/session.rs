@@ -0,0 +1,302 @@
+use std::collections::HashSet;
+
+use grammers_tl_types::{Cursor, Deserializable, Serializable};
+use log::{debug, info, trace, warn};
+
+use crate::{
+    config::Config,
+    connection::ConnId,
+    error::{Result, ServerError},
+    message_id::{Clock, MessageIdProvider, MessageIds, MAX_MSG_ID_AGE, MAX_MSG_ID_LEAD},
+    messages::{
+        decrypt_message, encrypt_message, InvokeWithLayer, Message, RpcError, INVOKE_WITH_LAYER,
+        MSG_CONTAINER,
+    },
+};
+
+const PING: u32 = 0x7abe77ec;
+const PONG: u32 = 0x347773c5;
+const BAD_MSG_NOTIFICATION: u32 = 0xa7eff811;
+const GET_FUTURE_SALTS: u32 = 0xb921bd04;
+const FUTURE_SALTS: u32 = 0xae500895;
+const NEW_SESSION_CREATED: u32 = 0x9ec20908;
+// Besides msg_container, the only message which doesn't need an acknowledgment,
+// and so has an even seq_no
+const MSGS_ACK: u32 = 0x62d6b459;
+
+// The error codes of bad_msg_notification
+const MSG_ID_TOO_LOW: i32 = 16;
+const MSG_ID_TOO_HIGH: i32 = 17;
+const MSG_ID_NOT_DIVISIBLE_BY_4: i32 = 18;
+const SEQ_NO_TOO_LOW: i32 = 32;
+const SEQ_NO_TOO_HIGH: i32 = 33;
+const SEQ_NO_EVEN_EXPECTED: i32 = 34;
+const SEQ_NO_ODD_EXPECTED: i32 = 35;
+
+// Unencrypted messages have a zero auth_key_id
+pub fn is_encrypted(packet: &[u8]) -> bool {
+    packet
+        .get(..8)
+        .is_some_and(|auth_key_id| auth_key_id != [0; 8])
+}
+
+// The encrypted messages of one connection
+pub struct Session {
+    id: ConnId,
+    message_ids: MessageIds,
+    // Content related messages sent so far, the seq_no of the next one is twice
+    // this plus one
+    content_messages: i32,
+    // The msg_id and seq_no of the latest message of the client
+    last_received: Option<(i64, i32)>,
+    // The session_ids the client used, each was announced with new_session_created
+    sessions: HashSet<i64>,
+}
+
+impl Session {
+    pub fn new(id: ConnId, config: &Config) -> Self {
+        Self {
+            id,
+            message_ids: (config.message_ids)(),
+            content_messages: 0,
+            last_received: None,
+            sessions: HashSet::new(),
+        }
+    }
+
+    // Returns the encrypted answer, if the message has one
+    pub fn handle_message(&mut self, config: &Config, packet: &[u8]) -> Result<Option<Vec<u8>>> {
+        let auth_key_id = i64::from_bytes(packet)?;
+        let auth_key = config.auth_keys.get(auth_key_id)?;
+        let message = decrypt_message(packet, &auth_key)?;
+        trace!("{} message: {:02x?}", self.id, message);
+
+        let mut answers = Vec::new();
+        self.dispatch(config, &message, &mut answers, false)?;
+        let answer = match answers.len() {
+            0 => return Ok(None),
+            1 => answers.pop().unwrap(),
+            _ => Message::pack_container(
+                self.message_ids.next_id(),
+                self.content_messages * 2,
+                &answers,
+            ),
+        };
+        trace!("{} answer: {:02x?}", self.id, answer);
+        Ok(Some(encrypt_message(&answer, &auth_key, &config.rng)?))
+    }
+
+    // Pushes the answers to the message, or to each message of a container
+    fn dispatch(
+        &mut self,
+        config: &Config,
+        message: &Message,
+        answers: &mut Vec<Message>,
+        in_container: bool,
+    ) -> Result<()> {
+        let constructor = message.constructor();
+        match constructor {
+            Some(constructor) => info!(
+                "{} message {:016x}: constructor {:08x}",
+                self.id, message.message_id, constructor
+            ),
+            None => info!(
+                "{} message {:016x} without a body",
+                self.id, message.message_id
+            ),
+        }
+
+        if let Some(error_code) = self.check_message(message, constructor) {
+            warn!(
+                "{} bad message {:016x} with seq_no {}: error_code {}",
+                self.id, message.message_id, message.seq_no, error_code
+            );
+            let body = bad_msg_notification(message, error_code);
+            answers.push(self.answer(config, message, body));
+            return Ok(());
+        }
+        // The messages of a container share its session_id
+        if !in_container && self.sessions.insert(message.session_id) {
+            let body = self.new_session_created(config, message);
+            answers.push(self.answer(config, message, body));
+        }
+        match constructor {
+            Some(MSG_CONTAINER) if in_container => {
+                return Err(ServerError::InvalidEncryptedMessage("nested msg_container"));
+            }
+            Some(MSG_CONTAINER) => {
+                for inner in message.unpack_container(config.max_vector_len)? {
+                    self.dispatch(config, &inner, answers, true)?;
+                }
+            }
+            Some(PING) => {
+                let body = self.pong(message)?;
+                answers.push(self.answer(config, message, body));
+            }
+            Some(GET_FUTURE_SALTS) => {
+                let body = self.future_salts(config, message)?;
+                answers.push(self.answer(config, message, body));
+            }
+            Some(INVOKE_WITH_LAYER) => self.invoke_with_layer(config, message, answers)?,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // A content related answer to `message`, with the current salt of the server
+    fn answer(&mut self, config: &Config, message: &Message, body: Vec<u8>) -> Message {
+        Message {
+            salt: config.salts.current().salt,
+            session_id: message.session_id,
+            message_id: self.message_ids.next_id(),
+            seq_no: self.next_seq_no(),
+            body,
+        }
+    }
+
+    // Returns the error_code of bad_msg_notification, if the message breaks a rule
+    // of msg_id or seq_no
+    fn check_message(&mut self, message: &Message, constructor: Option<u32>) -> Option<i32> {
+        let content_related = !matches!(constructor, Some(MSGS_ACK | MSG_CONTAINER));
+        let now = Clock.now() >> 32;
+        let sent = message.message_id >> 32;
+        if sent < now - MAX_MSG_ID_AGE {
+            return Some(MSG_ID_TOO_LOW);
+        }
+        if sent > now + MAX_MSG_ID_LEAD {
+            return Some(MSG_ID_TOO_HIGH);
+        }
+        if message.message_id % 4 != 0 {
+            return Some(MSG_ID_NOT_DIVISIBLE_BY_4);
+        }
+        if content_related && message.seq_no % 2 == 0 {
+            return Some(SEQ_NO_ODD_EXPECTED);
+        }
+        if !content_related && message.seq_no % 2 != 0 {
+            return Some(SEQ_NO_EVEN_EXPECTED);
+        }
+        // The seq_no of a container doesn't have to be in order with its messages
+        if constructor == Some(MSG_CONTAINER) {
+            return None;
+        }
+        // A later message can't have a lower seq_no and an earlier one a higher one
+        if let Some((last_id, last_seq_no)) = self.last_received {
+            if message.message_id > last_id && message.seq_no < last_seq_no {
+                return Some(SEQ_NO_TOO_LOW);
+            }
+            if message.message_id < last_id && message.seq_no > last_seq_no {
+                return Some(SEQ_NO_TOO_HIGH);
+            }
+        }
+        if self
+            .last_received
+            .is_none_or(|(last_id, _)| message.message_id > last_id)
+        {
+            self.last_received = Some((message.message_id, message.seq_no));
+        }
+        None
+    }
+
+    fn pong(&self, message: &Message) -> Result<Vec<u8>> {
+        // ping#7abe77ec ping_id:long = Pong
+        let ping_id = i64::deserialize(&mut Cursor::from_slice(&message.body[4..]))?;
+        debug!("{} ping {:016x}", self.id, ping_id);
+        // pong#347773c5 msg_id:long ping_id:long = Pong
+        let mut body = PONG.to_le_bytes().to_vec();
+        message.message_id.serialize(&mut body);
+        ping_id.serialize(&mut body);
+        Ok(body)
+    }
+
+    // Answers the wrapped request if the layer is supported
+    fn invoke_with_layer(
+        &mut self,
+        config: &Config,
+        message: &Message,
+        answers: &mut Vec<Message>,
+    ) -> Result<()> {
+        let invoke = InvokeWithLayer::parse(&mut Cursor::from_slice(&message.body))?;
+        if let Some(init) = &invoke.init_connection {
+            info!(
+                "{} app {} version {} on {} {}",
+                self.id, init.api_id, init.app_version, init.device_model, init.system_version
+            );
+        }
+        if !config.supports_layer(invoke.layer) {
+            warn!("{} rejecting layer {}", self.id, invoke.layer);
+            let error = RpcError {
+                error_code: 400,
+                error_message: "CONNECTION_LAYER_INVALID".to_owned(),
+            };
+            answers.push(self.answer(config, message, error.ser(message.message_id)));
+            return Ok(());
+        }
+        debug!("{} layer {}", self.id, invoke.layer);
+        let query = Message {
+            salt: message.salt,
+            session_id: message.session_id,
+            message_id: message.message_id,
+            seq_no: message.seq_no,
+            body: invoke.query,
+        };
+        // Otherwise a client could nest them until the stack overflows
+        if query.constructor() == Some(INVOKE_WITH_LAYER) {
+            return Err(ServerError::InvalidEncryptedMessage(
+                "nested invokeWithLayer",
+            ));
+        }
+        self.dispatch(config, &query, answers, true)
+    }
+
+    // Like pong, the answer isn't wrapped in rpc_result
+    fn future_salts(&self, config: &Config, message: &Message) -> Result<Vec<u8>> {
+        // get_future_salts#b921bd04 num:int = FutureSalts
+        let num = i32::deserialize(&mut Cursor::from_slice(&message.body[4..]))?;
+        let salts = config.salts.future(num.max(0) as usize);
+        debug!(
+            "{} {} future salts asked, answering {}",
+            self.id,
+            num,
+            salts.len()
+        );
+        // future_salts#ae500895 req_msg_id:long now:int salts:vector<future_salt>
+        // = FutureSalts, where the vector is bare, like the one of msg_container
+        let mut body = FUTURE_SALTS.to_le_bytes().to_vec();
+        message.message_id.serialize(&mut body);
+        ((Clock.now() >> 32) as i32).serialize(&mut body);
+        (salts.len() as u32).serialize(&mut body);
+        for salt in salts {
+            salt.valid_since.serialize(&mut body);
+            salt.valid_until.serialize(&mut body);
+            salt.salt.serialize(&mut body);
+        }
+        Ok(body)
+    }
+
+    // Sent before the answer to the first message of a session, which is content
+    // related like a real server's
+    fn new_session_created(&self, config: &Config, message: &Message) -> Vec<u8> {
+        info!("{} new session {:016x}", self.id, message.session_id);
+        // new_session_created#9ec20908 first_msg_id:long unique_id:long
+        // server_salt:long = NewSession
+        let mut body = NEW_SESSION_CREATED.to_le_bytes().to_vec();
+        message.message_id.serialize(&mut body);
+        config.rng.gen::<i64>().serialize(&mut body);
+        config.salts.current().salt.serialize(&mut body);
+        body
+    }
+
+    fn next_seq_no(&mut self) -> i32 {
+        self.content_messages += 1;
+        self.content_messages * 2 - 1
+    }
+}
+
+// bad_msg_notification#a7eff811 bad_msg_id:long bad_msg_seqno:int error_code:int
+fn bad_msg_notification(message: &Message, error_code: i32) -> Vec<u8> {
+    let mut body = BAD_MSG_NOTIFICATION.to_le_bytes().to_vec();
+    message.message_id.serialize(&mut body);
+    message.seq_no.serialize(&mut body);
+    error_code.serialize(&mut body);
+    body
+}
@@ -0,0 +1,130 @@
+// How far the connections got in the handshake and how long it took, tallied for a
+// summary on shutdown
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+// Handshakes the latency percentiles are computed over, the oldest ones are replaced
+// first
+const LATENCY_SAMPLES: usize = 4096;
+pub const PERCENTILES: [f64; 3] = [50.0, 95.0, 99.0];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stage {
+    // ResPq was answered
+    ReqPqMulti,
+    // ServerDHParams was answered
+    ReqDHParams,
+    // DhGenOk was answered, the auth key exists
+    DhGenOk,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 3] = [Stage::ReqPqMulti, Stage::ReqDHParams, Stage::DhGenOk];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::ReqPqMulti => "ReqPqMulti",
+            Stage::ReqDHParams => "ReqDHParams",
+            Stage::DhGenOk => "DhGenOk",
+        }
+    }
+}
+
+// A connection counts for every stage it passed, so the counts never grow along the
+// handshake
+#[derive(Default)]
+pub struct StageStats {
+    counts: [AtomicU64; Stage::ALL.len()],
+}
+
+impl StageStats {
+    pub fn record(&self, stage: Stage) {
+        self.counts[stage as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn count(&self, stage: Stage) -> u64 {
+        self.counts[stage as usize].load(Ordering::Relaxed)
+    }
+}
+
+impl fmt::Display for StageStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, stage) in Stage::ALL.into_iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{} {}", self.count(stage), stage.name())?;
+        }
+        Ok(())
+    }
+}
+
+// Durations of the full handshakes, from req_pq_multi to DhGenOk, over the last
+// LATENCY_SAMPLES of them
+#[derive(Default)]
+pub struct HandshakeLatency {
+    samples: Mutex<Samples>,
+}
+
+#[derive(Default)]
+struct Samples {
+    durations: Vec<Duration>,
+    // The oldest sample once there are LATENCY_SAMPLES of them
+    next: usize,
+}
+
+impl HandshakeLatency {
+    pub fn record(&self, duration: Duration) {
+        let mut samples = self.samples.lock().unwrap();
+        if samples.durations.len() < LATENCY_SAMPLES {
+            samples.durations.push(duration);
+        } else {
+            let next = samples.next;
+            samples.durations[next] = duration;
+            samples.next = (next + 1) % LATENCY_SAMPLES;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.lock().unwrap().durations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Nearest rank percentiles, `p` from 0 to 100. None before the first handshake
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        self.percentiles([p]).map(|[duration]| duration)
+    }
+
+    pub fn percentiles<const N: usize>(&self, ps: [f64; N]) -> Option<[Duration; N]> {
+        let mut sorted = self.samples.lock().unwrap().durations.clone();
+        if sorted.is_empty() {
+            return None;
+        }
+        sorted.sort_unstable();
+        Some(ps.map(|p| {
+            let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+            sorted[rank.clamp(1, sorted.len()) - 1]
+        }))
+    }
+}
+
+impl fmt::Display for HandshakeLatency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Some(durations) = self.percentiles(PERCENTILES) else {
+            return write!(f, "no handshakes yet");
+        };
+        for (p, duration) in PERCENTILES.into_iter().zip(durations) {
+            write!(f, "p{} {:?}, ", p, duration)?;
+        }
+        write!(f, "over {} handshakes", self.len())
+    }
+}
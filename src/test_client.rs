@@ -0,0 +1,225 @@
+// A minimal client of the obfuscated transport for other crates to test against: it
+// sends the obfuscation header, frames the packets and runs the handshake over any
+// stream. It shares the obfuscation, framing and key derivation with the server
+use std::io::{Read, Write};
+
+use aes::cipher::{KeyIvInit, StreamCipher};
+use grammers_tl_types::{self as tl, Deserializable, Identifiable, Serializable};
+use num_bigint::BigUint;
+use rand::RngCore;
+use sha1::{Digest, Sha1};
+
+use crate::{
+    connection::{
+        frame_abridged, frame_intermediate, validate_obfuscation_header, Aes256Ctr64Be,
+        ObfuscationKeys, ABRIDGED_TAG, INTERMEDIATE_TAG,
+    },
+    crypto::{ige_decrypt, ige_encrypt},
+    error::{Result, ServerError},
+    keys::{derive_tmp_aes, new_nonce_hash},
+    message_id::MessageIds,
+    pq,
+    rsa_key::TELEGRAM_FINGERPRINT,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TestTransport {
+    Abridged,
+    Intermediate,
+}
+
+pub struct TestClient<S> {
+    stream: S,
+    transport: TestTransport,
+    encryptor: Aes256Ctr64Be,
+    decryptor: Aes256Ctr64Be,
+    message_ids: MessageIds,
+}
+
+impl<S: Read + Write> TestClient<S> {
+    // Sends the obfuscation header for `dc_id`
+    pub fn connect(mut stream: S, transport: TestTransport, dc_id: i16) -> Result<Self> {
+        let mut init = [0; 64];
+        loop {
+            rand::thread_rng().fill_bytes(&mut init);
+            if validate_obfuscation_header(&init).is_ok() {
+                break;
+            }
+        }
+        let tag = match transport {
+            TestTransport::Abridged => ABRIDGED_TAG,
+            TestTransport::Intermediate => INTERMEDIATE_TAG,
+        };
+        init[56..60].copy_from_slice(&tag);
+        init[60..62].copy_from_slice(&dc_id.to_le_bytes());
+        // The server's keys the other way around
+        let keys = ObfuscationKeys::derive(&init);
+        let mut encryptor = Aes256Ctr64Be::new(&keys.encrypt_key.into(), &keys.encrypt_iv.into());
+        let decryptor = Aes256Ctr64Be::new(&keys.decrypt_key.into(), &keys.decrypt_iv.into());
+        // Only the tag and the DC are sent encrypted
+        let mut encrypted_init = init;
+        encryptor.apply_keystream(&mut encrypted_init);
+        init[56..].copy_from_slice(&encrypted_init[56..]);
+        stream.write_all(&init)?;
+
+        Ok(Self {
+            stream,
+            transport,
+            encryptor,
+            decryptor,
+            message_ids: MessageIds::default(),
+        })
+    }
+
+    pub fn send_packet(&mut self, packet: &[u8]) -> Result<()> {
+        let mut frame = match self.transport {
+            TestTransport::Abridged => frame_abridged(packet),
+            TestTransport::Intermediate => frame_intermediate(packet),
+        };
+        self.encryptor.apply_keystream(&mut frame);
+        self.stream.write_all(&frame)?;
+        Ok(())
+    }
+
+    pub fn read_packet(&mut self) -> Result<Vec<u8>> {
+        let len = match self.transport {
+            TestTransport::Abridged => match self.read(1)?[0] {
+                0x7f => {
+                    let mut len = [0; 4];
+                    len[..3].copy_from_slice(&self.read(3)?);
+                    u32::from_le_bytes(len) as usize * 4
+                }
+                len => len as usize * 4,
+            },
+            TestTransport::Intermediate => {
+                u32::from_le_bytes(self.read(4)?.try_into().unwrap()) as usize
+            }
+        };
+        self.read(len)
+    }
+
+    // Sends `body` in an unencrypted message and returns the body of the answer
+    pub fn call_unencrypted(&mut self, body: &[u8]) -> Result<Vec<u8>> {
+        let mut packet = Vec::new();
+        0i64.serialize(&mut packet);
+        // The client's ids are 0 mod 4, the server's 1
+        (self.message_ids.next_id() - 1).serialize(&mut packet);
+        (body.len() as u32).serialize(&mut packet);
+        packet.extend(body);
+        self.send_packet(&packet)?;
+        let answer = self.read_packet()?;
+        Ok(answer.get(20..).unwrap_or_default().to_vec())
+    }
+
+    pub fn req_pq_multi(&mut self, nonce: [u8; 16]) -> Result<tl::types::ResPq> {
+        let answer = self.call_unencrypted(&tl::functions::ReqPqMulti { nonce }.to_bytes())?;
+        let tl::enums::ResPq::Pq(res_pq) = tl::enums::ResPq::from_bytes(&answer)?;
+        Ok(res_pq)
+    }
+
+    // The whole handshake, against a server without RSA keys of its own: it advertises
+    // the fingerprint of Telegram's key, which nobody can encrypt p_q_inner_data for,
+    // and takes new_nonce to be zeroes. Returns the auth key, once dh_gen_ok proved the
+    // server derived the same one
+    pub fn handshake(&mut self, nonce: [u8; 16]) -> Result<[u8; 256]> {
+        let res_pq = self.req_pq_multi(nonce)?;
+        let server_nonce = res_pq.server_nonce;
+        let pq = pq::from_be_bytes(&res_pq.pq).ok_or_else(|| ServerError::InvalidPq {
+            pq: res_pq.pq.clone(),
+            reason: "longer than 64 bits".to_string(),
+        })?;
+        let (p, q) = pq::factorize(pq)?;
+
+        let req_dh_params = tl::functions::ReqDhParams {
+            nonce,
+            server_nonce,
+            p: p.to_be_bytes().to_vec(),
+            q: q.to_be_bytes().to_vec(),
+            public_key_fingerprint: TELEGRAM_FINGERPRINT,
+            encrypted_data: vec![0; 256],
+        };
+        let answer = self.call_unencrypted(&req_dh_params.to_bytes())?;
+        let server_dh_params = match tl::enums::ServerDhParams::from_bytes(&answer)? {
+            tl::enums::ServerDhParams::Ok(server_dh_params) => server_dh_params,
+            tl::enums::ServerDhParams::Fail(_) => {
+                return Err(ServerError::MagicMismatch {
+                    expected: "server_DH_params_ok",
+                    got: tl::types::ServerDhParamsFail::CONSTRUCTOR_ID,
+                })
+            }
+        };
+        let new_nonce = [0; 32];
+        let (tmp_aes_key, tmp_aes_iv) = derive_tmp_aes(new_nonce, server_nonce);
+        let answer_with_hash = ige_decrypt(
+            &server_dh_params.encrypted_answer,
+            &tmp_aes_key,
+            &tmp_aes_iv,
+        )?;
+        let tl::enums::ServerDhInnerData::Data(inner) = tl::enums::ServerDhInnerData::from_bytes(
+            answer_with_hash.get(20..).unwrap_or_default(),
+        )?;
+
+        let dh_prime = BigUint::from_bytes_be(&inner.dh_prime);
+        let mut b = [0; 256];
+        rand::thread_rng().fill_bytes(&mut b);
+        let b = BigUint::from_bytes_be(&b);
+        let g_b = BigUint::from(inner.g as u32).modpow(&b, &dh_prime);
+        let data = tl::enums::ClientDhInnerData::Data(tl::types::ClientDhInnerData {
+            nonce,
+            server_nonce,
+            retry_id: 0,
+            g_b: g_b.to_bytes_be(),
+        })
+        .to_bytes();
+        let mut data_with_hash = Sha1::digest(&data).to_vec();
+        data_with_hash.extend(data);
+        data_with_hash.resize(data_with_hash.len().next_multiple_of(16), 0);
+        let set_client_dh_params = tl::functions::SetClientDhParams {
+            nonce,
+            server_nonce,
+            encrypted_data: ige_encrypt(&data_with_hash, &tmp_aes_key, &tmp_aes_iv)?,
+        };
+        let answer = self.call_unencrypted(&set_client_dh_params.to_bytes())?;
+
+        let g_ab = BigUint::from_bytes_be(&inner.g_a)
+            .modpow(&b, &dh_prime)
+            .to_bytes_be();
+        let mut auth_key = [0; 256];
+        auth_key[256 - g_ab.len()..].copy_from_slice(&g_ab);
+        let dh_gen_ok = match tl::enums::SetClientDhParamsAnswer::from_bytes(&answer)? {
+            tl::enums::SetClientDhParamsAnswer::DhGenOk(dh_gen_ok) => dh_gen_ok,
+            tl::enums::SetClientDhParamsAnswer::DhGenRetry(_) => {
+                return Err(ServerError::MagicMismatch {
+                    expected: "dh_gen_ok",
+                    got: tl::types::DhGenRetry::CONSTRUCTOR_ID,
+                })
+            }
+            tl::enums::SetClientDhParamsAnswer::DhGenFail(_) => {
+                return Err(ServerError::MagicMismatch {
+                    expected: "dh_gen_ok",
+                    got: tl::types::DhGenFail::CONSTRUCTOR_ID,
+                })
+            }
+        };
+        let expected = new_nonce_hash(&new_nonce, 1, &auth_key);
+        if dh_gen_ok.new_nonce_hash1 != expected {
+            return Err(ServerError::NonceMismatch {
+                name: "new_nonce_hash1",
+                got: dh_gen_ok.new_nonce_hash1,
+                expected,
+            });
+        }
+        Ok(auth_key)
+    }
+
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+
+    fn read(&mut self, len: usize) -> Result<Vec<u8>> {
+        let mut data = vec![0; len];
+        self.stream.read_exact(&mut data)?;
+        self.decryptor.apply_keystream(&mut data);
+        Ok(data)
+    }
+}
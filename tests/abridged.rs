@@ -0,0 +1,171 @@
+mod common;
+
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    thread,
+};
+
+use aes::cipher::{KeyIvInit, StreamCipher};
+use bytes::BytesMut;
+use grammers_mtproto::transport::{Abridged, Transport};
+use rand::RngCore;
+use srv::{frame_abridged, handle_connection, script::Script, Config};
+
+use common::{req_pq_multi, Client, ABRIDGED_TAG};
+
+type Aes256Ctr64Be = ctr::Ctr64BE<aes::Aes256>;
+
+const RES_PQ_MAGIC: u32 = 0x05162463;
+
+// Sends a req_pq_multi padded to `len` bytes over the obfuscated abridged
+// transport and returns the constructor of the answer
+fn res_pq_magic(len: usize) -> u32 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (server, _) = listener.accept().unwrap();
+    let server = thread::spawn(move || handle_connection(server, &Config::default()));
+
+    let mut init = [0; 64];
+    loop {
+        rand::thread_rng().fill_bytes(&mut init);
+        if init[0] != 0xef && init[4..8] != [0; 4] {
+            break;
+        }
+    }
+    init[56..60].copy_from_slice(&[0xef; 4]);
+    let mut encryptor = Aes256Ctr64Be::new(init[8..40].into(), init[40..56].into());
+    let reversed: Vec<u8> = init.iter().rev().copied().collect();
+    let mut decryptor = Aes256Ctr64Be::new(reversed[8..40].into(), reversed[40..56].into());
+    let mut encrypted_init = init;
+    encryptor.apply_keystream(&mut encrypted_init);
+    init[56..].copy_from_slice(&encrypted_init[56..]);
+
+    let mut packet = vec![0; 8];
+    packet.extend(0x51e57ac42770964ai64.to_le_bytes());
+    packet.extend(20u32.to_le_bytes());
+    packet.extend(0xbe7e8ef1u32.to_le_bytes());
+    packet.extend([0x42; 16]);
+    packet.resize(len, 0);
+
+    let words = len / 4;
+    let mut request = if words < 0x7f {
+        vec![words as u8]
+    } else {
+        let mut header = vec![0x7f];
+        header.extend(&(words as u32).to_le_bytes()[..3]);
+        header
+    };
+    request.extend(packet);
+    encryptor.apply_keystream(&mut request);
+    stream.write_all(&init).unwrap();
+    stream.write_all(&request).unwrap();
+
+    let mut header = [0; 1];
+    stream.read_exact(&mut header).unwrap();
+    decryptor.apply_keystream(&mut header);
+    let mut answer = vec![0; header[0] as usize * 4];
+    stream.read_exact(&mut answer).unwrap();
+    decryptor.apply_keystream(&mut answer);
+
+    drop(stream);
+    let _ = server.join().unwrap();
+    u32::from_le_bytes(answer[20..24].try_into().unwrap())
+}
+
+#[test]
+fn short_length() {
+    assert_eq!(res_pq_magic(0x7e * 4), RES_PQ_MAGIC);
+}
+
+#[test]
+fn extended_length_boundary() {
+    assert_eq!(res_pq_magic(0x7f * 4), RES_PQ_MAGIC);
+}
+
+#[test]
+fn extended_length() {
+    assert_eq!(res_pq_magic(0x80 * 4), RES_PQ_MAGIC);
+}
+
+#[test]
+fn frames_short_packets_with_one_byte() {
+    assert_eq!(frame_abridged(&[0x42; 4]), [0x01, 0x42, 0x42, 0x42, 0x42]);
+    let frame = frame_abridged(&[0x42; 0x7e * 4]);
+    assert_eq!(frame[0], 0x7e);
+    assert_eq!(frame.len(), 1 + 0x7e * 4);
+}
+
+#[test]
+fn frames_long_packets_with_four_bytes() {
+    let frame = frame_abridged(&[0x42; 0x7f * 4]);
+    assert_eq!(frame[..4], [0x7f, 0x7f, 0x00, 0x00]);
+    assert_eq!(frame.len(), 4 + 0x7f * 4);
+    let frame = frame_abridged(&[0x42; 0x012345 * 4]);
+    assert_eq!(frame[..4], [0x7f, 0x45, 0x23, 0x01]);
+    assert_eq!(frame.len(), 4 + 0x012345 * 4);
+}
+
+// The same as the frames of grammers, without the tag in front of the first one
+#[test]
+fn matches_grammers_without_tag() {
+    let mut transport = Abridged::new();
+    for (i, len) in [4, 0x7e * 4, 0x7f * 4, 1000, 0x1000 * 4]
+        .into_iter()
+        .enumerate()
+    {
+        let packet = vec![0x42; len];
+        let mut expected = BytesMut::new();
+        transport.pack(&packet, &mut expected);
+        if i == 0 {
+            assert_eq!(expected.split_to(1)[..], [0xef]);
+        }
+        assert_eq!(frame_abridged(&packet), expected);
+    }
+}
+
+// The server frames the packets itself instead of stripping the tag grammers packs
+// in front of the first frame, so the length always describes the payload
+#[test]
+fn length_matches_payload() {
+    for words in (1..0x200).chain([0xffff, 0x10000, 0x123456]) {
+        let frame = frame_abridged(&vec![0x42; words * 4]);
+        let (len, header) = match frame[0] {
+            0x7f => {
+                let mut len = [0; 4];
+                len[..3].copy_from_slice(&frame[1..4]);
+                (u32::from_le_bytes(len) as usize, 4)
+            }
+            len => (len as usize, 1),
+        };
+        assert_eq!(len, words, "{} words", words);
+        assert_eq!(frame.len(), header + words * 4, "{} words", words);
+    }
+}
+
+// A scripted answer longer than 0x7e words, like server_DH_params_ok, framed by
+// the codec of the server
+#[test]
+fn extended_length_on_the_wire() {
+    let answer: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+    let script = format!(
+        "{{\"be7e8ef1\": \"{}\"}}",
+        answer
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>()
+    );
+    let config = Config {
+        script: Some(Script::parse(&script).unwrap()),
+        ..Default::default()
+    };
+    let mut client = Client::connect_with(ABRIDGED_TAG, config);
+    client.send_abridged(req_pq_multi());
+    // 0x7f and 250 words in 3 bytes
+    assert_eq!(client.receive(4), [0x7f, 0xfa, 0x00, 0x00]);
+    assert_eq!(client.receive(1000), answer);
+    client.close_write();
+    let (rest, res) = client.receive_all();
+    assert!(rest.is_empty());
+    res.unwrap();
+}
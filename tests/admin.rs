@@ -0,0 +1,120 @@
+mod common;
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+use log::LevelFilter;
+use srv::{
+    admin::{self, execute},
+    fault::Fault,
+    Config, ServerError,
+};
+
+use common::{req_pq_multi, Client, ABRIDGED_TAG};
+
+#[test]
+fn delay() {
+    let config = Config {
+        response_delay: Duration::from_millis(100),
+        ..Default::default()
+    };
+    assert_eq!(config.response_delay(), Duration::from_millis(100));
+    execute(&config, "delay 500").unwrap();
+    assert_eq!(config.response_delay(), Duration::from_millis(500));
+    execute(&config, "  delay   0 ").unwrap();
+    assert_eq!(config.response_delay(), Duration::ZERO);
+    assert!(matches!(
+        execute(&config, "delay -1"),
+        Err(ServerError::InvalidAdminCommand { .. })
+    ));
+}
+
+#[test]
+fn fault_on_and_off() {
+    let config = Config {
+        faults: vec!["truncate".parse().unwrap()],
+        ..Default::default()
+    };
+    execute(&config, "fault drop-after-respq on").unwrap();
+    let faults: Vec<_> = config.faults().iter().map(|rule| rule.fault).collect();
+    assert_eq!(faults, [Fault::Truncate, Fault::DropAfterResPq]);
+
+    // Turning a fault on again replaces its probability
+    execute(&config, "fault truncate@0.5 on").unwrap();
+    let faults = config.faults();
+    assert_eq!(faults.len(), 2);
+    assert_eq!(faults[1].fault, Fault::Truncate);
+    assert_eq!(faults[1].probability, 0.5);
+
+    execute(&config, "fault truncate off").unwrap();
+    let faults: Vec<_> = config.faults().iter().map(|rule| rule.fault).collect();
+    assert_eq!(faults, [Fault::DropAfterResPq]);
+    // The faults the server started with are kept
+    assert_eq!(config.faults.len(), 1);
+
+    execute(&config, "faults clear").unwrap();
+    assert!(config.faults().is_empty());
+
+    assert!(matches!(
+        execute(&config, "fault explode on"),
+        Err(ServerError::InvalidFault(_))
+    ));
+    assert!(matches!(
+        execute(&config, "fault truncate maybe"),
+        Err(ServerError::InvalidAdminCommand { .. })
+    ));
+}
+
+#[test]
+fn fault_reaches_the_connections() {
+    let config = Config::default();
+    execute(&config, "fault drop-after-respq on").unwrap();
+    let mut client = Client::connect_with(ABRIDGED_TAG, config);
+    client.send_abridged(req_pq_multi());
+    assert!(!client.receive_abridged().is_empty());
+    assert!(client.is_closed(Duration::from_secs(5)));
+}
+
+// The only test setting the level, it's global
+#[test]
+fn loglevel() {
+    let config = Config::default();
+    execute(&config, "loglevel trace").unwrap();
+    assert_eq!(log::max_level(), LevelFilter::Trace);
+    execute(&config, "loglevel warn").unwrap();
+    assert_eq!(log::max_level(), LevelFilter::Warn);
+    assert!(execute(&config, "loglevel loud").is_err());
+    assert_eq!(log::max_level(), LevelFilter::Warn);
+}
+
+#[test]
+fn over_tcp() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let config = Arc::new(Config::default());
+    thread::spawn({
+        let config = config.clone();
+        move || admin::serve(listener, config)
+    });
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    let mut answers = BufReader::new(stream.try_clone().unwrap()).lines();
+    let mut command = |command: &str| {
+        writeln!(stream, "{}", command).unwrap();
+        answers.next().unwrap().unwrap()
+    };
+    assert_eq!(command("delay 250"), "OK");
+    assert_eq!(command("fault corrupt-msg-key on"), "OK");
+    let answer = command("restart");
+    assert!(answer.starts_with("ERR "), "{}", answer);
+    assert!(answer.contains("unknown command"), "{}", answer);
+    assert!(command("").starts_with("ERR "));
+
+    assert_eq!(config.response_delay(), Duration::from_millis(250));
+    assert_eq!(config.faults()[0].fault, Fault::CorruptMsgKey);
+}
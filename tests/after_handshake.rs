@@ -0,0 +1,69 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::{
+    decrypt_answer_with, encrypted_message_with, message_id, skip_new_session_created, Client,
+    ABRIDGED_TAG,
+};
+use srv::{AfterHandshake, Config};
+
+fn connect(after_handshake: AfterHandshake) -> Client {
+    Client::connect_with(
+        ABRIDGED_TAG,
+        Config {
+            after_handshake,
+            timeout: Duration::from_secs(1),
+            ..Default::default()
+        },
+    )
+}
+
+#[test]
+fn close() {
+    let mut client = connect(AfterHandshake::Close);
+    client.handshake();
+    assert!(client.is_closed(Duration::from_millis(500)));
+    client.receive_all().1.unwrap();
+}
+
+#[test]
+fn wait() {
+    let mut client = connect(AfterHandshake::Wait);
+    let auth_key = client.handshake();
+    assert!(!client.is_closed(Duration::from_millis(500)));
+    // Packets are dropped, and don't close the connection either
+    client.send_abridged(encrypted_message_with(&auth_key, message_id(), 1, &[0; 12]));
+    assert!(!client.is_closed(Duration::from_millis(500)));
+
+    // Closed once idle for the timeout
+    let start = Instant::now();
+    assert!(client.is_closed(Duration::from_secs(2)));
+    assert!(start.elapsed() >= Duration::from_millis(400));
+    client.receive_all().1.unwrap();
+}
+
+#[test]
+fn wait_until_the_client_closes() {
+    let mut client = connect(AfterHandshake::Wait);
+    client.handshake();
+    client.close_write();
+    assert!(client.is_closed(Duration::from_millis(500)));
+    client.receive_all().1.unwrap();
+}
+
+#[test]
+fn echo() {
+    let mut client = connect(AfterHandshake::Echo);
+    let auth_key = client.handshake();
+    let mut ping = 0x7abe77ecu32.to_le_bytes().to_vec();
+    ping.extend(0x42i64.to_le_bytes());
+    client.send_abridged(encrypted_message_with(&auth_key, message_id(), 1, &ping));
+
+    let answer =
+        skip_new_session_created(&decrypt_answer_with(&auth_key, &client.receive_abridged()));
+    assert_eq!(answer[32..36], 0x347773c5u32.to_le_bytes());
+    assert_eq!(answer[44..52], 0x42i64.to_le_bytes());
+    client.close_write();
+    client.receive_all().1.unwrap();
+}
@@ -0,0 +1,64 @@
+#![cfg(feature = "tokio")]
+
+use bytes::BytesMut;
+use grammers_mtproto::transport::{Full, Transport};
+use grammers_tl_types::{self as tl, Deserializable, Serializable};
+use srv::{AsyncConnection, Config, ConnId};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    runtime::Builder,
+};
+
+const NONCE: [u8; 16] = [0x42; 16];
+
+// Without tokio-macros, which #[tokio::test] needs, the runtime is built by hand
+fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(future)
+}
+
+#[test]
+fn res_pq() {
+    block_on(async {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (stream, peer) = listener.accept().await.unwrap();
+        let server = tokio::spawn(async move {
+            let config = Config {
+                stop_after_res_pq: true,
+                ..Default::default()
+            };
+            AsyncConnection::new(ConnId::new(peer), stream, &config)
+                .run()
+                .await
+        });
+
+        let mut packet = vec![0; 8];
+        packet.extend(0x51e57ac42770964ai64.to_le_bytes());
+        let body = tl::functions::ReqPqMulti { nonce: NONCE }.to_bytes();
+        packet.extend((body.len() as u32).to_le_bytes());
+        packet.extend(body);
+        let mut transport = Full::new();
+        let mut request = BytesMut::new();
+        transport.pack(&packet, &mut request);
+        client.write_all(&request).await.unwrap();
+
+        let mut frames = Vec::new();
+        client.read_to_end(&mut frames).await.unwrap();
+        let mut answer = BytesMut::new();
+        let len = transport.unpack(&frames, &mut answer).unwrap();
+        assert_eq!(len, frames.len());
+        let tl::enums::ResPq::Pq(res_pq) = tl::enums::ResPq::from_bytes(&answer[20..]).unwrap();
+        assert_eq!(res_pq.nonce, NONCE);
+        assert_eq!(res_pq.pq.len(), 8);
+
+        let outcome = server.await.unwrap().unwrap();
+        assert!(outcome.auth_key.is_none());
+    });
+}
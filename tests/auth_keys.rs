@@ -0,0 +1,83 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use srv::{
+    auth_keys::{AuthKey, AuthKeyStore},
+    ServerError,
+};
+
+fn temp_key(expires_in: u64) -> AuthKey {
+    AuthKey {
+        key: [0x42; 256],
+        expires_in: Some(Duration::from_secs(expires_in)),
+    }
+}
+
+#[test]
+fn temporary_key_expires() {
+    let store = AuthKeyStore::default();
+    let key = temp_key(1);
+    let start = Instant::now();
+    store.insert_at(&key, start);
+
+    let before = start + Duration::from_millis(999);
+    assert_eq!(store.get_at(key.id(), before).unwrap(), key.key);
+    let after = start + Duration::from_secs(1);
+    assert!(matches!(
+        store.get_at(key.id(), after),
+        Err(ServerError::ExpiredAuthKey(id)) if id == key.id()
+    ));
+    // It's forgotten once its expiry is reported
+    assert!(matches!(
+        store.get_at(key.id(), after),
+        Err(ServerError::UnknownAuthKey(_))
+    ));
+}
+
+#[test]
+fn permanent_key_doesnt_expire() {
+    let store = AuthKeyStore::default();
+    let key = AuthKey {
+        key: [0x42; 256],
+        expires_in: None,
+    };
+    let start = Instant::now();
+    store.insert_at(&key, start);
+    let later = start + Duration::from_secs(365 * 24 * 60 * 60);
+    assert_eq!(store.get_at(key.id(), later).unwrap(), key.key);
+}
+
+#[test]
+fn unknown_key() {
+    let store = AuthKeyStore::default();
+    assert!(matches!(
+        store.get(0x1234),
+        Err(ServerError::UnknownAuthKey(0x1234))
+    ));
+}
+
+mod serve {
+    use srv::ServerError;
+
+    use crate::common::{req_pq_multi, Client, ABRIDGED_TAG};
+
+    // An encrypted message with the given auth_key_id, as far as the server reads it
+    fn encrypted(auth_key_id: i64) -> Vec<u8> {
+        let mut packet = req_pq_multi();
+        packet[..8].copy_from_slice(&auth_key_id.to_le_bytes());
+        let mut frame = vec![(packet.len() / 4) as u8];
+        frame.extend(packet);
+        frame
+    }
+
+    #[test]
+    fn unknown_key_rejected() {
+        let mut client = Client::connect(ABRIDGED_TAG);
+        client.send(encrypted(0x1234));
+
+        let (answer, res) = client.receive_all();
+        assert_eq!(answer, [0x01, 0x6c, 0xfe, 0xff, 0xff]);
+        assert!(matches!(res, Err(ServerError::UnknownAuthKey(0x1234))));
+    }
+}
@@ -0,0 +1,82 @@
+mod common;
+
+use common::{
+    config_with_auth_key, decrypt_answer, encrypted_message, message_id, Client, ABRIDGED_TAG,
+};
+
+fn ping() -> Vec<u8> {
+    let mut ping = 0x7abe77ecu32.to_le_bytes().to_vec();
+    ping.extend(0x42i64.to_le_bytes());
+    ping
+}
+
+fn msgs_ack(message_id: i64) -> Vec<u8> {
+    let mut msgs_ack = 0x62d6b459u32.to_le_bytes().to_vec();
+    // vector#1cb5c415 of one long
+    msgs_ack.extend(0x1cb5c415u32.to_le_bytes());
+    msgs_ack.extend(1u32.to_le_bytes());
+    msgs_ack.extend(message_id.to_le_bytes());
+    msgs_ack
+}
+
+// Sends the message and returns the error_code of the bad_msg_notification
+fn error_code(message_id: i64, seq_no: i32, body: &[u8]) -> i32 {
+    let mut client = Client::connect_with(ABRIDGED_TAG, config_with_auth_key());
+    client.send_abridged(encrypted_message(message_id, seq_no, body));
+    let answer = decrypt_answer(&client.receive_abridged());
+    client.close_write();
+    client.receive_all().1.unwrap();
+
+    assert_eq!(answer[28..32], 20u32.to_le_bytes());
+    assert_eq!(answer[32..36], 0xa7eff811u32.to_le_bytes());
+    assert_eq!(answer[36..44], message_id.to_le_bytes());
+    assert_eq!(answer[44..48], seq_no.to_le_bytes());
+    i32::from_le_bytes(answer[48..52].try_into().unwrap())
+}
+
+#[test]
+fn stale_message_id() {
+    let ten_minutes_ago = message_id() - (600 << 32);
+    assert_eq!(error_code(ten_minutes_ago, 1, &ping()), 16);
+}
+
+#[test]
+fn future_message_id() {
+    let in_a_minute = message_id() + (60 << 32);
+    assert_eq!(error_code(in_a_minute, 1, &ping()), 17);
+}
+
+#[test]
+fn message_id_not_divisible_by_4() {
+    assert_eq!(error_code(message_id() + 1, 1, &ping()), 18);
+}
+
+#[test]
+fn odd_seq_no_of_ack() {
+    let id = message_id();
+    assert_eq!(error_code(id, 1, &msgs_ack(id - 4)), 34);
+}
+
+#[test]
+fn even_seq_no_of_ping() {
+    assert_eq!(error_code(message_id(), 2, &ping()), 35);
+}
+
+#[test]
+fn seq_no_going_back() {
+    let mut client = Client::connect_with(ABRIDGED_TAG, config_with_auth_key());
+    let first = message_id();
+    client.send_abridged(encrypted_message(first, 3, &ping()));
+    client.receive_abridged();
+    // A later message with a lower seq_no
+    client.send_abridged(encrypted_message(first + 4, 1, &ping()));
+    let answer = decrypt_answer(&client.receive_abridged());
+    assert_eq!(answer[32..36], 0xa7eff811u32.to_le_bytes());
+    assert_eq!(answer[48..52], 32i32.to_le_bytes());
+    // An earlier message with a higher seq_no
+    client.send_abridged(encrypted_message(first - 4, 5, &ping()));
+    let answer = decrypt_answer(&client.receive_abridged());
+    assert_eq!(answer[48..52], 33i32.to_le_bytes());
+    client.close_write();
+    client.receive_all().1.unwrap();
+}
@@ -0,0 +1,86 @@
+mod common;
+
+use std::{
+    io::Read,
+    net::{TcpListener, TcpStream},
+    process::{Child, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use common::{Client, ABRIDGED_TAG};
+
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn spawn(listener: &TcpListener, args: &[&str]) -> Server {
+    Server(
+        Command::new(env!("CARGO_BIN_EXE_srv"))
+            .args(["--bind", &listener.local_addr().unwrap().to_string()])
+            .args(["--log-level", "warn"])
+            .args(args)
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap(),
+    )
+}
+
+#[test]
+fn bound_once_released() {
+    let held = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = held.local_addr().unwrap();
+    let mut server = spawn(&held, &["--bind-retries", "10", "--bind-retry-delay", "50"]);
+    thread::sleep(Duration::from_millis(300));
+    drop(held);
+
+    let start = Instant::now();
+    while TcpStream::connect(addr).is_err() {
+        assert!(server.0.try_wait().unwrap().is_none(), "server exited");
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "server didn't start"
+        );
+        thread::sleep(Duration::from_millis(50));
+    }
+    let mut client = Client::connect_to(addr, ABRIDGED_TAG);
+    client.handshake();
+
+    let _ = server.0.kill();
+    let mut log = String::new();
+    server
+        .0
+        .stderr
+        .take()
+        .unwrap()
+        .read_to_string(&mut log)
+        .unwrap();
+    assert!(
+        log.contains(&format!("{} is in use, retry 1 of 10 in 50ms", addr)),
+        "{}",
+        log
+    );
+}
+
+#[test]
+fn gives_up() {
+    let held = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut server = spawn(&held, &["--bind-retries", "2", "--bind-retry-delay", "10"]);
+    let status = server.0.wait().unwrap();
+    assert!(!status.success());
+    let mut log = String::new();
+    server
+        .0
+        .stderr
+        .take()
+        .unwrap()
+        .read_to_string(&mut log)
+        .unwrap();
+    assert!(log.contains("retry 2 of 2 in 20ms"), "{}", log);
+    assert!(!log.contains("retry 3"), "{}", log);
+}
@@ -0,0 +1,175 @@
+mod common;
+
+use aes::cipher::{KeyIvInit, StreamCipher};
+use bytes::BytesMut;
+use common::{req_pq_multi, ABRIDGED_TAG, INTERMEDIATE_TAG};
+use grammers_mtproto::transport::{self, Full, Transport};
+use rand::RngCore;
+use srv::{
+    frame_abridged, frame_intermediate, validate_obfuscation_header, Codec, ConnId,
+    ObfuscationKeys, ServerError,
+};
+
+type Aes256Ctr64Be = ctr::Ctr64BE<aes::Aes256>;
+
+const MAX_PACKET: usize = 1 << 20;
+
+fn conn_id() -> ConnId {
+    ConnId::unaddressed("test")
+}
+
+// The frames of the Full transport for `packets`, numbered from 0
+fn full_frames(packets: &[Vec<u8>]) -> Vec<u8> {
+    let mut transport = Full::new();
+    let mut frames = BytesMut::new();
+    for packet in packets {
+        transport.pack(packet, &mut frames);
+    }
+    frames.to_vec()
+}
+
+// Like the connections, the first 8 bytes tell the transport apart
+fn full_codec(frames: &mut [u8]) -> Codec {
+    let mut codec = Codec::full(conn_id(), frames[..8].try_into().unwrap(), MAX_PACKET).unwrap();
+    codec.feed(&mut frames[8..]).unwrap();
+    codec
+}
+
+#[test]
+fn full_frames_unpacked() {
+    let packets = [req_pq_multi(), vec![0x42; 64]];
+    let mut codec = full_codec(&mut full_frames(&packets));
+    for packet in &packets {
+        assert_eq!(codec.unpack().unwrap().unwrap()[..], packet[..]);
+    }
+    assert!(codec.unpack().unwrap().is_none());
+}
+
+#[test]
+fn full_crc_corrupted() {
+    let mut frames = full_frames(&[req_pq_multi()]);
+    *frames.last_mut().unwrap() ^= 0x01;
+    let mut codec = full_codec(&mut frames);
+    assert!(matches!(
+        codec.unpack(),
+        Err(ServerError::TransportFrame(transport::Error::BadCrc { .. }))
+    ));
+}
+
+#[test]
+fn full_payload_corrupted() {
+    let mut frames = full_frames(&[req_pq_multi()]);
+    frames[20] ^= 0x01;
+    let mut codec = full_codec(&mut frames);
+    assert!(matches!(
+        codec.unpack(),
+        Err(ServerError::TransportFrame(transport::Error::BadCrc { .. }))
+    ));
+}
+
+#[test]
+fn full_seq_no_corrupted() {
+    let mut frames = full_frames(&[req_pq_multi(), req_pq_multi()]);
+    // The seq_no of the second frame
+    let second = frames.len() / 2;
+    frames[second + 4..second + 8].copy_from_slice(&5u32.to_le_bytes());
+    let mut codec = full_codec(&mut frames);
+    assert!(codec.unpack().unwrap().is_some());
+    assert!(matches!(
+        codec.unpack(),
+        Err(ServerError::TransportFrame(transport::Error::BadSeq {
+            expected: 1,
+            got: 5
+        }))
+    ));
+}
+
+// A frame sent again keeps its seq_no
+#[test]
+fn full_frame_replayed() {
+    let mut frames = full_frames(&[req_pq_multi()]);
+    frames.extend(frames.clone());
+    let mut codec = full_codec(&mut frames);
+    assert!(codec.unpack().unwrap().is_some());
+    assert!(matches!(
+        codec.unpack(),
+        Err(ServerError::TransportFrame(transport::Error::BadSeq {
+            expected: 1,
+            got: 0
+        }))
+    ));
+}
+
+// A codec for the obfuscated transport of `tag`, and the cipher of the client
+fn obfuscated_codec(tag: [u8; 4]) -> (Codec, Aes256Ctr64Be) {
+    let mut init = [0; 64];
+    loop {
+        rand::thread_rng().fill_bytes(&mut init);
+        if validate_obfuscation_header(&init).is_ok() {
+            break;
+        }
+    }
+    init[56..60].copy_from_slice(&tag);
+    init[60..62].copy_from_slice(&2i16.to_le_bytes());
+    let keys = ObfuscationKeys::derive(&init);
+    let mut encryptor = Aes256Ctr64Be::new(&keys.encrypt_key.into(), &keys.encrypt_iv.into());
+    let mut encrypted_init = init;
+    encryptor.apply_keystream(&mut encrypted_init);
+    init[56..].copy_from_slice(&encrypted_init[56..]);
+    let codec = Codec::obfuscated(conn_id(), init, MAX_PACKET, None, None).unwrap();
+    (codec, encryptor)
+}
+
+// Feeds `frame` in two chunks split at `at`, nothing is unpacked before the second
+fn feed_split(codec: &mut Codec, encryptor: &mut Aes256Ctr64Be, mut frame: Vec<u8>, at: usize) {
+    encryptor.apply_keystream(&mut frame);
+    let (first, second) = frame.split_at_mut(at);
+    codec.feed(first).unwrap();
+    assert!(codec.unpack().unwrap().is_none(), "split at {}", at);
+    codec.feed(second).unwrap();
+}
+
+#[test]
+fn full_frame_in_two_reads() {
+    let mut frames = full_frames(&[req_pq_multi()]);
+    let (first, second) = frames.split_at_mut(20);
+    let mut codec = full_codec(first);
+    assert!(codec.unpack().unwrap().is_none());
+    codec.feed(second).unwrap();
+    assert_eq!(codec.unpack().unwrap().unwrap()[..], req_pq_multi()[..]);
+}
+
+#[test]
+fn intermediate_frame_in_two_reads() {
+    let packet = req_pq_multi();
+    // Within the length, at its end and within the packet
+    for at in [2, 4, 20] {
+        let (mut codec, mut encryptor) = obfuscated_codec(INTERMEDIATE_TAG);
+        feed_split(&mut codec, &mut encryptor, frame_intermediate(&packet), at);
+        assert_eq!(codec.unpack().unwrap().unwrap()[..], packet[..]);
+        assert!(codec.unpack().unwrap().is_none());
+    }
+}
+
+#[test]
+fn abridged_frame_in_two_reads() {
+    // The extended length takes 0x7f and 3 more bytes
+    let packet: Vec<u8> = (0..0x80 * 4).map(|i| i as u8).collect();
+    for at in [1, 2, 4, 100] {
+        let (mut codec, mut encryptor) = obfuscated_codec(ABRIDGED_TAG);
+        feed_split(&mut codec, &mut encryptor, frame_abridged(&packet), at);
+        assert_eq!(codec.unpack().unwrap().unwrap()[..], packet[..]);
+    }
+}
+
+#[test]
+fn partial_word_not_packed() {
+    let (mut codec, _) = obfuscated_codec(ABRIDGED_TAG);
+    for len in [1, 3, 5, 85] {
+        assert!(matches!(
+            codec.pack(&vec![0; len]),
+            Err(ServerError::UnalignedPacket(got)) if got == len
+        ));
+    }
+    assert!(codec.pack(&[0; 84]).is_ok());
+}
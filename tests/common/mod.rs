@@ -0,0 +1,336 @@
+// Each test crate only uses a part of the helpers
+#![allow(dead_code)]
+
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use aes::cipher::{KeyIvInit, StreamCipher};
+use grammers_tl_types::{self as tl, Deserializable, Serializable};
+use num_bigint::BigUint;
+use rand::RngCore;
+use sha1::{Digest, Sha1};
+use srv::{
+    auth_keys::AuthKey,
+    crypto::{ige_decrypt, ige_encrypt},
+    error::Result,
+    handle_connection,
+    keys::{auth_key_id, derive_message_aes, derive_tmp_aes, msg_key},
+    message_id::{Clock, MessageIdProvider},
+    pq,
+    rsa_key::TELEGRAM_FINGERPRINT,
+    validate_obfuscation_header, Config, ObfuscationKeys,
+};
+
+type Aes256Ctr64Be = ctr::Ctr64BE<aes::Aes256>;
+
+// The DC the client tells in the obfuscation header
+pub const DC_ID: i16 = 2;
+pub const ABRIDGED_TAG: [u8; 4] = [0xef; 4];
+pub const INTERMEDIATE_TAG: [u8; 4] = [0xee; 4];
+
+// A client of the obfuscated transport, which frames the packets itself
+pub struct Client {
+    stream: TcpStream,
+    encryptor: Aes256Ctr64Be,
+    decryptor: Aes256Ctr64Be,
+    // None when connected to a server of another process
+    server: Option<JoinHandle<Result<()>>>,
+}
+
+impl Client {
+    // Connects with the obfuscated transport of `tag` to a server of its own
+    pub fn connect(tag: [u8; 4]) -> Self {
+        Self::connect_with(tag, Config::default())
+    }
+
+    pub fn connect_with(tag: [u8; 4], config: Config) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        let server = thread::spawn(move || handle_connection(server, &config));
+        Self::obfuscated(stream, tag, Some(server))
+    }
+
+    // Connects with the obfuscated transport of `tag` to a running server
+    pub fn connect_to(addr: SocketAddr, tag: [u8; 4]) -> Self {
+        Self::obfuscated(TcpStream::connect(addr).unwrap(), tag, None)
+    }
+
+    fn obfuscated(
+        mut stream: TcpStream,
+        tag: [u8; 4],
+        server: Option<JoinHandle<Result<()>>>,
+    ) -> Self {
+        let mut init = [0; 64];
+        loop {
+            rand::thread_rng().fill_bytes(&mut init);
+            if validate_obfuscation_header(&init).is_ok() {
+                break;
+            }
+        }
+        init[56..60].copy_from_slice(&tag);
+        init[60..62].copy_from_slice(&DC_ID.to_le_bytes());
+        let keys = ObfuscationKeys::derive(&init);
+        let mut encryptor = Aes256Ctr64Be::new(&keys.encrypt_key.into(), &keys.encrypt_iv.into());
+        let decryptor = Aes256Ctr64Be::new(&keys.decrypt_key.into(), &keys.decrypt_iv.into());
+        let mut encrypted_init = init;
+        encryptor.apply_keystream(&mut encrypted_init);
+        init[56..].copy_from_slice(&encrypted_init[56..]);
+        stream.write_all(&init).unwrap();
+
+        Self {
+            stream,
+            encryptor,
+            decryptor,
+            server,
+        }
+    }
+
+    pub fn send(&mut self, mut frame: Vec<u8>) {
+        self.encryptor.apply_keystream(&mut frame);
+        self.stream.write_all(&frame).unwrap();
+    }
+
+    pub fn send_abridged(&mut self, packet: Vec<u8>) {
+        assert!(packet.len() < 0x7f * 4);
+        let mut frame = vec![(packet.len() / 4) as u8];
+        frame.extend(packet);
+        self.send(frame);
+    }
+
+    pub fn receive_abridged(&mut self) -> Vec<u8> {
+        let len = match self.receive(1)[0] {
+            0x7f => {
+                let mut len = [0; 4];
+                len[..3].copy_from_slice(&self.receive(3));
+                u32::from_le_bytes(len) as usize
+            }
+            len => len as usize,
+        };
+        self.receive(len * 4)
+    }
+
+    pub fn receive(&mut self, len: usize) -> Vec<u8> {
+        let mut answer = vec![0; len];
+        self.stream.read_exact(&mut answer).unwrap();
+        self.decryptor.apply_keystream(&mut answer);
+        answer
+    }
+
+    // Tells the server there is nothing more to read
+    pub fn close_write(&self) {
+        self.stream.shutdown(Shutdown::Write).unwrap();
+    }
+
+    // Runs the handshake against a server without RSA keys, which takes new_nonce
+    // for zeroes, and returns the auth key
+    pub fn handshake(&mut self) -> [u8; 256] {
+        self.send_abridged(req_pq_multi());
+        let tl::enums::ResPq::Pq(res_pq) =
+            tl::enums::ResPq::from_bytes(&self.receive_abridged()[20..]).unwrap();
+        let (p, q) = pq::factorize(u64::from_be_bytes(res_pq.pq[..].try_into().unwrap())).unwrap();
+        let server_nonce = res_pq.server_nonce;
+
+        let req_dh_params = tl::functions::ReqDhParams {
+            nonce: NONCE,
+            server_nonce,
+            p: p.to_be_bytes().to_vec(),
+            q: q.to_be_bytes().to_vec(),
+            public_key_fingerprint: TELEGRAM_FINGERPRINT,
+            encrypted_data: vec![0; 256],
+        };
+        self.send_abridged(unencrypted_message(&req_dh_params.to_bytes()));
+        let tl::enums::ServerDhParams::Ok(server_dh_params) =
+            tl::enums::ServerDhParams::from_bytes(&self.receive_abridged()[20..]).unwrap()
+        else {
+            panic!("expected server_DH_params_ok");
+        };
+        let (tmp_aes_key, tmp_aes_iv) = derive_tmp_aes([0; 32], server_nonce);
+        let answer_with_hash = ige_decrypt(
+            &server_dh_params.encrypted_answer,
+            &tmp_aes_key,
+            &tmp_aes_iv,
+        )
+        .unwrap();
+        let tl::enums::ServerDhInnerData::Data(inner) =
+            tl::enums::ServerDhInnerData::from_bytes(&answer_with_hash[20..]).unwrap();
+
+        let dh_prime = BigUint::from_bytes_be(&inner.dh_prime);
+        let mut b = [0; 256];
+        rand::thread_rng().fill_bytes(&mut b);
+        let b = BigUint::from_bytes_be(&b);
+        let g_b = BigUint::from(inner.g as u32).modpow(&b, &dh_prime);
+        let data = tl::enums::ClientDhInnerData::Data(tl::types::ClientDhInnerData {
+            nonce: NONCE,
+            server_nonce,
+            retry_id: 0,
+            g_b: g_b.to_bytes_be(),
+        })
+        .to_bytes();
+        let mut data_with_hash = Sha1::digest(&data).to_vec();
+        data_with_hash.extend(data);
+        data_with_hash.resize(data_with_hash.len().next_multiple_of(16), 0);
+        let set_client_dh_params = tl::functions::SetClientDhParams {
+            nonce: NONCE,
+            server_nonce,
+            encrypted_data: ige_encrypt(&data_with_hash, &tmp_aes_key, &tmp_aes_iv).unwrap(),
+        };
+        self.send_abridged(unencrypted_message(&set_client_dh_params.to_bytes()));
+        let answer = tl::enums::SetClientDhParamsAnswer::from_bytes(&self.receive_abridged()[20..]);
+        assert!(matches!(
+            answer,
+            Ok(tl::enums::SetClientDhParamsAnswer::DhGenOk(_))
+        ));
+
+        let g_a = BigUint::from_bytes_be(&inner.g_a);
+        let g_ab = g_a.modpow(&b, &dh_prime).to_bytes_be();
+        let mut auth_key = [0; 256];
+        auth_key[256 - g_ab.len()..].copy_from_slice(&g_ab);
+        auth_key
+    }
+
+    // Whether the server closed the connection within `wait`, it must not send
+    // anything meanwhile
+    pub fn is_closed(&mut self, wait: Duration) -> bool {
+        self.stream.set_read_timeout(Some(wait)).unwrap();
+        let res = self.stream.read(&mut [0]);
+        self.stream.set_read_timeout(None).unwrap();
+        match res {
+            Ok(0) => true,
+            Ok(_) => panic!("unexpected data"),
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => false,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    // Everything the server sends until it closes the connection
+    pub fn receive_all(mut self) -> (Vec<u8>, Result<()>) {
+        let mut answer = Vec::new();
+        self.stream.read_to_end(&mut answer).unwrap();
+        self.decryptor.apply_keystream(&mut answer);
+        let res = self.server.map_or(Ok(()), |server| server.join().unwrap());
+        (answer, res)
+    }
+}
+
+// The nonce of req_pq_multi()
+pub const NONCE: [u8; 16] = [0x42; 16];
+
+// An unencrypted req_pq_multi
+pub fn req_pq_multi() -> Vec<u8> {
+    let mut packet = vec![0; 8];
+    packet.extend(0x51e57ac42770964ai64.to_le_bytes());
+    packet.extend(20u32.to_le_bytes());
+    packet.extend(0xbe7e8ef1u32.to_le_bytes());
+    packet.extend(NONCE);
+    packet
+}
+
+pub fn unencrypted_message(body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![0; 8];
+    packet.extend(message_id().to_le_bytes());
+    packet.extend((body.len() as u32).to_le_bytes());
+    packet.extend(body);
+    packet
+}
+
+pub const SALT: i64 = 0x1111111111111111;
+pub const SESSION_ID: i64 = 0x2222222222222222;
+
+// The auth key 00 01 .. ff
+pub fn auth_key() -> [u8; 256] {
+    let mut auth_key = [0; 256];
+    for (i, byte) in auth_key.iter_mut().enumerate() {
+        *byte = i as u8;
+    }
+    auth_key
+}
+
+// A config which knows `auth_key()`
+pub fn config_with_auth_key() -> Config {
+    let config = Config::default();
+    config.auth_keys.insert(&AuthKey {
+        key: auth_key(),
+        expires_in: None,
+    });
+    config
+}
+
+// A msg_id of the client for the current time
+pub fn message_id() -> i64 {
+    Clock.now() & !3
+}
+
+// An encrypted message of the client, with `auth_key()`
+pub fn encrypted_message(message_id: i64, seq_no: i32, body: &[u8]) -> Vec<u8> {
+    encrypted_message_with(&auth_key(), message_id, seq_no, body)
+}
+
+pub fn encrypted_message_with(
+    auth_key: &[u8; 256],
+    message_id: i64,
+    seq_no: i32,
+    body: &[u8],
+) -> Vec<u8> {
+    encrypted_message_in(auth_key, SESSION_ID, message_id, seq_no, body)
+}
+
+pub fn encrypted_message_in(
+    auth_key: &[u8; 256],
+    session_id: i64,
+    message_id: i64,
+    seq_no: i32,
+    body: &[u8],
+) -> Vec<u8> {
+    let mut plaintext = Vec::new();
+    plaintext.extend(SALT.to_le_bytes());
+    plaintext.extend(session_id.to_le_bytes());
+    plaintext.extend(message_id.to_le_bytes());
+    plaintext.extend(seq_no.to_le_bytes());
+    plaintext.extend((body.len() as u32).to_le_bytes());
+    plaintext.extend(body);
+    plaintext.resize(
+        plaintext.len() + 12 + (16 - (plaintext.len() + 12) % 16) % 16,
+        0,
+    );
+
+    let msg_key = msg_key(auth_key, &plaintext, 0);
+    let (key, iv) = derive_message_aes(auth_key, &msg_key, 0);
+    let mut packet = auth_key_id(auth_key).to_le_bytes().to_vec();
+    packet.extend(msg_key);
+    packet.extend(ige_encrypt(&plaintext, &key, &iv).unwrap());
+    packet
+}
+
+// The plaintext of an encrypted answer of the server, after checking its msg_key
+pub fn decrypt_answer(packet: &[u8]) -> Vec<u8> {
+    decrypt_answer_with(&auth_key(), packet)
+}
+
+pub fn decrypt_answer_with(auth_key: &[u8; 256], packet: &[u8]) -> Vec<u8> {
+    assert_eq!(packet[..8], auth_key_id(auth_key).to_le_bytes());
+    let answer_msg_key: [u8; 16] = packet[8..24].try_into().unwrap();
+    let (key, iv) = derive_message_aes(auth_key, &answer_msg_key, 8);
+    let plaintext = ige_decrypt(&packet[24..], &key, &iv).unwrap();
+    assert_eq!(msg_key(auth_key, &plaintext, 8), answer_msg_key);
+    plaintext
+}
+
+// The first answer of a session comes in a container after new_session_created.
+// Returns the plaintext the other answer would have on its own, without padding
+pub fn skip_new_session_created(plaintext: &[u8]) -> Vec<u8> {
+    assert_eq!(plaintext[32..36], 0x73f1f8dcu32.to_le_bytes());
+    assert_eq!(plaintext[36..40], 2u32.to_le_bytes());
+    // msg_id, seq_no and the length of the first message
+    assert_eq!(plaintext[52..56], 28u32.to_le_bytes());
+    assert_eq!(plaintext[56..60], 0x9ec20908u32.to_le_bytes());
+    let pos = 56 + 28;
+    let len = u32::from_le_bytes(plaintext[pos + 12..pos + 16].try_into().unwrap()) as usize;
+    let mut answer = plaintext[..16].to_vec();
+    answer.extend(&plaintext[pos..pos + 16 + len]);
+    answer
+}
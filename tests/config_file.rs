@@ -0,0 +1,211 @@
+mod common;
+
+use std::{
+    fs,
+    io::Read,
+    net::{TcpListener, TcpStream},
+    process::{Child, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use srv::{
+    config_file::{ConfigFile, Value},
+    ServerError,
+};
+
+use common::{req_pq_multi, Client, ABRIDGED_TAG};
+
+const SAMPLE: &str = r#"
+# A sample config
+bind = ["127.0.0.1:11337", "[::1]:11337",]
+max-connections = 128   # more than the default
+rate_limit = 2.5
+min-layer = -1
+log-level = "debug"
+dump-dir = 'C:\dumps'
+fault = ["delay=100", "truncate@0.1"]
+stop-after-res-pq = true
+ipv6-only = false
+"#;
+
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn parse_err(toml: &str) -> (usize, String) {
+    match ConfigFile::parse(toml) {
+        Err(ServerError::InvalidConfigFile { line, reason }) => (line, reason),
+        Err(e) => panic!("unexpected error {}", e),
+        Ok(_) => panic!("parsed {:?}", toml),
+    }
+}
+
+#[test]
+fn parse_sample() {
+    let file = ConfigFile::parse(SAMPLE).unwrap();
+    let options: Vec<_> = file
+        .options
+        .iter()
+        .map(|(key, value)| (key.as_str(), value))
+        .collect();
+    assert_eq!(
+        options,
+        [
+            (
+                "bind",
+                &Value::Array(vec!["127.0.0.1:11337".into(), "[::1]:11337".into()])
+            ),
+            ("max_connections", &Value::Scalar("128".into())),
+            ("rate_limit", &Value::Scalar("2.5".into())),
+            ("min_layer", &Value::Scalar("-1".into())),
+            ("log_level", &Value::Scalar("debug".into())),
+            ("dump_dir", &Value::Scalar("C:\\dumps".into())),
+            (
+                "fault",
+                &Value::Array(vec!["delay=100".into(), "truncate@0.1".into()])
+            ),
+            ("stop_after_res_pq", &Value::Bool(true)),
+            ("ipv6_only", &Value::Bool(false)),
+        ]
+    );
+}
+
+#[test]
+fn args() {
+    let file = ConfigFile::parse(SAMPLE).unwrap();
+    assert_eq!(
+        file.args(|key| key == "bind" || key == "fault"),
+        [
+            "--max-connections=128",
+            "--rate-limit=2.5",
+            "--min-layer=-1",
+            "--log-level=debug",
+            "--dump-dir=C:\\dumps",
+            "--stop-after-res-pq",
+        ]
+    );
+    assert_eq!(
+        ConfigFile::parse("bind = [\"a\", \"b\"]")
+            .unwrap()
+            .args(|_| false),
+        ["--bind=a", "--bind=b"]
+    );
+}
+
+#[test]
+fn invalid() {
+    assert_eq!(
+        parse_err("\n[server]\n"),
+        (2, "tables aren't supported".into())
+    );
+    assert_eq!(parse_err("bind"), (1, "expected =".into()));
+    assert_eq!(parse_err("a b = 1"), (1, "invalid key".into()));
+    assert_eq!(
+        parse_err("timeout = 1\ntimeout = 2"),
+        (2, "the key is set twice".into())
+    );
+    assert_eq!(
+        parse_err("log-level = \"debug"),
+        (1, "unterminated string".into())
+    );
+    assert_eq!(
+        parse_err("timeout = 1 2"),
+        (1, "trailing characters after the value".into())
+    );
+    assert_eq!(
+        parse_err("bind = [\"a\" \"b\"]"),
+        (1, "expected , or ] in the array".into())
+    );
+    assert_eq!(parse_err("timeout ="), (1, "expected a value".into()));
+}
+
+#[test]
+fn command_line_takes_precedence() {
+    let [file_addr, cli_addr] = [(); 2].map(|_| {
+        TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+    });
+    let path = std::env::temp_dir().join(format!("tg_srv-{}.toml", std::process::id()));
+    fs::write(
+        &path,
+        format!(
+            "bind = [\"{}\"]\nlog-level = \"debug\"\nstop-after-res-pq = true\n",
+            file_addr
+        ),
+    )
+    .unwrap();
+    let mut server = Server(
+        Command::new(env!("CARGO_BIN_EXE_srv"))
+            .arg("--config")
+            .arg(&path)
+            .args(["--bind", &cli_addr.to_string()])
+            .args(["--log-level", "info"])
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap(),
+    );
+    let start = Instant::now();
+    while TcpStream::connect(cli_addr).is_err() {
+        assert!(
+            start.elapsed() < Duration::from_secs(5),
+            "server didn't start"
+        );
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    // The file still sets what the command line doesn't
+    let mut client = Client::connect_to(cli_addr, ABRIDGED_TAG);
+    client.send_abridged(req_pq_multi());
+    client.receive_abridged();
+    assert!(client.is_closed(Duration::from_secs(5)));
+    assert!(TcpStream::connect(file_addr).is_err());
+
+    let _ = server.0.kill();
+    let mut log = String::new();
+    server
+        .0
+        .stderr
+        .take()
+        .unwrap()
+        .read_to_string(&mut log)
+        .unwrap();
+    fs::remove_file(path).unwrap();
+    assert!(
+        log.contains(&format!("listening on {}", cli_addr)),
+        "{}",
+        log
+    );
+    assert!(
+        !log.contains(&format!("listening on {}", file_addr)),
+        "{}",
+        log
+    );
+    assert!(!log.contains(" DEBUG "), "{}", log);
+}
+
+#[test]
+fn unknown_option() {
+    let path = std::env::temp_dir().join(format!("tg_srv-unknown-{}.toml", std::process::id()));
+    fs::write(&path, "max-conections = 1\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_srv"))
+        .arg("--config")
+        .arg(&path)
+        .output()
+        .unwrap();
+    fs::remove_file(path).unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("unknown option max_conections"),
+        "{}",
+        stderr
+    );
+}
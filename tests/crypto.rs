@@ -0,0 +1,92 @@
+use aes::{cipher::KeyInit, Aes128};
+use srv::{
+    crypto::{ige_decrypt, ige_decrypt_with, ige_encrypt, ige_encrypt_with},
+    ServerError,
+};
+
+fn hex(s: &str) -> Vec<u8> {
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+fn range<const N: usize>(start: u8) -> [u8; N] {
+    std::array::from_fn(|i| start + i as u8)
+}
+
+// The AES-128 vectors of OpenSSL's test/igetest.c, the only published ones
+#[test]
+fn openssl_vectors() {
+    let vectors = [
+        (
+            range::<16>(0),
+            range::<32>(0),
+            vec![0; 32],
+            hex("1a8519a6557be652e9da8e43da4ef4453cf456b4ca488aa383c79c98b34797cb"),
+        ),
+        (
+            *b"This is an imple",
+            *b"mentation of IGE mode for OpenSS",
+            hex("99706487a1cde613bc6de0b6f24b1c7aa448c8b9c3403e3467a8cad89340f53b"),
+            b"L. Let's hope Ben got it right!\n".to_vec(),
+        ),
+    ];
+    for (key, iv, plaintext, ciphertext) in vectors {
+        let cipher = Aes128::new(&key.into());
+        assert_eq!(
+            ige_encrypt_with(&cipher, &plaintext, &iv).unwrap(),
+            ciphertext
+        );
+        assert_eq!(
+            ige_decrypt_with(&cipher, &ciphertext, &iv).unwrap(),
+            plaintext
+        );
+    }
+}
+
+// AES-256 as MTProto uses it, computed with the AES-256-ECB of OpenSSL and the IGE
+// chaining done separately
+#[test]
+fn aes_256_vector() {
+    let key = range::<32>(0);
+    let iv = range::<32>(0x20);
+    let plaintext = range::<48>(0x40);
+    let ciphertext = hex(concat!(
+        "b6b23cb46d2f43de2c67fc9a3a9e3510",
+        "4fad6ed15177969c1cebc616bcfa482c",
+        "b220e4d159bedfd570df191a805e9d9d",
+    ));
+    assert_eq!(ige_encrypt(&plaintext, &key, &iv).unwrap(), ciphertext);
+    assert_eq!(ige_decrypt(&ciphertext, &key, &iv).unwrap(), plaintext);
+}
+
+#[test]
+fn round_trip() {
+    let key = [0x42; 32];
+    let iv = [0x24; 32];
+    for len in [0, 16, 32, 256, 1024] {
+        let plaintext: Vec<u8> = (0..len).map(|i| (i * 7) as u8).collect();
+        let ciphertext = ige_encrypt(&plaintext, &key, &iv).unwrap();
+        assert_eq!(ciphertext.len(), len);
+        if len > 0 {
+            assert_ne!(ciphertext, plaintext);
+        }
+        assert_eq!(ige_decrypt(&ciphertext, &key, &iv).unwrap(), plaintext);
+    }
+}
+
+#[test]
+fn partial_block_rejected() {
+    for len in [1, 15, 17, 33] {
+        let data = vec![0; len];
+        assert!(matches!(
+            ige_encrypt(&data, &[0; 32], &[0; 32]),
+            Err(ServerError::CryptoError(_))
+        ));
+        assert!(matches!(
+            ige_decrypt(&data, &[0; 32], &[0; 32]),
+            Err(ServerError::CryptoError(_))
+        ));
+    }
+}
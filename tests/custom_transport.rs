@@ -0,0 +1,79 @@
+mod common;
+
+use bytes::{Buf, BufMut, BytesMut};
+use grammers_mtproto::transport::{Error, Transport};
+use grammers_tl_types::{self as tl, Deserializable};
+use srv::Config;
+
+use common::{req_pq_multi, Client, ABRIDGED_TAG};
+
+const WORDS_TAG: [u8; 4] = [0xaa; 4];
+
+// The length in words in 2 bytes, then the packet
+struct Words;
+
+impl Transport for Words {
+    fn pack(&mut self, input: &[u8], output: &mut BytesMut) {
+        output.put_u16_le((input.len() / 4) as u16);
+        output.put_slice(input);
+    }
+
+    fn unpack(&mut self, input: &[u8], output: &mut BytesMut) -> Result<usize, Error> {
+        let mut needle = input;
+        if needle.len() < 2 {
+            return Err(Error::MissingBytes);
+        }
+        let len = needle.get_u16_le() as usize * 4;
+        if needle.len() < len {
+            return Err(Error::MissingBytes);
+        }
+        output.put_slice(&needle[..len]);
+        Ok(2 + len)
+    }
+}
+
+fn config() -> Config {
+    Config {
+        custom_transport: Some(Box::new(|tag| {
+            (tag == WORDS_TAG).then(|| Box::new(Words) as Box<dyn Transport + Send>)
+        })),
+        stop_after_res_pq: true,
+        ..Default::default()
+    }
+}
+
+fn assert_res_pq(answer: &[u8]) {
+    let tl::enums::ResPq::Pq(res_pq) = tl::enums::ResPq::from_bytes(&answer[20..]).unwrap();
+    assert_eq!(res_pq.nonce, [0x42; 16]);
+}
+
+#[test]
+fn words() {
+    let mut client = Client::connect_with(WORDS_TAG, config());
+    let packet = req_pq_multi();
+    let mut frame = ((packet.len() / 4) as u16).to_le_bytes().to_vec();
+    frame.extend(packet);
+    client.send(frame);
+
+    let len = u16::from_le_bytes(client.receive(2).try_into().unwrap());
+    assert_res_pq(&client.receive(len as usize * 4));
+    client.receive_all().1.unwrap();
+}
+
+// The tags the hook doesn't pick are left to the built-in transports
+#[test]
+fn built_in_transports_kept() {
+    let mut client = Client::connect_with(ABRIDGED_TAG, config());
+    client.send_abridged(req_pq_multi());
+    assert_res_pq(&client.receive_abridged());
+    client.receive_all().1.unwrap();
+}
+
+#[test]
+fn unknown_tag() {
+    let client = Client::connect_with([0xab; 4], config());
+    assert!(matches!(
+        client.receive_all().1,
+        Err(srv::ServerError::UnknownTransport([0xab, 0xab, 0xab, 0xab]))
+    ));
+}
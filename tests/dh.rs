@@ -0,0 +1,68 @@
+use num_bigint::BigUint;
+use srv::dh::{generate_safe_prime, generator, is_safe_prime, prime, DhParams};
+
+fn pow2(exp: usize) -> BigUint {
+    BigUint::from(1u32) << exp
+}
+
+#[test]
+fn trivial_g_b() {
+    let dh = DhParams::default();
+    assert!(!dh.is_valid_g_b(&BigUint::from(0u32)));
+    assert!(!dh.is_valid_g_b(&BigUint::from(1u32)));
+    assert!(!dh.is_valid_g_b(&(&dh.prime - 1u32)));
+    assert!(!dh.is_valid_g_b(&dh.prime));
+}
+
+#[test]
+fn mid_range_g_b() {
+    let dh = DhParams::default();
+    assert!(dh.is_valid_g_b(&(prime() >> 1)));
+    // What a client computes
+    assert!(dh.is_valid_g_b(&dh.g_a(&BigUint::from(0x1234_5678_9abc_def0u64))));
+}
+
+#[test]
+fn safe_range_bounds() {
+    let dh = DhParams::default();
+    let margin = pow2(2048 - 64);
+    assert!(dh.is_valid_g_b(&margin));
+    assert!(!dh.is_valid_g_b(&(&margin - 1u32)));
+    // Small but above 1, in the range only the basic check accepts
+    assert!(!dh.is_valid_g_b(&BigUint::from(2u32)));
+    assert!(dh.is_valid_g_b(&(&dh.prime - &margin)));
+    assert!(!dh.is_valid_g_b(&(&dh.prime - &margin + 1u32)));
+    assert!(!dh.is_valid_g_b(&(&dh.prime - 2u32)));
+}
+
+#[test]
+fn default_prime_is_safe() {
+    let dh = DhParams::default();
+    assert_eq!(dh.prime.bits(), 2048);
+    assert!(is_safe_prime(&dh.prime));
+    assert_eq!(dh.g, 3);
+    assert_eq!(generator(&dh.prime), Some(3));
+}
+
+#[test]
+fn small_safe_primes() {
+    for p in [5u32, 7, 11, 23, 47, 59, 83, 107] {
+        assert!(is_safe_prime(&BigUint::from(p)), "{}", p);
+    }
+    // Primes with a composite (p - 1) / 2, and composites
+    for n in [13u32, 17, 19, 29, 31, 15, 21, 45] {
+        assert!(!is_safe_prime(&BigUint::from(n)), "{}", n);
+    }
+    assert!(!is_safe_prime(&(prime() + 2u32)));
+}
+
+#[test]
+fn generated_prime() {
+    let prime = generate_safe_prime(256);
+    assert_eq!(prime.bits(), 256);
+    assert!(is_safe_prime(&prime));
+    let g = generator(&prime).unwrap();
+    // g^((p - 1) / 2) = 1 is what makes g generate the subgroup of order (p - 1) / 2
+    let q = &prime >> 1;
+    assert_eq!(BigUint::from(g).modpow(&q, &prime), BigUint::from(1u32));
+}
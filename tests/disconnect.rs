@@ -0,0 +1,50 @@
+mod common;
+
+use std::{
+    io::{self, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    thread,
+};
+
+use srv::{handle_connection, Config, ServerError};
+
+use common::{req_pq_multi, Client, ABRIDGED_TAG};
+
+#[test]
+fn classification() {
+    assert!(ServerError::ConnectionClosed.is_disconnect());
+    for kind in [io::ErrorKind::UnexpectedEof, io::ErrorKind::ConnectionReset] {
+        assert!(ServerError::Io(kind.into()).is_disconnect());
+    }
+    assert!(!ServerError::Io(io::ErrorKind::WouldBlock.into()).is_disconnect());
+    assert!(!ServerError::InvalidObfuscationHeader("test").is_disconnect());
+    assert!(!ServerError::UnknownTransport([0; 4]).is_disconnect());
+}
+
+#[test]
+fn eof_in_the_obfuscation_header() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (server, _) = listener.accept().unwrap();
+    let server = thread::spawn(move || handle_connection(server, &Config::default()));
+    stream.write_all(&[0x42; 10]).unwrap();
+    stream.shutdown(Shutdown::Write).unwrap();
+
+    let e = server.join().unwrap().unwrap_err();
+    assert!(e.is_disconnect(), "{}", e);
+}
+
+#[test]
+fn eof_in_req_pq_multi() {
+    let mut client = Client::connect(ABRIDGED_TAG);
+    // Only the length and the first half of the packet
+    let packet = req_pq_multi();
+    let mut frame = vec![(packet.len() / 4) as u8];
+    frame.extend(&packet[..packet.len() / 2]);
+    client.send(frame);
+    client.close_write();
+
+    let (answer, res) = client.receive_all();
+    assert!(answer.is_empty());
+    assert!(matches!(res, Err(ServerError::ConnectionClosed)));
+}
@@ -0,0 +1,63 @@
+mod common;
+
+use bytes::BytesMut;
+use common::{req_pq_multi, NONCE};
+use grammers_mtproto::transport::{Full, Transport};
+use grammers_tl_types::{self as tl, Deserializable};
+use srv::{Config, ConnId, Driver, ServerError, Step};
+
+fn res_pq(transport: &mut Full, frame: &[u8]) -> tl::types::ResPq {
+    let mut answer = BytesMut::new();
+    assert_eq!(transport.unpack(frame, &mut answer).unwrap(), frame.len());
+    let tl::enums::ResPq::Pq(res_pq) = tl::enums::ResPq::from_bytes(&answer[20..]).unwrap();
+    res_pq
+}
+
+// Without any I/O, byte by byte
+#[test]
+fn res_pq_from_bytes() {
+    let config = Config::default();
+    let mut driver = Driver::new(ConnId::unaddressed("test"), &config);
+    let mut transport = Full::new();
+    let mut request = BytesMut::new();
+    transport.pack(&req_pq_multi(), &mut request);
+    for byte in request.iter_mut() {
+        assert_eq!(driver.poll(), Step::Read);
+        driver.feed(std::slice::from_mut(byte));
+    }
+
+    let Step::Write(frame) = driver.poll() else {
+        panic!("expected ResPq");
+    };
+    assert_eq!(res_pq(&mut transport, &frame).nonce, NONCE);
+    assert_eq!(driver.poll(), Step::Read);
+    // The client may not leave in the middle of the handshake
+    driver.feed(&mut []);
+    assert_eq!(driver.poll(), Step::Close);
+    assert!(matches!(
+        driver.finish(),
+        Err(ServerError::ConnectionClosed)
+    ));
+}
+
+#[test]
+fn stop_after_res_pq() {
+    let config = Config {
+        stop_after_res_pq: true,
+        ..Default::default()
+    };
+    let mut driver = Driver::new(ConnId::unaddressed("test"), &config);
+    let mut transport = Full::new();
+    let mut request = BytesMut::new();
+    transport.pack(&req_pq_multi(), &mut request);
+    assert_eq!(driver.poll(), Step::Read);
+    driver.feed(&mut request);
+
+    let Step::Write(frame) = driver.poll() else {
+        panic!("expected ResPq");
+    };
+    assert_eq!(res_pq(&mut transport, &frame).nonce, NONCE);
+    assert_eq!(driver.poll(), Step::Close);
+    let outcome = driver.finish().unwrap();
+    assert!(outcome.auth_key.is_none());
+}
@@ -0,0 +1,75 @@
+use srv::{messages::decrypt_message, ServerError};
+
+// A ping#7abe77ec with the auth key 00 01 .. ff, encrypted like a client does
+const PING: &str = "32d1586ea457dfc888bd0fe38a45aaf3750f8f41c334c84d02ddbd2c7f25e056b386e9f8e335621eeaabf3bff5ed40d29d38ae9d87f8091e294b87eda39842f9fc12a2736439e61924f2cde308143fb05142f79f9e18bb4f";
+
+fn auth_key() -> [u8; 256] {
+    let mut auth_key = [0; 256];
+    for (i, byte) in auth_key.iter_mut().enumerate() {
+        *byte = i as u8;
+    }
+    auth_key
+}
+
+fn hex(hex: &str) -> Vec<u8> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+#[test]
+fn known_ciphertext() {
+    let message = decrypt_message(&hex(PING), &auth_key()).unwrap();
+    assert_eq!(message.salt, 0x1111111111111111);
+    assert_eq!(message.session_id, 0x2222222222222222);
+    assert_eq!(message.message_id, 0x5e0b700a00000004);
+    assert_eq!(message.seq_no, 1);
+    assert_eq!(message.constructor(), Some(0x7abe77ec));
+    let mut body = 0x7abe77ecu32.to_le_bytes().to_vec();
+    body.extend(0x0123456789abcdefi64.to_le_bytes());
+    assert_eq!(message.body, body);
+}
+
+#[test]
+fn msg_key_mismatch() {
+    let mut packet = hex(PING);
+    packet[8] ^= 1;
+    assert!(matches!(
+        decrypt_message(&packet, &auth_key()),
+        Err(ServerError::InvalidEncryptedMessage("msg_key mismatch"))
+    ));
+}
+
+#[test]
+fn tampered_ciphertext() {
+    let mut packet = hex(PING);
+    *packet.last_mut().unwrap() ^= 1;
+    assert!(matches!(
+        decrypt_message(&packet, &auth_key()),
+        Err(ServerError::InvalidEncryptedMessage("msg_key mismatch"))
+    ));
+}
+
+#[test]
+fn other_auth_key() {
+    let mut auth_key = auth_key();
+    auth_key[0] ^= 1;
+    assert!(matches!(
+        decrypt_message(&hex(PING), &auth_key),
+        Err(ServerError::UnknownAuthKey(_))
+    ));
+}
+
+#[test]
+fn truncated() {
+    let packet = hex(PING);
+    assert!(matches!(
+        decrypt_message(&packet[..20], &auth_key()),
+        Err(ServerError::InvalidEncryptedMessage(_))
+    ));
+    assert!(matches!(
+        decrypt_message(&packet[..packet.len() - 1], &auth_key()),
+        Err(ServerError::CryptoError(_))
+    ));
+}
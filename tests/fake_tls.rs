@@ -0,0 +1,182 @@
+mod common;
+
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    thread,
+};
+
+use aes::cipher::{KeyIvInit, StreamCipher};
+use grammers_tl_types::{self as tl, Deserializable};
+use hmac::{Hmac, Mac, NewMac};
+use rand::RngCore;
+use sha2::Sha256;
+use srv::{
+    fake_tls::{ClientHello, FakeTlsSecret},
+    handle_connection, validate_obfuscation_header, Config, ObfuscationKeys, ServerError,
+};
+
+use common::{req_pq_multi, ABRIDGED_TAG, DC_ID};
+
+type Aes256Ctr64Be = ctr::Ctr64BE<aes::Aes256>;
+
+const SECRET: FakeTlsSecret = FakeTlsSecret([0x5e; 16]);
+const TIMESTAMP: u32 = 1_700_000_000;
+
+fn hmac(secret: &FakeTlsSecret, parts: &[&[u8]]) -> [u8; 32] {
+    let mut mac = Hmac::<Sha256>::new_from_slice(&secret.0).unwrap();
+    for part in parts {
+        mac.update(part);
+    }
+    mac.finalize().into_bytes().into()
+}
+
+// A ClientHello shaped like the ones of Telegram's clients: TLS 1.3 cipher suites,
+// the SNI of the disguise domain and padding up to 517 bytes, with the random set
+// from the HMAC of `secret` and the timestamp
+fn client_hello(secret: &FakeTlsSecret) -> Vec<u8> {
+    let mut session_id = [0; 32];
+    rand::thread_rng().fill_bytes(&mut session_id);
+    let mut body = vec![0x03, 0x03];
+    body.extend([0; 32]);
+    body.push(32);
+    body.extend(session_id);
+    body.extend([0x00, 0x06, 0x13, 0x01, 0x13, 0x02, 0x13, 0x03]);
+    body.extend([0x01, 0x00]);
+    let domain = b"example.com";
+    let mut extensions = vec![0x00, 0x00];
+    extensions.extend((domain.len() as u16 + 5).to_be_bytes());
+    extensions.extend((domain.len() as u16 + 3).to_be_bytes());
+    extensions.push(0x00);
+    extensions.extend((domain.len() as u16).to_be_bytes());
+    extensions.extend(domain);
+    // The padding extension fills the record up to 517 bytes
+    let padding = 517 - (5 + 4 + body.len() + 2 + extensions.len() + 4);
+    extensions.extend([0x00, 0x15]);
+    extensions.extend((padding as u16).to_be_bytes());
+    extensions.extend(vec![0; padding]);
+    body.extend((extensions.len() as u16).to_be_bytes());
+    body.extend(extensions);
+
+    let mut record = vec![0x16, 0x03, 0x01];
+    record.extend(((body.len() + 4) as u16).to_be_bytes());
+    record.push(0x01);
+    record.extend(&(body.len() as u32).to_be_bytes()[1..]);
+    record.extend(body);
+    assert_eq!(record.len(), 517);
+
+    let mut random = hmac(secret, &[&record]);
+    for (byte, timestamp) in random[28..].iter_mut().zip(TIMESTAMP.to_le_bytes()) {
+        *byte ^= timestamp;
+    }
+    record[11..43].copy_from_slice(&random);
+    record
+}
+
+#[test]
+fn parses_client_hello() {
+    let record = client_hello(&SECRET);
+    let client_hello = ClientHello::parse(&record, &SECRET).unwrap();
+    assert_eq!(client_hello.random, record[11..43]);
+    assert_eq!(client_hello.session_id, record[44..76]);
+    assert_eq!(client_hello.timestamp, TIMESTAMP);
+}
+
+#[test]
+fn rejects_client_hello_of_other_secret() {
+    let record = client_hello(&FakeTlsSecret([0x42; 16]));
+    assert!(matches!(
+        ClientHello::parse(&record, &SECRET),
+        Err(ServerError::FakeTls(_))
+    ));
+}
+
+#[test]
+fn parses_secret() {
+    let hex = "5e".repeat(16);
+    assert_eq!(hex.parse::<FakeTlsSecret>().unwrap(), SECRET);
+    // The ee form of a link, with the hex of example.com
+    let link = format!("ee{}6578616d706c652e636f6d", hex);
+    assert_eq!(link.parse::<FakeTlsSecret>().unwrap(), SECRET);
+    assert!("5e5e".parse::<FakeTlsSecret>().is_err());
+    assert!("zz".repeat(16).parse::<FakeTlsSecret>().is_err());
+}
+
+fn read_record(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+    let mut header = [0; 5];
+    stream.read_exact(&mut header).unwrap();
+    assert_eq!(header[1..3], [0x03, 0x03]);
+    let mut payload = vec![0; u16::from_be_bytes([header[3], header[4]]) as usize];
+    stream.read_exact(&mut payload).unwrap();
+    (header[0], payload)
+}
+
+fn record(kind: u8, payload: &[u8]) -> Vec<u8> {
+    let mut record = vec![kind, 0x03, 0x03];
+    record.extend((payload.len() as u16).to_be_bytes());
+    record.extend(payload);
+    record
+}
+
+#[test]
+fn res_pq_over_fake_tls() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (server, _) = listener.accept().unwrap();
+    let server = thread::spawn(move || {
+        let config = Config {
+            fake_tls: Some(SECRET),
+            stop_after_res_pq: true,
+            ..Default::default()
+        };
+        handle_connection(server, &config)
+    });
+
+    let hello = client_hello(&SECRET);
+    stream.write_all(&hello).unwrap();
+    // ServerHello, ChangeCipherSpec and the fake encrypted extensions
+    let mut answer = Vec::new();
+    for kind in [0x16, 0x14, 0x17] {
+        let (got, payload) = read_record(&mut stream);
+        assert_eq!(got, kind);
+        answer.extend(record(kind, &payload));
+    }
+    let random: [u8; 32] = answer[11..43].try_into().unwrap();
+    answer[11..43].fill(0);
+    assert_eq!(hmac(&SECRET, &[&hello[11..43], &answer]), random);
+
+    let mut init = [0; 64];
+    loop {
+        rand::thread_rng().fill_bytes(&mut init);
+        if validate_obfuscation_header(&init).is_ok() {
+            break;
+        }
+    }
+    init[56..60].copy_from_slice(&ABRIDGED_TAG);
+    init[60..62].copy_from_slice(&DC_ID.to_le_bytes());
+    let keys = ObfuscationKeys::derive_with_secret(&init, &SECRET);
+    let mut encryptor = Aes256Ctr64Be::new(&keys.encrypt_key.into(), &keys.encrypt_iv.into());
+    let mut decryptor = Aes256Ctr64Be::new(&keys.decrypt_key.into(), &keys.decrypt_iv.into());
+    let mut encrypted_init = init;
+    encryptor.apply_keystream(&mut encrypted_init);
+    init[56..].copy_from_slice(&encrypted_init[56..]);
+
+    let packet = req_pq_multi();
+    let mut frame = vec![(packet.len() / 4) as u8];
+    frame.extend(packet);
+    encryptor.apply_keystream(&mut frame);
+    let mut payload = init.to_vec();
+    payload.extend(frame);
+    let mut request = record(0x14, &[0x01]);
+    request.extend(record(0x17, &payload));
+    stream.write_all(&request).unwrap();
+
+    let (kind, mut res_pq) = read_record(&mut stream);
+    assert_eq!(kind, 0x17);
+    decryptor.apply_keystream(&mut res_pq);
+    assert_eq!(res_pq[0] as usize * 4, res_pq.len() - 1);
+    let tl::enums::ResPq::Pq(res_pq) = tl::enums::ResPq::from_bytes(&res_pq[21..]).unwrap();
+    assert_eq!(res_pq.nonce, [0x42; 16]);
+
+    server.join().unwrap().unwrap();
+}
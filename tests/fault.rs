@@ -0,0 +1,139 @@
+mod common;
+
+use std::time::Duration;
+
+use srv::{
+    fault::{self, Fault, FaultRule},
+    rng::ServerRng,
+    ConnId, ServerError,
+};
+
+#[test]
+fn parse() {
+    let rule = |fault, probability| FaultRule { fault, probability };
+    assert_eq!(
+        "drop-after-respq".parse::<FaultRule>().unwrap(),
+        rule(Fault::DropAfterResPq, 1.0)
+    );
+    assert_eq!(
+        "truncate@0.25".parse::<FaultRule>().unwrap(),
+        rule(Fault::Truncate, 0.25)
+    );
+    assert_eq!(
+        "corrupt-msg-key".parse::<FaultRule>().unwrap(),
+        rule(Fault::CorruptMsgKey, 1.0)
+    );
+    assert_eq!(
+        "delay=250@0.5".parse::<FaultRule>().unwrap(),
+        rule(Fault::Delay(Duration::from_millis(250)), 0.5)
+    );
+}
+
+#[test]
+fn parse_invalid() {
+    for s in [
+        "",
+        "drop",
+        "delay=",
+        "delay=-1",
+        "truncate@",
+        "truncate@2",
+        "truncate@x",
+    ] {
+        assert!(
+            matches!(s.parse::<FaultRule>(), Err(ServerError::InvalidFault(_))),
+            "{:?}",
+            s
+        );
+    }
+}
+
+#[test]
+fn seeded_rolls() {
+    let rules = ["drop-after-respq@0.5".parse().unwrap()];
+    let rolls = |rng: ServerRng| -> Vec<bool> {
+        (0..64)
+            .map(|_| fault::drop_after_res_pq(ConnId::unaddressed("test"), &rules, &rng))
+            .collect()
+    };
+    let first = rolls(ServerRng::seeded([7; 32]));
+    assert_eq!(first, rolls(ServerRng::seeded([7; 32])));
+    assert!(first.contains(&true) && first.contains(&false));
+}
+
+mod serve {
+    use std::time::{Duration, Instant};
+
+    use grammers_tl_types::{self as tl, Deserializable};
+    use srv::{
+        crypto::ige_decrypt,
+        fault::FaultRule,
+        keys::{derive_message_aes, msg_key},
+        Config,
+    };
+
+    use crate::common::{
+        auth_key, config_with_auth_key, encrypted_message, message_id, req_pq_multi, Client,
+        ABRIDGED_TAG,
+    };
+
+    fn config(faults: &[&str]) -> Config {
+        Config {
+            faults: faults.iter().map(|fault| fault.parse().unwrap()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn drop_after_res_pq() {
+        let mut client = Client::connect_with(ABRIDGED_TAG, config(&["drop-after-respq"]));
+        client.send_abridged(req_pq_multi());
+        let answer = client.receive_abridged();
+        assert!(tl::enums::ResPq::from_bytes(&answer[20..]).is_ok());
+        assert!(client.is_closed(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn truncate() {
+        let mut client = Client::connect_with(ABRIDGED_TAG, config(&["truncate"]));
+        client.send_abridged(req_pq_multi());
+        let answer = client.receive_abridged();
+        // res_pq is 84 bytes with a single fingerprint
+        assert_eq!(answer.len(), 40);
+        assert!(tl::enums::ResPq::from_bytes(&answer[20..]).is_err());
+    }
+
+    #[test]
+    fn never_injected() {
+        let mut client = Client::connect_with(ABRIDGED_TAG, config(&["truncate@0"]));
+        client.handshake();
+    }
+
+    #[test]
+    fn delay() {
+        let mut client = Client::connect_with(ABRIDGED_TAG, config(&["delay=300"]));
+        let start = Instant::now();
+        client.send_abridged(req_pq_multi());
+        client.receive_abridged();
+        assert!(start.elapsed() >= Duration::from_millis(300));
+    }
+
+    #[test]
+    fn corrupt_msg_key() {
+        let mut config = config_with_auth_key();
+        config.faults = vec!["corrupt-msg-key".parse::<FaultRule>().unwrap()];
+        let mut client = Client::connect_with(ABRIDGED_TAG, config);
+        let mut ping = 0x7abe77ecu32.to_le_bytes().to_vec();
+        ping.extend(0i64.to_le_bytes());
+        client.send_abridged(encrypted_message(message_id(), 1, &ping));
+        let answer = client.receive_abridged();
+
+        // Only the first bit is flipped, the rest of the answer is encrypted with the
+        // real msg_key
+        let mut real_msg_key: [u8; 16] = answer[8..24].try_into().unwrap();
+        real_msg_key[0] ^= 1;
+        let (key, iv) = derive_message_aes(&auth_key(), &real_msg_key, 8);
+        let plaintext = ige_decrypt(&answer[24..], &key, &iv).unwrap();
+        assert_eq!(msg_key(&auth_key(), &plaintext, 8), real_msg_key);
+    }
+}
@@ -0,0 +1,39 @@
+mod common;
+
+use common::{req_pq_multi, Client, INTERMEDIATE_TAG};
+use grammers_mtproto::transport;
+use srv::ServerError;
+
+// The length announces a byte more than the whole words of the packet
+#[test]
+fn length_and_payload_disagree() {
+    let mut client = Client::connect(INTERMEDIATE_TAG);
+    let packet = req_pq_multi();
+    let mut frame = (packet.len() as u32 + 1).to_le_bytes().to_vec();
+    frame.extend(packet);
+    frame.push(0);
+    client.send(frame);
+    client.close_write();
+
+    let (answer, res) = client.receive_all();
+    assert!(answer.is_empty());
+    assert!(matches!(
+        res,
+        Err(ServerError::TransportFrame(transport::Error::BadLen {
+            got: 41
+        }))
+    ));
+}
+
+#[test]
+fn consistent_frame() {
+    let mut client = Client::connect(INTERMEDIATE_TAG);
+    let packet = req_pq_multi();
+    let mut frame = (packet.len() as u32).to_le_bytes().to_vec();
+    frame.extend(packet);
+    client.send(frame);
+    let len = u32::from_le_bytes(client.receive(4).try_into().unwrap());
+    assert_eq!(len % 4, 0);
+    let answer = client.receive(len as usize);
+    assert_eq!(answer[20..24], 0x05162463u32.to_le_bytes());
+}
@@ -0,0 +1,105 @@
+mod common;
+
+use srv::salts::{SaltStore, MAX_FUTURE_SALTS, SALT_PERIOD};
+
+use common::{
+    config_with_auth_key, decrypt_answer, encrypted_message, message_id, skip_new_session_created,
+    Client, ABRIDGED_TAG, SALT, SESSION_ID,
+};
+
+// Some time in the middle of a window
+const NOW: i32 = 1_700_000_000 - 1_700_000_000 % SALT_PERIOD + 100;
+
+fn i32_at(data: &[u8], pos: usize) -> i32 {
+    i32::from_le_bytes(data[pos..pos + 4].try_into().unwrap())
+}
+
+fn i64_at(data: &[u8], pos: usize) -> i64 {
+    i64::from_le_bytes(data[pos..pos + 8].try_into().unwrap())
+}
+
+#[test]
+fn consecutive_windows() {
+    let store = SaltStore::default();
+    let salts = store.future_at(NOW, 3);
+    assert_eq!(salts.len(), 3);
+    assert_eq!(salts[0].valid_since, NOW - 100);
+    for pair in salts.windows(2) {
+        assert_eq!(pair[1].valid_since, pair[0].valid_since + SALT_PERIOD);
+        // Consecutive salts overlap for a window
+        assert_eq!(pair[0].valid_until, pair[1].valid_since + SALT_PERIOD);
+        assert_ne!(pair[0].salt, pair[1].salt);
+    }
+    assert_eq!(store.current_at(NOW), salts[0]);
+    // The same salts are answered until they expire
+    assert_eq!(store.future_at(NOW + 10, 3), salts);
+}
+
+#[test]
+fn clamped_count() {
+    let store = SaltStore::default();
+    assert_eq!(store.future_at(NOW, 0).len(), 1);
+    assert_eq!(store.future_at(NOW, 1000).len(), MAX_FUTURE_SALTS);
+}
+
+#[test]
+fn rotation() {
+    let store = SaltStore::default();
+    let salts = store.future_at(NOW, 2);
+    assert!(store.is_valid_at(salts[0].salt, NOW));
+    // Not valid yet
+    assert!(!store.is_valid_at(salts[1].salt, NOW));
+
+    let next = NOW + SALT_PERIOD;
+    assert_eq!(store.current_at(next), salts[1]);
+    // The previous salt is still accepted during the next window
+    assert!(store.is_valid_at(salts[0].salt, next));
+    assert!(store.is_valid_at(salts[1].salt, next));
+
+    let later = NOW + 2 * SALT_PERIOD;
+    assert!(!store.is_valid_at(salts[0].salt, later));
+    assert!(store.is_valid_at(salts[1].salt, later));
+    assert!(!store.is_valid_at(SALT, later));
+}
+
+#[test]
+fn get_future_salts() {
+    let mut client = Client::connect_with(ABRIDGED_TAG, config_with_auth_key());
+    let request_id = message_id();
+    let mut request = 0xb921bd04u32.to_le_bytes().to_vec();
+    request.extend(3i32.to_le_bytes());
+    client.send_abridged(encrypted_message(request_id, 1, &request));
+
+    let answer = skip_new_session_created(&decrypt_answer(&client.receive_abridged()));
+    client.close_write();
+    let (rest, res) = client.receive_all();
+    assert!(rest.is_empty());
+    res.unwrap();
+
+    assert_eq!(i64_at(&answer, 8), SESSION_ID);
+    assert_eq!(answer[24..28], 3i32.to_le_bytes());
+    // 4 + 8 + 4 + 4 and 16 bytes for each salt
+    assert_eq!(answer[28..32], (20u32 + 3 * 16).to_le_bytes());
+    let body = &answer[32..];
+    assert_eq!(body[..4], 0xae500895u32.to_le_bytes());
+    assert_eq!(i64_at(body, 4), request_id);
+    let now = i32_at(body, 12);
+    assert!((now as i64 - (request_id >> 32)).abs() <= 1);
+    // The vector is bare, there is only the count
+    assert_eq!(i32_at(body, 16), 3);
+    let salt = |i: usize| {
+        let pos = 20 + i * 16;
+        (
+            i32_at(body, pos),
+            i32_at(body, pos + 4),
+            i64_at(body, pos + 8),
+        )
+    };
+    let (valid_since, valid_until, current) = salt(0);
+    assert!(valid_since <= now && now < valid_until);
+    // The answer itself carries the current salt
+    assert_eq!(i64_at(&answer, 0), current);
+    for i in 1..3 {
+        assert_eq!(salt(i).0, valid_since + i as i32 * SALT_PERIOD);
+    }
+}
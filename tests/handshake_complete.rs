@@ -0,0 +1,53 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use common::{Client, ABRIDGED_TAG};
+use srv::{keys::auth_key_id, Config};
+
+#[test]
+fn same_key_as_the_client() {
+    let completed = Arc::new(Mutex::new(Vec::new()));
+    let config = Config {
+        on_handshake_complete: Some(Box::new({
+            let completed = completed.clone();
+            move |complete| {
+                completed.lock().unwrap().push((
+                    complete.auth_key.key,
+                    complete.auth_key_id,
+                    complete.nonces.nonce,
+                ))
+            }
+        })),
+        ..Default::default()
+    };
+    let mut client = Client::connect_with(ABRIDGED_TAG, config);
+    let auth_key = client.handshake();
+    let (_, res) = client.receive_all();
+    res.unwrap();
+
+    let completed = completed.lock().unwrap();
+    assert_eq!(completed.len(), 1);
+    let (key, id, nonce) = completed[0];
+    assert_eq!(key, auth_key);
+    assert_eq!(id, auth_key_id(&auth_key));
+    assert_eq!(nonce, [0x42; 16]);
+}
+
+#[test]
+fn not_called_for_an_unfinished_handshake() {
+    let called = Arc::new(Mutex::new(false));
+    let config = Config {
+        stop_after_res_pq: true,
+        on_handshake_complete: Some(Box::new({
+            let called = called.clone();
+            move |_| *called.lock().unwrap() = true
+        })),
+        ..Default::default()
+    };
+    let mut client = Client::connect_with(ABRIDGED_TAG, config);
+    client.send_abridged(common::req_pq_multi());
+    let (_, res) = client.receive_all();
+    res.unwrap();
+    assert!(!*called.lock().unwrap());
+}
@@ -0,0 +1,238 @@
+mod common;
+
+use common::{req_pq_multi, unencrypted_message, NONCE};
+use grammers_tl_types::{self as tl, Deserializable, Serializable};
+use num_bigint::BigUint;
+use sha1::{Digest, Sha1};
+use srv::{
+    crypto::{ige_decrypt, ige_encrypt},
+    dh::DhGenError,
+    keys::{auth_key_id, derive_tmp_aes},
+    pq,
+    rsa_key::TELEGRAM_FINGERPRINT,
+    stats::Stage,
+    Config, ConnId, HandshakeState, ServerError,
+};
+
+fn new_state() -> HandshakeState {
+    HandshakeState::new(ConnId::unaddressed("test"), None)
+}
+
+// Steps through ReqPqMulti, returning the ResPq
+fn res_pq(state: &mut HandshakeState, config: &Config) -> tl::types::ResPq {
+    let answer = state.step(config, &req_pq_multi()).unwrap().unwrap();
+    let tl::enums::ResPq::Pq(res_pq) = tl::enums::ResPq::from_bytes(&answer[20..]).unwrap();
+    res_pq
+}
+
+// Without RSA keys new_nonce is taken for zeroes
+fn req_dh_params(res_pq: &tl::types::ResPq) -> Vec<u8> {
+    let (p, q) = pq::factorize(u64::from_be_bytes(res_pq.pq[..].try_into().unwrap())).unwrap();
+    unencrypted_message(
+        &tl::functions::ReqDhParams {
+            nonce: NONCE,
+            server_nonce: res_pq.server_nonce,
+            p: p.to_be_bytes().to_vec(),
+            q: q.to_be_bytes().to_vec(),
+            public_key_fingerprint: TELEGRAM_FINGERPRINT,
+            encrypted_data: vec![0; 256],
+        }
+        .to_bytes(),
+    )
+}
+
+#[test]
+fn await_req_pq() {
+    let config = Config::default();
+    let mut state = new_state();
+    assert!(matches!(state, HandshakeState::AwaitReqPq { .. }));
+    assert_eq!(state.answered(), None);
+
+    let res_pq = res_pq(&mut state, &config);
+    assert_eq!(res_pq.nonce, NONCE);
+    assert!(matches!(state, HandshakeState::AwaitReqDhParams(_)));
+    assert_eq!(state.answered(), Some("ResPq"));
+    assert_eq!(config.stage_stats.count(Stage::ReqPqMulti), 1);
+}
+
+#[test]
+fn await_req_dh_params() {
+    let config = Config::default();
+    let mut state = new_state();
+    let res_pq = res_pq(&mut state, &config);
+
+    let answer = state
+        .step(&config, &req_dh_params(&res_pq))
+        .unwrap()
+        .unwrap();
+    let server_dh_params = tl::enums::ServerDhParams::from_bytes(&answer[20..]).unwrap();
+    assert!(matches!(server_dh_params, tl::enums::ServerDhParams::Ok(_)));
+    assert!(matches!(state, HandshakeState::AwaitSetClientDhParams(_)));
+    assert_eq!(state.answered(), Some("ServerDHParams"));
+    assert_eq!(config.stage_stats.count(Stage::ReqDHParams), 1);
+}
+
+// Steps through ReqDHParams, returning the server_DH_inner_data
+fn server_dh_inner_data(
+    state: &mut HandshakeState,
+    config: &Config,
+    res_pq: &tl::types::ResPq,
+) -> tl::types::ServerDhInnerData {
+    let answer = state.step(config, &req_dh_params(res_pq)).unwrap().unwrap();
+    let tl::enums::ServerDhParams::Ok(server_dh_params) =
+        tl::enums::ServerDhParams::from_bytes(&answer[20..]).unwrap()
+    else {
+        panic!("expected server_DH_params_ok");
+    };
+    let (tmp_aes_key, tmp_aes_iv) = derive_tmp_aes([0; 32], res_pq.server_nonce);
+    let answer_with_hash = ige_decrypt(
+        &server_dh_params.encrypted_answer,
+        &tmp_aes_key,
+        &tmp_aes_iv,
+    )
+    .unwrap();
+    let tl::enums::ServerDhInnerData::Data(inner) =
+        tl::enums::ServerDhInnerData::from_bytes(&answer_with_hash[20..]).unwrap();
+    inner
+}
+
+// Hashes, pads and encrypts the client_DH_inner_data with the zero new_nonce
+fn set_client_dh_params(res_pq: &tl::types::ResPq, data: Vec<u8>) -> Vec<u8> {
+    let (tmp_aes_key, tmp_aes_iv) = derive_tmp_aes([0; 32], res_pq.server_nonce);
+    let mut data_with_hash = Sha1::digest(&data).to_vec();
+    data_with_hash.extend(data);
+    data_with_hash.resize(data_with_hash.len().next_multiple_of(16), 0);
+    unencrypted_message(
+        &tl::functions::SetClientDhParams {
+            nonce: NONCE,
+            server_nonce: res_pq.server_nonce,
+            encrypted_data: ige_encrypt(&data_with_hash, &tmp_aes_key, &tmp_aes_iv).unwrap(),
+        }
+        .to_bytes(),
+    )
+}
+
+fn client_dh_inner_data(res_pq: &tl::types::ResPq, g_b: Vec<u8>) -> Vec<u8> {
+    tl::enums::ClientDhInnerData::Data(tl::types::ClientDhInnerData {
+        nonce: NONCE,
+        server_nonce: res_pq.server_nonce,
+        retry_id: 0,
+        g_b,
+    })
+    .to_bytes()
+}
+
+#[test]
+fn await_set_client_dh_params() {
+    let config = Config::default();
+    let mut state = new_state();
+    let res_pq = res_pq(&mut state, &config);
+    let inner = server_dh_inner_data(&mut state, &config, &res_pq);
+
+    let dh_prime = BigUint::from_bytes_be(&inner.dh_prime);
+    let b = BigUint::from_bytes_be(&[0x55; 256]);
+    let g_b = BigUint::from(inner.g as u32).modpow(&b, &dh_prime);
+    let data = client_dh_inner_data(&res_pq, g_b.to_bytes_be());
+
+    let answer = state
+        .step(&config, &set_client_dh_params(&res_pq, data))
+        .unwrap()
+        .unwrap();
+    assert!(matches!(
+        tl::enums::SetClientDhParamsAnswer::from_bytes(&answer[20..]),
+        Ok(tl::enums::SetClientDhParamsAnswer::DhGenOk(_))
+    ));
+    assert_eq!(state.answered(), Some("DhGenOk"));
+    assert_eq!(config.stage_stats.count(Stage::DhGenOk), 1);
+
+    let g_ab = BigUint::from_bytes_be(&inner.g_a).modpow(&b, &dh_prime);
+    let mut expected = [0; 256];
+    let g_ab = g_ab.to_bytes_be();
+    expected[256 - g_ab.len()..].copy_from_slice(&g_ab);
+    let HandshakeState::Established(auth_key) = &state else {
+        panic!("expected Established");
+    };
+    assert_eq!(auth_key.key, expected);
+    assert!(config.auth_keys.get(auth_key_id(&expected)).is_ok());
+}
+
+#[test]
+fn established_answers_nothing() {
+    let config = Config::default();
+    let mut state = HandshakeState::Established(srv::auth_keys::AuthKey {
+        key: [1; 256],
+        expires_in: None,
+    });
+    assert_eq!(state.step(&config, &req_pq_multi()).unwrap(), None);
+    assert!(matches!(state, HandshakeState::Established(_)));
+}
+
+#[test]
+fn wrong_packet_for_the_state() {
+    let config = Config::default();
+    let mut state = new_state();
+    res_pq(&mut state, &config);
+    // Another req_pq_multi instead of req_DH_params
+    assert!(state.step(&config, &req_pq_multi()).is_err());
+    assert_eq!(config.stage_stats.count(Stage::ReqDHParams), 0);
+}
+
+#[test]
+fn wrong_req_dh_params_constructor() {
+    let config = Config::default();
+    let mut state = new_state();
+    let res_pq = res_pq(&mut state, &config);
+    let mut packet = req_dh_params(&res_pq);
+    packet[20..24].copy_from_slice(&0xdeadbeefu32.to_le_bytes());
+    assert!(matches!(
+        state.step(&config, &packet),
+        Err(ServerError::MagicMismatch {
+            expected: "req_DH_params",
+            got: 0xdeadbeef,
+        })
+    ));
+}
+
+#[test]
+fn req_dh_params_with_auth_key_id() {
+    let config = Config::default();
+    let mut state = new_state();
+    let res_pq = res_pq(&mut state, &config);
+    let mut packet = req_dh_params(&res_pq);
+    packet[..8].copy_from_slice(&1i64.to_le_bytes());
+    assert!(matches!(
+        state.step(&config, &packet),
+        Err(ServerError::UnexpectedAuthKeyId(1))
+    ));
+}
+
+#[test]
+fn wrong_set_client_dh_params_constructor() {
+    let config = Config::default();
+    let mut state = new_state();
+    let res_pq = res_pq(&mut state, &config);
+    server_dh_inner_data(&mut state, &config, &res_pq);
+    let mut packet = set_client_dh_params(&res_pq, client_dh_inner_data(&res_pq, vec![2]));
+    packet[20..24].copy_from_slice(&0xdeadbeefu32.to_le_bytes());
+    assert!(matches!(
+        state.step(&config, &packet),
+        Err(ServerError::MagicMismatch {
+            expected: "set_client_DH_params",
+            got: 0xdeadbeef,
+        })
+    ));
+}
+
+#[test]
+fn wrong_client_dh_inner_data_constructor() {
+    let config = Config::default();
+    let mut state = new_state();
+    let res_pq = res_pq(&mut state, &config);
+    server_dh_inner_data(&mut state, &config, &res_pq);
+    let mut data = client_dh_inner_data(&res_pq, vec![2]);
+    data[..4].copy_from_slice(&0xdeadbeefu32.to_le_bytes());
+    assert!(matches!(
+        state.step(&config, &set_client_dh_params(&res_pq, data)),
+        Err(ServerError::DhGen(DhGenError::Fail))
+    ));
+}
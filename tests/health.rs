@@ -0,0 +1,40 @@
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    thread,
+};
+
+use srv::health;
+
+fn get(addr: SocketAddr, path: &str) -> String {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+// A single test, the health is global
+#[test]
+fn healthz() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || health::serve(listener));
+
+    // Not healthy before the accept loop runs
+    let response = get(addr, "/healthz");
+    assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+
+    health::set_healthy(true);
+    let response = get(addr, "/healthz");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with("\r\n\r\nOK\n"));
+
+    assert!(get(addr, "/metrics").starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+    // Shutting down
+    health::set_healthy(false);
+    let response = get(addr, "/healthz");
+    assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+}
@@ -0,0 +1,46 @@
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    thread,
+};
+
+use bytes::BytesMut;
+use grammers_mtproto::transport::{Full, Transport};
+use grammers_tl_types::{self as tl, Cursor, Deserializable, Serializable};
+use srv::{handle_connection, Config};
+
+#[test]
+fn req_pq_multi() {
+    let listener = TcpListener::bind("[::1]:0").unwrap();
+    let mut stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (server, peer) = listener.accept().unwrap();
+    assert!(peer.is_ipv6());
+    let server = thread::spawn(move || handle_connection(server, &Config::default()));
+
+    let body = tl::functions::ReqPqMulti { nonce: [0x42; 16] }.to_bytes();
+    let mut packet = Vec::new();
+    0i64.serialize(&mut packet);
+    0x51e57ac42770964ai64.serialize(&mut packet);
+    (body.len() as u32).serialize(&mut packet);
+    packet.extend(body);
+
+    let mut transport = Full::new();
+    let mut request = BytesMut::new();
+    transport.pack(&packet, &mut request);
+    stream.write_all(&request).unwrap();
+
+    let mut buffer = Vec::new();
+    let mut answer = BytesMut::new();
+    while transport.unpack(&buffer, &mut answer).is_err() {
+        let mut chunk = [0; 1024];
+        let len = stream.read(&mut chunk).unwrap();
+        assert_ne!(len, 0);
+        buffer.extend_from_slice(&chunk[..len]);
+    }
+
+    drop(stream);
+    let _ = server.join().unwrap();
+    let mut cur = Cursor::from_slice(&answer[20..]);
+    let tl::enums::ResPq::Pq(res_pq) = tl::enums::ResPq::deserialize(&mut cur).unwrap();
+    assert_eq!(res_pq.nonce, [0x42; 16]);
+}
@@ -0,0 +1,47 @@
+use std::{fs, path::PathBuf, process::Command};
+
+use srv::{rsa_key::RsaKey, Config};
+
+fn temp_file(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("tg_srv-{}-{}.pem", name, std::process::id()))
+}
+
+fn keygen(args: &[&str]) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_srv"))
+        .arg("keygen")
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn loads_with_the_printed_fingerprint() {
+    let path = temp_file("keygen");
+    let output = keygen(&["--out", path.to_str().unwrap()]);
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    let key = RsaKey::load(&path).unwrap();
+    assert_eq!(stdout, format!("{:016x}\n", key.fingerprint()));
+    let config = Config {
+        rsa_keys: vec![key],
+        ..Default::default()
+    };
+    let fingerprint = srv::rsa_key::parse_fingerprint(stdout.trim()).unwrap();
+    assert_eq!(config.fingerprints(), [fingerprint]);
+    assert!(config.rsa_key(fingerprint).unwrap().is_some());
+
+    // An existing key is never overwritten
+    let pem = fs::read(&path).unwrap();
+    assert!(!keygen(&["--out", path.to_str().unwrap()]).status.success());
+    assert_eq!(fs::read(&path).unwrap(), pem);
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn only_2048_bits() {
+    let path = temp_file("keygen-1024");
+    let output = keygen(&["--bits", "1024", "--out", path.to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert!(!path.exists());
+}
@@ -0,0 +1,29 @@
+use srv::keys::derive_tmp_aes;
+
+fn hex(s: &str) -> Vec<u8> {
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+// Computed separately with the SHA1 formulas of the spec:
+// tmp_aes_key = SHA1(new_nonce + server_nonce) + substr(SHA1(server_nonce + new_nonce), 0, 12)
+// tmp_aes_iv = substr(SHA1(server_nonce + new_nonce), 12, 8) + SHA1(new_nonce + new_nonce)
+//     + substr(new_nonce, 0, 4)
+#[test]
+fn tmp_aes_worked_example() {
+    let new_nonce = std::array::from_fn(|i| i as u8);
+    let server_nonce = std::array::from_fn(|i| 0xa0 + i as u8);
+    let (key, iv) = derive_tmp_aes(new_nonce, server_nonce);
+    assert_eq!(
+        key[..],
+        hex("941b0898393c11062591ff3f392be96353057ab522a6b658ecfb88fc926a0d53")
+    );
+    assert_eq!(
+        iv[..],
+        hex("1ecd69ad3a61561ee864070e166e6218f6783e8511471a5ab7802cf200010203")
+    );
+    // The IV ends with the start of new_nonce
+    assert_eq!(iv[28..], new_nonce[..4]);
+}
@@ -0,0 +1,145 @@
+mod common;
+
+use grammers_tl_types::{self as tl, Cursor, Serializable};
+use srv::{messages::InvokeWithLayer, Config, ServerError};
+
+use common::{
+    config_with_auth_key, decrypt_answer, encrypted_message, message_id, skip_new_session_created,
+    Client, ABRIDGED_TAG,
+};
+
+const LAYER: i32 = 158;
+
+fn i32_at(data: &[u8], pos: usize) -> i32 {
+    i32::from_le_bytes(data[pos..pos + 4].try_into().unwrap())
+}
+
+fn i64_at(data: &[u8], pos: usize) -> i64 {
+    i64::from_le_bytes(data[pos..pos + 8].try_into().unwrap())
+}
+
+// invokeWithLayer(initConnection(ping)) with a proxy and params, which are skipped
+fn ping_with_layer(layer: i32) -> Vec<u8> {
+    tl::functions::InvokeWithLayer {
+        layer,
+        query: tl::functions::InitConnection {
+            api_id: 4,
+            device_model: "Pixel 7".to_owned(),
+            system_version: "Android 14".to_owned(),
+            app_version: "10.0.0".to_owned(),
+            system_lang_code: "en".to_owned(),
+            lang_pack: "android".to_owned(),
+            lang_code: "en".to_owned(),
+            proxy: Some(tl::enums::InputClientProxy::Proxy(
+                tl::types::InputClientProxy {
+                    address: "127.0.0.1".to_owned(),
+                    port: 443,
+                },
+            )),
+            params: Some(tl::enums::Jsonvalue::JsonObject(tl::types::JsonObject {
+                value: vec![tl::enums::JsonobjectValue::JsonObjectValue(
+                    tl::types::JsonObjectValue {
+                        key: "tz_offset".to_owned(),
+                        value: tl::enums::Jsonvalue::JsonNumber(tl::types::JsonNumber {
+                            value: 3600.0,
+                        }),
+                    },
+                )],
+            })),
+            query: tl::functions::Ping {
+                ping_id: 0x0123456789abcdef,
+            },
+        },
+    }
+    .to_bytes()
+}
+
+// Sends the request and returns the body of the answer
+fn exchange(config: Config, request: &[u8]) -> (i64, Vec<u8>) {
+    let mut client = Client::connect_with(ABRIDGED_TAG, config);
+    let request_id = message_id();
+    client.send_abridged(encrypted_message(request_id, 1, request));
+    let answer = skip_new_session_created(&decrypt_answer(&client.receive_abridged()));
+    client.close_write();
+    let (rest, res) = client.receive_all();
+    assert!(rest.is_empty());
+    res.unwrap();
+    let len = i32_at(&answer, 28) as usize;
+    (request_id, answer[32..32 + len].to_vec())
+}
+
+#[test]
+fn parse() {
+    let request = ping_with_layer(LAYER);
+    let invoke = InvokeWithLayer::parse(&mut Cursor::from_slice(&request)).unwrap();
+    assert_eq!(invoke.layer, LAYER);
+    let init = invoke.init_connection.unwrap();
+    assert_eq!(init.api_id, 4);
+    assert_eq!(init.device_model, "Pixel 7");
+    assert_eq!(init.system_version, "Android 14");
+    assert_eq!(init.app_version, "10.0.0");
+    assert_eq!(init.lang_code, "en");
+    assert_eq!(
+        invoke.query,
+        tl::functions::Ping {
+            ping_id: 0x0123456789abcdef
+        }
+        .to_bytes()
+    );
+}
+
+#[test]
+fn parse_without_init_connection() {
+    let request = tl::functions::InvokeWithLayer {
+        layer: LAYER,
+        query: tl::functions::Ping { ping_id: 1 },
+    }
+    .to_bytes();
+    let invoke = InvokeWithLayer::parse(&mut Cursor::from_slice(&request)).unwrap();
+    assert!(invoke.init_connection.is_none());
+    assert_eq!(invoke.query, request[8..]);
+}
+
+#[test]
+fn parse_other_method() {
+    let request = tl::functions::Ping { ping_id: 1 }.to_bytes();
+    assert!(matches!(
+        InvokeWithLayer::parse(&mut Cursor::from_slice(&request)),
+        Err(ServerError::MagicMismatch {
+            expected: "invokeWithLayer",
+            ..
+        })
+    ));
+}
+
+#[test]
+fn supported_layer() {
+    let config = Config {
+        min_layer: Some(LAYER - 10),
+        max_layer: Some(LAYER),
+        ..config_with_auth_key()
+    };
+    let (request_id, body) = exchange(config, &ping_with_layer(LAYER));
+    // The wrapped ping is answered
+    assert_eq!(body[..4], 0x347773c5u32.to_le_bytes());
+    assert_eq!(i64_at(&body, 4), request_id);
+    assert_eq!(i64_at(&body, 12), 0x0123456789abcdef);
+}
+
+#[test]
+fn unsupported_layer() {
+    let config = Config {
+        min_layer: Some(LAYER + 1),
+        ..config_with_auth_key()
+    };
+    let (request_id, body) = exchange(config, &ping_with_layer(LAYER));
+    assert_eq!(body[..4], 0xf35c6d01u32.to_le_bytes());
+    assert_eq!(i64_at(&body, 4), request_id);
+    assert_eq!(body[12..16], 0x2144ca19u32.to_le_bytes());
+    assert_eq!(i32_at(&body, 16), 400);
+    let mut message = Vec::new();
+    "CONNECTION_LAYER_INVALID"
+        .to_owned()
+        .serialize(&mut message);
+    assert_eq!(body[20..], message);
+}
@@ -0,0 +1,44 @@
+mod common;
+
+use std::time::Duration;
+
+use log::{LevelFilter, Log, Metadata, Record};
+use srv::Config;
+
+use common::{req_pq_multi, Client, ABRIDGED_TAG};
+
+struct HostLogger;
+
+impl Log for HostLogger {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, _: &Record) {}
+
+    fn flush(&self) {}
+}
+
+fn answer_req_pq_multi() {
+    let config = Config {
+        stop_after_res_pq: true,
+        ..Default::default()
+    };
+    let mut client = Client::connect_with(ABRIDGED_TAG, config);
+    client.send_abridged(req_pq_multi());
+    assert!(!client.receive_abridged().is_empty());
+    assert!(client.is_closed(Duration::from_secs(5)));
+}
+
+// Both steps share the global logger, so they run in one test
+#[test]
+fn library_leaves_the_logger_to_the_host() {
+    // Nothing is installed yet
+    answer_req_pq_multi();
+
+    log::set_logger(&HostLogger).unwrap();
+    log::set_max_level(LevelFilter::Trace);
+    assert!(srv::logging::init(Some(LevelFilter::Debug), false).is_err());
+    assert!(srv::logging::init(None, true).is_err());
+    answer_req_pq_multi();
+}
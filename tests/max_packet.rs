@@ -0,0 +1,65 @@
+mod common;
+
+use common::{req_pq_multi, Client, ABRIDGED_TAG, INTERMEDIATE_TAG};
+use grammers_mtproto::transport;
+use srv::{Config, ServerError};
+
+// Only the header is sent, the server would wait for the rest if it buffered it
+#[test]
+fn abridged_extended_length() {
+    let mut client = Client::connect(ABRIDGED_TAG);
+    client.send(vec![0x7f, 0xff, 0xff, 0xff]);
+
+    let (answer, res) = client.receive_all();
+    assert!(answer.is_empty());
+    assert!(matches!(
+        res,
+        Err(ServerError::TransportFrame(transport::Error::BadLen {
+            got: 0x3fffffc
+        }))
+    ));
+}
+
+#[test]
+fn intermediate_length() {
+    let mut client = Client::connect(INTERMEDIATE_TAG);
+    client.send((2u32 << 20).to_le_bytes().to_vec());
+
+    let (answer, res) = client.receive_all();
+    assert!(answer.is_empty());
+    assert!(matches!(
+        res,
+        Err(ServerError::TransportFrame(transport::Error::BadLen {
+            got: 0x200000
+        }))
+    ));
+}
+
+#[test]
+fn configured_limit() {
+    let packet = req_pq_multi();
+    let frame = |packet: &[u8]| {
+        let mut frame = vec![(packet.len() / 4) as u8];
+        frame.extend(packet);
+        frame
+    };
+    let config = || Config {
+        max_packet: packet.len(),
+        stop_after_res_pq: true,
+        ..Default::default()
+    };
+
+    let mut client = Client::connect_with(ABRIDGED_TAG, config());
+    client.send(frame(&packet));
+    let (answer, res) = client.receive_all();
+    assert!(!answer.is_empty());
+    res.unwrap();
+
+    let mut client = Client::connect_with(ABRIDGED_TAG, config());
+    let mut longer = packet.clone();
+    longer.extend([0; 4]);
+    client.send(frame(&longer));
+    let (answer, res) = client.receive_all();
+    assert!(answer.is_empty());
+    assert!(matches!(res, Err(ServerError::TransportFrame(_))));
+}
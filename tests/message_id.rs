@@ -0,0 +1,80 @@
+mod common;
+
+use std::iter;
+
+use common::{req_pq_multi, Client, ABRIDGED_TAG};
+use srv::{
+    message_id::{check_window, Clock, MessageIds},
+    Config, ServerError,
+};
+
+#[test]
+fn clock_increasing() {
+    let mut message_ids = MessageIds::new(Clock);
+    let first = message_ids.next_id();
+    let second = message_ids.next_id();
+    assert!(second > first);
+    assert_eq!(first % 4, 1);
+    assert_eq!(second % 4, 1);
+}
+
+#[test]
+fn fixed_sequence() {
+    let mut sequence = [0x100, 0x100, 0x0, 0x206].into_iter();
+    let mut message_ids = MessageIds::new(move || sequence.next().unwrap());
+    let ids: Vec<_> = iter::repeat_with(|| message_ids.next_id())
+        .take(4)
+        .collect();
+    assert_eq!(ids, [0x101, 0x105, 0x109, 0x209]);
+}
+
+#[test]
+fn provider_of_the_config() {
+    let config = Config {
+        message_ids: Box::new(|| {
+            let mut sequence = [0x100, 0x200].into_iter();
+            MessageIds::new(move || sequence.next().unwrap())
+        }),
+        stop_after_res_pq: true,
+        ..Default::default()
+    };
+    let mut client = Client::connect_with(ABRIDGED_TAG, config);
+    client.send_abridged(req_pq_multi());
+    let res_pq = client.receive_abridged();
+    assert_eq!(res_pq[8..16], 0x101i64.to_le_bytes());
+}
+
+const NOW: i64 = 1_700_000_000;
+
+#[test]
+fn window_in() {
+    check_window(NOW << 32, NOW).unwrap();
+    check_window((NOW - 300) << 32, NOW).unwrap();
+    check_window((NOW + 30) << 32 | 0xffff_fffc, NOW).unwrap();
+}
+
+#[test]
+fn window_old() {
+    let message_id = (NOW - 301) << 32;
+    assert!(matches!(
+        check_window(message_id, NOW),
+        Err(ServerError::MessageIdOutOfWindow { skew: -301, .. })
+    ));
+}
+
+#[test]
+fn window_future() {
+    let message_id = (NOW + 31) << 32;
+    let e = check_window(message_id, NOW).unwrap_err();
+    assert!(matches!(
+        e,
+        ServerError::MessageIdOutOfWindow { skew: 31, .. }
+    ));
+    assert_eq!(
+        e.to_string(),
+        format!(
+            "message_id {:016x} is +31 seconds off the server time",
+            message_id
+        )
+    );
+}
@@ -0,0 +1,160 @@
+mod common;
+
+use common::{req_pq_multi, unencrypted_message, NONCE};
+use grammers_tl_types::{self as tl, Cursor, Deserializable, Serializable};
+use srv::{
+    messages::{ReqDHParams, ReqPqMulti, ResPq, REQ_PQ, REQ_PQ_MULTI, SERVER_NONCE},
+    rsa_key::TELEGRAM_FINGERPRINT,
+    ServerError,
+};
+
+const PQ: u64 = 0x17ED48941A08F981;
+
+#[test]
+fn req_pq_multi_parse() {
+    let packet = req_pq_multi();
+    let req_pq_multi = ReqPqMulti::parse(&mut Cursor::from_slice(&packet)).unwrap();
+    assert_eq!(req_pq_multi.auth_key_id, 0);
+    assert_eq!(req_pq_multi.message_id, 0x51e57ac42770964a);
+    assert_eq!(req_pq_multi.message_length, 20);
+    assert_eq!(req_pq_multi.nonce, NONCE);
+}
+
+#[test]
+fn req_pq_multi_variant() {
+    let packet = req_pq_multi();
+    let req_pq_multi = ReqPqMulti::parse(&mut Cursor::from_slice(&packet)).unwrap();
+    assert_eq!(req_pq_multi.magic, REQ_PQ_MULTI);
+    assert!(!req_pq_multi.is_legacy());
+    assert_eq!(req_pq_multi.name(), "req_pq_multi");
+}
+
+#[test]
+fn req_pq_legacy_parse() {
+    let packet = unencrypted_message(&tl::functions::ReqPq { nonce: NONCE }.to_bytes());
+    let req_pq = ReqPqMulti::parse(&mut Cursor::from_slice(&packet)).unwrap();
+    assert_eq!(req_pq.magic, REQ_PQ);
+    assert!(req_pq.is_legacy());
+    assert_eq!(req_pq.name(), "req_pq");
+    assert_eq!(req_pq.nonce, NONCE);
+}
+
+#[test]
+fn req_pq_multi_wrong_magic() {
+    let mut packet = req_pq_multi();
+    packet[20] ^= 0xff;
+    assert!(ReqPqMulti::parse(&mut Cursor::from_slice(&packet)).is_err());
+}
+
+#[test]
+fn res_pq_round_trip() {
+    let packet = req_pq_multi();
+    let req_pq_multi = ReqPqMulti::parse(&mut Cursor::from_slice(&packet)).unwrap();
+    let res_pq = ResPq::generate(
+        req_pq_multi.nonce,
+        SERVER_NONCE,
+        0x51e57ac42770964d,
+        PQ.to_be_bytes().to_vec(),
+        vec![TELEGRAM_FINGERPRINT],
+    )
+    .unwrap();
+    let bytes = res_pq.ser();
+
+    let mut cur = Cursor::from_slice(&bytes);
+    assert_eq!(i64::deserialize(&mut cur).unwrap(), 0);
+    assert_eq!(i64::deserialize(&mut cur).unwrap(), res_pq.message_id);
+    assert_eq!(
+        u32::deserialize(&mut cur).unwrap() as usize,
+        bytes.len() - 20
+    );
+
+    let tl::enums::ResPq::Pq(parsed) = tl::enums::ResPq::deserialize(&mut cur).unwrap();
+    assert_eq!(cur.pos(), bytes.len());
+    assert_eq!(parsed.nonce, NONCE);
+    assert_eq!(parsed.server_nonce, SERVER_NONCE);
+    assert_eq!(parsed.pq, PQ.to_be_bytes());
+    assert_eq!(
+        parsed.server_public_key_fingerprints,
+        res_pq.server_public_key_fingerprints
+    );
+}
+
+#[test]
+fn res_pq_rejects_zero_pq() {
+    assert!(ResPq::generate(
+        NONCE,
+        SERVER_NONCE,
+        0x51e57ac42770964d,
+        0u64.to_be_bytes().to_vec(),
+        vec![TELEGRAM_FINGERPRINT]
+    )
+    .is_err());
+}
+
+#[test]
+fn req_dh_params_parse() {
+    let packet = unencrypted_message(
+        &tl::functions::ReqDhParams {
+            nonce: NONCE,
+            server_nonce: SERVER_NONCE,
+            p: vec![0x49, 0x4c, 0x55, 0x3b],
+            q: vec![0x53, 0x91, 0x10, 0x73],
+            public_key_fingerprint: TELEGRAM_FINGERPRINT,
+            encrypted_data: vec![0x42; 256],
+        }
+        .to_bytes(),
+    );
+    let mut cur = Cursor::from_slice(&packet);
+    let req_dh_params = ReqDHParams::parse(&mut cur).unwrap();
+    assert_eq!(cur.pos(), packet.len());
+    assert_eq!(req_dh_params.p, [0x49, 0x4c, 0x55, 0x3b]);
+    assert_eq!(req_dh_params.q, [0x53, 0x91, 0x10, 0x73]);
+    assert_eq!(req_dh_params.public_key_fingerprint, TELEGRAM_FINGERPRINT);
+    assert_eq!(req_dh_params.encrypted_data, [0x42; 256]);
+}
+
+#[test]
+fn req_dh_params_truncated_p() {
+    let mut body = Vec::new();
+    0xd712e4be_u32.serialize(&mut body);
+    NONCE.serialize(&mut body);
+    SERVER_NONCE.serialize(&mut body);
+    // The longest length a TL string can claim, followed by only 4 bytes
+    body.extend([0xfe, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00]);
+    let packet = unencrypted_message(&body);
+    assert!(matches!(
+        ReqDHParams::parse(&mut Cursor::from_slice(&packet)),
+        Err(ServerError::ShortRead(_))
+    ));
+}
+
+#[test]
+fn req_pq_multi_truncated_nonce() {
+    let mut packet = req_pq_multi();
+    packet.truncate(packet.len() - 6);
+    assert!(matches!(
+        ReqPqMulti::parse(&mut Cursor::from_slice(&packet)),
+        Err(ServerError::ShortField {
+            field: "nonce",
+            expected: 16,
+            available: 10,
+        })
+    ));
+}
+
+#[test]
+fn req_dh_params_truncated_server_nonce() {
+    let mut body = Vec::new();
+    0xd712e4be_u32.serialize(&mut body);
+    NONCE.serialize(&mut body);
+    body.extend(&SERVER_NONCE[..3]);
+    let packet = unencrypted_message(&body);
+    assert!(matches!(
+        ReqDHParams::parse(&mut Cursor::from_slice(&packet)),
+        Err(ServerError::ShortField {
+            field: "server_nonce",
+            expected: 16,
+            available: 3,
+        })
+    ));
+}
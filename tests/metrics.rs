@@ -0,0 +1,17 @@
+#![cfg(feature = "metrics")]
+
+use std::{net::TcpListener, process::Command};
+
+#[test]
+fn bind_failure_is_fatal() {
+    let held = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = held.local_addr().unwrap().to_string();
+    let output = Command::new(env!("CARGO_BIN_EXE_srv"))
+        .args(["--bind", "127.0.0.1:0"])
+        .args(["--metrics-bind", &addr])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let log = String::from_utf8(output.stderr).unwrap();
+    assert!(log.contains(&format!("failed to bind {}", addr)), "{}", log);
+}
@@ -0,0 +1,152 @@
+mod common;
+
+use grammers_mtproto::transport;
+use srv::{messages::Message, ServerError, DEFAULT_MAX_VECTOR_LEN};
+
+fn ping(ping_id: i64) -> Vec<u8> {
+    let mut ping = 0x7abe77ecu32.to_le_bytes().to_vec();
+    ping.extend(ping_id.to_le_bytes());
+    ping
+}
+
+fn message(message_id: i64, seq_no: i32, body: Vec<u8>) -> Message {
+    Message {
+        salt: 0,
+        session_id: 0,
+        message_id,
+        seq_no,
+        body,
+    }
+}
+
+#[test]
+fn unpack() {
+    let messages = [message(4, 1, ping(1)), message(8, 3, ping(2))];
+    let container = Message::pack_container(12, 4, &messages);
+    assert_eq!(container.constructor(), Some(0x73f1f8dc));
+    let unpacked = container.unpack_container(DEFAULT_MAX_VECTOR_LEN).unwrap();
+    assert_eq!(unpacked.len(), 2);
+    for (unpacked, message) in unpacked.iter().zip(&messages) {
+        assert_eq!(unpacked.message_id, message.message_id);
+        assert_eq!(unpacked.seq_no, message.seq_no);
+        assert_eq!(unpacked.body, message.body);
+    }
+}
+
+#[test]
+fn truncated() {
+    let mut container = Message::pack_container(12, 4, &[message(4, 1, ping(1))]);
+    container.body.truncate(container.body.len() - 1);
+    assert!(matches!(
+        container.unpack_container(DEFAULT_MAX_VECTOR_LEN),
+        Err(ServerError::InvalidEncryptedMessage(_))
+    ));
+}
+
+#[test]
+fn count_beyond_the_body() {
+    let mut container = Message::pack_container(12, 4, &[message(4, 1, ping(1))]);
+    container.body[4..8].copy_from_slice(&2u32.to_le_bytes());
+    assert!(matches!(
+        container.unpack_container(DEFAULT_MAX_VECTOR_LEN),
+        Err(ServerError::ShortRead(_))
+    ));
+}
+
+#[test]
+fn count_beyond_the_limit() {
+    let mut container = Message::pack_container(12, 4, &[message(4, 1, ping(1))]);
+    container.body[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert!(matches!(
+        container.unpack_container(DEFAULT_MAX_VECTOR_LEN),
+        Err(ServerError::TransportFrame(transport::Error::BadLen {
+            got: u32::MAX
+        }))
+    ));
+    // The limit itself is accepted
+    let messages: Vec<_> = (1..=3).map(|i| message(4 * i, 1, ping(i))).collect();
+    let container = Message::pack_container(16, 4, &messages);
+    assert_eq!(container.unpack_container(3).unwrap().len(), 3);
+    assert!(matches!(
+        container.unpack_container(2),
+        Err(ServerError::TransportFrame(transport::Error::BadLen {
+            got: 3
+        }))
+    ));
+}
+
+mod serve {
+    use grammers_mtproto::transport;
+    use srv::{messages::Message, ServerError};
+
+    use super::{message, ping};
+    use crate::common::{
+        config_with_auth_key, decrypt_answer, encrypted_message, message_id, Client, ABRIDGED_TAG,
+    };
+
+    fn i64_at(data: &[u8], pos: usize) -> i64 {
+        i64::from_le_bytes(data[pos..pos + 8].try_into().unwrap())
+    }
+
+    fn i32_at(data: &[u8], pos: usize) -> i32 {
+        i32::from_le_bytes(data[pos..pos + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn two_pings() {
+        let first = message_id();
+        let container = Message::pack_container(
+            first + 8,
+            4,
+            &[message(first, 1, ping(1)), message(first + 4, 3, ping(2))],
+        );
+        let mut client = Client::connect_with(ABRIDGED_TAG, config_with_auth_key());
+        client.send_abridged(encrypted_message(
+            container.message_id,
+            container.seq_no,
+            &container.body,
+        ));
+        let answer = decrypt_answer(&client.receive_abridged());
+        client.close_write();
+        client.receive_all().1.unwrap();
+
+        // Both pongs in a container after new_session_created, after their own msg_ids
+        let container_id = i64_at(&answer, 16);
+        assert_eq!(i32_at(&answer, 24), 6);
+        assert_eq!(answer[32..36], 0x73f1f8dcu32.to_le_bytes());
+        assert_eq!(i32_at(&answer, 36), 3);
+        assert_eq!(i32_at(&answer, 48), 1);
+        assert_eq!(answer[56..60], 0x9ec20908u32.to_le_bytes());
+        assert_eq!(i64_at(&answer, 60), first + 8);
+        let mut pos = 40 + 16 + 28;
+        for (i, (seq_no, ping_id)) in [(3, 1), (5, 2)].into_iter().enumerate() {
+            assert!(i64_at(&answer, pos) < container_id);
+            assert_eq!(i32_at(&answer, pos + 8), seq_no);
+            assert_eq!(i32_at(&answer, pos + 12), 20);
+            assert_eq!(answer[pos + 16..pos + 20], 0x347773c5u32.to_le_bytes());
+            assert_eq!(i64_at(&answer, pos + 20), first + 4 * i as i64);
+            assert_eq!(i64_at(&answer, pos + 28), ping_id);
+            pos += 36;
+        }
+    }
+
+    #[test]
+    fn oversized_count() {
+        let first = message_id();
+        let mut container = Message::pack_container(first + 4, 2, &[message(first, 1, ping(1))]);
+        container.body[4..8].copy_from_slice(&0x10000000u32.to_le_bytes());
+        let mut client = Client::connect_with(ABRIDGED_TAG, config_with_auth_key());
+        client.send_abridged(encrypted_message(
+            container.message_id,
+            container.seq_no,
+            &container.body,
+        ));
+        let (_, res) = client.receive_all();
+        assert!(matches!(
+            res,
+            Err(ServerError::TransportFrame(transport::Error::BadLen {
+                got: 0x10000000
+            }))
+        ));
+    }
+}
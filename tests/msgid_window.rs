@@ -0,0 +1,58 @@
+mod common;
+
+use common::{message_id, req_pq_multi, Client, ABRIDGED_TAG};
+use srv::{Config, ServerError};
+
+// A req_pq_multi sent `offset` seconds from now
+fn req_pq_multi_at(offset: i64) -> Vec<u8> {
+    let mut packet = req_pq_multi();
+    let message_id = message_id() + (offset << 32);
+    packet[8..16].copy_from_slice(&message_id.to_le_bytes());
+    packet
+}
+
+fn config() -> Config {
+    Config {
+        check_msgid_window: true,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn in_window() {
+    let mut client = Client::connect_with(ABRIDGED_TAG, config());
+    client.send_abridged(req_pq_multi_at(-60));
+    assert!(!client.receive_abridged().is_empty());
+}
+
+#[test]
+fn old() {
+    let mut client = Client::connect_with(ABRIDGED_TAG, config());
+    client.send_abridged(req_pq_multi_at(-3600));
+    let (answer, res) = client.receive_all();
+    assert!(answer.is_empty());
+    assert!(matches!(
+        res,
+        Err(ServerError::MessageIdOutOfWindow { skew, .. }) if skew <= -3599
+    ));
+}
+
+#[test]
+fn future() {
+    let mut client = Client::connect_with(ABRIDGED_TAG, config());
+    client.send_abridged(req_pq_multi_at(3600));
+    let (answer, res) = client.receive_all();
+    assert!(answer.is_empty());
+    assert!(matches!(
+        res,
+        Err(ServerError::MessageIdOutOfWindow { skew, .. }) if skew >= 3599
+    ));
+}
+
+#[test]
+fn unchecked_by_default() {
+    let mut client = Client::connect(ABRIDGED_TAG);
+    // The fixed msg_id of req_pq_multi is from 2013
+    client.send_abridged(req_pq_multi());
+    assert!(!client.receive_abridged().is_empty());
+}
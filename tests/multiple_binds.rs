@@ -0,0 +1,72 @@
+mod common;
+
+use std::{
+    io::Read,
+    net::{TcpListener, TcpStream},
+    process::{Child, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use common::{Client, ABRIDGED_TAG};
+
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+#[test]
+fn handshake_on_each_listener() {
+    let addrs = [(); 2].map(|_| {
+        TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+    });
+    let mut server = Server(
+        Command::new(env!("CARGO_BIN_EXE_srv"))
+            .args(["--bind", &addrs[0].to_string()])
+            .args(["--bind", &addrs[1].to_string()])
+            .args(["--log-level", "info"])
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap(),
+    );
+    // Both listeners are bound before either accepts
+    let start = Instant::now();
+    while TcpStream::connect(addrs[1]).is_err() {
+        assert!(
+            start.elapsed() < Duration::from_secs(5),
+            "server didn't start"
+        );
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    for addr in addrs {
+        let mut client = Client::connect_to(addr, ABRIDGED_TAG);
+        client.handshake();
+        assert!(client.is_closed(Duration::from_secs(5)));
+    }
+
+    let _ = server.0.kill();
+    let mut log = String::new();
+    server
+        .0
+        .stderr
+        .take()
+        .unwrap()
+        .read_to_string(&mut log)
+        .unwrap();
+    for addr in addrs {
+        assert!(log.contains(&format!("listening on {}", addr)), "{}", log);
+        assert!(
+            log.contains(&format!("connection accepted on {}", addr)),
+            "{}",
+            log
+        );
+    }
+}
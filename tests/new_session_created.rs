@@ -0,0 +1,106 @@
+mod common;
+
+use common::{
+    auth_key, config_with_auth_key, decrypt_answer, encrypted_message_in, message_id, Client,
+    ABRIDGED_TAG, SESSION_ID,
+};
+
+const NEW_SESSION_CREATED: [u8; 4] = 0x9ec20908u32.to_le_bytes();
+const PONG: [u8; 4] = 0x347773c5u32.to_le_bytes();
+
+fn i64_at(data: &[u8], pos: usize) -> i64 {
+    i64::from_le_bytes(data[pos..pos + 8].try_into().unwrap())
+}
+
+fn i32_at(data: &[u8], pos: usize) -> i32 {
+    i32::from_le_bytes(data[pos..pos + 4].try_into().unwrap())
+}
+
+fn ping(ping_id: i64) -> Vec<u8> {
+    let mut ping = 0x7abe77ecu32.to_le_bytes().to_vec();
+    ping.extend(ping_id.to_le_bytes());
+    ping
+}
+
+// The constructors of the answer, or of the messages of its container
+fn constructors(answer: &[u8]) -> Vec<[u8; 4]> {
+    let constructor = |pos: usize| answer[pos..pos + 4].try_into().unwrap();
+    if answer[32..36] != 0x73f1f8dcu32.to_le_bytes() {
+        return vec![constructor(32)];
+    }
+    let mut pos = 40;
+    (0..i32_at(answer, 36))
+        .map(|_| {
+            let constructor = constructor(pos + 16);
+            pos += 16 + i32_at(answer, pos + 12) as usize;
+            constructor
+        })
+        .collect()
+}
+
+#[test]
+fn once_per_session() {
+    let mut client = Client::connect_with(ABRIDGED_TAG, config_with_auth_key());
+    let mut exchange = |session_id: i64, message_id: i64, seq_no: i32| {
+        client.send_abridged(encrypted_message_in(
+            &auth_key(),
+            session_id,
+            message_id,
+            seq_no,
+            &ping(message_id),
+        ));
+        decrypt_answer(&client.receive_abridged())
+    };
+
+    let first_id = message_id();
+    let first = exchange(SESSION_ID, first_id, 1);
+    assert_eq!(constructors(&first), [NEW_SESSION_CREATED, PONG]);
+    // new_session_created#9ec20908 first_msg_id:long unique_id:long server_salt:long
+    assert_eq!(i32_at(&first, 52), 28);
+    assert_eq!(i64_at(&first, 60), first_id);
+    assert_eq!(i64_at(&first, 76), i64_at(&first, 0));
+    let unique_id = i64_at(&first, 68);
+
+    // Known from now on
+    let second = exchange(SESSION_ID, first_id + 4, 3);
+    assert_eq!(constructors(&second), [PONG]);
+
+    let other_session = exchange(SESSION_ID + 1, first_id + 8, 5);
+    assert_eq!(other_session[8..16], (SESSION_ID + 1).to_le_bytes());
+    assert_eq!(constructors(&other_session), [NEW_SESSION_CREATED, PONG]);
+    assert_eq!(i64_at(&other_session, 60), first_id + 8);
+    assert_ne!(i64_at(&other_session, 68), unique_id);
+
+    client.close_write();
+    let (rest, res) = client.receive_all();
+    assert!(rest.is_empty());
+    res.unwrap();
+}
+
+#[test]
+fn not_for_a_bad_message() {
+    let mut client = Client::connect_with(ABRIDGED_TAG, config_with_auth_key());
+    // Even seq_no of a content related message
+    let message_id = message_id();
+    client.send_abridged(encrypted_message_in(
+        &auth_key(),
+        SESSION_ID,
+        message_id,
+        2,
+        &ping(1),
+    ));
+    let bad = decrypt_answer(&client.receive_abridged());
+    assert_eq!(constructors(&bad), [0xa7eff811u32.to_le_bytes()]);
+
+    client.send_abridged(encrypted_message_in(
+        &auth_key(),
+        SESSION_ID,
+        message_id + 4,
+        3,
+        &ping(2),
+    ));
+    let answer = decrypt_answer(&client.receive_abridged());
+    assert_eq!(constructors(&answer), [NEW_SESSION_CREATED, PONG]);
+    client.close_write();
+    client.receive_all().1.unwrap();
+}
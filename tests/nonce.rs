@@ -0,0 +1,92 @@
+mod common;
+
+use srv::{nonce::NonceContext, ServerError};
+
+const NONCE: [u8; 16] = [0x42; 16];
+const SERVER_NONCE: [u8; 16] = [0x24; 16];
+
+#[test]
+fn matching_echo() {
+    let nonces = NonceContext::new(NONCE, SERVER_NONCE);
+    nonces.verify(&NONCE, &SERVER_NONCE).unwrap();
+}
+
+#[test]
+fn mismatching_echo() {
+    let nonces = NonceContext::new(NONCE, SERVER_NONCE);
+    let mut other = NONCE;
+    other[15] ^= 1;
+    assert!(matches!(
+        nonces.verify(&other, &SERVER_NONCE),
+        Err(ServerError::NonceMismatch { name: "nonce", got, expected })
+            if got == other && expected == NONCE
+    ));
+    assert!(matches!(
+        nonces.verify(&NONCE, &NONCE),
+        Err(ServerError::NonceMismatch {
+            name: "server_nonce",
+            ..
+        })
+    ));
+    // The nonce is checked first
+    assert!(matches!(
+        nonces.verify(&SERVER_NONCE, &NONCE),
+        Err(ServerError::NonceMismatch { name: "nonce", .. })
+    ));
+}
+
+mod serve {
+    use grammers_tl_types::{self as tl, Deserializable, Serializable};
+    use srv::{pq, rsa_key::TELEGRAM_FINGERPRINT, ServerError};
+
+    use crate::common::{req_pq_multi, unencrypted_message, Client, ABRIDGED_TAG};
+
+    // Sends req_DH_params with the nonces of ResPq changed by `tamper`
+    fn req_dh_params(tamper: impl FnOnce(&mut [u8; 16], &mut [u8; 16])) -> Client {
+        let mut client = Client::connect(ABRIDGED_TAG);
+        client.send_abridged(req_pq_multi());
+        let tl::enums::ResPq::Pq(res_pq) =
+            tl::enums::ResPq::from_bytes(&client.receive_abridged()[20..]).unwrap();
+        let (p, q) = pq::factorize(u64::from_be_bytes(res_pq.pq[..].try_into().unwrap())).unwrap();
+        let (mut nonce, mut server_nonce) = (res_pq.nonce, res_pq.server_nonce);
+        tamper(&mut nonce, &mut server_nonce);
+        let req_dh_params = tl::functions::ReqDhParams {
+            nonce,
+            server_nonce,
+            p: p.to_be_bytes().to_vec(),
+            q: q.to_be_bytes().to_vec(),
+            public_key_fingerprint: TELEGRAM_FINGERPRINT,
+            encrypted_data: vec![0; 256],
+        };
+        client.send_abridged(unencrypted_message(&req_dh_params.to_bytes()));
+        client
+    }
+
+    #[test]
+    fn matching_req_dh_params() {
+        let mut client = req_dh_params(|_, _| {});
+        let answer = tl::enums::ServerDhParams::from_bytes(&client.receive_abridged()[20..]);
+        assert!(matches!(answer, Ok(tl::enums::ServerDhParams::Ok(_))));
+    }
+
+    #[test]
+    fn mismatching_server_nonce() {
+        let client = req_dh_params(|_, server_nonce| server_nonce[0] ^= 0xff);
+        assert!(matches!(
+            client.receive_all().1,
+            Err(ServerError::NonceMismatch {
+                name: "server_nonce",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn mismatching_nonce() {
+        let client = req_dh_params(|nonce, _| nonce[0] ^= 0xff);
+        assert!(matches!(
+            client.receive_all().1,
+            Err(ServerError::NonceMismatch { name: "nonce", .. })
+        ));
+    }
+}
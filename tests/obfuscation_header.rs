@@ -0,0 +1,109 @@
+use srv::{validate_obfuscation_header, DcId, ObfuscationKeys, ServerError};
+
+fn header(prefix: &[u8]) -> [u8; 64] {
+    let mut init = [0x42; 64];
+    init[..prefix.len()].copy_from_slice(prefix);
+    init
+}
+
+fn assert_rejected(init: [u8; 64]) {
+    match validate_obfuscation_header(&init) {
+        Err(ServerError::InvalidObfuscationHeader(_)) => {}
+        res => panic!("{:02x?} wasn't rejected: {:?}", &init[..8], res),
+    }
+}
+
+#[test]
+fn valid() {
+    validate_obfuscation_header(&header(&[])).unwrap();
+    // Only the whole 4 bytes are forbidden
+    validate_obfuscation_header(&header(&[0xee, 0xee, 0xee])).unwrap();
+    validate_obfuscation_header(&header(b"GE")).unwrap();
+}
+
+#[test]
+fn abridged() {
+    assert_rejected(header(&[0xef]));
+    assert_rejected(header(&[0xef; 4]));
+}
+
+#[test]
+fn intermediate() {
+    assert_rejected(header(&[0xee; 4]));
+    assert_rejected(header(&[0xdd; 4]));
+}
+
+#[test]
+fn http() {
+    for method in [b"HEAD", b"POST", b"GET ", b"OPTI"] {
+        assert_rejected(header(method));
+    }
+}
+
+#[test]
+fn tls() {
+    assert_rejected(header(&[0x16, 0x03, 0x01, 0x02]));
+}
+
+#[test]
+fn full() {
+    assert_rejected(header(&[0x42, 0x42, 0x42, 0x42, 0, 0, 0, 0]));
+}
+
+#[test]
+fn keys() {
+    let mut init = [0; 64];
+    for (i, byte) in init.iter_mut().enumerate() {
+        *byte = i as u8;
+    }
+    let keys = ObfuscationKeys::derive(&init);
+    assert_eq!(keys.encrypt_key[..], init[8..40]);
+    assert_eq!(keys.encrypt_iv[..], init[40..56]);
+    // The reversed header starts with byte 63, so its key is bytes 55 down to 24
+    let decrypt_key: Vec<u8> = (24..56).rev().collect();
+    let decrypt_iv: Vec<u8> = (8..24).rev().collect();
+    assert_eq!(keys.decrypt_key[..], decrypt_key);
+    assert_eq!(keys.decrypt_iv[..], decrypt_iv);
+}
+
+fn header_with_dc(dc_id: i16) -> [u8; 64] {
+    let mut init = header(&[]);
+    init[56..60].copy_from_slice(&[0xef; 4]);
+    init[60..62].copy_from_slice(&dc_id.to_le_bytes());
+    init
+}
+
+#[test]
+fn dc_id() {
+    let dc_id = DcId::from_header(&header_with_dc(2));
+    assert_eq!(dc_id, DcId(2));
+    assert_eq!(dc_id.number(), 2);
+    assert!(!dc_id.is_media());
+    assert!(!dc_id.is_test());
+    assert!(dc_id.is_plausible());
+    assert_eq!(dc_id.to_string(), "2");
+}
+
+#[test]
+fn media_and_test_dc_ids() {
+    let media = DcId::from_header(&header_with_dc(-4));
+    assert_eq!(
+        (media.number(), media.is_media(), media.is_test()),
+        (4, true, false)
+    );
+    assert!(media.is_plausible());
+    let test = DcId::from_header(&header_with_dc(-10002));
+    assert_eq!(
+        (test.number(), test.is_media(), test.is_test()),
+        (2, true, true)
+    );
+    assert!(test.is_plausible());
+    assert_eq!(test.to_string(), "2 (test) (media)");
+}
+
+#[test]
+fn implausible_dc_ids() {
+    for dc_id in [0, 6, -6, 10000, 10006, 20002, i16::MIN] {
+        assert!(!DcId(dc_id).is_plausible(), "{}", dc_id);
+    }
+}
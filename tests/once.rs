@@ -0,0 +1,124 @@
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    process::{Child, Command, ExitStatus, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use bytes::BytesMut;
+use grammers_mtproto::transport::{Full, Transport};
+use grammers_tl_types::{self as tl, Deserializable, Serializable};
+
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+impl Server {
+    fn start(args: &[&str]) -> (Self, TcpStream) {
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let server = Self(
+            Command::new(env!("CARGO_BIN_EXE_srv"))
+                .args(["--bind", &addr.to_string(), "--once"])
+                .args(args)
+                .stderr(Stdio::piped())
+                .spawn()
+                .unwrap(),
+        );
+        // The first connection is the one handled
+        let start = Instant::now();
+        let stream = loop {
+            match TcpStream::connect(addr) {
+                Ok(stream) => break stream,
+                Err(_) if start.elapsed() < Duration::from_secs(5) => {
+                    thread::sleep(Duration::from_millis(50))
+                }
+                Err(e) => panic!("the server didn't start: {}", e),
+            }
+        };
+        (server, stream)
+    }
+
+    fn wait(&mut self) -> ExitStatus {
+        let start = Instant::now();
+        loop {
+            if let Some(status) = self.0.try_wait().unwrap() {
+                return status;
+            }
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "the server didn't exit"
+            );
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    // Once it exited
+    fn log(&mut self) -> String {
+        let mut log = String::new();
+        self.0
+            .stderr
+            .take()
+            .unwrap()
+            .read_to_string(&mut log)
+            .unwrap();
+        log
+    }
+}
+
+#[test]
+fn exits_after_one_connection() {
+    let (mut server, mut stream) = Server::start(&["--stop-after-res-pq"]);
+    let body = tl::functions::ReqPqMulti { nonce: [0x42; 16] }.to_bytes();
+    let mut packet = Vec::new();
+    0i64.serialize(&mut packet);
+    0x51e57ac42770964ai64.serialize(&mut packet);
+    (body.len() as u32).serialize(&mut packet);
+    packet.extend(body);
+    let mut transport = Full::new();
+    let mut request = BytesMut::new();
+    transport.pack(&packet, &mut request);
+    stream.write_all(&request).unwrap();
+
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut buffer = Vec::new();
+    let mut answer = BytesMut::new();
+    while transport.unpack(&buffer, &mut answer).is_err() {
+        let mut chunk = [0; 1024];
+        let len = stream.read(&mut chunk).unwrap();
+        assert_ne!(len, 0, "connection closed");
+        buffer.extend_from_slice(&chunk[..len]);
+    }
+    let tl::enums::ResPq::Pq(res_pq) = tl::enums::ResPq::from_bytes(&answer[20..]).unwrap();
+    assert_eq!(res_pq.nonce, [0x42; 16]);
+
+    assert!(server.wait().success());
+}
+
+#[test]
+fn failed_connection_fails_the_process() {
+    let (mut server, mut stream) = Server::start(&["--log-level", "info"]);
+    // The unobfuscated abridged transport isn't accepted
+    stream.write_all(&[0xef; 64]).unwrap();
+    let status = server.wait();
+    assert!(!status.success());
+    assert_eq!(status.code(), Some(1));
+    // The failure doesn't skip the shutdown
+    let log = server.log();
+    assert!(
+        log.contains("shutting down, waiting for active connections"),
+        "{}",
+        log
+    );
+    assert!(log.contains("handshake stages reached"), "{}", log);
+}
@@ -0,0 +1,99 @@
+mod common;
+
+use grammers_tl_types::{self as tl, Deserializable};
+use srv::{strip_padding, Config};
+
+use common::{
+    config_with_auth_key, decrypt_answer, encrypted_message, message_id, req_pq_multi,
+    skip_new_session_created, Client,
+};
+
+const PADDED_INTERMEDIATE_TAG: [u8; 4] = [0xdd; 4];
+
+fn padded(packet: &[u8], padding: usize) -> Vec<u8> {
+    let mut padded = packet.to_vec();
+    padded.extend((0..padding).map(|i| 0xf0 | i as u8));
+    padded
+}
+
+fn send_padded(client: &mut Client, packet: &[u8], padding: usize) {
+    let packet = padded(packet, padding);
+    let mut frame = (packet.len() as u32).to_le_bytes().to_vec();
+    frame.extend(packet);
+    client.send(frame);
+}
+
+// Returns the answer with its padding
+fn receive_padded(client: &mut Client) -> Vec<u8> {
+    let len = u32::from_le_bytes(client.receive(4).try_into().unwrap());
+    client.receive(len as usize)
+}
+
+#[test]
+fn strip_every_padding() {
+    let unencrypted = req_pq_multi();
+    let mut encrypted = vec![0x11; 8];
+    encrypted.extend([0x22; 16]);
+    encrypted.extend([0x33; 48]);
+    for padding in 0..16 {
+        for packet in [&unencrypted, &encrypted] {
+            assert_eq!(
+                strip_padding(&padded(packet, padding)),
+                &packet[..],
+                "{} bytes of padding",
+                padding
+            );
+        }
+        assert_eq!(strip_padding(&padded(&[0x44; 4], padding % 4)), [0x44; 4]);
+    }
+}
+
+#[test]
+fn res_pq() {
+    let config = Config {
+        repeat_handshakes: true,
+        ..Default::default()
+    };
+    let mut client = Client::connect_with(PADDED_INTERMEDIATE_TAG, config);
+    let mut paddings = Vec::new();
+    for padding in 0..16 {
+        send_padded(&mut client, &req_pq_multi(), padding);
+        let answer = receive_padded(&mut client);
+        paddings.push(answer.len() - 84);
+        let answer = strip_padding(&answer);
+        assert_eq!(answer.len(), 84);
+        let tl::enums::ResPq::Pq(res_pq) = tl::enums::ResPq::from_bytes(&answer[20..]).unwrap();
+        assert_eq!(res_pq.nonce, [0x42; 16]);
+    }
+    assert!(paddings.iter().all(|&padding| padding < 16));
+    // 16 random paddings are hardly ever the same
+    assert!(paddings.iter().any(|&padding| padding != paddings[0]));
+}
+
+#[test]
+fn pong() {
+    let mut client = Client::connect_with(PADDED_INTERMEDIATE_TAG, config_with_auth_key());
+    let first = message_id();
+    for padding in 0..16 {
+        let mut ping = 0x7abe77ecu32.to_le_bytes().to_vec();
+        ping.extend((padding as i64).to_le_bytes());
+        let ping_message_id = first + 4 * padding as i64;
+        send_padded(
+            &mut client,
+            &encrypted_message(ping_message_id, 2 * padding as i32 + 1, &ping),
+            padding,
+        );
+
+        let answer = decrypt_answer(strip_padding(&receive_padded(&mut client)));
+        let answer = match padding {
+            0 => skip_new_session_created(&answer),
+            _ => answer,
+        };
+        let pong = answer
+            .windows(4)
+            .position(|w| w == 0x347773c5u32.to_le_bytes())
+            .expect("no pong");
+        assert_eq!(answer[pong + 4..pong + 12], ping_message_id.to_le_bytes());
+        assert_eq!(answer[pong + 12..pong + 20], (padding as i64).to_le_bytes());
+    }
+}
@@ -0,0 +1,160 @@
+mod common;
+
+use std::{fs, path::PathBuf, process::Command, time::Duration};
+
+use aes::cipher::{KeyIvInit, StreamCipher};
+use bytes::BytesMut;
+use grammers_mtproto::transport::{Full, Transport};
+use rand::RngCore;
+use srv::{frame_abridged, parse_req_pq_multi, validate_obfuscation_header, Config, ServerError};
+
+use common::{req_pq_multi, Client, ABRIDGED_TAG};
+
+type Aes256Ctr64Be = ctr::Ctr64BE<aes::Aes256>;
+
+const MAX_PACKET: usize = 1 << 20;
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("tg_srv-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// The obfuscated transport encrypted with `key` and `iv` instead of the keys of the
+// header, like the clients of a proxy do
+fn dump_with_keys(key: [u8; 32], iv: [u8; 16]) -> Vec<u8> {
+    let mut init = [0; 64];
+    loop {
+        rand::thread_rng().fill_bytes(&mut init);
+        if validate_obfuscation_header(&init).is_ok() {
+            break;
+        }
+    }
+    init[56..60].copy_from_slice(&ABRIDGED_TAG);
+    let mut encrypted_init = init;
+    let mut encryptor = Aes256Ctr64Be::new(&key.into(), &iv.into());
+    encryptor.apply_keystream(&mut encrypted_init);
+
+    let mut dump = init[..56].to_vec();
+    dump.extend(&encrypted_init[56..]);
+    let mut frame = frame_abridged(&req_pq_multi());
+    encryptor.apply_keystream(&mut frame);
+    dump.extend(frame);
+    dump
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[test]
+fn parses_the_dump_of_a_connection() {
+    let dir = temp_dir("parse-dump");
+    let config = Config {
+        dump_dir: Some(dir.clone()),
+        stop_after_res_pq: true,
+        ..Default::default()
+    };
+    let mut client = Client::connect_with(ABRIDGED_TAG, config);
+    client.send_abridged(req_pq_multi());
+    client.receive_abridged();
+    assert!(client.is_closed(Duration::from_secs(5)));
+
+    let inbound = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .find(|path| path.extension().is_some_and(|ext| ext == "in"))
+        .unwrap();
+    let req_pq_multi = parse_req_pq_multi(&fs::read(inbound).unwrap(), None, MAX_PACKET).unwrap();
+    assert_eq!(req_pq_multi.auth_key_id, 0);
+    assert_eq!(req_pq_multi.message_id, 0x51e57ac42770964a);
+    assert_eq!(req_pq_multi.nonce, [0x42; 16]);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn parses_the_full_transport() {
+    let mut dump = BytesMut::new();
+    Full::new().pack(&req_pq_multi(), &mut dump);
+    let req_pq_multi = parse_req_pq_multi(&dump, None, MAX_PACKET).unwrap();
+    assert_eq!(req_pq_multi.nonce, [0x42; 16]);
+}
+
+#[test]
+fn decrypts_with_the_given_keys() {
+    let (key, iv) = ([0x11; 32], [0x22; 16]);
+    let dump = dump_with_keys(key, iv);
+    let req_pq_multi = parse_req_pq_multi(&dump, Some((key, iv)), MAX_PACKET).unwrap();
+    assert_eq!(req_pq_multi.nonce, [0x42; 16]);
+    // The keys of the header decrypt garbage
+    assert!(matches!(
+        parse_req_pq_multi(&dump, None, MAX_PACKET),
+        Err(ServerError::UnknownTransport(_))
+    ));
+}
+
+#[test]
+fn truncated_dump() {
+    let dump = dump_with_keys([0x11; 32], [0x22; 16]);
+    // The abridged frame of the 40 bytes of req_pq_multi has a 1 byte header
+    assert!(matches!(
+        parse_req_pq_multi(&dump[..70], Some(([0x11; 32], [0x22; 16])), MAX_PACKET),
+        Err(ServerError::ShortField {
+            field: "frame",
+            expected: 41,
+            available: 6,
+        })
+    ));
+    assert!(matches!(
+        parse_req_pq_multi(&dump[..64], Some(([0x11; 32], [0x22; 16])), MAX_PACKET),
+        Err(ServerError::ShortField {
+            field: "frame",
+            expected: 1,
+            available: 0,
+        })
+    ));
+}
+
+#[test]
+fn truncated_obfuscation_header() {
+    let dump = dump_with_keys([0x11; 32], [0x22; 16]);
+    assert!(matches!(
+        parse_req_pq_multi(&dump[..40], None, MAX_PACKET),
+        Err(ServerError::ShortField {
+            field: "obfuscation header",
+            expected: 64,
+            available: 40,
+        })
+    ));
+}
+
+#[test]
+fn parse_subcommand() {
+    let dir = temp_dir("parse-subcommand");
+    let file = dir.join("packet.bin");
+    let (key, iv) = ([0x33; 32], [0x44; 16]);
+    fs::write(&file, dump_with_keys(key, iv)).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_srv"))
+        .arg("parse")
+        .arg("--file")
+        .arg(&file)
+        .args(["--key", &hex(&key), "--iv", &hex(&iv)])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("ReqPqMulti {"), "{}", stdout);
+    assert!(stdout.contains("0x51e57ac42770964a"), "{}", stdout);
+    assert!(stdout.contains("0xbe7e8ef1"), "{}", stdout);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_srv"))
+        .arg("parse")
+        .arg("--file")
+        .arg(&file)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    fs::remove_dir_all(dir).unwrap();
+}
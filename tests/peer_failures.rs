@@ -0,0 +1,97 @@
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{IpAddr, Ipv4Addr, TcpListener, TcpStream},
+    process::{Child, Command},
+    thread,
+    time::{Duration, Instant},
+};
+
+use srv::peer_failures::PeerFailures;
+
+const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+// Whether the server closed the connection without waiting for more
+fn closed(stream: &mut TcpStream) -> bool {
+    stream
+        .set_read_timeout(Some(Duration::from_millis(300)))
+        .unwrap();
+    match stream.read(&mut [0; 1]) {
+        Ok(0) => true,
+        Err(e) if e.kind() == ErrorKind::ConnectionReset => true,
+        Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => false,
+        res => panic!("unexpected read: {:?}", res),
+    }
+}
+
+#[test]
+fn counter_increments() {
+    let peer_failures = PeerFailures::new(None);
+    assert_eq!(peer_failures.count(LOCALHOST), 0);
+    assert!(peer_failures.is_empty());
+    assert_eq!(peer_failures.record(LOCALHOST), 1);
+    assert_eq!(peer_failures.record(LOCALHOST), 2);
+    assert_eq!(peer_failures.count(LOCALHOST), 2);
+    // IPv4 clients of a dual-stack listener count as their address
+    let mapped = IpAddr::V6(Ipv4Addr::LOCALHOST.to_ipv6_mapped());
+    assert_eq!(peer_failures.record(mapped), 3);
+    let other = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2));
+    assert_eq!(peer_failures.record(other), 1);
+    assert_eq!(peer_failures.to_string(), "127.0.0.1 3, 127.0.0.2 1");
+    // Without a threshold nobody is banned
+    assert!(!peer_failures.is_banned(LOCALHOST));
+}
+
+#[test]
+fn ban_threshold() {
+    let peer_failures = PeerFailures::new(Some(2));
+    peer_failures.record(LOCALHOST);
+    assert!(!peer_failures.is_banned(LOCALHOST));
+    peer_failures.record(LOCALHOST);
+    assert!(peer_failures.is_banned(LOCALHOST));
+    assert!(!peer_failures.is_banned(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2))));
+}
+
+#[test]
+fn failing_peer_banned() {
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let _server = Server(
+        Command::new(env!("CARGO_BIN_EXE_srv"))
+            .args(["--bind", &addr.to_string(), "--ban-threshold", "2"])
+            .spawn()
+            .unwrap(),
+    );
+    let start = Instant::now();
+    let mut stream = loop {
+        match TcpStream::connect(addr) {
+            Ok(stream) => break stream,
+            Err(_) if start.elapsed() < Duration::from_secs(5) => {
+                thread::sleep(Duration::from_millis(50))
+            }
+            Err(e) => panic!("the server didn't start: {}", e),
+        }
+    };
+
+    for i in 0..2 {
+        if i > 0 {
+            stream = TcpStream::connect(addr).unwrap();
+        }
+        // The unobfuscated abridged transport isn't accepted
+        stream.write_all(&[0xef; 64]).unwrap();
+        assert!(closed(&mut stream));
+    }
+    // The failure is counted once the handler returns, after closing the socket
+    thread::sleep(Duration::from_millis(200));
+    assert!(closed(&mut TcpStream::connect(addr).unwrap()));
+}
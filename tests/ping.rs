@@ -0,0 +1,36 @@
+mod common;
+
+use common::{
+    config_with_auth_key, decrypt_answer, encrypted_message, message_id, skip_new_session_created,
+    Client, ABRIDGED_TAG, SALT, SESSION_ID,
+};
+
+fn i64_at(data: &[u8], pos: usize) -> i64 {
+    i64::from_le_bytes(data[pos..pos + 8].try_into().unwrap())
+}
+
+#[test]
+fn pong() {
+    let mut client = Client::connect_with(ABRIDGED_TAG, config_with_auth_key());
+    let ping_message_id = message_id();
+    let mut ping = 0x7abe77ecu32.to_le_bytes().to_vec();
+    ping.extend(0x0123456789abcdefi64.to_le_bytes());
+    client.send_abridged(encrypted_message(ping_message_id, 1, &ping));
+
+    let answer = skip_new_session_created(&decrypt_answer(&client.receive_abridged()));
+    client.close_write();
+    let (rest, res) = client.receive_all();
+    assert!(rest.is_empty());
+    res.unwrap();
+
+    // The salt of the server, not the client's
+    assert_ne!(i64_at(&answer, 0), SALT);
+    assert_eq!(i64_at(&answer, 8), SESSION_ID);
+    assert_eq!(i64_at(&answer, 16) % 4, 1);
+    // The content related message after new_session_created
+    assert_eq!(answer[24..28], 3i32.to_le_bytes());
+    assert_eq!(answer[28..32], 20u32.to_le_bytes());
+    assert_eq!(answer[32..36], 0x347773c5u32.to_le_bytes());
+    assert_eq!(i64_at(&answer, 36), ping_message_id);
+    assert_eq!(i64_at(&answer, 44), 0x0123456789abcdef);
+}
@@ -0,0 +1,70 @@
+mod common;
+
+use grammers_tl_types::{self as tl, Deserializable};
+use num_bigint::BigUint;
+use srv::{
+    error::Result,
+    messages::{ReqDHParams, ReqPqMulti, ResPq, ServerDHInnerData},
+    policy::{DefaultPolicy, HandshakePolicy},
+    Config,
+};
+
+use common::{req_pq_multi, Client, ABRIDGED_TAG};
+
+// The pq of the examples of the MTProto documentation
+const PQ: u64 = 0x17ed48941a08f981;
+
+struct FixedPq;
+
+impl HandshakePolicy for FixedPq {
+    fn on_req_pq_multi(
+        &self,
+        config: &Config,
+        req: &ReqPqMulti,
+        server_nonce: [u8; 16],
+        message_id: i64,
+    ) -> Result<ResPq> {
+        ResPq::generate(
+            req.nonce,
+            server_nonce,
+            message_id,
+            PQ.to_be_bytes().to_vec(),
+            config.fingerprints(),
+        )
+    }
+
+    fn on_req_dh_params(
+        &self,
+        config: &Config,
+        req: &ReqDHParams,
+        a: &BigUint,
+        message_id: i64,
+    ) -> Result<ServerDHInnerData> {
+        DefaultPolicy.on_req_dh_params(config, req, a, message_id)
+    }
+}
+
+fn config() -> Config {
+    Config {
+        policy: Box::new(FixedPq),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn fixed_pq() {
+    let mut client = Client::connect_with(ABRIDGED_TAG, config());
+    client.send_abridged(req_pq_multi());
+    let tl::enums::ResPq::Pq(res_pq) =
+        tl::enums::ResPq::from_bytes(&client.receive_abridged()[20..]).unwrap();
+    assert_eq!(res_pq.pq, PQ.to_be_bytes());
+    assert_eq!(res_pq.nonce, [0x42; 16]);
+}
+
+#[test]
+fn handshake_with_fixed_pq() {
+    let mut client = Client::connect_with(ABRIDGED_TAG, config());
+    client.handshake();
+    client.close_write();
+    client.receive_all().1.unwrap();
+}
@@ -0,0 +1,62 @@
+use srv::{pq, ServerError};
+
+#[test]
+fn requested_bit_length() {
+    for bits in [16, 17, 32, 48, 62, 63] {
+        for _ in 0..5 {
+            let pq = pq::generate_pq(bits).unwrap();
+            assert_eq!(64 - pq.leading_zeros(), bits, "{:x}", pq);
+            let (p, q) = pq::factorize(pq).unwrap();
+            assert!(p < q);
+            assert_eq!(p as u64 * q as u64, pq);
+            // Both are about half as long
+            assert!(32 - p.leading_zeros() >= bits / 2 - 1);
+            assert!(32 - q.leading_zeros() <= bits - bits / 2);
+        }
+    }
+}
+
+#[test]
+fn unrealistic_bit_length() {
+    for bits in [0, 15, 64, 128] {
+        assert!(matches!(
+            pq::generate_pq(bits),
+            Err(ServerError::InvalidPqBits(b)) if b == bits
+        ));
+    }
+}
+
+#[test]
+fn leading_zeroes() {
+    assert_eq!(pq::from_be_bytes(&[0, 0, 0x12, 0x34]), Some(0x1234));
+    assert_eq!(pq::from_be_bytes(&[0x12, 0x34]), Some(0x1234));
+    assert_eq!(pq::from_be_bytes(&[]), Some(0));
+    assert_eq!(pq::from_be_bytes(&[0; 12]), Some(0));
+    assert_eq!(pq::from_be_bytes(&[1; 9]), None);
+}
+
+// The example of the MTProto documentation
+#[test]
+fn documented_pq() {
+    assert_eq!(
+        pq::factorize(0x17ED48941A08F981).unwrap(),
+        (0x494C553B, 0x53911073)
+    );
+}
+
+fn invalid_reason(pq: u64) -> String {
+    match pq::factorize(pq) {
+        Err(ServerError::InvalidPq { reason, .. }) => reason,
+        res => panic!("{:x}: {:?}", pq, res),
+    }
+}
+
+#[test]
+fn no_two_factors() {
+    assert_eq!(invalid_reason(0), "no nontrivial factors");
+    assert_eq!(invalid_reason(1), "no nontrivial factors");
+    // The largest prime below 2^32, and the Mersenne prime 2^61 - 1
+    assert_eq!(invalid_reason(0xfffffffb), "prime");
+    assert_eq!(invalid_reason(0x1fffffffffffffff), "prime");
+    assert_eq!(invalid_reason(3 * 5 * 7), "not a product of two primes");
+}
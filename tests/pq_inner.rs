@@ -0,0 +1,164 @@
+use grammers_tl_types::{self as tl, Cursor, Serializable};
+use sha1::{Digest, Sha1};
+use srv::{
+    messages::{validate_pq_inner, PQInnerData, PqInnerDataError},
+    ServerError,
+};
+
+const NONCE: [u8; 16] = [0x11; 16];
+const SERVER_NONCE: [u8; 16] = [0x22; 16];
+const P: u32 = 1229739323;
+const Q: u32 = 1402015859;
+
+// Offsets of the fields in the serialized p_q_inner_data
+const PQ_OFFSET: usize = 4 + 1;
+const P_OFFSET: usize = 4 + 12 + 1;
+const Q_OFFSET: usize = 4 + 12 + 8 + 1;
+const NONCE_OFFSET: usize = 4 + 12 + 8 + 8;
+const SERVER_NONCE_OFFSET: usize = NONCE_OFFSET + 16;
+
+fn pq_inner_data() -> Vec<u8> {
+    tl::enums::PQInnerData::Data(tl::types::PQInnerData {
+        pq: (P as u64 * Q as u64).to_be_bytes().to_vec(),
+        p: P.to_be_bytes().to_vec(),
+        q: Q.to_be_bytes().to_vec(),
+        nonce: NONCE,
+        server_nonce: SERVER_NONCE,
+        new_nonce: [0x33; 32],
+    })
+    .to_bytes()
+}
+
+fn with_hash(data: &[u8]) -> Vec<u8> {
+    let mut res = Sha1::digest(data).to_vec();
+    res.extend(data);
+    res.resize(255, 0x44);
+    res
+}
+
+fn validate(data_with_hash: &[u8]) -> Result<(), PqInnerDataError> {
+    match validate_pq_inner(data_with_hash, &NONCE, &SERVER_NONCE, P, Q) {
+        Ok(_) => Ok(()),
+        Err(ServerError::PqInnerData(e)) => Err(e),
+        Err(e) => panic!("unexpected error: {}", e),
+    }
+}
+
+fn flip(offset: usize) -> Result<(), PqInnerDataError> {
+    let mut data = pq_inner_data();
+    data[offset] ^= 1;
+    validate(&with_hash(&data))
+}
+
+#[test]
+fn valid() {
+    let data_with_hash = with_hash(&pq_inner_data());
+    let pq_inner_data = validate_pq_inner(&data_with_hash, &NONCE, &SERVER_NONCE, P, Q).unwrap();
+    assert_eq!(pq_inner_data.new_nonce, [0x33; 32]);
+}
+
+#[test]
+fn bad_hash() {
+    let mut data_with_hash = with_hash(&pq_inner_data());
+    data_with_hash[0] ^= 1;
+    assert_eq!(validate(&data_with_hash), Err(PqInnerDataError::BadHash));
+}
+
+#[test]
+fn nonce_mismatch() {
+    assert_eq!(flip(NONCE_OFFSET), Err(PqInnerDataError::NonceMismatch));
+}
+
+#[test]
+fn server_nonce_mismatch() {
+    assert_eq!(
+        flip(SERVER_NONCE_OFFSET),
+        Err(PqInnerDataError::NonceMismatch)
+    );
+}
+
+#[test]
+fn pq_mismatch() {
+    assert_eq!(flip(PQ_OFFSET), Err(PqInnerDataError::PqMismatch));
+    assert_eq!(flip(P_OFFSET), Err(PqInnerDataError::PqMismatch));
+    assert_eq!(flip(Q_OFFSET), Err(PqInnerDataError::PqMismatch));
+}
+
+fn parse_variant(data: tl::enums::PQInnerData) -> PQInnerData {
+    validate_pq_inner(&with_hash(&data.to_bytes()), &NONCE, &SERVER_NONCE, P, Q).unwrap()
+}
+
+#[test]
+fn dc_variant() {
+    let pq_inner_data = parse_variant(tl::enums::PQInnerData::Dc(tl::types::PQInnerDataDc {
+        pq: (P as u64 * Q as u64).to_be_bytes().to_vec(),
+        p: P.to_be_bytes().to_vec(),
+        q: Q.to_be_bytes().to_vec(),
+        nonce: NONCE,
+        server_nonce: SERVER_NONCE,
+        new_nonce: [0x33; 32],
+        dc: 2,
+    }));
+    assert_eq!(pq_inner_data.new_nonce, [0x33; 32]);
+    assert_eq!(pq_inner_data.dc, Some(2));
+    assert!(!pq_inner_data.is_temp());
+}
+
+#[test]
+fn temp_variant() {
+    let pq_inner_data = parse_variant(tl::enums::PQInnerData::Temp(tl::types::PQInnerDataTemp {
+        pq: (P as u64 * Q as u64).to_be_bytes().to_vec(),
+        p: P.to_be_bytes().to_vec(),
+        q: Q.to_be_bytes().to_vec(),
+        nonce: NONCE,
+        server_nonce: SERVER_NONCE,
+        new_nonce: [0x33; 32],
+        expires_in: 3600,
+    }));
+    assert_eq!(pq_inner_data.dc, None);
+    assert_eq!(pq_inner_data.expires_in, Some(3600));
+    assert!(pq_inner_data.is_temp());
+}
+
+#[test]
+fn temp_dc_variant() {
+    let pq_inner_data = parse_variant(tl::enums::PQInnerData::TempDc(
+        tl::types::PQInnerDataTempDc {
+            pq: (P as u64 * Q as u64).to_be_bytes().to_vec(),
+            p: P.to_be_bytes().to_vec(),
+            q: Q.to_be_bytes().to_vec(),
+            nonce: NONCE,
+            server_nonce: SERVER_NONCE,
+            new_nonce: [0x33; 32],
+            dc: -2,
+            expires_in: 86400,
+        },
+    ));
+    assert_eq!(pq_inner_data.new_nonce, [0x33; 32]);
+    assert_eq!(pq_inner_data.dc, Some(-2));
+    assert_eq!(pq_inner_data.expires_in, Some(86400));
+}
+
+#[test]
+fn unknown_constructor() {
+    let mut data = pq_inner_data();
+    data[0] ^= 1;
+    assert!(matches!(
+        PQInnerData::parse(&mut Cursor::from_slice(&data)),
+        Err(ServerError::MagicMismatch { .. })
+    ));
+}
+
+#[test]
+fn truncated_new_nonce() {
+    let data = pq_inner_data();
+    let new_nonce_offset = SERVER_NONCE_OFFSET + 16;
+    assert!(matches!(
+        PQInnerData::parse(&mut Cursor::from_slice(&data[..new_nonce_offset + 20])),
+        Err(ServerError::ShortField {
+            field: "new_nonce",
+            expected: 32,
+            available: 20,
+        })
+    ));
+}
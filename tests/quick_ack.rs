@@ -0,0 +1,49 @@
+mod common;
+
+use common::{req_pq_multi, Client, ABRIDGED_TAG, INTERMEDIATE_TAG};
+use sha1::{Digest, Sha1};
+
+const RES_PQ_MAGIC: [u8; 4] = 0x05162463u32.to_le_bytes();
+
+fn token(packet: &[u8]) -> u32 {
+    u32::from_be_bytes(Sha1::digest(packet)[..4].try_into().unwrap()) | 1 << 31
+}
+
+#[test]
+fn abridged() {
+    let mut client = Client::connect(ABRIDGED_TAG);
+    let packet = req_pq_multi();
+    let mut frame = vec![0x80 | (packet.len() / 4) as u8];
+    frame.extend(&packet);
+    client.send(frame);
+
+    assert_eq!(client.receive(4), token(&packet).to_be_bytes());
+    // The answer follows as usual
+    let len = client.receive(1)[0] as usize * 4;
+    assert_eq!(client.receive(len)[20..24], RES_PQ_MAGIC);
+}
+
+#[test]
+fn intermediate() {
+    let mut client = Client::connect(INTERMEDIATE_TAG);
+    let packet = req_pq_multi();
+    let mut frame = (packet.len() as u32 | 1 << 31).to_le_bytes().to_vec();
+    frame.extend(&packet);
+    client.send(frame);
+
+    assert_eq!(client.receive(4), token(&packet).to_le_bytes());
+    let len = u32::from_le_bytes(client.receive(4).try_into().unwrap()) as usize;
+    assert_eq!(client.receive(len)[20..24], RES_PQ_MAGIC);
+}
+
+#[test]
+fn not_requested() {
+    let mut client = Client::connect(ABRIDGED_TAG);
+    let packet = req_pq_multi();
+    let mut frame = vec![(packet.len() / 4) as u8];
+    frame.extend(&packet);
+    client.send(frame);
+
+    let len = client.receive(1)[0] as usize * 4;
+    assert_eq!(client.receive(len)[20..24], RES_PQ_MAGIC);
+}
@@ -0,0 +1,96 @@
+use std::{
+    io::{ErrorKind, Read},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, TcpListener, TcpStream},
+    process::{Child, Command},
+    thread,
+    time::{Duration, Instant},
+};
+
+use srv::rate_limit::RateLimiter;
+
+const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+// Whether the server closed the connection instead of waiting for the
+// obfuscation header
+fn rejected(stream: &mut TcpStream) -> bool {
+    stream
+        .set_read_timeout(Some(Duration::from_millis(300)))
+        .unwrap();
+    match stream.read(&mut [0; 1]) {
+        Ok(0) => true,
+        Err(e) if e.kind() == ErrorKind::ConnectionReset => true,
+        Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => false,
+        res => panic!("unexpected read: {:?}", res),
+    }
+}
+
+#[test]
+fn bucket() {
+    let rate_limiter = RateLimiter::new(2.0, 3);
+    let start = Instant::now();
+    for _ in 0..3 {
+        assert!(rate_limiter.allow_at(LOCALHOST, start));
+    }
+    assert!(!rate_limiter.allow_at(LOCALHOST, start));
+    // Other IPs have their own buckets
+    assert!(rate_limiter.allow_at(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2)), start));
+
+    // A token every half a second
+    assert!(!rate_limiter.allow_at(LOCALHOST, start + Duration::from_millis(400)));
+    assert!(rate_limiter.allow_at(LOCALHOST, start + Duration::from_millis(500)));
+    assert!(!rate_limiter.allow_at(LOCALHOST, start + Duration::from_millis(500)));
+}
+
+#[test]
+fn ipv4_mapped_shares_the_bucket() {
+    let rate_limiter = RateLimiter::new(1.0, 1);
+    let start = Instant::now();
+    assert!(rate_limiter.allow_at(LOCALHOST, start));
+    let mapped = IpAddr::V6(Ipv4Addr::LOCALHOST.to_ipv6_mapped());
+    assert!(!rate_limiter.allow_at(mapped, start));
+    assert!(rate_limiter.allow_at(IpAddr::V6(Ipv6Addr::LOCALHOST), start));
+}
+
+#[test]
+fn excess_connections_rejected() {
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let _server = Server(
+        Command::new(env!("CARGO_BIN_EXE_srv"))
+            .args(["--bind", &addr.to_string()])
+            .args(["--rate-limit", "0.1", "--rate-burst", "3"])
+            .spawn()
+            .unwrap(),
+    );
+    let start = Instant::now();
+    let mut first = loop {
+        match TcpStream::connect(addr) {
+            Ok(stream) => break stream,
+            Err(_) if start.elapsed() < Duration::from_secs(5) => {
+                thread::sleep(Duration::from_millis(50))
+            }
+            Err(e) => panic!("the server didn't start: {}", e),
+        }
+    };
+
+    let mut allowed: Vec<_> = (0..2).map(|_| TcpStream::connect(addr).unwrap()).collect();
+    let mut excess: Vec<_> = (0..3).map(|_| TcpStream::connect(addr).unwrap()).collect();
+    assert!(!rejected(&mut first));
+    for stream in &mut allowed {
+        assert!(!rejected(stream));
+    }
+    for stream in &mut excess {
+        assert!(rejected(stream));
+    }
+}
@@ -0,0 +1,254 @@
+mod common;
+
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use common::{req_pq_multi, Client, ABRIDGED_TAG};
+use srv::{records::JsonRecords, Config, ConnId, ServerError};
+
+#[derive(Clone, Default)]
+struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Json {
+    Null,
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(BTreeMap<String, Json>),
+}
+
+impl Json {
+    fn field(&self, key: &str) -> &Json {
+        match self {
+            Json::Object(fields) => &fields[key],
+            _ => panic!("{:?} is not an object", self),
+        }
+    }
+
+    fn str(&self) -> &str {
+        match self {
+            Json::String(s) => s,
+            _ => panic!("{:?} is not a string", self),
+        }
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        match self {
+            Json::Object(fields) => fields.keys().map(String::as_str).collect(),
+            _ => panic!("{:?} is not an object", self),
+        }
+    }
+}
+
+// Just enough JSON for the records, panics on anything else
+fn parse(s: &mut &str) -> Json {
+    *s = s.trim_start();
+    if let Some(rest) = s.strip_prefix("null") {
+        *s = rest;
+        return Json::Null;
+    }
+    let (c, rest) = (s.chars().next().unwrap(), &s[1..]);
+    match c {
+        '"' => {
+            let mut string = String::new();
+            let mut chars = rest.char_indices();
+            loop {
+                match chars.next().unwrap() {
+                    (end, '"') => {
+                        *s = &rest[end + 1..];
+                        return Json::String(string);
+                    }
+                    (_, '\\') => match chars.next().unwrap().1 {
+                        'n' => string.push('\n'),
+                        'r' => string.push('\r'),
+                        't' => string.push('\t'),
+                        'u' => {
+                            let hex: String = (0..4).map(|_| chars.next().unwrap().1).collect();
+                            let code = u32::from_str_radix(&hex, 16).unwrap();
+                            string.push(char::from_u32(code).unwrap());
+                        }
+                        c @ ('"' | '\\' | '/') => string.push(c),
+                        c => panic!("unexpected escape {:?}", c),
+                    },
+                    (_, c) => {
+                        // JSON only asks for the escape of the C0 controls
+                        assert!(c >= ' ', "unescaped {:?}", c);
+                        string.push(c);
+                    }
+                }
+            }
+        }
+        '[' => {
+            *s = rest;
+            let mut values = Vec::new();
+            if let Some(rest) = s.strip_prefix(']') {
+                *s = rest;
+                return Json::Array(values);
+            }
+            loop {
+                values.push(parse(s));
+                let (c, rest) = (s.chars().next().unwrap(), &s[1..]);
+                *s = rest;
+                match c {
+                    ',' => continue,
+                    ']' => return Json::Array(values),
+                    c => panic!("unexpected {:?}", c),
+                }
+            }
+        }
+        '{' => {
+            *s = rest;
+            let mut fields = BTreeMap::new();
+            loop {
+                let Json::String(key) = parse(s) else {
+                    panic!("expected a key");
+                };
+                *s = s.strip_prefix(':').unwrap();
+                fields.insert(key, parse(s));
+                let (c, rest) = (s.chars().next().unwrap(), &s[1..]);
+                *s = rest;
+                match c {
+                    ',' => continue,
+                    '}' => return Json::Object(fields),
+                    c => panic!("unexpected {:?}", c),
+                }
+            }
+        }
+        _ => {
+            let end = s
+                .find(|c: char| !(c.is_ascii_digit() || "-.e".contains(c)))
+                .unwrap_or(s.len());
+            let number = s[..end].parse().unwrap();
+            *s = &s[end..];
+            Json::Number(number)
+        }
+    }
+}
+
+fn records(buffer: &Buffer) -> Vec<Json> {
+    let out = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+    out.lines()
+        .map(|mut line| {
+            let json = parse(&mut line);
+            assert!(line.is_empty(), "trailing {:?}", line);
+            json
+        })
+        .collect()
+}
+
+fn config(buffer: &Buffer) -> Config {
+    Config {
+        json_records: Some(JsonRecords::new(buffer.clone())),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn completed_handshake() {
+    let buffer = Buffer::default();
+    let mut client = Client::connect_with(ABRIDGED_TAG, config(&buffer));
+    let auth_key = client.handshake();
+    let (_, res) = client.receive_all();
+    res.unwrap();
+
+    let records = records(&buffer);
+    assert_eq!(records.len(), 1);
+    let record = &records[0];
+    assert_eq!(
+        record.keys(),
+        [
+            "auth_key_id",
+            "connection",
+            "duration_ms",
+            "error",
+            "outcome",
+            "peer",
+            "req_pq",
+            "res_pq",
+            "stage"
+        ]
+    );
+    assert!(matches!(record.field("connection"), Json::Number(_)));
+    assert!(matches!(record.field("duration_ms"), Json::Number(ms) if *ms >= 0.0));
+    assert!(record.field("peer").str().starts_with("127.0.0.1:"));
+    assert_eq!(record.field("outcome").str(), "done");
+    assert_eq!(record.field("stage").str(), "SetClientDHParams");
+    assert_eq!(
+        record.field("auth_key_id").str(),
+        format!("{:016x}", srv::keys::auth_key_id(&auth_key))
+    );
+    assert_eq!(record.field("error"), &Json::Null);
+
+    let req_pq = record.field("req_pq");
+    assert_eq!(req_pq.keys(), ["message_id", "name", "nonce"]);
+    assert_eq!(req_pq.field("name").str(), "req_pq_multi");
+    assert_eq!(req_pq.field("nonce").str(), "42".repeat(16));
+
+    let res_pq = record.field("res_pq");
+    assert_eq!(
+        res_pq.keys(),
+        ["fingerprints", "message_id", "nonce", "pq", "server_nonce"]
+    );
+    assert_eq!(res_pq.field("nonce").str(), "42".repeat(16));
+    assert_eq!(res_pq.field("server_nonce").str().len(), 32);
+    assert_eq!(res_pq.field("pq").str().len(), 16);
+    assert!(
+        matches!(res_pq.field("fingerprints"), Json::Array(fingerprints) if fingerprints.len() == 1)
+    );
+}
+
+#[test]
+fn failed_handshake() {
+    let buffer = Buffer::default();
+    let mut client = Client::connect_with(ABRIDGED_TAG, config(&buffer));
+    client.send_abridged(req_pq_multi());
+    client.receive_abridged();
+    client.send_abridged(req_pq_multi());
+    let (_, res) = client.receive_all();
+    assert!(res.is_err());
+
+    let records = records(&buffer);
+    assert_eq!(records.len(), 1);
+    let record = &records[0];
+    assert_eq!(record.field("outcome").str(), "failed");
+    assert_eq!(record.field("stage").str(), "ReqDHParams");
+    assert_eq!(record.field("auth_key_id"), &Json::Null);
+    assert!(!record.field("error").str().is_empty());
+    assert!(matches!(record.field("res_pq"), Json::Object(_)));
+}
+
+#[test]
+fn escaped_strings() {
+    let buffer = Buffer::default();
+    let records = JsonRecords::new(buffer.clone());
+    let peer = "unix \"socket\" \\tmp\\srv";
+    let reason = "\"quoted\", back\\slash\\, new\nline, tab\t, bell\u{7}, nul\0, del\u{7f}, é";
+    let error = ServerError::InvalidScript(reason.to_string());
+    records
+        .finish(ConnId::unaddressed(peer), Duration::ZERO, &Err(error))
+        .unwrap();
+
+    let records = self::records(&buffer);
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].field("peer").str(), peer);
+    assert_eq!(
+        records[0].field("error").str(),
+        format!("invalid script: {}", reason)
+    );
+}
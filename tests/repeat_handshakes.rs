@@ -0,0 +1,49 @@
+mod common;
+
+use common::{req_pq_multi, Client, ABRIDGED_TAG};
+use grammers_tl_types::{self as tl, Deserializable};
+use srv::Config;
+
+fn config() -> Config {
+    Config {
+        repeat_handshakes: true,
+        ..Default::default()
+    }
+}
+
+fn server_nonce(answer: &[u8]) -> [u8; 16] {
+    let tl::enums::ResPq::Pq(res_pq) = tl::enums::ResPq::from_bytes(&answer[20..]).unwrap();
+    res_pq.server_nonce
+}
+
+#[test]
+fn back_to_back_req_pq_multi() {
+    let mut client = Client::connect_with(ABRIDGED_TAG, config());
+    client.send_abridged(req_pq_multi());
+    let first = server_nonce(&client.receive_abridged());
+    client.send_abridged(req_pq_multi());
+    let second = server_nonce(&client.receive_abridged());
+    assert_ne!(first, second);
+}
+
+#[test]
+fn two_auth_keys() {
+    let mut client = Client::connect_with(ABRIDGED_TAG, config());
+    let first = client.handshake();
+    let second = client.handshake();
+    assert_ne!(first, second);
+    client.close_write();
+    let (rest, res) = client.receive_all();
+    assert!(rest.is_empty());
+    res.unwrap();
+}
+
+#[test]
+fn one_handshake_without_the_flag() {
+    let mut client = Client::connect(ABRIDGED_TAG);
+    client.send_abridged(req_pq_multi());
+    client.receive_abridged();
+    client.send_abridged(req_pq_multi());
+    let (_, res) = client.receive_all();
+    assert!(res.is_err());
+}
@@ -0,0 +1,30 @@
+mod common;
+
+use common::{unencrypted_message, Client, ABRIDGED_TAG};
+use grammers_tl_types::{self as tl, Cursor, Deserializable, Serializable};
+
+const NONCE: [u8; 16] = [0x42; 16];
+
+// Sends `body` as the first request and returns the ResPq of the answer
+fn res_pq(body: &[u8]) -> tl::types::ResPq {
+    let mut client = Client::connect(ABRIDGED_TAG);
+    client.send_abridged(unencrypted_message(body));
+    let answer = client.receive_abridged();
+    let mut cur = Cursor::from_slice(&answer[20..]);
+    let tl::enums::ResPq::Pq(res_pq) = tl::enums::ResPq::deserialize(&mut cur).unwrap();
+    res_pq
+}
+
+#[test]
+fn req_pq_multi() {
+    let res_pq = res_pq(&tl::functions::ReqPqMulti { nonce: NONCE }.to_bytes());
+    assert_eq!(res_pq.nonce, NONCE);
+    assert!(!res_pq.server_public_key_fingerprints.is_empty());
+}
+
+#[test]
+fn legacy_req_pq() {
+    let res_pq = res_pq(&tl::functions::ReqPq { nonce: NONCE }.to_bytes());
+    assert_eq!(res_pq.nonce, NONCE);
+    assert!(!res_pq.server_public_key_fingerprints.is_empty());
+}
@@ -0,0 +1,58 @@
+mod common;
+
+use std::process::Command;
+
+use common::{req_pq_multi, Client, ABRIDGED_TAG, INTERMEDIATE_TAG};
+use srv::{Config, ServerError};
+
+fn config(code: i32) -> Config {
+    Config {
+        respond_error: Some(code),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn abridged() {
+    let mut client = Client::connect_with(ABRIDGED_TAG, config(-404));
+    client.send_abridged(req_pq_multi());
+
+    let (answer, res) = client.receive_all();
+    // One word, -404
+    assert_eq!(answer, [0x01, 0x6c, 0xfe, 0xff, 0xff]);
+    res.unwrap();
+}
+
+#[test]
+fn intermediate() {
+    let mut client = Client::connect_with(INTERMEDIATE_TAG, config(-429));
+    let packet = req_pq_multi();
+    let mut frame = (packet.len() as u32).to_le_bytes().to_vec();
+    frame.extend(packet);
+    client.send(frame);
+
+    let (answer, res) = client.receive_all();
+    // 4 bytes, -429
+    assert_eq!(answer, [0x04, 0x00, 0x00, 0x00, 0x53, 0xfe, 0xff, 0xff]);
+    res.unwrap();
+}
+
+#[test]
+fn only_for_req_pq_multi() {
+    let mut client = Client::connect_with(ABRIDGED_TAG, config(-404));
+    let mut packet = req_pq_multi();
+    packet[20..24].copy_from_slice(&0xdeadbeefu32.to_le_bytes());
+    client.send_abridged(packet);
+
+    let (_, res) = client.receive_all();
+    assert!(matches!(res, Err(ServerError::MagicMismatch { .. })));
+}
+
+#[test]
+fn positive_code_rejected() {
+    let output = Command::new(env!("CARGO_BIN_EXE_srv"))
+        .args(["--respond-error", "404"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+}
@@ -0,0 +1,33 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::{req_pq_multi, Client, ABRIDGED_TAG};
+use srv::Config;
+
+const DELAY: Duration = Duration::from_millis(200);
+
+fn config() -> Config {
+    Config {
+        response_delay: DELAY,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn res_pq_delayed() {
+    let mut client = Client::connect_with(ABRIDGED_TAG, config());
+    let start = Instant::now();
+    client.send_abridged(req_pq_multi());
+    client.receive_abridged();
+    assert!(start.elapsed() >= DELAY, "{:?}", start.elapsed());
+}
+
+#[test]
+fn every_answer_delayed() {
+    let mut client = Client::connect_with(ABRIDGED_TAG, config());
+    let start = Instant::now();
+    client.handshake();
+    // ResPq, ServerDHParams and DhGenOk
+    assert!(start.elapsed() >= 3 * DELAY, "{:?}", start.elapsed());
+}
@@ -0,0 +1,68 @@
+mod common;
+
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    thread,
+};
+
+use aes::cipher::{KeyIvInit, StreamCipher};
+use grammers_tl_types::{self as tl, Deserializable};
+use rand::RngCore;
+use srv::{handle_connection, validate_obfuscation_header, Config, ObfuscationKeys};
+
+use common::{req_pq_multi, ABRIDGED_TAG};
+
+type Aes256Ctr64Be = ctr::Ctr64BE<aes::Aes256>;
+
+// The answers continue the keystream of the ones before, so the bytes of two
+// answers read at once decrypt with a single cipher
+#[test]
+fn two_answers_one_keystream() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (server, _) = listener.accept().unwrap();
+    let config = Config {
+        repeat_handshakes: true,
+        ..Default::default()
+    };
+    let server = thread::spawn(move || handle_connection(server, &config));
+
+    let mut init = [0; 64];
+    loop {
+        rand::thread_rng().fill_bytes(&mut init);
+        if validate_obfuscation_header(&init).is_ok() {
+            break;
+        }
+    }
+    init[56..60].copy_from_slice(&ABRIDGED_TAG);
+    let keys = ObfuscationKeys::derive(&init);
+    let mut encryptor = Aes256Ctr64Be::new(&keys.encrypt_key.into(), &keys.encrypt_iv.into());
+    let mut encrypted_init = init;
+    encryptor.apply_keystream(&mut encrypted_init);
+    init[56..].copy_from_slice(&encrypted_init[56..]);
+
+    let mut request = init.to_vec();
+    for _ in 0..2 {
+        let packet = req_pq_multi();
+        let mut frame = vec![(packet.len() / 4) as u8];
+        frame.extend(packet);
+        encryptor.apply_keystream(&mut frame);
+        request.extend(frame);
+    }
+    stream.write_all(&request).unwrap();
+
+    // Two res_pq of 84 bytes with a single fingerprint, each after its length
+    let mut answers = vec![0; 2 * (1 + 84)];
+    stream.read_exact(&mut answers).unwrap();
+    drop(stream);
+    let _ = server.join().unwrap();
+
+    let mut decryptor = Aes256Ctr64Be::new(&keys.decrypt_key.into(), &keys.decrypt_iv.into());
+    decryptor.apply_keystream(&mut answers);
+    for answer in answers.chunks(1 + 84) {
+        assert_eq!(answer[0], 84 / 4);
+        let tl::enums::ResPq::Pq(res_pq) = tl::enums::ResPq::from_bytes(&answer[21..]).unwrap();
+        assert_eq!(res_pq.nonce, [0x42; 16]);
+    }
+}
@@ -0,0 +1,74 @@
+mod common;
+
+use std::{
+    net::{SocketAddr, TcpListener, TcpStream},
+    process::{Child, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use common::{Client, ABRIDGED_TAG};
+
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+// Starts a server on `addr` and waits until it accepts connections, panics if it
+// exits instead
+fn start(addr: SocketAddr, args: &[&str]) -> Server {
+    let mut server = Server(
+        Command::new(env!("CARGO_BIN_EXE_srv"))
+            .args(["--bind", &addr.to_string(), "--backlog", "16"])
+            .args(args)
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap(),
+    );
+    let start = Instant::now();
+    while TcpStream::connect(addr).is_err() {
+        if let Some(status) = server.0.try_wait().unwrap() {
+            panic!("server exited with {}", status);
+        }
+        assert!(
+            start.elapsed() < Duration::from_secs(5),
+            "server didn't start"
+        );
+        thread::sleep(Duration::from_millis(50));
+    }
+    server
+}
+
+fn free_addr() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+// The server closes the connection after the handshake, so its end stays in
+// TIME_WAIT once it's killed
+#[test]
+fn restart_on_the_same_port() {
+    let addr = free_addr();
+    for _ in 0..2 {
+        let _server = start(addr, &[]);
+        let mut client = Client::connect_to(addr, ABRIDGED_TAG);
+        client.handshake();
+        assert!(client.is_closed(Duration::from_secs(5)));
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn reuse_port() {
+    let addr = free_addr();
+    let _first = start(addr, &["--reuse-port"]);
+    let _second = start(addr, &["--reuse-port"]);
+    let mut client = Client::connect_to(addr, ABRIDGED_TAG);
+    client.handshake();
+}
@@ -0,0 +1,60 @@
+use srv::{
+    rsa_key::{parse_fingerprint, RsaKey},
+    Config,
+};
+
+fn config() -> Config {
+    Config {
+        rsa_keys: vec![
+            RsaKey::from_pem(include_str!("data/rsa1.pem")).unwrap(),
+            RsaKey::from_pem(include_str!("data/rsa2.pem")).unwrap(),
+        ],
+        ..Default::default()
+    }
+}
+
+#[test]
+fn advertises_every_key() {
+    let config = config();
+    let fingerprints = config.fingerprints();
+    assert_eq!(fingerprints.len(), 2);
+    assert_ne!(fingerprints[0], fingerprints[1]);
+}
+
+#[test]
+fn picks_key_by_fingerprint() {
+    let config = config();
+    for fingerprint in config.fingerprints() {
+        let key = config.rsa_key(fingerprint).unwrap().unwrap();
+        assert_eq!(key.fingerprint(), fingerprint);
+    }
+}
+
+#[test]
+fn rejects_unknown_fingerprint() {
+    let config = config();
+    assert!(config.rsa_key(0x1337).is_err());
+}
+
+#[test]
+fn fingerprint_override_picks_first_key() {
+    let config = Config {
+        fingerprint_override: Some(0x1337),
+        ..config()
+    };
+    assert_eq!(config.fingerprints(), [0x1337]);
+    let key = config.rsa_key(0x1337).unwrap().unwrap();
+    assert_eq!(key.fingerprint(), config.rsa_keys[0].fingerprint());
+}
+
+#[test]
+fn parses_fingerprint() {
+    assert_eq!(
+        parse_fingerprint("d09d1d85de64fd85").unwrap(),
+        0xd09d1d85de64fd85u64 as i64
+    );
+    assert_eq!(parse_fingerprint("0x1337").unwrap(), 0x1337);
+    assert!(parse_fingerprint("").is_err());
+    assert!(parse_fingerprint("d09d1d85de64fd8500").is_err());
+    assert!(parse_fingerprint("xyz").is_err());
+}
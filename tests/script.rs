@@ -0,0 +1,120 @@
+use srv::{script::Script, ServerError};
+
+const REQ_PQ_MULTI: &str =
+    "0000000000000000000000000000000014000000f18e7ebe42424242424242424242424242424242";
+
+fn assert_invalid(json: &str) {
+    match Script::parse(json) {
+        Err(ServerError::InvalidScript(_)) => {}
+        Err(e) => panic!("{:?}: unexpected error {}", json, e),
+        Ok(_) => panic!("{:?} was accepted", json),
+    }
+}
+
+fn hex(hex: &str) -> Vec<u8> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+#[test]
+fn parse() {
+    let script = Script::parse(
+        r#"
+        {
+            "be7e8ef1": "01020304",
+            "D712E4BE" : ""
+        }
+        "#,
+    )
+    .unwrap();
+    assert_eq!(script.respond(&hex(REQ_PQ_MULTI)).unwrap(), [1, 2, 3, 4]);
+
+    let mut req_dh_params = hex(REQ_PQ_MULTI);
+    req_dh_params[20..24].copy_from_slice(&0xd712e4be_u32.to_le_bytes());
+    assert_eq!(script.respond(&req_dh_params).unwrap(), []);
+}
+
+#[test]
+fn unscripted() {
+    let script = Script::parse("{}").unwrap();
+    assert!(matches!(
+        script.respond(&hex(REQ_PQ_MULTI)),
+        Err(ServerError::Unscripted(0xbe7e8ef1))
+    ));
+    assert!(matches!(
+        script.respond(&[0; 20]),
+        Err(ServerError::ShortRead(_))
+    ));
+}
+
+#[test]
+fn invalid() {
+    assert_invalid("");
+    assert_invalid("[]");
+    assert_invalid(r#"{"be7e8ef1": "01020304""#);
+    assert_invalid(r#"{"be7e8ef1": "01020304",}"#);
+    assert_invalid(r#"{"be7e8ef1": "01020304"} {}"#);
+    assert_invalid(r#"{"be7e8e": "01020304"}"#);
+    assert_invalid(r#"{"req_pq_multi": "01020304"}"#);
+    assert_invalid(r#"{"be7e8ef1": "0102030"}"#);
+    assert_invalid(r#"{"be7e8ef1": "0102030g"}"#);
+    assert_invalid(r#"{"be7e8ef1": "01\"02"}"#);
+    assert_invalid(r#"{"be7e8ef1": "01020304", "be7e8ef1": "05060708"}"#);
+}
+
+// The transports only frame whole 4 byte words
+#[test]
+fn partial_words() {
+    for response in ["01", "010203", "0102030405"] {
+        assert_invalid(&format!(r#"{{"be7e8ef1": "{}"}}"#, response));
+    }
+}
+
+mod serve {
+    use std::{
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        thread,
+    };
+
+    use bytes::BytesMut;
+    use grammers_mtproto::transport::{Full, Transport};
+    use srv::{handle_connection, script::Script, Config};
+
+    use super::{hex, REQ_PQ_MULTI};
+
+    #[test]
+    fn scripted_response() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        let server = thread::spawn(move || {
+            let config = Config {
+                script: Some(Script::parse(r#"{"be7e8ef1": "0102030405060708"}"#).unwrap()),
+                ..Default::default()
+            };
+            handle_connection(server, &config)
+        });
+
+        let mut transport = Full::new();
+        let mut request = BytesMut::new();
+        transport.pack(&hex(REQ_PQ_MULTI), &mut request);
+        stream.write_all(&request).unwrap();
+
+        let mut buffer = Vec::new();
+        let mut answer = BytesMut::new();
+        while transport.unpack(&buffer, &mut answer).is_err() {
+            let mut chunk = [0; 1024];
+            let len = stream.read(&mut chunk).unwrap();
+            assert_ne!(len, 0);
+            buffer.extend_from_slice(&chunk[..len]);
+        }
+        assert_eq!(answer[..], [1, 2, 3, 4, 5, 6, 7, 8]);
+
+        // The script is served until the client leaves
+        drop(stream);
+        server.join().unwrap().unwrap();
+    }
+}
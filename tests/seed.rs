@@ -0,0 +1,87 @@
+mod common;
+
+use common::{req_pq_multi, unencrypted_message, Client, ABRIDGED_TAG, NONCE};
+use grammers_tl_types::{self as tl, Cursor, Deserializable, Serializable};
+use srv::{
+    message_id::{FixedClock, MessageIds},
+    pq,
+    rng::ServerRng,
+    rsa_key::TELEGRAM_FINGERPRINT,
+    Config,
+};
+
+// Like the server of --seed, whose clock is stopped too
+fn config(rng: ServerRng) -> Config {
+    Config {
+        rng,
+        message_ids: Box::new(|| MessageIds::new(FixedClock(1_700_000_000))),
+        ..Default::default()
+    }
+}
+
+// The ResPq and ServerDHParams of a fresh server, as they were sent
+fn transcript(rng: ServerRng) -> (Vec<u8>, Vec<u8>) {
+    let mut client = Client::connect_with(ABRIDGED_TAG, config(rng));
+    client.send_abridged(req_pq_multi());
+    let res_pq = client.receive_abridged();
+    let tl::enums::ResPq::Pq(pq) = tl::enums::ResPq::from_bytes(&res_pq[20..]).unwrap();
+    let (p, q) = pq::factorize(u64::from_be_bytes(pq.pq[..].try_into().unwrap())).unwrap();
+    let req_dh_params = tl::functions::ReqDhParams {
+        nonce: NONCE,
+        server_nonce: pq.server_nonce,
+        p: p.to_be_bytes().to_vec(),
+        q: q.to_be_bytes().to_vec(),
+        public_key_fingerprint: TELEGRAM_FINGERPRINT,
+        encrypted_data: vec![0; 256],
+    };
+    client.send_abridged(unencrypted_message(&req_dh_params.to_bytes()));
+    (res_pq, client.receive_abridged())
+}
+
+fn res_pq(rng: ServerRng) -> Vec<u8> {
+    let mut client = Client::connect_with(ABRIDGED_TAG, config(rng));
+    client.send_abridged(req_pq_multi());
+    client.receive_abridged()
+}
+
+fn server_nonce(res_pq: &[u8]) -> [u8; 16] {
+    let mut cur = Cursor::from_slice(&res_pq[20..]);
+    let tl::enums::ResPq::Pq(res_pq) = tl::enums::ResPq::deserialize(&mut cur).unwrap();
+    res_pq.server_nonce
+}
+
+#[test]
+fn same_seed_same_transcript() {
+    let first = transcript(ServerRng::seeded([0x42; 32]));
+    let second = transcript(ServerRng::seeded([0x42; 32]));
+    assert_eq!(first.0, second.0);
+    assert_eq!(first.1, second.1);
+}
+
+#[test]
+fn other_seed_other_res_pq() {
+    let first = res_pq(ServerRng::seeded([0x42; 32]));
+    let second = res_pq(ServerRng::seeded([0x43; 32]));
+    assert_ne!(server_nonce(&first), server_nonce(&second));
+}
+
+#[test]
+fn unseeded_is_random() {
+    let first = res_pq(ServerRng::default());
+    let second = res_pq(ServerRng::default());
+    assert_ne!(server_nonce(&first), server_nonce(&second));
+}
+
+#[test]
+fn seeded_values() {
+    let rng = ServerRng::seeded([1; 32]);
+    assert!(rng.is_seeded());
+    let mut first = [0; 32];
+    rng.fill_bytes(&mut first);
+    let other = ServerRng::seeded([1; 32]);
+    let mut second = [0; 32];
+    other.fill_bytes(&mut second);
+    assert_eq!(first, second);
+    assert_eq!(rng.gen::<i64>(), other.gen::<i64>());
+    assert!(!ServerRng::default().is_seeded());
+}
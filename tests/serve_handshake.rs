@@ -0,0 +1,101 @@
+use std::io::{self, Cursor, Read, Write};
+
+use bytes::BytesMut;
+use grammers_mtproto::transport::{Full, Transport};
+use grammers_tl_types::{self as tl, Deserializable, Serializable};
+use srv::{serve_handshake, stats::Stage, Config, ServerError};
+
+// Reads what the client sent up front and keeps what the server writes
+struct MemoryStream {
+    input: Cursor<Vec<u8>>,
+    output: Vec<u8>,
+}
+
+impl Read for MemoryStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl Write for MemoryStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// A req_pq_multi framed by the Full transport, which needs no obfuscation
+fn req_pq_multi(transport: &mut Full) -> Vec<u8> {
+    let body = tl::functions::ReqPqMulti { nonce: [0x42; 16] }.to_bytes();
+    let mut packet = Vec::new();
+    0i64.serialize(&mut packet);
+    0x51e57ac42770964ai64.serialize(&mut packet);
+    (body.len() as u32).serialize(&mut packet);
+    packet.extend(body);
+
+    let mut request = BytesMut::new();
+    transport.pack(&packet, &mut request);
+    request.to_vec()
+}
+
+#[test]
+fn res_pq_over_memory() {
+    let mut transport = Full::new();
+    let mut stream = MemoryStream {
+        input: Cursor::new(req_pq_multi(&mut transport)),
+        output: Vec::new(),
+    };
+    let config = Config {
+        stop_after_res_pq: true,
+        ..Default::default()
+    };
+    let outcome = serve_handshake(&mut stream, &config).unwrap();
+    assert!(outcome.dc_id.is_none());
+    assert!(outcome.auth_key.is_none());
+    assert_eq!(config.stage_stats.count(Stage::ReqPqMulti), 1);
+    assert_eq!(config.stage_stats.count(Stage::ReqDHParams), 0);
+
+    let mut answer = BytesMut::new();
+    assert_eq!(
+        transport.unpack(&stream.output, &mut answer).unwrap(),
+        stream.output.len()
+    );
+    let tl::enums::ResPq::Pq(res_pq) = tl::enums::ResPq::from_bytes(&answer[20..]).unwrap();
+    assert_eq!(res_pq.nonce, [0x42; 16]);
+}
+
+#[test]
+fn closed_before_req_dh_params() {
+    let mut stream = MemoryStream {
+        input: Cursor::new(req_pq_multi(&mut Full::new())),
+        output: Vec::new(),
+    };
+    assert!(matches!(
+        serve_handshake(&mut stream, &Config::default()),
+        Err(ServerError::ConnectionClosed)
+    ));
+    assert!(!stream.output.is_empty());
+}
+
+#[test]
+fn advertises_fingerprint_override() {
+    let mut transport = Full::new();
+    let mut stream = MemoryStream {
+        input: Cursor::new(req_pq_multi(&mut transport)),
+        output: Vec::new(),
+    };
+    let config = Config {
+        fingerprint_override: Some(0x0123456789abcdef),
+        stop_after_res_pq: true,
+        ..Default::default()
+    };
+    serve_handshake(&mut stream, &config).unwrap();
+
+    let mut answer = BytesMut::new();
+    transport.unpack(&stream.output, &mut answer).unwrap();
+    let tl::enums::ResPq::Pq(res_pq) = tl::enums::ResPq::from_bytes(&answer[20..]).unwrap();
+    assert_eq!(res_pq.server_public_key_fingerprints, [0x0123456789abcdef]);
+}
@@ -0,0 +1,86 @@
+mod common;
+
+use common::{req_pq_multi, Client, ABRIDGED_TAG};
+use grammers_tl_types::{self as tl, Deserializable, Serializable};
+use num_bigint::BigUint;
+use rsa::{pkcs1::DecodeRsaPrivateKey, traits::PublicKeyParts, RsaPrivateKey};
+use sha1::{Digest, Sha1};
+use srv::{messages::PqInnerDataError, pq, rsa_key::RsaKey, Config, ServerError};
+
+const PEM: &str = include_str!("data/rsa1.pem");
+const NONCE: [u8; 16] = [0x42; 16];
+const NEW_NONCE: [u8; 32] = [0x33; 32];
+
+fn message(body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![0; 8];
+    packet.extend(0x51e57ac42770964ai64.to_le_bytes());
+    packet.extend((body.len() as u32).to_le_bytes());
+    packet.extend(body);
+    packet
+}
+
+// RSA without padding, like the server decrypts it
+fn encrypt(data_with_hash: &[u8]) -> Vec<u8> {
+    let key = RsaPrivateKey::from_pkcs1_pem(PEM).unwrap();
+    let n = BigUint::from_bytes_be(&key.n().to_bytes_be());
+    let e = BigUint::from_bytes_be(&key.e().to_bytes_be());
+    BigUint::from_bytes_be(data_with_hash)
+        .modpow(&e, &n)
+        .to_bytes_be()
+}
+
+#[test]
+fn tampered_inner_data() {
+    let config = Config {
+        rsa_keys: vec![RsaKey::from_pem(PEM).unwrap()],
+        ..Default::default()
+    };
+    let mut client = Client::connect_with(ABRIDGED_TAG, config);
+    client.send_abridged(req_pq_multi());
+    let tl::enums::ResPq::Pq(res_pq) =
+        tl::enums::ResPq::from_bytes(&client.receive_abridged()[20..]).unwrap();
+    let (p, q) = pq::factorize(u64::from_be_bytes(res_pq.pq[..].try_into().unwrap())).unwrap();
+
+    let data = tl::enums::PQInnerData::Data(tl::types::PQInnerData {
+        pq: res_pq.pq.clone(),
+        p: p.to_be_bytes().to_vec(),
+        q: q.to_be_bytes().to_vec(),
+        nonce: NONCE,
+        server_nonce: res_pq.server_nonce,
+        new_nonce: NEW_NONCE,
+    })
+    .to_bytes();
+    let mut data_with_hash = Sha1::digest(&data).to_vec();
+    // The hash doesn't match anymore
+    data_with_hash[0] ^= 1;
+    data_with_hash.extend(data);
+    data_with_hash.resize(255, 0);
+    let req_dh_params = tl::functions::ReqDhParams {
+        nonce: NONCE,
+        server_nonce: res_pq.server_nonce,
+        p: p.to_be_bytes().to_vec(),
+        q: q.to_be_bytes().to_vec(),
+        public_key_fingerprint: res_pq.server_public_key_fingerprints[0],
+        encrypted_data: encrypt(&data_with_hash),
+    };
+    client.send_abridged(message(&req_dh_params.to_bytes()));
+
+    let answer = client.receive_abridged();
+    let (rest, res) = client.receive_all();
+    assert!(rest.is_empty());
+    assert!(matches!(
+        res,
+        Err(ServerError::ServerDhParamsFail {
+            reason: PqInnerDataError::BadHash,
+            ..
+        })
+    ));
+    let tl::enums::ServerDhParams::Fail(fail) =
+        tl::enums::ServerDhParams::from_bytes(&answer[20..]).unwrap()
+    else {
+        panic!("expected server_DH_params_fail");
+    };
+    assert_eq!(fail.nonce, NONCE);
+    assert_eq!(fail.server_nonce, res_pq.server_nonce);
+    assert_eq!(fail.new_nonce_hash[..], Sha1::digest(NEW_NONCE)[4..]);
+}
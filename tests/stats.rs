@@ -0,0 +1,70 @@
+use std::time::Duration;
+
+use srv::stats::{HandshakeLatency, Stage, StageStats, PERCENTILES};
+
+#[test]
+fn counts_each_stage() {
+    let stats = StageStats::default();
+    // Three connections got ResPq, two of them ServerDHParams and one an auth key
+    for stage in [
+        Stage::ReqPqMulti,
+        Stage::ReqPqMulti,
+        Stage::ReqPqMulti,
+        Stage::ReqDHParams,
+        Stage::ReqDHParams,
+        Stage::DhGenOk,
+    ] {
+        stats.record(stage);
+    }
+    assert_eq!(stats.count(Stage::ReqPqMulti), 3);
+    assert_eq!(stats.count(Stage::ReqDHParams), 2);
+    assert_eq!(stats.count(Stage::DhGenOk), 1);
+    assert_eq!(stats.to_string(), "3 ReqPqMulti, 2 ReqDHParams, 1 DhGenOk");
+}
+
+#[test]
+fn starts_at_zero() {
+    let stats = StageStats::default();
+    for stage in Stage::ALL {
+        assert_eq!(stats.count(stage), 0);
+    }
+}
+
+#[test]
+fn latency_percentiles() {
+    let latency = HandshakeLatency::default();
+    assert_eq!(latency.percentile(50.0), None);
+    assert_eq!(latency.to_string(), "no handshakes yet");
+
+    // 1ms to 100ms, shuffled
+    for i in 0..100u64 {
+        latency.record(Duration::from_millis((i * 37) % 100 + 1));
+    }
+    assert_eq!(latency.len(), 100);
+    let [p50, p95, p99] = latency.percentiles(PERCENTILES).unwrap();
+    let within = |duration: Duration, expected: u64| {
+        duration.abs_diff(Duration::from_millis(expected)) <= Duration::from_millis(1)
+    };
+    assert!(within(p50, 50), "{:?}", p50);
+    assert!(within(p95, 95), "{:?}", p95);
+    assert!(within(p99, 99), "{:?}", p99);
+    assert_eq!(latency.percentile(0.0), Some(Duration::from_millis(1)));
+    assert_eq!(latency.percentile(100.0), Some(Duration::from_millis(100)));
+    assert_eq!(
+        latency.to_string(),
+        "p50 50ms, p95 95ms, p99 99ms, over 100 handshakes"
+    );
+}
+
+#[test]
+fn latency_keeps_the_recent_handshakes() {
+    let latency = HandshakeLatency::default();
+    // Slow handshakes, then enough fast ones to replace all of them
+    for _ in 0..100 {
+        latency.record(Duration::from_secs(1));
+    }
+    for _ in 0..10_000 {
+        latency.record(Duration::from_millis(10));
+    }
+    assert_eq!(latency.percentile(99.0), Some(Duration::from_millis(10)));
+}
@@ -0,0 +1,52 @@
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    thread,
+    time::Duration,
+};
+
+use bytes::BytesMut;
+use grammers_mtproto::transport::{Full, Transport};
+use grammers_tl_types::{self as tl, Serializable};
+use srv::{handle_connection, Config};
+
+#[test]
+fn closed_after_res_pq() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (server, _) = listener.accept().unwrap();
+    let server = thread::spawn(move || {
+        let config = Config {
+            stop_after_res_pq: true,
+            ..Default::default()
+        };
+        handle_connection(server, &config)
+    });
+
+    let body = tl::functions::ReqPqMulti { nonce: [0x42; 16] }.to_bytes();
+    let mut packet = Vec::new();
+    0i64.serialize(&mut packet);
+    0x51e57ac42770964ai64.serialize(&mut packet);
+    (body.len() as u32).serialize(&mut packet);
+    packet.extend(body);
+
+    let mut transport = Full::new();
+    let mut request = BytesMut::new();
+    transport.pack(&packet, &mut request);
+    stream.write_all(&request).unwrap();
+
+    // The server closes the socket while the client is still connected, so the
+    // read ends with EOF instead of timing out
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut buffer = Vec::new();
+    stream.read_to_end(&mut buffer).unwrap();
+    let mut answer = BytesMut::new();
+    assert_eq!(
+        transport.unpack(&buffer, &mut answer).unwrap(),
+        buffer.len()
+    );
+
+    server.join().unwrap().unwrap();
+}
@@ -0,0 +1,66 @@
+use std::{
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use srv::{
+    handle_connection,
+    keys::auth_key_id,
+    test_client::{TestClient, TestTransport},
+    Config,
+};
+
+const NONCE: [u8; 16] = [0x42; 16];
+
+fn res_pq(transport: TestTransport) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (server, _) = listener.accept().unwrap();
+    let server = thread::spawn(move || {
+        let config = Config {
+            stop_after_res_pq: true,
+            ..Default::default()
+        };
+        handle_connection(server, &config)
+    });
+
+    let mut client = TestClient::connect(stream, transport, 2).unwrap();
+    let res_pq = client.req_pq_multi(NONCE).unwrap();
+    assert_eq!(res_pq.nonce, NONCE);
+    assert_eq!(res_pq.pq.len(), 8);
+    assert!(!res_pq.server_public_key_fingerprints.is_empty());
+    server.join().unwrap().unwrap();
+}
+
+#[test]
+fn abridged() {
+    res_pq(TestTransport::Abridged);
+}
+
+#[test]
+fn intermediate() {
+    res_pq(TestTransport::Intermediate);
+}
+
+#[test]
+fn handshake() {
+    let completed = Arc::new(Mutex::new(Vec::new()));
+    let config = Config {
+        on_handshake_complete: Some(Box::new({
+            let completed = completed.clone();
+            move |complete| completed.lock().unwrap().push(complete.auth_key_id)
+        })),
+        ..Default::default()
+    };
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (server, _) = listener.accept().unwrap();
+    let server = thread::spawn(move || handle_connection(server, &config));
+
+    let mut client = TestClient::connect(stream, TestTransport::Intermediate, 2).unwrap();
+    let auth_key = client.handshake(NONCE).unwrap();
+    drop(client);
+    server.join().unwrap().unwrap();
+    assert_eq!(*completed.lock().unwrap(), [auth_key_id(&auth_key)]);
+}
@@ -0,0 +1,25 @@
+use std::{
+    net::{TcpListener, TcpStream},
+    time::{Duration, Instant},
+};
+
+use srv::{handle_connection, Config};
+
+#[test]
+fn silent_client_times_out() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    // Connects and never sends the obfuscation header
+    let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (stream, _) = listener.accept().unwrap();
+
+    let start = Instant::now();
+    assert!(handle_connection(
+        stream,
+        &Config {
+            timeout: Duration::from_millis(200),
+            ..Default::default()
+        }
+    )
+    .is_err());
+    assert!(start.elapsed() < Duration::from_secs(2));
+}
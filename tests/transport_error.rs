@@ -0,0 +1,49 @@
+mod common;
+
+use common::{req_pq_multi, Client, ABRIDGED_TAG, INTERMEDIATE_TAG};
+use srv::ServerError;
+
+// A req_pq_multi with a wrong constructor
+fn bad_magic() -> Vec<u8> {
+    let mut packet = req_pq_multi();
+    packet[20..24].copy_from_slice(&0xdeadbeefu32.to_le_bytes());
+    packet
+}
+
+#[test]
+fn abridged_error_frame() {
+    let mut client = Client::connect(ABRIDGED_TAG);
+    let packet = bad_magic();
+    let mut frame = vec![(packet.len() / 4) as u8];
+    frame.extend(packet);
+    client.send(frame);
+
+    let (answer, res) = client.receive_all();
+    assert_eq!(answer, [0x01, 0x6c, 0xfe, 0xff, 0xff]);
+    assert!(matches!(res, Err(ServerError::MagicMismatch { .. })));
+}
+
+#[test]
+fn intermediate_error_frame() {
+    let mut client = Client::connect(INTERMEDIATE_TAG);
+    let packet = bad_magic();
+    let mut frame = (packet.len() as u32).to_le_bytes().to_vec();
+    frame.extend(packet);
+    client.send(frame);
+
+    let (answer, res) = client.receive_all();
+    assert_eq!(answer, [0x04, 0x00, 0x00, 0x00, 0x6c, 0xfe, 0xff, 0xff]);
+    assert!(matches!(res, Err(ServerError::MagicMismatch { .. })));
+}
+
+#[test]
+fn inbound_error() {
+    let mut client = Client::connect(ABRIDGED_TAG);
+    let mut frame = vec![0x01];
+    frame.extend((-429i32).to_le_bytes());
+    client.send(frame);
+
+    let (answer, res) = client.receive_all();
+    assert!(answer.is_empty());
+    assert!(matches!(res, Err(ServerError::TransportError(-429))));
+}
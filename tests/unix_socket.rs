@@ -0,0 +1,85 @@
+#![cfg(unix)]
+
+use std::{
+    io::{Read, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    process::{Child, Command},
+    thread,
+    time::{Duration, Instant},
+};
+
+use bytes::BytesMut;
+use grammers_mtproto::transport::{Full, Transport};
+use grammers_tl_types::{self as tl, Deserializable, Serializable};
+
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn wait_for(what: &str, mut f: impl FnMut() -> bool) {
+    let start = Instant::now();
+    while !f() {
+        assert!(start.elapsed() < Duration::from_secs(5), "{}", what);
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn req_pq_multi() {
+    let path = std::env::temp_dir().join(format!("tg_srv-{}.sock", std::process::id()));
+    // A stale socket of a killed server
+    drop(UnixListener::bind(&path).unwrap());
+    let mut server = Server(
+        Command::new(env!("CARGO_BIN_EXE_srv"))
+            .arg("--unix")
+            .arg(&path)
+            .spawn()
+            .unwrap(),
+    );
+    let mut stream = None;
+    wait_for("server didn't start", || {
+        stream = UnixStream::connect(&path).ok();
+        stream.is_some()
+    });
+    let mut stream = stream.unwrap();
+
+    let body = tl::functions::ReqPqMulti { nonce: [0x42; 16] }.to_bytes();
+    let mut packet = Vec::new();
+    0i64.serialize(&mut packet);
+    0x51e57ac42770964ai64.serialize(&mut packet);
+    (body.len() as u32).serialize(&mut packet);
+    packet.extend(body);
+    let mut transport = Full::new();
+    let mut request = BytesMut::new();
+    transport.pack(&packet, &mut request);
+    stream.write_all(&request).unwrap();
+
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut buffer = Vec::new();
+    let mut answer = BytesMut::new();
+    while transport.unpack(&buffer, &mut answer).is_err() {
+        let mut chunk = [0; 1024];
+        let len = stream.read(&mut chunk).unwrap();
+        assert_ne!(len, 0, "connection closed");
+        buffer.extend_from_slice(&chunk[..len]);
+    }
+    let tl::enums::ResPq::Pq(res_pq) = tl::enums::ResPq::from_bytes(&answer[20..]).unwrap();
+    assert_eq!(res_pq.nonce, [0x42; 16]);
+    drop(stream);
+
+    // A graceful shutdown removes the socket
+    let status = Command::new("kill")
+        .args(["-INT", &server.0.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert!(server.0.wait().unwrap().success());
+    assert!(!path.exists());
+}